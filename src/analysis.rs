@@ -1,31 +1,112 @@
 use crate::ast::*;
+use crate::emit::{
+    paragraph_text, BibNameFormat, BibSort, CitationStyle, EmitOptions, EquationNumberSide,
+    MathBackend,
+};
+use crate::math_html::*;
+use crate::math_mathml::*;
 use crate::math_svg::*;
+use crate::parse::replace_accents;
+use crate::util::{display_escaped, slug};
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::ptr::addr_of;
 
+pub type AlignNumbering<'a> = HashMap<*const Math<'a>, Vec<Option<String>>>;
+
 pub struct Analysis<'a> {
-    // The number strings assigned to theorem-like document parts:
+    // The number strings assigned to numbered document parts:
     // - TheoremLike
     // - Section
     // - Subsection
+    // - Subsubsection
+    // - Figure and Table, if they have a caption
     pub doc_part_numbering: HashMap<*const DocumentPart<'a>, String>,
 
+    // The letters of subfigures with a caption, e.g. 'a' for the first. The number of a subfigure is
+    // that of its figure followed by the letter, e.g. "1a".
+    pub subfigure_letters: HashMap<*const Subfigure<'a>, char>,
+
+    // The html ids of section, subsection and subsubsection headings.
+    pub heading_ids: HashMap<*const DocumentPart<'a>, String>,
+
+    // The file names of the pages on which the elements with given html ids are. Only set if the
+    // output is split into pages.
+    pub id_pages: HashMap<String, String>,
+
     // Numbering strings assigned to equations.
     pub math_numbering: HashMap<*const Math<'a>, String>,
 
+    // Numbering strings assigned to the lines of align environments, or None for unnumbered lines.
+    pub align_numbering: AlignNumbering<'a>,
+
+    // Html for math that is rendered without pdflatex, or placeholders for math that failed to
+    // compile. Math nodes in here have no image.
+    pub math_html: HashMap<*const Math<'a>, String>,
+
+    // Display math nodes that end a proof. The qed symbol is placed into the equation row of these.
+    pub qed_math: HashSet<*const Math<'a>>,
+
+    // Display math nodes whose images are too wide for the text body.
+    pub wide_math: HashSet<*const Math<'a>>,
+
+    // Tabular environments that are estimated to be too wide for the text body.
+    pub wide_tabulars: HashSet<*const ParagraphPart<'a>>,
+
     // The "src" attributes of math images.
     pub math_image_source: HashMap<*const Math<'a>, String>,
 
+    // The inline "style" attributes of math images. These are only set for standalone output,
+    // where the geometry css file is not available.
+    pub math_image_style: HashMap<*const Math<'a>, String>,
+
+    // The intrinsic width and height in px of math images, so that browsers can reserve space for
+    // them before the css that sizes them is loaded.
+    pub math_image_size: HashMap<*const Math<'a>, (u32, u32)>,
+
     // The text by which references to a given id should refer to what they are referencing.
     pub ref_display_text: HashMap<&'a str, String>,
 
+    // The type and number of what \cref and \Cref refer to by a given id.
+    pub cref_targets: HashMap<&'a str, CrefTarget>,
+
     // The list of bibliography entries that should be displayed. In the order as they should be
     // displayed.
     pub bib_entries: Vec<&'a BibEntry<'a>>,
 
     // The text by which citations to a given id should refer to what they are citing.
     pub cite_display_text: HashMap<&'a str, String>,
+
+    // The author and year text of bibliography entries for author-year citations, e.g.
+    // ("Smith and Jones", "2020a"). Only set if the citation style is author-year.
+    pub cite_author_year: HashMap<&'a str, (String, String)>,
+
+    // How citations are displayed.
+    pub citation_style: CitationStyle,
+
+    // The side on which equation numbers are shown.
+    pub equation_numbers: EquationNumberSide,
+
+    // The numbers of \footnote nodes, starting at 1.
+    pub footnote_numbering: HashMap<*const ParagraphPart<'a>, usize>,
+
+    // The contents of all footnotes, ordered by their number.
+    pub footnotes: Vec<&'a [Paragraph<'a>]>,
+
+    // The format of author names in the bibliography.
+    pub bib_name_format: BibNameFormat,
+
+    // The ids of \ref, \eqref and \cref nodes that don't refer to a numbered label, in the order in
+    // which they appear in the source.
+    pub unresolved_refs: Vec<&'a str>,
+
+    // The ids of \cite nodes without bibliography entry, in the order in which they appear in the
+    // source.
+    pub unresolved_cites: Vec<&'a str>,
+
+    // Labels that are defined more than once, as pairs of the first definition and a later one.
+    pub duplicate_labels: Vec<(&'a str, &'a str)>,
 }
 
 impl<'a> Analysis<'a> {
@@ -33,44 +114,298 @@ impl<'a> Analysis<'a> {
         doc: &'a Document<'a>,
         all_bib_entries: &'a [BibEntry<'a>],
         node_lists: &'a NodeLists<'a>,
+        options: &EmitOptions,
+        math_svg_info: &HashMap<MathDigest, SvgInfo>,
+        // The contents of the svg files. Only needed for standalone output.
+        math_svg_data: &HashMap<MathDigest, Vec<u8>>,
     ) -> Self {
         let doc_part_numbering = doc_part_numbering(doc);
-        let math_numbering = math_numbering(node_lists);
-        let math_image_source = math_image_source(doc, node_lists);
-        let ref_display_text =
-            ref_display_text(doc, node_lists, &doc_part_numbering, &math_numbering);
-        let bib_entries = bib_entries(all_bib_entries, node_lists);
+        let subfigure_letters = subfigure_letters(doc, &doc_part_numbering);
+        let heading_ids = heading_ids(doc, node_lists);
+        let (math_numbering, align_numbering) =
+            math_numbering(doc, node_lists, &doc_part_numbering, options);
+        let mut math_html = math_html(node_lists, options);
+        math_html.extend(failed_math_html(
+            doc,
+            node_lists,
+            options,
+            &math_html,
+            math_svg_info,
+        ));
+        let qed_math = qed_math(doc);
+        let wide_math = wide_math(doc, node_lists, options, math_svg_info);
+        let wide_tabulars = wide_tabulars(node_lists, options);
+        let math_image_source =
+            math_image_source(doc, node_lists, options, &math_html, math_svg_data);
+        let math_image_style =
+            math_image_style(doc, node_lists, options, &math_html, math_svg_info);
+        let math_image_size = math_image_size(doc, node_lists, options, &math_html, math_svg_info);
+        let ref_display_text = ref_display_text(
+            doc,
+            node_lists,
+            &doc_part_numbering,
+            &subfigure_letters,
+            &math_numbering,
+            &align_numbering,
+        );
+        let cref_targets = cref_targets(
+            doc,
+            node_lists,
+            &doc_part_numbering,
+            &subfigure_letters,
+            &ref_display_text,
+        );
+        let bib_entries = bib_entries(all_bib_entries, node_lists, options.bib_sort);
         let cite_display_text = cite_display_text(bib_entries.iter().copied());
+        let id_pages = if options.split_sections && !options.standalone {
+            id_pages(doc, &heading_ids, &bib_entries)
+        } else {
+            HashMap::new()
+        };
+        let cite_author_year = match options.citation_style {
+            CitationStyle::Numeric => HashMap::new(),
+            CitationStyle::AuthorYear => cite_author_year(&bib_entries),
+        };
+        let footnote_numbering = footnote_numbering(node_lists);
+        let footnotes = footnotes(node_lists);
+        let bib_name_format = bib_name_format(doc, options);
+        let unresolved_refs = unresolved_ids(&node_lists.ref_ids, &ref_display_text);
+        let unresolved_cites = unresolved_ids(&node_lists.cite_ids, &cite_display_text);
+        let duplicate_labels = duplicate_labels(doc, node_lists);
         Analysis {
             doc_part_numbering,
+            subfigure_letters,
+            heading_ids,
+            id_pages,
             math_numbering,
+            align_numbering,
+            math_html,
+            qed_math,
+            wide_math,
+            wide_tabulars,
             math_image_source,
+            math_image_style,
+            math_image_size,
+            ref_display_text,
+            cref_targets,
+            bib_entries,
+            cite_display_text,
+            cite_author_year,
+            citation_style: options.citation_style,
+            equation_numbers: options.equation_numbers,
+            footnote_numbering,
+            footnotes,
+            bib_name_format,
+            unresolved_refs,
+            unresolved_cites,
+            duplicate_labels,
+        }
+    }
+}
+
+// The numbers, labels and citations of a document, for use outside of this crate. Unlike Analysis,
+// this refers to nodes by reference instead of by address.
+#[derive(Debug)]
+pub struct DocumentSummary<'a> {
+    // The numbered sections, subsections, subsubsections, theorem-like environments, figures and
+    // tables together with their numbers, in document order.
+    pub numbered_parts: Vec<(&'a DocumentPart<'a>, String)>,
+
+    // The numbered equations together with their numbers, in document order. Lines of align
+    // environments are numbered individually and listed in numbered_align_lines instead.
+    pub numbered_math: Vec<(&'a Math<'a>, String)>,
+
+    // The numbered lines of align environments together with their numbers, in document order.
+    pub numbered_align_lines: Vec<(&'a AlignLine<'a>, String)>,
+
+    // The text that \ref displays for a given label.
+    pub ref_display_text: HashMap<&'a str, String>,
+
+    // The cited ids in the order in which they are first cited.
+    pub cite_order: Vec<&'a str>,
+
+    // The entries of the bibliography, in the order in which they are listed.
+    pub bib_entries: Vec<&'a BibEntry<'a>>,
+
+    // The text that citations of a given id display, e.g. "3" for numeric citations.
+    pub cite_display_text: HashMap<&'a str, String>,
+}
+
+impl<'a> DocumentSummary<'a> {
+    pub fn new(
+        doc: &'a Document<'a>,
+        all_bib_entries: &'a [BibEntry<'a>],
+        options: &EmitOptions,
+    ) -> Self {
+        let node_lists = NodeLists::new(doc);
+        let doc_part_numbering = doc_part_numbering(doc);
+        let subfigure_letters = subfigure_letters(doc, &doc_part_numbering);
+        let (math_numbering, align_numbering) =
+            math_numbering(doc, &node_lists, &doc_part_numbering, options);
+        let ref_display_text = ref_display_text(
+            doc,
+            &node_lists,
+            &doc_part_numbering,
+            &subfigure_letters,
+            &math_numbering,
+            &align_numbering,
+        );
+        let bib_entries = bib_entries(all_bib_entries, &node_lists, options.bib_sort);
+        let cite_display_text = cite_display_text(bib_entries.iter().copied());
+
+        let numbered_parts = doc
+            .parts
+            .iter()
+            .filter_map(|part| {
+                let number = doc_part_numbering.get(&addr_of!(*part))?;
+                Some((part, number.clone()))
+            })
+            .collect();
+        let numbered_math = node_lists
+            .math
+            .iter()
+            .copied()
+            .filter_map(|math| {
+                let number = math_numbering.get(&addr_of!(*math))?;
+                Some((math, number.clone()))
+            })
+            .collect();
+        let mut numbered_align_lines = Vec::new();
+        for math in node_lists.math.iter().copied() {
+            if let Math::Align { lines, .. } = math {
+                let numbers = &align_numbering[&addr_of!(*math)];
+                for (line, number) in lines.iter().zip(numbers) {
+                    if let Some(number) = number {
+                        numbered_align_lines.push((line, number.clone()));
+                    }
+                }
+            }
+        }
+
+        DocumentSummary {
+            numbered_parts,
+            numbered_math,
+            numbered_align_lines,
             ref_display_text,
+            cite_order: node_lists.cite_order,
             bib_entries,
             cite_display_text,
         }
     }
 }
 
+// The letter with which the appendix section with the given number is numbered, e.g. "B" for 2.
+fn appendix_letter(number: u32) -> String {
+    let index = u8::try_from((number - 1) % 26).unwrap();
+    char::from(b'A' + index).to_string()
+}
+
 fn doc_part_numbering<'a>(doc: &Document<'a>) -> HashMap<*const DocumentPart<'a>, String> {
     let mut map: HashMap<*const DocumentPart<'a>, String> = HashMap::new();
-    let mut current_theorem_like = 0;
+    // Theorem-like environments are numbered by counters that may be shared between environments
+    // and reset at sections or subsections.
+    let config_of = |tag: &str| {
+        doc.config
+            .theorem_like_configs
+            .iter()
+            .find(|config| config.tag == tag)
+    };
+    let mut theorem_like_counters: HashMap<&str, u32> = HashMap::new();
+    let reset_counters_within = |unit: &str, counters: &mut HashMap<&str, u32>| {
+        counters.retain(|counter, _| {
+            config_of(counter).and_then(|config| config.numbered_within) != Some(unit)
+        })
+    };
+    // After \appendix, sections are numbered A, B, C and so on.
+    let mut appendix = false;
     let mut current_section = 0;
+    let mut section = current_section.to_string();
     let mut current_subsection = 0;
+    let mut current_subsubsection = 0;
+    // As in latex, only figures and tables with a caption are numbered. Their counters are
+    // independent of each other and, unless \numberwithin says otherwise, of sections.
+    let figure_within = doc.config.figure_numbered_within;
+    let table_within = doc.config.table_numbered_within;
+    let mut current_figure = 0;
+    let mut current_table = 0;
+    let float_number =
+        |within: Option<&str>, number: u32, section: &str, subsection: u32| match within {
+            Some("section") => format!("{section}.{number}"),
+            Some("subsection") => format!("{section}.{subsection}.{number}"),
+            _ => number.to_string(),
+        };
     for part in doc.parts.iter() {
         match part {
-            DocumentPart::TheoremLike { .. } => {
-                current_theorem_like += 1;
-                map.insert(part, current_theorem_like.to_string());
+            DocumentPart::Figure {
+                caption: Some(_), ..
+            } => {
+                current_figure += 1;
+                let number =
+                    float_number(figure_within, current_figure, &section, current_subsection);
+                map.insert(part, number);
+            }
+            DocumentPart::Table {
+                caption: Some(_), ..
+            } => {
+                current_table += 1;
+                let number =
+                    float_number(table_within, current_table, &section, current_subsection);
+                map.insert(part, number);
             }
-            DocumentPart::Section { .. } => {
+            DocumentPart::TheoremLike { tag, .. } => {
+                let counter = config_of(tag).map_or(*tag, |config| config.counter);
+                let current_theorem_like = theorem_like_counters.entry(counter).or_insert(0);
+                *current_theorem_like += 1;
+                let number = match config_of(counter).and_then(|config| config.numbered_within) {
+                    Some("section") => format!("{section}.{current_theorem_like}"),
+                    Some("subsection") => {
+                        format!("{section}.{current_subsection}.{current_theorem_like}")
+                    }
+                    _ => current_theorem_like.to_string(),
+                };
+                map.insert(part, number);
+            }
+            DocumentPart::Section { numbered: true, .. } => {
+                reset_counters_within("section", &mut theorem_like_counters);
+                reset_counters_within("subsection", &mut theorem_like_counters);
+                if figure_within.is_some() {
+                    current_figure = 0;
+                }
+                if table_within.is_some() {
+                    current_table = 0;
+                }
                 current_section += 1;
                 current_subsection = 0;
-                map.insert(part, current_section.to_string());
+                current_subsubsection = 0;
+                section = if appendix {
+                    appendix_letter(current_section)
+                } else {
+                    current_section.to_string()
+                };
+                map.insert(part, section.clone());
             }
-            DocumentPart::Subsection { .. } => {
+            DocumentPart::Appendix => {
+                appendix = true;
+                current_section = 0;
+            }
+            DocumentPart::Subsection { numbered: true, .. } => {
+                reset_counters_within("subsection", &mut theorem_like_counters);
+                if figure_within == Some("subsection") {
+                    current_figure = 0;
+                }
+                if table_within == Some("subsection") {
+                    current_table = 0;
+                }
                 current_subsection += 1;
-                map.insert(part, format!("{current_section}.{current_subsection}"));
+                current_subsubsection = 0;
+                map.insert(part, format!("{section}.{current_subsection}"));
+            }
+            DocumentPart::Subsubsection { numbered: true, .. } => {
+                current_subsubsection += 1;
+                map.insert(
+                    part,
+                    format!("{section}.{current_subsection}.{current_subsubsection}"),
+                );
             }
             _ => (),
         }
@@ -78,31 +413,350 @@ fn doc_part_numbering<'a>(doc: &Document<'a>) -> HashMap<*const DocumentPart<'a>
     map
 }
 
-fn math_numbering<'a>(node_lists: &NodeLists<'a>) -> HashMap<*const Math<'a>, String> {
+fn footnote_numbering<'a>(node_lists: &NodeLists<'a>) -> HashMap<*const ParagraphPart<'a>, usize> {
+    node_lists
+        .footnotes
+        .iter()
+        .enumerate()
+        .map(|(i, footnote)| (addr_of!(**footnote), i + 1))
+        .collect()
+}
+
+fn footnotes<'a>(node_lists: &NodeLists<'a>) -> Vec<&'a [Paragraph<'a>]> {
+    node_lists
+        .footnotes
+        .iter()
+        .map(|footnote| match footnote {
+            ParagraphPart::Footnote(content) => content.as_slice(),
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+// Numbers equations and lines of align environments in document order. Unless all display math is
+// numbered, only referenced equations and lines receive a number, and none do if equation numbers
+// are disabled. The numbers are html.
+fn math_numbering<'a>(
+    doc: &'a Document<'a>,
+    node_lists: &NodeLists<'a>,
+    doc_part_numbering: &HashMap<*const DocumentPart<'a>, String>,
+    options: &EmitOptions,
+) -> (HashMap<*const Math<'a>, String>, AlignNumbering<'a>) {
     let mut result: HashMap<*const Math<'a>, String> = HashMap::new();
+    let mut align_result: AlignNumbering<'a> = HashMap::new();
+    // With \numberwithin{equation}{...}, numbers are prefixed with the number of the current
+    // section or subsection, at which the counter is reset.
+    let within = doc.config.equation_numbered_within;
     let mut current_number = 0;
-    for math in node_lists.math.iter().copied() {
-        if let Some(label) = math.label() {
-            if node_lists.ref_ids.contains(label) {
-                current_number += 1;
-                result.insert(math, format!("({current_number})"));
+    // Equations before the first section or subsection have 0 in its place.
+    let mut prefix = match within {
+        Some("subsection") => "0.0".to_string(),
+        _ => "0".to_string(),
+    };
+    let numbered = options.equation_numbers != EquationNumberSide::None;
+    let number_all = options.number_all_display_math;
+    let is_referenced = |label: Option<&str>| {
+        numbered && label.is_some_and(|label| node_lists.ref_ids.contains(label))
+    };
+    for part in doc.parts.iter() {
+        match part {
+            DocumentPart::Section { numbered: true, .. } if within.is_some() => {
+                current_number = 0;
+                prefix.clone_from(&doc_part_numbering[&std::ptr::addr_of!(*part)]);
+                if within == Some("subsection") {
+                    prefix.push_str(".0");
+                }
+            }
+            DocumentPart::Subsection { numbered: true, .. } if within == Some("subsection") => {
+                current_number = 0;
+                prefix.clone_from(&doc_part_numbering[&std::ptr::addr_of!(*part)]);
+            }
+            DocumentPart::SetCounter {
+                counter: "equation",
+                value,
+            } => current_number = *value,
+            _ => (),
+        }
+        let mut next_number = || {
+            current_number += 1;
+            match within {
+                Some(_) => format!("({prefix}.{current_number})"),
+                None => format!("({current_number})"),
+            }
+        };
+        let part_math = NodeLists::for_parts(std::slice::from_ref(part)).math;
+        for math in part_math.iter().copied() {
+            match math {
+                Math::Inline(_) => (),
+                // Equations with a \tag show the tag instead of a number, whether they are referenced
+                // or not, and don't advance the counter.
+                Math::Display { tag: Some(tag), .. } | Math::Mathpar { tag: Some(tag), .. } => {
+                    if numbered {
+                        result.insert(math, format!("({})", display_escaped(tag)));
+                    }
+                }
+                // Unlike \begin{equation}, \[ ... \] and $$ ... $$ are only numbered if referenced.
+                Math::Display {
+                    source,
+                    label,
+                    nonumber,
+                    ..
+                } => {
+                    let is_equation_env = source.starts_with("\\begin{equation}");
+                    let number_all = numbered && number_all && is_equation_env;
+                    if !nonumber && (number_all || is_referenced(*label)) {
+                        result.insert(math, next_number());
+                    }
+                }
+                Math::Mathpar { label, .. } => {
+                    if is_referenced(*label) {
+                        result.insert(math, next_number());
+                    }
+                }
+                Math::Align { lines, .. } => {
+                    let line_numbers = lines
+                        .iter()
+                        .map(|line| {
+                            let number_all = numbered && number_all;
+                            (!line.nonumber && (number_all || is_referenced(line.label)))
+                                .then(&mut next_number)
+                        })
+                        .collect();
+                    align_result.insert(math, line_numbers);
+                }
             }
         }
     }
-    result
+    (result, align_result)
+}
+
+fn math_html<'a>(
+    node_lists: &NodeLists<'a>,
+    options: &EmitOptions,
+) -> HashMap<*const Math<'a>, String> {
+    if options.math_backend == MathBackend::MathMl {
+        return node_lists
+            .math
+            .iter()
+            .copied()
+            .filter_map(|math| Some((addr_of!(*math), math_to_mathml(math).ok()?)))
+            .collect();
+    }
+
+    if !options.trivial_math_html {
+        return HashMap::new();
+    }
+
+    node_lists
+        .math
+        .iter()
+        .copied()
+        .filter_map(|math| match math {
+            Math::Inline(source) => Some((addr_of!(*math), trivial_math_to_html(source)?)),
+            Math::Display { .. } | Math::Mathpar { .. } | Math::Align { .. } => None,
+        })
+        .collect()
+}
+
+// The math nodes that are not rendered as html and hence need to be compiled to svg images.
+pub fn svg_math<'a>(node_lists: &NodeLists<'a>, options: &EmitOptions) -> Vec<&'a Math<'a>> {
+    let math_html = math_html(node_lists, options);
+    node_lists
+        .math
+        .iter()
+        .copied()
+        .filter(|math| !math_html.contains_key(&addr_of!(**math)))
+        .collect()
+}
+
+// Placeholders showing the source of math that should have been compiled to svg but has no svg,
+// which happens only if it failed to compile in best effort mode.
+fn failed_math_html<'a>(
+    doc: &Document,
+    node_lists: &NodeLists<'a>,
+    options: &EmitOptions,
+    math_html: &HashMap<*const Math<'a>, String>,
+    math_svg_info: &HashMap<MathDigest, SvgInfo>,
+) -> HashMap<*const Math<'a>, String> {
+    node_lists
+        .math
+        .iter()
+        .copied()
+        .filter(|math| !math_html.contains_key(&addr_of!(**math)))
+        .filter(|math| !math_svg_info.contains_key(&hash_math(&doc.preamble, math, options)))
+        .map(|math| {
+            let source = display_escaped(crate::math_source(math));
+            let html =
+                format!(r#"<code class="math-error" title="Failed to compile">{source}</code>"#);
+            (addr_of!(*math), html)
+        })
+        .collect()
+}
+
+// Browsers' default font size, which is not overridden by our style sheet.
+const FONT_SIZE_PX: f64 = 16.0;
+
+fn wide_math<'a>(
+    doc: &Document,
+    node_lists: &NodeLists<'a>,
+    options: &EmitOptions,
+    math_svg_info: &HashMap<MathDigest, SvgInfo>,
+) -> HashSet<*const Math<'a>> {
+    node_lists
+        .math
+        .iter()
+        .copied()
+        .filter(|math| match math {
+            Math::Inline(_) => false,
+            Math::Display { .. } | Math::Mathpar { .. } | Math::Align { .. } => {
+                match math_svg_info.get(&hash_math(&doc.preamble, math, options)) {
+                    None => false,
+                    Some(svg_info) => {
+                        svg_info.width_em * FONT_SIZE_PX > f64::from(options.max_width_px)
+                    }
+                }
+            }
+        })
+        .map(|math| addr_of!(*math))
+        .collect()
+}
+
+// The average width of a character of text in em, and the horizontal padding of a tabular cell.
+const CHAR_WIDTH_EM: f64 = 0.5;
+const CELL_PADDING_EM: f64 = 1.0;
+
+// Estimates the width of tabulars from the longest text in each of their columns.
+fn wide_tabulars<'a>(
+    node_lists: &NodeLists<'a>,
+    options: &EmitOptions,
+) -> HashSet<*const ParagraphPart<'a>> {
+    node_lists
+        .tabulars
+        .iter()
+        .copied()
+        .filter(|tabular| {
+            let ParagraphPart::Tabular { rows, .. } = tabular else {
+                unreachable!()
+            };
+            let mut column_chars: Vec<usize> = Vec::new();
+            for row in rows {
+                for (i, cell) in row.cells.iter().enumerate() {
+                    let chars = paragraph_text(cell).trim().chars().count();
+                    if i == column_chars.len() {
+                        column_chars.push(0);
+                    }
+                    column_chars[i] = column_chars[i].max(chars);
+                }
+            }
+            let width_em: f64 = column_chars
+                .iter()
+                .map(|chars| *chars as f64 * CHAR_WIDTH_EM + CELL_PADDING_EM)
+                .sum();
+            width_em * FONT_SIZE_PX > f64::from(options.max_width_px)
+        })
+        .map(|tabular| addr_of!(*tabular))
+        .collect()
+}
+
+// Returns the display math node a paragraph ends with, ignoring trailing whitespace.
+pub fn trailing_display_math<'a, 'b>(paragraph: &'b Paragraph<'a>) -> Option<&'b Math<'a>> {
+    let last_part = paragraph
+        .iter()
+        .rev()
+        .find(|part| !matches!(part, ParagraphPart::InlineWhitespace(_)))?;
+    match last_part {
+        ParagraphPart::Math(
+            math @ (Math::Display { .. } | Math::Mathpar { .. } | Math::Align { .. }),
+        ) => Some(math),
+        _ => None,
+    }
+}
+
+fn qed_math<'a>(doc: &Document<'a>) -> HashSet<*const Math<'a>> {
+    doc.parts
+        .iter()
+        .filter_map(|part| match part {
+            DocumentPart::Proof(ps) => trailing_display_math(ps.last()?),
+            _ => None,
+        })
+        .map(|math| addr_of!(*math))
+        .collect()
 }
 
 fn math_image_source<'a>(
     doc: &Document,
     node_lists: &NodeLists<'a>,
+    options: &EmitOptions,
+    math_html: &HashMap<*const Math<'a>, String>,
+    math_svg_data: &HashMap<MathDigest, Vec<u8>>,
+) -> HashMap<*const Math<'a>, String> {
+    node_lists
+        .math
+        .iter()
+        .copied()
+        .filter(|math| !math_html.contains_key(&addr_of!(**math)))
+        .map(|math| {
+            let digest = hash_math(&doc.preamble, math, options);
+            let source = if options.standalone {
+                let svg = math_svg_data.get(&digest).unwrap();
+                format!("data:image/svg+xml;base64,{}", base64::encode(svg))
+            } else {
+                format!("{SVG_OUT_DIR}/{digest}.svg")
+            };
+            (addr_of!(*math), source)
+        })
+        .collect()
+}
+
+fn math_image_style<'a>(
+    doc: &Document,
+    node_lists: &NodeLists<'a>,
+    options: &EmitOptions,
+    math_html: &HashMap<*const Math<'a>, String>,
+    math_svg_info: &HashMap<MathDigest, SvgInfo>,
 ) -> HashMap<*const Math<'a>, String> {
+    if !options.standalone {
+        return HashMap::new();
+    }
+
     node_lists
         .math
         .iter()
         .copied()
+        .filter(|math| !math_html.contains_key(&addr_of!(**math)))
         .map(|math| {
-            let digest = hash_math(&doc.preamble, math);
-            (addr_of!(*math), format!("{SVG_OUT_DIR}/{digest}.svg"))
+            let svg_info = math_svg_info
+                .get(&hash_math(&doc.preamble, math, options))
+                .unwrap();
+            let SvgInfo {
+                width_em,
+                height_em,
+                ..
+            } = svg_info;
+            let top_em = svg_info.top_em();
+            let style = format!("width: {width_em}em; height: {height_em}em; top: {top_em}em;");
+            (addr_of!(*math), style)
+        })
+        .collect()
+}
+
+fn math_image_size<'a>(
+    doc: &Document,
+    node_lists: &NodeLists<'a>,
+    options: &EmitOptions,
+    math_html: &HashMap<*const Math<'a>, String>,
+    math_svg_info: &HashMap<MathDigest, SvgInfo>,
+) -> HashMap<*const Math<'a>, (u32, u32)> {
+    node_lists
+        .math
+        .iter()
+        .copied()
+        .filter(|math| !math_html.contains_key(&addr_of!(**math)))
+        .filter_map(|math| {
+            let svg_info = math_svg_info.get(&hash_math(&doc.preamble, math, options))?;
+            let width_px = (svg_info.width_em * FONT_SIZE_PX).round() as u32;
+            let height_px = (svg_info.height_em * FONT_SIZE_PX).round() as u32;
+            Some((addr_of!(*math), (width_px, height_px)))
         })
         .collect()
 }
@@ -111,27 +765,55 @@ fn ref_display_text<'a>(
     doc: &Document<'a>,
     node_lists: &NodeLists<'a>,
     doc_part_numbering: &HashMap<*const DocumentPart, String>,
+    subfigure_letters: &HashMap<*const Subfigure, char>,
     math_numbering: &HashMap<*const Math, String>,
+    align_numbering: &AlignNumbering,
 ) -> HashMap<&'a str, String> {
     let mut text = HashMap::new();
     for part in doc.parts.iter() {
         use DocumentPart::*;
         match part {
-            TheoremLike { label, .. } | Section { label, .. } | Subsection { label, .. } => {
-                if let Some(label) = label {
-                    let number = doc_part_numbering.get(&std::ptr::addr_of!(*part)).unwrap();
+            TheoremLike { label, .. }
+            | Section { label, .. }
+            | Subsection { label, .. }
+            | Subsubsection { label, .. } => {
+                // Unnumbered sections have no number to refer to.
+                let number = doc_part_numbering.get(&std::ptr::addr_of!(*part));
+                if let (Some(label), Some(number)) = (label, number) {
                     text.insert(*label, number.clone());
                 }
             }
+            Figure {
+                label, subfigures, ..
+            } => {
+                let Some(number) = doc_part_numbering.get(&std::ptr::addr_of!(*part)) else {
+                    continue;
+                };
+                if let Some(label) = label {
+                    text.insert(*label, format!("Figure {number}"));
+                }
+                for subfigure in subfigures {
+                    let letter = subfigure_letters.get(&std::ptr::addr_of!(*subfigure));
+                    if let (Some(label), Some(letter)) = (subfigure.label, letter) {
+                        text.insert(label, format!("Figure {number}{letter}"));
+                    }
+                }
+            }
+            Table { label, .. } => {
+                let number = doc_part_numbering.get(&std::ptr::addr_of!(*part));
+                if let (Some(label), Some(number)) = (label, number) {
+                    text.insert(*label, format!("Table {number}"));
+                }
+            }
             _ => (),
         }
     }
 
-    for item_list in node_lists.item_lists.iter() {
-        for (i, item) in item_list.iter().enumerate() {
-            if let Some(label) = item.label {
-                text.insert(label, (i + 1).to_string());
-            }
+    // Nested enumerations are numbered as in latex, e.g. "2", "2(a)", "2(a)i" and "2(a)iA".
+    let mut nested: HashSet<*const Vec<Item>> = HashSet::new();
+    for item_list in node_lists.item_lists.iter().copied() {
+        if !nested.contains(&addr_of!(*item_list)) {
+            item_numbering(item_list, 0, "", &mut nested, &mut text);
         }
     }
 
@@ -141,29 +823,400 @@ fn ref_display_text<'a>(
                 text.insert(label, number.clone());
             }
         }
+        if let Math::Align { lines, .. } = math {
+            let numbers = align_numbering.get(&std::ptr::addr_of!(*math)).unwrap();
+            for (line, number) in lines.iter().zip(numbers) {
+                if let (Some(label), Some(number)) = (line.label, number) {
+                    text.insert(label, number.clone());
+                }
+            }
+        }
     }
     text
 }
 
+fn item_numbering<'a>(
+    items: &'a [Item<'a>],
+    depth: usize,
+    prefix: &str,
+    nested: &mut HashSet<*const Vec<Item<'a>>>,
+    text: &mut HashMap<&'a str, String>,
+) {
+    for (n, item) in (1..).zip(items.iter()) {
+        let number = match depth {
+            0 => format!("{n}"),
+            1 => format!("{prefix}({})", appendix_letter(n).to_lowercase()),
+            2 => format!("{prefix}{}", roman_numeral(n)),
+            _ => format!("{prefix}{}", appendix_letter(n)),
+        };
+        if let Some(label) = item.label {
+            text.insert(label, number.clone());
+        }
+        nested_item_numbering(&item.content, depth + 1, &number, nested, text);
+    }
+}
+
+// Numbers the enumerations in the given paragraphs, looking through itemize environments, which
+// don't affect the numbering.
+fn nested_item_numbering<'a>(
+    paragraphs: &'a [Paragraph<'a>],
+    depth: usize,
+    prefix: &str,
+    nested: &mut HashSet<*const Vec<Item<'a>>>,
+    text: &mut HashMap<&'a str, String>,
+) {
+    for part in paragraphs.iter().flatten() {
+        match part {
+            ParagraphPart::Enumerate(items) => {
+                nested.insert(addr_of!(*items));
+                item_numbering(items, depth, prefix, nested, text);
+            }
+            ParagraphPart::Itemize(items) => {
+                for item in items.iter() {
+                    nested_item_numbering(&item.content, depth, prefix, nested, text);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn roman_numeral(mut n: u32) -> String {
+    const NUMERALS: [(u32, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+    let mut result = String::new();
+    for (value, numeral) in NUMERALS {
+        while n >= value {
+            result.push_str(numeral);
+            n -= value;
+        }
+    }
+    result
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrefTarget {
+    // The name of the type of what is referenced, e.g. "Theorem" or "Section".
+    pub type_name: String,
+    pub number: String,
+}
+
+fn cref_targets<'a>(
+    doc: &Document<'a>,
+    node_lists: &NodeLists<'a>,
+    doc_part_numbering: &HashMap<*const DocumentPart, String>,
+    subfigure_letters: &HashMap<*const Subfigure, char>,
+    ref_display_text: &HashMap<&'a str, String>,
+) -> HashMap<&'a str, CrefTarget> {
+    let mut targets = HashMap::new();
+    let mut insert = |label: &'a str, type_name: String, number: Option<&String>| {
+        if let Some(number) = number {
+            let number = number.clone();
+            targets.insert(label, CrefTarget { type_name, number });
+        }
+    };
+
+    let mut appendix = false;
+    for part in doc.parts.iter() {
+        use DocumentPart::*;
+        match part {
+            Appendix => appendix = true,
+            TheoremLike {
+                tag,
+                label: Some(label),
+                ..
+            } => {
+                let config = doc
+                    .config
+                    .theorem_like_configs
+                    .iter()
+                    .find(|config| config.tag == *tag);
+                let Some(config) = config else {
+                    continue;
+                };
+                let name: String = config
+                    .name
+                    .iter()
+                    .map(|part| match part {
+                        ParagraphPart::TextToken(tok) => display_escaped(tok).to_string(),
+                        _ => " ".to_string(),
+                    })
+                    .collect();
+                insert(label, name, ref_display_text.get(label));
+            }
+            Section {
+                label: Some(label), ..
+            }
+            | Subsection {
+                label: Some(label), ..
+            }
+            | Subsubsection {
+                label: Some(label), ..
+            } => {
+                let type_name = if appendix { "Appendix" } else { "Section" };
+                insert(label, type_name.to_string(), ref_display_text.get(label));
+            }
+            // The reference text of figures includes the type already, so we take the number.
+            Figure {
+                label, subfigures, ..
+            } => {
+                let number = doc_part_numbering.get(&std::ptr::addr_of!(*part));
+                if let Some(label) = label {
+                    insert(label, "Figure".to_string(), number);
+                }
+                for subfigure in subfigures {
+                    let letter = subfigure_letters.get(&std::ptr::addr_of!(*subfigure));
+                    if let (Some(label), Some(letter)) = (subfigure.label, letter) {
+                        let number = number.map(|number| format!("{number}{letter}"));
+                        insert(label, "Figure".to_string(), number.as_ref());
+                    }
+                }
+            }
+            Table {
+                label: Some(label), ..
+            } => {
+                let number = doc_part_numbering.get(&std::ptr::addr_of!(*part));
+                insert(label, "Table".to_string(), number);
+            }
+            _ => (),
+        }
+    }
+
+    for item in node_lists.item_lists.iter().copied().flatten() {
+        if let Some(label) = item.label {
+            insert(label, "Item".to_string(), ref_display_text.get(label));
+        }
+    }
+
+    for math in node_lists.math.iter().copied() {
+        let line_labels = match math {
+            Math::Align { lines, .. } => lines.iter().filter_map(|line| line.label).collect(),
+            _ => Vec::new(),
+        };
+        for label in math.label().into_iter().chain(line_labels) {
+            insert(label, "Equation".to_string(), ref_display_text.get(label));
+        }
+    }
+    targets
+}
+
+// All labels in the given document parts, ordered by their position in the source.
+fn labels<'a>(parts: &[DocumentPart<'a>], node_lists: &NodeLists<'a>) -> Vec<&'a str> {
+    let mut labels: Vec<&'a str> = Vec::new();
+    for part in parts.iter() {
+        use DocumentPart::*;
+        match part {
+            TheoremLike { label, .. }
+            | Section { label, .. }
+            | Subsection { label, .. }
+            | Subsubsection { label, .. }
+            | ParagraphHeading { label, .. }
+            | SubparagraphHeading { label, .. }
+            | Table { label, .. } => labels.extend(label),
+            Figure {
+                label, subfigures, ..
+            } => {
+                labels.extend(label);
+                labels.extend(subfigures.iter().filter_map(|subfigure| subfigure.label));
+            }
+            _ => (),
+        }
+    }
+    for item in node_lists.item_lists.iter().copied().flatten() {
+        labels.extend(item.label);
+    }
+    for math in node_lists.math.iter().copied() {
+        labels.extend(math.label());
+        if let Math::Align { lines, .. } = math {
+            labels.extend(lines.iter().filter_map(|line| line.label));
+        }
+    }
+
+    // Labels are slices of the source, so ordering them by address orders them by position.
+    labels.sort_by_key(|label| label.as_ptr());
+    labels
+}
+
+fn duplicate_labels<'a>(doc: &Document<'a>, node_lists: &NodeLists<'a>) -> Vec<(&'a str, &'a str)> {
+    let labels = labels(&doc.parts, node_lists);
+    let mut first_labels: HashMap<&'a str, &'a str> = HashMap::new();
+    let mut result = Vec::new();
+    for label in labels {
+        match first_labels.entry(label) {
+            Entry::Vacant(entry) => {
+                entry.insert(label);
+            }
+            Entry::Occupied(entry) => result.push((*entry.get(), label)),
+        }
+    }
+    result
+}
+
+// Subfigures with a caption are lettered in order, and numbered by the number of their figure
+// followed by the letter, e.g. "1a". Subfigures of unnumbered figures are unnumbered.
+fn subfigure_letters<'a>(
+    doc: &Document<'a>,
+    doc_part_numbering: &HashMap<*const DocumentPart<'a>, String>,
+) -> HashMap<*const Subfigure<'a>, char> {
+    let mut map = HashMap::new();
+    for part in doc.parts.iter() {
+        let DocumentPart::Figure { subfigures, .. } = part else {
+            continue;
+        };
+        if !doc_part_numbering.contains_key(&addr_of!(*part)) {
+            continue;
+        }
+        let captioned = subfigures.iter().filter(|sub| sub.caption.is_some());
+        for (subfigure, letter) in captioned.zip('a'..='z') {
+            map.insert(addr_of!(*subfigure), letter);
+        }
+    }
+    map
+}
+
+// Html ids of section headings. Headings with a label use the id derived from the label, other
+// headings an id derived from their text, with a numeric suffix if the id is taken already.
+fn heading_ids<'a>(
+    doc: &'a Document<'a>,
+    node_lists: &NodeLists<'a>,
+) -> HashMap<*const DocumentPart<'a>, String> {
+    let labels = labels(&doc.parts, node_lists);
+    let mut used_ids: HashSet<String> = labels.into_iter().map(slug).collect();
+    let mut ids = HashMap::new();
+    for part in doc.parts.iter() {
+        use DocumentPart::*;
+        let (Section { label, name, .. }
+        | Subsection { label, name, .. }
+        | Subsubsection { label, name, .. }) = part
+        else {
+            continue;
+        };
+        let id = match label {
+            Some(label) => slug(label),
+            None => {
+                let text: String = name
+                    .iter()
+                    .map(|part| match part {
+                        ParagraphPart::TextToken(tok) => tok,
+                        _ => " ",
+                    })
+                    .collect();
+                let base = match slug(&text) {
+                    base if base.is_empty() => "section".to_string(),
+                    base => base,
+                };
+                let mut id = base.clone();
+                let mut suffix = 2;
+                while used_ids.contains(&id) {
+                    id = format!("{base}-{suffix}");
+                    suffix += 1;
+                }
+                used_ids.insert(id.clone());
+                id
+            }
+        };
+        ids.insert(std::ptr::addr_of!(*part), id);
+    }
+    ids
+}
+
+// Splits document parts into pages: The first page contains everything before the first section,
+// and every section starts a new page.
+pub fn split_pages<'a, 'b>(parts: &'b [DocumentPart<'a>]) -> Vec<&'b [DocumentPart<'a>]> {
+    let mut pages = Vec::new();
+    let mut page_begin = 0;
+    for (i, part) in parts.iter().enumerate() {
+        if let DocumentPart::Section { .. } = part {
+            pages.push(&parts[page_begin..i]);
+            page_begin = i;
+        }
+    }
+    pages.push(&parts[page_begin..]);
+    pages
+}
+
+// The file name of the page with the given index when the output is split into pages.
+pub fn page_file_name(index: usize) -> String {
+    match index {
+        0 => "index.html".to_string(),
+        _ => format!("section-{index}.html"),
+    }
+}
+
+// The file names of the pages on which the elements with given html ids are when the output is
+// split into pages.
+fn id_pages<'a>(
+    doc: &'a Document<'a>,
+    heading_ids: &HashMap<*const DocumentPart<'a>, String>,
+    bib_entries: &[&BibEntry],
+) -> HashMap<String, String> {
+    let mut pages = HashMap::new();
+    for (index, parts) in split_pages(&doc.parts).into_iter().enumerate() {
+        let file_name = page_file_name(index);
+        let node_lists = NodeLists::for_parts(parts);
+        let mut ids: Vec<String> = labels(parts, &node_lists).into_iter().map(slug).collect();
+        for part in parts {
+            ids.extend(heading_ids.get(&std::ptr::addr_of!(*part)).cloned());
+            if let DocumentPart::Bibliography(_) = part {
+                ids.extend(bib_entries.iter().map(|entry| slug(entry.tag)));
+            }
+        }
+        for id in ids {
+            pages.insert(id, file_name.clone());
+        }
+    }
+    pages
+}
+
 fn bib_entries<'a>(
     all_bib_entries: &'a [BibEntry<'a>],
-    node_lists: &'a NodeLists<'a>,
+    node_lists: &NodeLists<'a>,
+    bib_sort: BibSort,
 ) -> Vec<&'a BibEntry<'a>> {
     let mut result: Vec<&'a BibEntry> = all_bib_entries
         .iter()
-        .filter(|entry| node_lists.cite_ids.contains(entry.tag))
+        .filter(|entry| node_lists.nocite_all || node_lists.cite_ids.contains(entry.tag))
         .collect();
-    result.sort_unstable_by(
-        |lhs, rhs| match (lhs.authors.as_deref(), rhs.authors.as_deref()) {
-            (None, _) => Ordering::Less,
-            (Some([]), _) => Ordering::Less,
-            (_, None) => Ordering::Greater,
-            (_, Some([])) => Ordering::Greater,
-            (Some([lhs_author, ..]), Some([rhs_author, ..])) => {
-                lhs_author.last_name.cmp(rhs_author.last_name)
-            }
-        },
-    );
+    match bib_sort {
+        BibSort::Alphabetical => {
+            result.sort_unstable_by(|lhs, rhs| {
+                match (lhs.authors.as_deref(), rhs.authors.as_deref()) {
+                    (None, _) => Ordering::Less,
+                    (Some([]), _) => Ordering::Less,
+                    (_, None) => Ordering::Greater,
+                    (_, Some([])) => Ordering::Greater,
+                    (Some([lhs_author, ..]), Some([rhs_author, ..])) => {
+                        replace_accents(lhs_author.last_name)
+                            .cmp(&replace_accents(rhs_author.last_name))
+                    }
+                }
+            });
+        }
+        BibSort::CitationOrder => {
+            let citation_index: HashMap<&str, usize> = node_lists
+                .cite_order
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (*id, i))
+                .collect();
+            // Entries included by \nocite{*} follow the cited entries in the order of the bib file.
+            result
+                .sort_by_key(|entry| citation_index.get(entry.tag).copied().unwrap_or(usize::MAX));
+        }
+    }
     result
 }
 
@@ -177,3 +1230,836 @@ fn cite_display_text<'a>(
     }
     result
 }
+
+// The ids for which there is no display text. Ids are slices of the source, so ordering them by
+// address orders them by position in the source.
+fn unresolved_ids<'a>(
+    ids: &HashSet<&'a str>,
+    display_text: &HashMap<&str, String>,
+) -> Vec<&'a str> {
+    let mut result: Vec<&'a str> = ids
+        .iter()
+        .copied()
+        .filter(|id| !display_text.contains_key(id))
+        .collect();
+    result.sort_by_key(|id| id.as_ptr());
+    result
+}
+
+// The last names of the authors of an entry as shown in author-year citations.
+fn cite_authors(entry: &BibEntry) -> String {
+    match entry.authors.as_deref() {
+        None | Some([]) => entry.title.unwrap_or(entry.tag).to_string(),
+        Some([author]) => replace_accents(author.last_name),
+        Some([first, second]) => format!(
+            "{} and {}",
+            replace_accents(first.last_name),
+            replace_accents(second.last_name)
+        ),
+        Some([first, ..]) => format!("{} et al.", replace_accents(first.last_name)),
+    }
+}
+
+fn cite_author_year<'a>(bib_entries: &[&'a BibEntry<'a>]) -> HashMap<&'a str, (String, String)> {
+    let author_years: Vec<(String, &str)> = bib_entries
+        .iter()
+        .map(|entry| (cite_authors(entry), entry.year.unwrap_or("n.d.")))
+        .collect();
+
+    // Entries with the same authors and year are distinguished by suffixes "a", "b", ... in the
+    // order in which they appear in the bibliography.
+    let mut counts: HashMap<&(String, &str), usize> = HashMap::new();
+    for author_year in author_years.iter() {
+        *counts.entry(author_year).or_default() += 1;
+    }
+    let mut next_suffixes: HashMap<&(String, &str), u8> = HashMap::new();
+    let mut result = HashMap::new();
+    for (entry, author_year) in bib_entries.iter().zip(author_years.iter()) {
+        let (authors, year) = author_year;
+        let year = if counts[author_year] > 1 {
+            let suffix = next_suffixes.entry(author_year).or_insert(b'a');
+            let year = format!("{year}{}", *suffix as char);
+            *suffix += 1;
+            year
+        } else {
+            year.to_string()
+        };
+        result.insert(entry.tag, (authors.clone(), year));
+    }
+    result
+}
+
+fn bib_name_format(doc: &Document, options: &EmitOptions) -> BibNameFormat {
+    let style_name_format = || {
+        doc.parts.iter().find_map(|part| match part {
+            DocumentPart::BibliographyStyle(style) => BibNameFormat::from_bibliography_style(style),
+            _ => None,
+        })
+    };
+    options
+        .bib_name_format
+        .or_else(style_name_format)
+        .unwrap_or(BibNameFormat::FirstLast)
+}
+
+#[test]
+fn subsubsection_numbering() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section{A}
+        \subsection{B}
+        \subsubsection{C}
+        \subsubsection{D}
+        \subsection{E}
+        \subsubsection{F}
+        \section{G}
+        \subsection{H}
+        \subsubsection{I}
+        \end{document}
+    "#})
+    .unwrap();
+    let numbering = doc_part_numbering(&doc);
+    let numbers: Vec<&str> = doc
+        .parts
+        .iter()
+        .map(|part| numbering.get(&addr_of!(*part)).unwrap().as_str())
+        .collect();
+    assert_eq!(
+        numbers,
+        ["1", "1.1", "1.1.1", "1.1.2", "1.2", "1.2.1", "2", "2.1", "2.1.1"]
+    );
+}
+
+#[test]
+fn theorem_like_counters() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \newtheorem{claim}{Claim}
+        \newtheorem{theorem}{Theorem}[section]
+        \newtheorem{lemma}[theorem]{Lemma}
+        \begin{document}
+        \section{A}
+        \begin{claim} a \end{claim}
+        \begin{theorem} b \end{theorem}
+        \begin{lemma} c \end{lemma}
+        \section{B}
+        \begin{lemma} d \end{lemma}
+        \begin{claim} e \end{claim}
+        \end{document}
+    "#})
+    .unwrap();
+    let numbering = doc_part_numbering(&doc);
+    let numbers: Vec<&str> = doc
+        .parts
+        .iter()
+        .map(|part| numbering.get(&addr_of!(*part)).unwrap().as_str())
+        .collect();
+    assert_eq!(numbers, ["1", "1", "1.1", "1.2", "2", "2.1", "2"]);
+}
+
+#[test]
+fn shared_theorem_counter_numbered_within_sections() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \numberwithin{theorem}{section}
+        \begin{document}
+        \section{A}
+        \begin{theorem} a \end{theorem}
+        \begin{lemma} b \end{lemma}
+        \section{B}
+        \begin{definition} c \end{definition}
+        \end{document}
+    "#})
+    .unwrap();
+    let numbering = doc_part_numbering(&doc);
+    let numbers: Vec<&str> = doc
+        .parts
+        .iter()
+        .map(|part| numbering.get(&addr_of!(*part)).unwrap().as_str())
+        .collect();
+    assert_eq!(numbers, ["1", "1.1", "1.2", "2", "2.1"]);
+}
+
+#[test]
+fn unresolved_refs_and_cites() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section{A}
+        \label{sec:a}
+        See \ref{sec:a}, \ref{sec:b} and \cite{known, unknown}.
+        \end{document}
+    "#})
+    .unwrap();
+    let bib_entries = [BibEntry {
+        entry_type: BibEntryType::Misc,
+        tag: "known",
+        title: None,
+        year: None,
+        month: None,
+        authors: None,
+        editors: None,
+        url: None,
+        journal: None,
+        booktitle: None,
+        series: None,
+        publisher: None,
+        volume: None,
+        number: None,
+        pages: None,
+        school: None,
+        institution: None,
+        kind: None,
+        howpublished: None,
+        doi: None,
+        isbn: None,
+    }];
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &bib_entries,
+        &node_lists,
+        &EmitOptions::default(),
+        &HashMap::new(),
+        &HashMap::new(),
+    );
+    assert_eq!(analysis.unresolved_refs, vec!["sec:b"]);
+    assert_eq!(analysis.unresolved_cites, vec!["unknown"]);
+}
+
+#[test]
+fn duplicate_labels_are_detected() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{equation}
+          \label{eq:1}
+          x = 1
+        \end{equation}
+        \begin{equation}
+          \label{eq:2}
+          y = 2
+        \end{equation}
+        \begin{equation}
+          \label{eq:1}
+          z = 3
+        \end{equation}
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let duplicates = duplicate_labels(&doc, &node_lists);
+    assert_eq!(duplicates.len(), 1);
+    let (first, duplicate) = duplicates[0];
+    assert_eq!((first, duplicate), ("eq:1", "eq:1"));
+    assert!(first.as_ptr() < duplicate.as_ptr());
+}
+
+#[test]
+fn author_year_disambiguation() {
+    let (_, entries) = crate::parse::bib(indoc::indoc! {r#"
+        @article{a,
+          title={A},
+          author={Smith, John},
+          year={2020}
+        }
+        @article{b,
+          title={B},
+          author={Smith, John},
+          year={2020}
+        }
+        @article{c,
+          title={C},
+          author={Smith, John and Jones, Jane and Doe, Joe},
+          year={2021}
+        }
+    "#})
+    .unwrap();
+    let entries: Vec<&BibEntry> = entries.iter().collect();
+    let author_year = cite_author_year(&entries);
+    let text = |id| {
+        let (authors, year) = &author_year[id];
+        format!("{authors}, {year}")
+    };
+    assert_eq!(text("a"), "Smith, 2020a");
+    assert_eq!(text("b"), "Smith, 2020b");
+    assert_eq!(text("c"), "Smith et al., 2021");
+}
+
+#[test]
+fn bib_citation_order() {
+    let (_, entries) = crate::parse::bib(indoc::indoc! {r#"
+        @misc{adams, title={A}, author={Adams, Ann}}
+        @misc{baker, title={B}, author={Baker, Bob}}
+        @misc{unused, title={U}, author={Carter, Cid}}
+    "#})
+    .unwrap();
+    let cited_tags = |tex: &str, bib_sort| {
+        let (_, doc) = crate::parse::document(tex).unwrap();
+        let node_lists = NodeLists::new(&doc);
+        let options = EmitOptions {
+            bib_sort,
+            ..EmitOptions::default()
+        };
+        let analysis = Analysis::new(
+            &doc,
+            &entries,
+            &node_lists,
+            &options,
+            &HashMap::new(),
+            &HashMap::new(),
+        );
+        let tags: Vec<&str> = analysis.bib_entries.iter().map(|entry| entry.tag).collect();
+        let numbers: Vec<&str> = tags
+            .iter()
+            .map(|tag| analysis.cite_display_text[tag].as_str())
+            .collect();
+        assert_eq!(numbers, ["1", "2"]);
+        tags.join(" ")
+    };
+    let baker_first = indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        See \cite{baker} and \cite{adams, baker}.
+        \end{document}
+    "#};
+    let adams_first = indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        See \cite{adams} and \cite{baker}.
+        \end{document}
+    "#};
+    assert_eq!(
+        cited_tags(baker_first, BibSort::CitationOrder),
+        "baker adams"
+    );
+    assert_eq!(
+        cited_tags(adams_first, BibSort::CitationOrder),
+        "adams baker"
+    );
+    assert_eq!(
+        cited_tags(baker_first, BibSort::Alphabetical),
+        "adams baker"
+    );
+}
+
+#[test]
+fn equation_tags() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{equation}
+          \label{eq:star}
+          x = 1 \tag{$\star$}
+        \end{equation}
+        \begin{equation}
+          \label{eq:y}
+          y = 2
+        \end{equation}
+        See \eqref{eq:star} and \eqref{eq:y}.
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &EmitOptions::default(),
+        &HashMap::new(),
+        &HashMap::new(),
+    );
+    assert_eq!(analysis.ref_display_text["eq:star"], "($\\star$)");
+    // The tagged equation doesn't advance the counter.
+    assert_eq!(analysis.ref_display_text["eq:y"], "(1)");
+}
+
+#[test]
+fn number_all_display_math() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{equation}
+          a
+        \end{equation}
+        \begin{equation}
+          b \nonumber
+        \end{equation}
+        \[ c \]
+        \begin{equation}
+          \label{eq:d}
+          d
+        \end{equation}
+        See \eqref{eq:d}.
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let numbers = |number_all_display_math| {
+        let options = EmitOptions {
+            number_all_display_math,
+            ..EmitOptions::default()
+        };
+        let analysis = Analysis::new(
+            &doc,
+            &[],
+            &node_lists,
+            &options,
+            &HashMap::new(),
+            &HashMap::new(),
+        );
+        node_lists
+            .math
+            .iter()
+            .map(|math| analysis.math_numbering.get(&addr_of!(**math)).cloned())
+            .collect::<Vec<_>>()
+    };
+
+    let number = |n: &str| Some(n.to_string());
+    assert_eq!(numbers(false), [None, None, None, number("(1)")]);
+    assert_eq!(numbers(true), [number("(1)"), None, None, number("(2)")]);
+}
+
+#[test]
+fn subfigures_are_numbered() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{figure}
+          \centering
+          \begin{subfigure}[b]{0.45\textwidth}
+            \includegraphics{a.png}
+            \caption{A}
+            \label{fig:a}
+          \end{subfigure}
+          \hfill
+          \begin{subfigure}[b]{0.45\textwidth}
+            \includegraphics{b.png}
+            \caption{B}
+            \label{fig:b}
+          \end{subfigure}
+          \caption{Both}
+          \label{fig:both}
+        \end{figure}
+        See \ref{fig:a}, \cref{fig:b} and \ref{fig:both}.
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &EmitOptions::default(),
+        &HashMap::new(),
+        &HashMap::new(),
+    );
+    assert_eq!(analysis.ref_display_text["fig:a"], "Figure 1a");
+    assert_eq!(analysis.ref_display_text["fig:b"], "Figure 1b");
+    assert_eq!(analysis.ref_display_text["fig:both"], "Figure 1");
+    assert_eq!(analysis.cref_targets["fig:b"].number, "1b");
+    assert!(analysis.unresolved_refs.is_empty());
+}
+
+#[test]
+fn figure_numbering() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section{A}
+        \begin{figure}
+          \includegraphics{a.png}
+          \caption{A}
+          \label{fig:a}
+        \end{figure}
+        \begin{figure}
+          \includegraphics{logo.png}
+        \end{figure}
+        \begin{figure}
+          \includegraphics{b.png}
+          \caption{B}
+          \label{fig:b}
+        \end{figure}
+        See \ref{fig:a} and \ref{fig:b}.
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &EmitOptions::default(),
+        &HashMap::new(),
+        &HashMap::new(),
+    );
+    assert_eq!(analysis.ref_display_text["fig:a"], "Figure 1");
+    assert_eq!(analysis.ref_display_text["fig:b"], "Figure 2");
+    assert!(analysis.unresolved_refs.is_empty());
+}
+
+#[test]
+fn figure_and_table_numbering() {
+    // The label may come before or after the caption; either way it refers to the float.
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section{A}
+        \begin{table}
+          \label{tab:a}
+          \caption{A}
+          \begin{tabular}{ll}
+            a & b
+          \end{tabular}
+        \end{table}
+        \begin{figure}
+          \includegraphics{a.png}
+          \caption{A}
+          \label{fig:a}
+        \end{figure}
+        \section{B}
+        \begin{figure}
+          \label{fig:b}
+          \includegraphics{b.png}
+          \caption{B}
+        \end{figure}
+        \begin{table}
+          \begin{tabular}{l}
+            c
+          \end{tabular}
+        \end{table}
+        \begin{table}
+          \caption{B}
+          \begin{tabular}{l}
+            d
+          \end{tabular}
+          \label{tab:b}
+        \end{table}
+        See \ref{tab:a}, \ref{tab:b}, \ref{fig:a} and \cref{fig:b,tab:b}.
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &EmitOptions::default(),
+        &HashMap::new(),
+        &HashMap::new(),
+    );
+    assert_eq!(analysis.ref_display_text["tab:a"], "Table 1");
+    assert_eq!(analysis.ref_display_text["tab:b"], "Table 2");
+    assert_eq!(analysis.ref_display_text["fig:a"], "Figure 1");
+    assert_eq!(analysis.ref_display_text["fig:b"], "Figure 2");
+    assert_eq!(analysis.cref_targets["tab:b"].type_name, "Table");
+    assert_eq!(analysis.cref_targets["tab:b"].number, "2");
+    assert!(analysis.unresolved_refs.is_empty());
+}
+
+#[test]
+fn figures_and_tables_numbered_within_sections() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \numberwithin{table}{section}
+        \begin{document}
+        \section{A}
+        \begin{figure}
+          \includegraphics{a.png}
+          \caption{A}
+          \label{fig:a}
+        \end{figure}
+        \begin{table}
+          \caption{A}
+          \label{tab:a}
+        \end{table}
+        \section{B}
+        \begin{table}
+          \caption{B}
+          \label{tab:b}
+        \end{table}
+        \begin{figure}
+          \includegraphics{b.png}
+          \caption{B}
+          \label{fig:b}
+        \end{figure}
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &EmitOptions::default(),
+        &HashMap::new(),
+        &HashMap::new(),
+    );
+    assert_eq!(analysis.ref_display_text["tab:a"], "Table 1.1");
+    assert_eq!(analysis.ref_display_text["tab:b"], "Table 2.1");
+    assert_eq!(analysis.ref_display_text["fig:a"], "Figure 1");
+    assert_eq!(analysis.ref_display_text["fig:b"], "Figure 2");
+}
+
+#[test]
+fn cref_type_names() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section{A}
+        \label{sec:a}
+        \begin{theorem}
+        \label{thm:a}
+        A.
+        \end{theorem}
+        \begin{lemma}
+        \label{lem:b}
+        B.
+        \end{lemma}
+        \begin{equation}
+        \label{eq:c}
+        c
+        \end{equation}
+        See \Cref{lem:b}, \cref{thm:a}, \cref{sec:a} and \Cref{eq:c}.
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &EmitOptions::default(),
+        &HashMap::new(),
+        &HashMap::new(),
+    );
+    let target = |label| {
+        let CrefTarget { type_name, number } = &analysis.cref_targets[label];
+        (type_name.as_str(), number.as_str())
+    };
+    assert_eq!(target("lem:b"), ("Lemma", "2"));
+    assert_eq!(target("thm:a"), ("Theorem", "1"));
+    assert_eq!(target("sec:a"), ("Section", "1"));
+    assert_eq!(target("eq:c"), ("Equation", "(1)"));
+    assert!(analysis.unresolved_refs.is_empty());
+}
+
+#[test]
+fn appendix_sections_are_lettered() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section{Intro}
+        \section{Main}
+        \appendix
+        \section{Proofs}
+        \label{sec:proofs}
+        \subsection{Details}
+        \label{sec:details}
+        \section{Tables}
+        See \ref{sec:details} and \cref{sec:proofs}.
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &EmitOptions::default(),
+        &HashMap::new(),
+        &HashMap::new(),
+    );
+    let numbers: Vec<&str> = doc
+        .parts
+        .iter()
+        .filter_map(|part| analysis.doc_part_numbering.get(&std::ptr::addr_of!(*part)))
+        .map(String::as_str)
+        .collect();
+    assert_eq!(numbers, ["1", "2", "A", "A.1", "B"]);
+    assert_eq!(analysis.ref_display_text["sec:details"], "A.1");
+    let target = &analysis.cref_targets["sec:proofs"];
+    assert_eq!(
+        (target.type_name.as_str(), target.number.as_str()),
+        ("Appendix", "A")
+    );
+}
+
+#[test]
+fn equation_counter_commands() {
+    let numbers = |tex: &str| {
+        let (_, doc) = crate::parse::document(tex).unwrap();
+        let node_lists = NodeLists::new(&doc);
+        let options = EmitOptions {
+            number_all_display_math: true,
+            ..EmitOptions::default()
+        };
+        let analysis = Analysis::new(
+            &doc,
+            &[],
+            &node_lists,
+            &options,
+            &HashMap::new(),
+            &HashMap::new(),
+        );
+        node_lists
+            .math
+            .iter()
+            .filter_map(|math| analysis.math_numbering.get(&addr_of!(**math)).cloned())
+            .collect::<Vec<_>>()
+    };
+
+    let numbered_within = numbers(indoc::indoc! {r#"
+        \documentclass{article}
+        \numberwithin{equation}{section}
+        \begin{document}
+        \section{One}
+        \begin{equation}
+          a
+        \end{equation}
+        \begin{equation}
+          b
+        \end{equation}
+        \section{Two}
+        \begin{equation}
+          c
+        \end{equation}
+        \end{document}
+    "#});
+    assert_eq!(numbered_within, ["(1.1)", "(1.2)", "(2.1)"]);
+
+    let before_first_section = numbers(indoc::indoc! {r#"
+        \documentclass{article}
+        \numberwithin{equation}{section}
+        \begin{document}
+        \begin{equation}
+          a
+        \end{equation}
+        \section{One}
+        \begin{equation}
+          b
+        \end{equation}
+        \end{document}
+    "#});
+    assert_eq!(before_first_section, ["(0.1)", "(1.1)"]);
+
+    let before_first_subsection = numbers(indoc::indoc! {r#"
+        \documentclass{article}
+        \numberwithin{equation}{subsection}
+        \begin{document}
+        \section{One}
+        \begin{equation}
+          a
+        \end{equation}
+        \subsection{Sub}
+        \begin{equation}
+          b
+        \end{equation}
+        \end{document}
+    "#});
+    assert_eq!(before_first_subsection, ["(1.0.1)", "(1.1.1)"]);
+
+    let set_counter = numbers(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{equation}
+          a
+        \end{equation}
+        \setcounter{equation}{5}
+        \begin{equation}
+          b
+        \end{equation}
+        \end{document}
+    "#});
+    assert_eq!(set_counter, ["(1)", "(6)"]);
+}
+
+#[test]
+fn nested_item_labels() {
+    let (_, doc) = crate::parse::document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{enumerate}
+          \item \label{item:first} First
+          \item Second
+            \begin{enumerate}
+              \item \label{item:nested} Nested
+              \item
+                \begin{itemize}
+                  \item
+                    \begin{enumerate}
+                      \item Deeper
+                      \item \label{item:deeper} Deeper
+                    \end{enumerate}
+                \end{itemize}
+            \end{enumerate}
+        \end{enumerate}
+        See \ref{item:first}, \ref{item:nested} and \ref{item:deeper}.
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &EmitOptions::default(),
+        &HashMap::new(),
+        &HashMap::new(),
+    );
+    assert_eq!(analysis.ref_display_text["item:first"], "1");
+    assert_eq!(analysis.ref_display_text["item:nested"], "2(a)");
+    assert_eq!(analysis.ref_display_text["item:deeper"], "2(b)ii");
+}
+
+#[test]
+fn nocite_includes_uncited_entries() {
+    let (_, entries) = crate::parse::bib(indoc::indoc! {r#"
+        @misc{a, title={A}}
+        @misc{b, title={B}}
+        @misc{c, title={C}}
+    "#})
+    .unwrap();
+    let cited_tags = |tex: &str| {
+        let (_, doc) = crate::parse::document(tex).unwrap();
+        let node_lists = NodeLists::new(&doc);
+        let options = EmitOptions {
+            bib_sort: BibSort::CitationOrder,
+            ..EmitOptions::default()
+        };
+        let analysis = Analysis::new(
+            &doc,
+            &entries,
+            &node_lists,
+            &options,
+            &HashMap::new(),
+            &HashMap::new(),
+        );
+        analysis
+            .bib_entries
+            .iter()
+            .map(|entry| format!("{} {}", entry.tag, analysis.cite_display_text[entry.tag]))
+            .collect::<Vec<_>>()
+    };
+
+    let tags = cited_tags(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        See \cite{b}.
+        \nocite{c}
+        \bibliography{refs}
+        \end{document}
+    "#});
+    assert_eq!(tags, ["b 1", "c 2"]);
+
+    let tags = cited_tags(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        See \cite{b}.
+        \nocite{*}
+        \bibliography{refs}
+        \end{document}
+    "#});
+    assert_eq!(tags, ["b 1", "a 2", "c 3"]);
+}
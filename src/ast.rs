@@ -6,24 +6,59 @@ pub enum Math<'a> {
     // and Mathpar have the whole environment, i.e. including \begin{equation} and \end{equation}.
     // TODO: Make this more uniform.
     Inline(&'a str),
+    // The tag is the argument of a \tag command, which replaces the automatic number. Nonumber is
+    // set if the equation is excluded from numbering by \nonumber or \notag.
     Display {
         source: &'a str,
         label: Option<&'a str>,
+        tag: Option<&'a str>,
+        nonumber: bool,
     },
     Mathpar {
         source: &'a str,
         label: Option<&'a str>,
+        tag: Option<&'a str>,
     },
+    // An align or align* environment, including \begin and \end. Lines are numbered separately,
+    // so labels are stored per line.
+    Align {
+        source: &'a str,
+        lines: Vec<AlignLine<'a>>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AlignLine<'a> {
+    pub label: Option<&'a str>,
+    // Whether the line is excluded from numbering by \nonumber or \notag, or because it is part
+    // of an align* environment.
+    pub nonumber: bool,
 }
 
 impl<'a> Math<'a> {
     pub fn label(&self) -> Option<&'a str> {
         use Math::*;
         match self {
-            Inline(_) => None,
+            Inline(_) | Align { .. } => None,
             Display { label, .. } | Mathpar { label, .. } => *label,
         }
     }
+
+    pub fn tag(&self) -> Option<&'a str> {
+        use Math::*;
+        match self {
+            Inline(_) | Align { .. } => None,
+            Display { tag, .. } | Mathpar { tag, .. } => *tag,
+        }
+    }
+}
+
+// A subfigure environment in a figure, which is numbered like "1a" if it has a caption.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subfigure<'a> {
+    pub label: Option<&'a str>,
+    pub graphics: Vec<&'a str>,
+    pub caption: Option<Paragraph<'a>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -38,22 +73,94 @@ pub enum ParagraphPart<'a> {
     TextToken(&'a str),
     Math(Math<'a>),
     Ref(&'a str),
+    // \cref or, if capitalized, \Cref, with a list of ids.
+    Cref {
+        ids: Vec<&'a str>,
+        capitalized: bool,
+    },
+    // A citation with the notes of the optional arguments, e.g. \citep[see][p. 5]{key}. With a
+    // single optional argument, it is the postnote.
     Cite {
+        command: CiteCommand,
         ids: Vec<&'a str>,
-        text: Option<Paragraph<'a>>,
+        prenote: Option<Paragraph<'a>>,
+        postnote: Option<Paragraph<'a>>,
     },
     Emph(Paragraph<'a>),
     Textbf(Paragraph<'a>),
     Textit(Paragraph<'a>),
+    Texttt(Paragraph<'a>),
+    Textsc(Paragraph<'a>),
+    Textsf(Paragraph<'a>),
+    Underline(Paragraph<'a>),
+    Textsuperscript(Paragraph<'a>),
+    Textsubscript(Paragraph<'a>),
+    // \textcolor[model]{color}{content}, where the color is a name such as "red" if the model is
+    // missing.
+    TextColor {
+        model: Option<&'a str>,
+        color: &'a str,
+        content: Paragraph<'a>,
+    },
+    // The verbatim content of \verb, excluding the delimiters.
+    Code(&'a str),
+    // The content of a verbatim environment.
+    Verbatim(&'a str),
+    Href {
+        link: &'a str,
+        text: Paragraph<'a>,
+    },
     Qed,
     Enumerate(Vec<Item<'a>>),
     Itemize(Vec<Item<'a>>),
+    // The items of a description environment as pairs of term and body.
+    Description(Vec<(Paragraph<'a>, Vec<Paragraph<'a>>)>),
+    // The content of a center environment or of \centerline.
+    Center(Vec<Paragraph<'a>>),
     Todo,
+    // The thin space \,.
+    ThinSpace,
+    // \today, which is displayed as the date on which the document is converted.
+    Today,
     Footnote(Vec<Paragraph<'a>>),
+    // A \thanks note in the title or an author, shown as a footnote of the title block.
+    Thanks(Vec<Paragraph<'a>>),
+    // A tabular environment. Columns holds the alignments of the column specification, e.g. {lcr}.
+    Tabular {
+        columns: Vec<ColumnAlignment>,
+        rows: Vec<TabularRow<'a>>,
+    },
+}
+
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum ColumnAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TabularRow<'a> {
+    pub cells: Vec<Paragraph<'a>>,
+    // Whether there is an \hline above or below the row.
+    pub border_top: bool,
+    pub border_bottom: bool,
 }
 
 pub type Paragraph<'a> = Vec<ParagraphPart<'a>>;
 
+// The command with which something was cited. The commands differ only in author-year citation
+// style.
+#[derive(Copy, Debug, Clone, PartialEq, Eq)]
+pub enum CiteCommand {
+    // \cite, which is treated like \citep.
+    Cite,
+    // \citep, a parenthetical citation such as "(Smith, 2020)".
+    Citep,
+    // \citet, a textual citation such as "Smith (2020)".
+    Citet,
+}
+
 #[derive(Copy, Debug, Clone, PartialEq, Eq)]
 pub enum TheoremStyle {
     Theorem,
@@ -66,6 +173,14 @@ pub struct TheoremLikeConfig<'a> {
     pub tag: &'a str,
     pub name: Paragraph<'a>,
     pub style: TheoremStyle,
+    // The tag of the environment whose counter is used for numbering. Environments declared with
+    // a shared counter, e.g. \newtheorem{lemma}[theorem]{Lemma}, use the counter of another
+    // environment.
+    pub counter: &'a str,
+    // The sectioning unit within which the counter is reset and whose number prefixes the
+    // counter, e.g. "section" for \newtheorem{theorem}{Theorem}[section]. Only relevant for the
+    // environment that owns the counter.
+    pub numbered_within: Option<&'a str>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -73,17 +188,39 @@ pub enum DocumentPart<'a> {
     FreeParagraph(Paragraph<'a>),
     Title(Paragraph<'a>),
     Author(Paragraph<'a>),
-    Date(),
+    // The argument of \date, or None if it is empty, in which case no date is shown.
+    Date(Option<Paragraph<'a>>),
     Maketitle(),
     Section {
         label: Option<&'a str>,
         name: Paragraph<'a>,
+        // False for the starred variant.
+        numbered: bool,
     },
     Subsection {
         label: Option<&'a str>,
         name: Paragraph<'a>,
+        numbered: bool,
+    },
+    Subsubsection {
+        label: Option<&'a str>,
+        name: Paragraph<'a>,
+        numbered: bool,
+    },
+    // A run-in \paragraph or \subparagraph heading together with the paragraph following it.
+    ParagraphHeading {
+        label: Option<&'a str>,
+        name: Paragraph<'a>,
+        body: Paragraph<'a>,
+    },
+    SubparagraphHeading {
+        label: Option<&'a str>,
+        name: Paragraph<'a>,
+        body: Paragraph<'a>,
     },
     Abstract(Vec<Paragraph<'a>>),
+    // \keywords{...}, usually placed after the abstract.
+    Keywords(Paragraph<'a>),
     TheoremLike {
         tag: &'a str,
         note: Option<Paragraph<'a>>,
@@ -91,12 +228,45 @@ pub enum DocumentPart<'a> {
         label: Option<&'a str>,
     },
     Proof(Vec<Paragraph<'a>>),
-    Bibliography,
+    // \bibliography{name}, where name is the path of the bib file, usually without .bib extension.
+    Bibliography(&'a str),
+    BibliographyStyle(&'a str),
+    // A figure environment. Graphics holds the paths of its \includegraphics commands.
+    Figure {
+        label: Option<&'a str>,
+        graphics: Vec<&'a str>,
+        caption: Option<Paragraph<'a>>,
+        subfigures: Vec<Subfigure<'a>>,
+    },
+    // A table environment. Tabulars holds its tabular environments.
+    Table {
+        label: Option<&'a str>,
+        tabulars: Vec<ParagraphPart<'a>>,
+        caption: Option<Paragraph<'a>>,
+    },
+    ListOfFigures,
+    ListOfTables,
+    // \appendix, after which sections are numbered with letters.
+    Appendix,
+    // \setcounter{counter}{value}.
+    SetCounter {
+        counter: &'a str,
+        value: u32,
+    },
+    // \nocite{...}, whose ids are included in the bibliography without being cited. The id * stands
+    // for all entries.
+    Nocite(Vec<&'a str>),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DocumentConfig<'a> {
     pub theorem_like_configs: Vec<TheoremLikeConfig<'a>>,
+    // The unit within which equations are numbered as declared with \numberwithin, i.e. "section"
+    // or "subsection". Equations are numbered across the whole document if not set.
+    pub equation_numbered_within: Option<&'a str>,
+    // The units within which figures and tables are numbered, as for equations.
+    pub figure_numbered_within: Option<&'a str>,
+    pub table_numbered_within: Option<&'a str>,
 }
 
 impl Default for DocumentConfig<'static> {
@@ -107,38 +277,55 @@ impl Default for DocumentConfig<'static> {
                     tag: "theorem",
                     name: vec![ParagraphPart::TextToken("Theorem")],
                     style: TheoremStyle::Theorem,
+                    counter: "theorem",
+                    numbered_within: None,
                 },
                 TheoremLikeConfig {
                     tag: "proposition",
                     name: vec![ParagraphPart::TextToken("Proposition")],
                     style: TheoremStyle::Theorem,
+                    counter: "theorem",
+                    numbered_within: None,
                 },
                 TheoremLikeConfig {
                     tag: "definition",
                     name: vec![ParagraphPart::TextToken("Definition")],
                     style: TheoremStyle::Definition,
+                    counter: "theorem",
+                    numbered_within: None,
                 },
                 TheoremLikeConfig {
                     tag: "lemma",
                     name: vec![ParagraphPart::TextToken("Lemma")],
                     style: TheoremStyle::Theorem,
+                    counter: "theorem",
+                    numbered_within: None,
                 },
                 TheoremLikeConfig {
                     tag: "remark",
                     name: vec![ParagraphPart::TextToken("Remark")],
                     style: TheoremStyle::Remark,
+                    counter: "theorem",
+                    numbered_within: None,
                 },
                 TheoremLikeConfig {
                     tag: "corollary",
                     name: vec![ParagraphPart::TextToken("Corollary")],
                     style: TheoremStyle::Theorem,
+                    counter: "theorem",
+                    numbered_within: None,
                 },
                 TheoremLikeConfig {
                     tag: "example",
                     style: TheoremStyle::Definition,
+                    counter: "theorem",
+                    numbered_within: None,
                     name: vec![ParagraphPart::TextToken("Example")],
                 },
             ],
+            equation_numbered_within: None,
+            figure_numbered_within: None,
+            table_numbered_within: None,
         }
     }
 }
@@ -154,7 +341,8 @@ pub struct NodeLists<'a> {
     // The list of all math nodes.
     pub math: Vec<&'a Math<'a>>,
 
-    // The list containing the list of items for each \itemize or \enumerate.
+    // The list containing the list of items for each \enumerate, in document order. Items of
+    // \itemize environments can't be labeled and aren't included.
     pub item_lists: Vec<&'a Vec<Item<'a>>>,
 
     // The set of \ref or \eqref values.
@@ -162,38 +350,88 @@ pub struct NodeLists<'a> {
 
     // The set of \cite values.
     pub cite_ids: HashSet<&'a str>,
+
+    // The \cite values in the order in which they first appear in the document.
+    pub cite_order: Vec<&'a str>,
+
+    // Whether all bib entries are included in the bibliography because of \nocite{*}.
+    pub nocite_all: bool,
+
+    // The list of all \footnote nodes, in the order in which they appear in the document.
+    pub footnotes: Vec<&'a ParagraphPart<'a>>,
+
+    // The list of all tabular environments.
+    pub tabulars: Vec<&'a ParagraphPart<'a>>,
 }
 
 impl<'a> NodeLists<'a> {
     pub fn new(doc: &'a Document<'a>) -> Self {
+        Self::for_parts(&doc.parts)
+    }
+
+    // The node lists of a part of a document, e.g. of a single page.
+    pub fn for_parts(parts: &'a [DocumentPart<'a>]) -> Self {
         let mut result = NodeLists {
             math: Vec::new(),
             item_lists: Vec::new(),
             ref_ids: HashSet::new(),
             cite_ids: HashSet::new(),
+            cite_order: Vec::new(),
+            nocite_all: false,
+            footnotes: Vec::new(),
+            tabulars: Vec::new(),
         };
 
-        doc.parts.iter().for_each(|part| result.add_doc_part(part));
+        parts.iter().for_each(|part| result.add_doc_part(part));
         result
     }
 
     fn add_doc_part(&mut self, part: &'a DocumentPart<'a>) {
         use DocumentPart::*;
         match part {
-            Date() | Maketitle() | Bibliography => (),
+            Date(None)
+            | Maketitle()
+            | Bibliography(_)
+            | BibliographyStyle(_)
+            | ListOfFigures
+            | ListOfTables
+            | Appendix
+            | SetCounter { .. } => (),
+            Nocite(ids) => {
+                for id in ids.iter().copied() {
+                    if id == "*" {
+                        self.nocite_all = true;
+                    } else if self.cite_ids.insert(id) {
+                        self.cite_order.push(id);
+                    }
+                }
+            }
             FreeParagraph(par)
             | Title(par)
             | Author(par)
+            | Date(Some(par))
+            | Keywords(par)
             | Section {
                 name: par,
                 label: _,
+                numbered: _,
             }
             | Subsection {
                 name: par,
                 label: _,
+                numbered: _,
+            }
+            | Subsubsection {
+                name: par,
+                label: _,
+                numbered: _,
             } => {
                 par.iter().for_each(|part| self.add_par_part(part));
             }
+            ParagraphHeading { name, body, .. } | SubparagraphHeading { name, body, .. } => {
+                name.iter().for_each(|part| self.add_par_part(part));
+                body.iter().for_each(|part| self.add_par_part(part));
+            }
             TheoremLike {
                 content,
                 note,
@@ -213,32 +451,77 @@ impl<'a> NodeLists<'a> {
                     .flatten()
                     .for_each(|part| self.add_par_part(part));
             }
+            Figure {
+                caption,
+                subfigures,
+                ..
+            } => {
+                let subfigure_captions = subfigures.iter().filter_map(|sub| sub.caption.as_ref());
+                caption
+                    .iter()
+                    .chain(subfigure_captions)
+                    .flatten()
+                    .for_each(|part| self.add_par_part(part));
+            }
+            Table {
+                tabulars, caption, ..
+            } => {
+                tabulars
+                    .iter()
+                    .chain(caption.iter().flatten())
+                    .for_each(|part| self.add_par_part(part));
+            }
         }
     }
 
     fn add_par_part(&mut self, part: &'a ParagraphPart<'a>) {
         use ParagraphPart::*;
         match part {
-            InlineWhitespace(_) | TextToken(_) | Qed | Todo => (),
-            Cite { ids, text } => {
+            InlineWhitespace(_) | TextToken(_) | Code(_) | Verbatim(_) | Qed | Todo | ThinSpace
+            | Today => (),
+            Cite {
+                ids,
+                prenote,
+                postnote,
+                ..
+            } => {
                 for id in ids.iter().copied() {
-                    self.cite_ids.insert(id);
+                    if self.cite_ids.insert(id) {
+                        self.cite_order.push(id);
+                    }
                 }
-                text.iter()
+                prenote
+                    .iter()
+                    .chain(postnote.iter())
                     .flatten()
                     .for_each(|part| self.add_par_part(part));
             }
             Ref(id) => {
                 self.ref_ids.insert(id);
             }
+            Cref { ids, .. } => {
+                self.ref_ids.extend(ids.iter().copied());
+            }
             Math(math) => {
                 self.math.push(math);
             }
-            Emph(par) | Textbf(par) | Textit(par) => {
+            Emph(par)
+            | Textbf(par)
+            | Textit(par)
+            | Texttt(par)
+            | Textsc(par)
+            | Textsf(par)
+            | Underline(par)
+            | Textsuperscript(par)
+            | Textsubscript(par)
+            | Href { text: par, .. }
+            | TextColor { content: par, .. } => {
                 par.iter().for_each(|part| self.add_par_part(part));
             }
             Enumerate(items) | Itemize(items) => {
-                self.item_lists.push(items);
+                if let Enumerate(_) = part {
+                    self.item_lists.push(items);
+                }
                 items
                     .iter()
                     .map(|it| &it.content)
@@ -248,11 +531,32 @@ impl<'a> NodeLists<'a> {
                         self.add_par_part(part);
                     });
             }
+            Description(items) => {
+                for (term, body) in items.iter() {
+                    term.iter().for_each(|part| self.add_par_part(part));
+                    body.iter()
+                        .flatten()
+                        .for_each(|part| self.add_par_part(part));
+                }
+            }
             Footnote(pars) => {
+                self.footnotes.push(part);
+                pars.iter()
+                    .flatten()
+                    .for_each(|part| self.add_par_part(part));
+            }
+            Thanks(pars) | Center(pars) => {
                 pars.iter()
                     .flatten()
                     .for_each(|part| self.add_par_part(part));
             }
+            Tabular { rows, .. } => {
+                self.tabulars.push(part);
+                rows.iter()
+                    .flat_map(|row| row.cells.iter())
+                    .flatten()
+                    .for_each(|part| self.add_par_part(part));
+            }
         }
     }
 }
@@ -265,6 +569,11 @@ pub enum BibEntryType {
     Inproceedings,
     Thesis,
     Incollection,
+    Phdthesis,
+    Mastersthesis,
+    Techreport,
+    // @online or @electronic.
+    Online,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -279,17 +588,20 @@ pub struct BibPerson<'a> {
     pub last_name: &'a str,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct BibPages {
-    pub first: u64,
-    pub last: Option<u64>,
+// Page identifiers are kept as written since they need not be numbers, e.g. "iv" or "e1001".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BibPages<'a> {
+    pub first: &'a str,
+    pub last: Option<&'a str>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BibEntryItem<'a> {
     Title(&'a str),
     Year(&'a str),
+    Month(&'a str),
     Authors(Vec<BibPerson<'a>>),
+    Editors(Vec<BibPerson<'a>>),
     Url(&'a str),
     Journal(&'a str),
     Booktitle(&'a str),
@@ -297,7 +609,13 @@ pub enum BibEntryItem<'a> {
     Publisher(&'a str),
     Volume(&'a str),
     Number(&'a str),
-    Pages(BibPages),
+    Pages(BibPages<'a>),
+    School(&'a str),
+    Institution(&'a str),
+    Type(&'a str),
+    Howpublished(&'a str),
+    Doi(&'a str),
+    Isbn(&'a str),
     Unused,
 }
 
@@ -308,7 +626,9 @@ pub struct BibEntry<'a> {
 
     pub title: Option<&'a str>,
     pub year: Option<&'a str>,
+    pub month: Option<&'a str>,
     pub authors: Option<Vec<BibPerson<'a>>>,
+    pub editors: Option<Vec<BibPerson<'a>>>,
     pub url: Option<&'a str>,
     pub journal: Option<&'a str>,
     pub booktitle: Option<&'a str>,
@@ -316,5 +636,13 @@ pub struct BibEntry<'a> {
     pub publisher: Option<&'a str>,
     pub volume: Option<&'a str>,
     pub number: Option<&'a str>,
-    pub pages: Option<BibPages>,
+    pub pages: Option<BibPages<'a>>,
+    pub school: Option<&'a str>,
+    pub institution: Option<&'a str>,
+    // The "type" field, e.g. "Habilitation thesis", which overrides the default description of
+    // theses and reports.
+    pub kind: Option<&'a str>,
+    pub howpublished: Option<&'a str>,
+    pub doi: Option<&'a str>,
+    pub isbn: Option<&'a str>,
 }
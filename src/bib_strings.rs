@@ -0,0 +1,237 @@
+use crate::parse::{
+    bib_entry_tag, bib_entry_type, bib_quoted_value, bib_ws, raw_command_arg, Result,
+};
+use nom::branch::alt;
+use nom::bytes::complete::{tag_no_case, take_while1};
+use nom::character::complete::{char, digit1};
+use nom::combinator::recognize;
+use nom::multi::separated_list1;
+use nom::sequence::{delimited, tuple};
+use nom::{Offset, Parser};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+// Bibtex @string macros and the concatenation of values with # are resolved textually before the
+// bib file is parsed: @string entries are removed, and field values are replaced by a single group
+// in braces containing the resolved value. For example,
+//
+//   @string{ieee = "IEEE Transactions"}
+//   @article{..., journal = ieee # " on Computers"}
+//
+// becomes
+//
+//   @article{..., journal = {IEEE Transactions on Computers}}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndefinedStringError {
+    pub name: String,
+    // The byte offset of the reference to the undefined string in the bib source.
+    pub offset: usize,
+}
+
+impl Display for UndefinedStringError {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        let name = &self.name;
+        write!(out, "Undefined string \"{name}\"")
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ValuePart<'a> {
+    // The content of a group in braces or a quoted string, or a number.
+    Literal(&'a str),
+    // A reference to a string defined with @string.
+    Reference(&'a str),
+}
+
+fn string_name<'a>(i: &'a str) -> Result<'a, &'a str> {
+    take_while1(|c: char| c.is_ascii_alphanumeric() || "_-:.+/".contains(c))(i)
+}
+
+fn value_part<'a>(i: &'a str) -> Result<'a, ValuePart<'a>> {
+    alt((
+        delimited(char('{'), raw_command_arg, char('}')).map(ValuePart::Literal),
+        bib_quoted_value.map(ValuePart::Literal),
+        digit1.map(ValuePart::Literal),
+        string_name.map(ValuePart::Reference),
+    ))(i)
+}
+
+// Parses a field value, i.e. a list of value parts separated by #.
+fn value<'a>(i: &'a str) -> Result<'a, Vec<ValuePart<'a>>> {
+    separated_list1(tuple((bib_ws, char('#'), bib_ws)), value_part)(i)
+}
+
+// Parses a field up to and including the =, returning the consumed text, and the field value.
+fn field<'a>(i: &'a str) -> Result<'a, (&'a str, Vec<ValuePart<'a>>)> {
+    let (i, name) = recognize(tuple((string_name, bib_ws, char('='), bib_ws)))(i)?;
+    let (i, value) = value(i)?;
+    Ok((i, (name, value)))
+}
+
+// Parses @string{name = value} or @string(name = value).
+fn string_definition<'a>(i: &'a str) -> Result<'a, (&'a str, Vec<ValuePart<'a>>)> {
+    let (i, _) = tuple((char('@'), tag_no_case("string"), bib_ws))(i)?;
+    let (i, close) = alt((char('{').map(|_| '}'), char('(').map(|_| ')')))(i)?;
+    let (i, _) = bib_ws(i)?;
+    let (i, name) = string_name(i)?;
+    let (i, _) = tuple((bib_ws, char('='), bib_ws))(i)?;
+    let (i, value) = value(i)?;
+    let (i, _) = tuple((bib_ws, char(close)))(i)?;
+    Ok((i, (name, value)))
+}
+
+// Parses the beginning of an entry up to and including the comma after the tag.
+fn entry_header<'a>(i: &'a str) -> Result<'a, &'a str> {
+    recognize(tuple((
+        char('@'),
+        bib_entry_type,
+        bib_ws,
+        char('{'),
+        bib_ws,
+        bib_entry_tag,
+        bib_ws,
+        char(','),
+    )))(i)
+}
+
+// The strings that bibtex styles predefine.
+fn predefined_strings() -> HashMap<String, String> {
+    [
+        ("jan", "January"),
+        ("feb", "February"),
+        ("mar", "March"),
+        ("apr", "April"),
+        ("may", "May"),
+        ("jun", "June"),
+        ("jul", "July"),
+        ("aug", "August"),
+        ("sep", "September"),
+        ("oct", "October"),
+        ("nov", "November"),
+        ("dec", "December"),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect()
+}
+
+pub fn expand_bib_strings(bib: &str) -> std::result::Result<String, UndefinedStringError> {
+    // String names are case insensitive, so they are stored in lowercase.
+    let mut strings = predefined_strings();
+    let resolve = |strings: &HashMap<String, String>, parts: &[ValuePart]| {
+        let mut result = String::new();
+        for part in parts.iter().copied() {
+            match part {
+                ValuePart::Literal(literal) => result.push_str(literal),
+                ValuePart::Reference(name) => match strings.get(&name.to_lowercase()) {
+                    Some(value) => result.push_str(value),
+                    None => {
+                        return Err(UndefinedStringError {
+                            name: name.to_string(),
+                            offset: bib.offset(name),
+                        })
+                    }
+                },
+            }
+        }
+        Ok(result)
+    };
+
+    let mut result = String::with_capacity(bib.len());
+    let mut i = bib;
+    while let Some(pos) = i.find('@') {
+        result.push_str(&i[..pos]);
+        i = &i[pos..];
+
+        if let Ok((rest, (name, value))) = string_definition(i) {
+            let value = resolve(&strings, &value)?;
+            strings.insert(name.to_lowercase(), value);
+            i = rest;
+            continue;
+        }
+
+        // Anything that isn't the beginning of an entry is left to the bib parser to report.
+        let Ok((rest, header)) = entry_header(i) else {
+            result.push('@');
+            i = &i[1..];
+            continue;
+        };
+        result.push_str(header);
+        i = rest;
+
+        // Rewrite the fields of the entry. The first field directly follows the header, later
+        // fields follow a comma.
+        let mut first = true;
+        loop {
+            let prefix = if first {
+                recognize(bib_ws)(i)
+            } else {
+                recognize(tuple((bib_ws, char(','), bib_ws)))(i)
+            };
+            let Ok((rest, (prefix, (name, value)))) = prefix.and_then(|(i, prefix)| {
+                let (i, field) = field(i)?;
+                Ok((i, (prefix, field)))
+            }) else {
+                break;
+            };
+            let value = resolve(&strings, &value)?;
+            result.push_str(prefix);
+            result.push_str(name);
+            result.push('{');
+            result.push_str(&value);
+            result.push('}');
+            i = rest;
+            first = false;
+        }
+    }
+    result.push_str(i);
+    Ok(result)
+}
+
+#[test]
+fn string_definitions_and_concatenation() {
+    let bib = indoc::indoc! {r#"
+        @string{ieee = "IEEE Transactions"}
+        @STRING(acm = {ACM})
+        @article{paper,
+          title = "A {"}quoted{"} title",
+          journal = ieee # " on Computers",
+          publisher = acm,
+          month = jan,
+          year = 2020
+        }
+    "#};
+    let expanded = expand_bib_strings(bib).unwrap();
+    assert_eq!(
+        expanded,
+        indoc::indoc! {r#"
+
+
+        @article{paper,
+          title = {A {"}quoted{"} title},
+          journal = {IEEE Transactions on Computers},
+          publisher = {ACM},
+          month = {January},
+          year = {2020}
+        }
+    "#}
+    );
+
+    let (_, entries) = crate::parse::bib(&expanded).unwrap();
+    assert_eq!(entries[0].journal, Some("IEEE Transactions on Computers"));
+    assert_eq!(entries[0].publisher, Some("ACM"));
+    assert_eq!(entries[0].year, Some("2020"));
+}
+
+#[test]
+fn undefined_string() {
+    let bib = "@article{paper, journal = undefined # {X}}";
+    assert_eq!(
+        expand_bib_strings(bib),
+        Err(UndefinedStringError {
+            name: "undefined".to_string(),
+            offset: 26,
+        })
+    );
+}
@@ -44,6 +44,16 @@ fn line_locations<'a>(source: &'a str) -> impl 'a + Clone + Iterator<Item = Loca
     })
 }
 
+// The one-based line and column number of a byte offset into source. Columns count characters, not
+// bytes.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_begin = before.rfind('\n').map_or(0, |pos| pos + 1);
+    let column = before[line_begin..].chars().count() + 1;
+    (line, column)
+}
+
 // An iterator over all lines that intersect a given location. The iterator yields pairs consisting
 // of the zero-based line index and the location of the full row.
 fn intersecting_line_locations<'a>(
@@ -95,9 +105,9 @@ impl<'a> Display for SourceDisplay<'a> {
 
         if let Some(source_path) = source_path {
             let source_path = source_path_pointer(source_path);
-            let (first_num, _) = nums_locs.clone().next().unwrap();
+            let (line, column) = line_column(source, location.0);
             write_padding(f, max_line_num_digits)?;
-            write!(f, "{source_path}:{first_num}\n")?;
+            write!(f, "{source_path}:{line}:{column}\n")?;
         }
         write_padding(f, max_line_num_digits)?;
         write!(f, " | \n")?;
@@ -1,43 +1,387 @@
 use crate::analysis::*;
 use crate::ast::*;
+use crate::json::Json;
 use crate::math_svg::*;
+use crate::parse::replace_accents;
 use crate::util::*;
-use convert_case::{Case, Casing};
 use indoc::{indoc, writedoc};
 use itertools::Itertools;
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter, Result, Write};
 use std::fs;
 use std::io::Write as IoWrite;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use std::ptr::addr_of;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::write;
 
+#[derive(Debug, Clone)]
+pub struct EmitOptions {
+    // Render trivial inline math such as $x$ or $x_i^2$ as html instead of compiling it to svg.
+    pub trivial_math_html: bool,
+
+    // The maximal width of the text body in px.
+    pub max_width_px: u32,
+
+    // The maximal width in px of display math that doesn't fit into the text body.
+    pub full_bleed_max_width_px: u32,
+
+    // The format of author names in the bibliography. If not set, the format is derived from the
+    // \bibliographystyle of the document.
+    pub bib_name_format: Option<BibNameFormat>,
+
+    // How citations are displayed.
+    pub citation_style: CitationStyle,
+
+    // The order of the entries in the bibliography.
+    pub bib_sort: BibSort,
+
+    // If an entry of the bibliography has more authors than this, only the first et_al_authors
+    // authors are listed, followed by "et al.". All authors are listed if not set.
+    pub max_authors: Option<usize>,
+    pub et_al_authors: usize,
+
+    // Mark the output as draft: Search engines are asked not to index it, and a banner is shown.
+    pub draft: bool,
+
+    // How math is rendered.
+    pub math_backend: MathBackend,
+
+    // The side on which the numbers of display math are shown.
+    pub equation_numbers: EquationNumberSide,
+
+    // Number all equation environments and lines of align environments, as latex does. Otherwise
+    // only equations that are referenced are numbered.
+    pub number_all_display_math: bool,
+
+    // The tex engine with which math is compiled to svg.
+    pub latex_engine: LatexEngine,
+
+    // The path of the tex engine program. If not set, the program of latex_engine is looked up in
+    // the PATH.
+    pub latex_program: Option<PathBuf>,
+
+    // The paths of the pdfcrop and pdf2svg programs, which are looked up in the PATH unless they
+    // contain a slash.
+    pub pdfcrop_program: PathBuf,
+    pub pdf2svg_program: PathBuf,
+
+    // The packages that are loaded after the preamble of the document when compiling math, so
+    // that e.g. cases and matrices work without \usepackage{amsmath} in the preamble.
+    pub math_packages: Vec<String>,
+
+    // Compile math with \mathtoolsset{showonlyrefs} if mathtools is among the math packages.
+    // Numbers of display math are shown in the html and never compiled into the svgs, so this
+    // only matters for preambles that rely on it.
+    pub math_showonlyrefs: bool,
+
+    // Lines that are added to the preamble when compiling math, after the math packages.
+    pub math_preamble_extra: Vec<String>,
+
+    // The size in pt of the font of the document, which corresponds to 1em of the html text.
+    pub base_font_pt: f64,
+
+    // The factor by which math is scaled relative to the surrounding text.
+    pub math_scale: f64,
+
+    // Draw math svgs in currentColor, which is black, or white if the reader prefers a dark color
+    // scheme.
+    pub math_current_color: bool,
+
+    // The time after which the tex engine, pdfcrop and pdf2svg are killed when compiling a
+    // formula.
+    pub latex_timeout: Duration,
+
+    // Don't abort if a formula fails to compile. The formula is shown as a placeholder instead.
+    pub best_effort_math: bool,
+
+    // The number of formulas that are compiled in parallel. Defaults to the number of cpus.
+    pub math_jobs: Option<usize>,
+
+    // Delete the svgs of formulas that are no longer in the document from the output directory.
+    // Off by default, since it deletes files that other documents in the same directory use.
+    pub prune_math_svgs: bool,
+
+    // Produce a single self-contained index.html: The stylesheet is inlined and math svgs are
+    // embedded as data urls, so that no other files are written.
+    pub standalone: bool,
+
+    // Write every section to a page of its own, with index.html containing the part of the
+    // document before the first section and a table of contents. Ignored for standalone output.
+    pub split_sections: bool,
+
+    // Also write structure.json, which describes the title, the sections, the theorem-like
+    // environments and the bibliography of the document. Ignored for standalone output.
+    pub structure_json: bool,
+
+    // Css that is appended to the built-in stylesheet, e.g. to override its rules.
+    pub extra_css: Option<String>,
+}
+
+impl Default for EmitOptions {
+    fn default() -> Self {
+        EmitOptions {
+            trivial_math_html: false,
+            max_width_px: 600,
+            full_bleed_max_width_px: 960,
+            bib_name_format: None,
+            citation_style: CitationStyle::Numeric,
+            bib_sort: BibSort::Alphabetical,
+            max_authors: None,
+            et_al_authors: 1,
+            draft: false,
+            standalone: false,
+            split_sections: false,
+            structure_json: false,
+            math_backend: MathBackend::Svg,
+            equation_numbers: EquationNumberSide::Right,
+            number_all_display_math: false,
+            latex_engine: LatexEngine::PdfLatex,
+            latex_program: None,
+            pdfcrop_program: PathBuf::from("pdfcrop"),
+            pdf2svg_program: PathBuf::from("pdf2svg"),
+            math_packages: vec!["amsmath".to_string(), "mathtools".to_string()],
+            math_preamble_extra: Vec::new(),
+            math_showonlyrefs: true,
+            base_font_pt: 10.0,
+            math_scale: 1.0,
+            math_current_color: false,
+            latex_timeout: DEFAULT_LATEX_TIMEOUT,
+            best_effort_math: false,
+            math_jobs: None,
+            prune_math_svgs: false,
+            extra_css: None,
+        }
+    }
+}
+
+impl EmitOptions {
+    // The size of an em of the svgs of math in pt.
+    pub fn math_em_pt(&self) -> f64 {
+        self.base_font_pt / self.math_scale
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MathBackend {
+    // Compile math to svg images with pdflatex, pdfcrop and pdf2svg.
+    Svg,
+    // Convert math to inline MathML. Only a subset of latex math is supported, see math_mathml.rs.
+    MathMl,
+}
+
+impl FromStr for MathBackend {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, ()> {
+        match value {
+            "svg" => Ok(MathBackend::Svg),
+            "mathml" => Ok(MathBackend::MathMl),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EquationNumberSide {
+    Left,
+    Right,
+    // Display math is not numbered, even if it is referenced.
+    None,
+}
+
+impl FromStr for EquationNumberSide {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, ()> {
+        match value {
+            "left" => Ok(EquationNumberSide::Left),
+            "right" => Ok(EquationNumberSide::Right),
+            "none" => Ok(EquationNumberSide::None),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CitationStyle {
+    // "[1]" for all cite commands.
+    Numeric,
+    // "Smith (2020)" for \citet and "(Smith, 2020)" for \citep and \cite.
+    AuthorYear,
+}
+
+impl FromStr for CitationStyle {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, ()> {
+        match value {
+            "numeric" => Ok(CitationStyle::Numeric),
+            "author-year" => Ok(CitationStyle::AuthorYear),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BibSort {
+    // By the last name of the first author.
+    Alphabetical,
+    // By the first citation in the text.
+    CitationOrder,
+}
+
+impl FromStr for BibSort {
+    type Err = ();
+
+    fn from_str(value: &str) -> std::result::Result<Self, ()> {
+        match value {
+            "alphabetical" => Ok(BibSort::Alphabetical),
+            "citation-order" => Ok(BibSort::CitationOrder),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BibNameFormat {
+    // "Michael Ching" for all authors.
+    FirstLast,
+    // "Ching, Michael" for the first author and "Michael Ching" for all others.
+    LastFirst,
+    // "Ching, M." for all authors.
+    LastInitials,
+}
+
+impl BibNameFormat {
+    // The name format of a bibtex style, if we know it.
+    pub fn from_bibliography_style(style: &str) -> Option<Self> {
+        use BibNameFormat::*;
+        match style {
+            "plain" | "unsrt" | "alpha" | "abbrv" | "plainnat" | "unsrtnat" | "abbrvnat"
+            | "ieeetr" | "siam" => Some(FirstLast),
+            "chicago" => Some(LastFirst),
+            "apalike" | "acm" | "agsm" => Some(LastInitials),
+            _ => None,
+        }
+    }
+}
+
 fn display_math<'a>(analysis: &'a Analysis<'a>, math: &'a Math<'a>) -> impl 'a + Display {
-    let src = analysis.math_image_source.get(&addr_of!(*math)).unwrap();
+    let html = analysis.math_html.get(&addr_of!(*math));
+    let src = analysis.math_image_source.get(&addr_of!(*math));
+    let style = analysis.math_image_style.get(&addr_of!(*math));
+    let size = analysis.math_image_size.get(&addr_of!(*math));
     let number = analysis.math_numbering.get(&addr_of!(*math));
+    let align_numbers = match math {
+        Math::Align { lines, .. } => {
+            let numbers = analysis.align_numbering.get(&addr_of!(*math)).unwrap();
+            Some(lines.iter().zip(numbers))
+        }
+        _ => None,
+    };
+    let qed = analysis.qed_math.contains(&addr_of!(*math));
+    let full_bleed_class = if analysis.wide_math.contains(&addr_of!(*math)) {
+        " full-bleed"
+    } else {
+        ""
+    };
+    let numbers_left = analysis.equation_numbers == EquationNumberSide::Left;
     DisplayFn(move |out: &mut Formatter| {
+        let style_attr = DisplayFn(|out: &mut Formatter| match style {
+            None => Ok(()),
+            Some(style) => write!(out, r#" style="{style}""#),
+        });
+        let size_attrs = DisplayFn(|out: &mut Formatter| match size {
+            None => Ok(()),
+            Some((width, height)) => write!(out, r#" width="{width}" height="{height}""#),
+        });
+        // Images carry the latex source as alt text for screen readers and copy-paste.
+        let alt = display_escaped(crate::math_source(math));
+
         use Math::*;
         match math {
-            Inline(_) => {
-                write!(out, r#"<img src="{src}" class="inline-math">"#)?;
-            }
-            Display { source: _, label } | Mathpar { source: _, label } => {
-                let id_attr = display_label_id_attr(*label);
+            Inline(_) => match html {
+                Some(html) => out.write_str(html)?,
+                None => {
+                    let src = src.unwrap();
+                    write!(
+                        out,
+                        r#"<img src="{src}"{size_attrs}{style_attr} alt="{alt}" role="math" class="inline-math">"#
+                    )?;
+                }
+            },
+            Display { .. } | Mathpar { .. } | Align { .. } => {
+                let id_attr = display_label_id_attr(math.label());
+                let numbers_left_class = if numbers_left { " numbers-left" } else { "" };
                 writedoc! {out, r#"
-                    <div{id_attr} class="display-math-row">
+                    <div{id_attr} class="display-math-row{full_bleed_class}{numbers_left_class}">
                 "#}?;
 
-                if let Some(number) = number {
+                // The span on the right holds the equation number and the qed symbol if this
+                // equation ends a proof. The span on the left has the same content but is hidden,
+                // so that the image is centered. If numbers are shown on the left, then only the
+                // qed symbol is hidden in the left span, and only the number in the right span.
+                // For align environments, the numbers of all lines are stacked in a column; only
+                // the copy with the visible numbers carries the ids of the lines.
+                let side_span = |with_ids: bool| {
+                    let align_numbers = align_numbers.clone();
+                    DisplayFn(move |out: &mut Formatter| {
+                        match align_numbers.clone() {
+                            None => {
+                                write!(out, "<span>")?;
+                                if let Some(number) = number {
+                                    write!(out, "{number}")?;
+                                }
+                            }
+                            Some(align_numbers) => {
+                                write!(out, r#"<span class="align-side">"#)?;
+                                write!(out, r#"<span class="align-numbers">"#)?;
+                                for (line, number) in align_numbers {
+                                    match number {
+                                        Some(number) if with_ids => {
+                                            let id_attr = display_label_id_attr(line.label);
+                                            write!(out, "<span{id_attr}>{number}</span>")?;
+                                        }
+                                        Some(number) => write!(out, "<span>{number}</span>")?,
+                                        None => write!(out, "<span></span>")?,
+                                    }
+                                }
+                                write!(out, "</span>")?;
+                            }
+                        }
+                        if qed {
+                            write!(out, r#"<span class="qed">∎</span>"#)?;
+                        }
+                        write!(out, "</span>")?;
+                        Ok(())
+                    })
+                };
+                let has_align_numbers = align_numbers
+                    .clone()
+                    .is_some_and(|mut numbers| numbers.any(|(_, number)| number.is_some()));
+                let has_side_spans = number.is_some() || has_align_numbers || qed;
+
+                if has_side_spans {
+                    let side_span = side_span(numbers_left);
                     writedoc! {out, r#"
-                        <span>{number}</span>
+                        {side_span}
                     "#}?;
                 }
-                writedoc! {out, r#"
-                    <img src="{src}">
-                "#}?;
-                if let Some(number) = number {
+                match html {
+                    Some(html) => writeln!(out, "{html}")?,
+                    None => {
+                        let src = src.unwrap();
+                        writedoc! {out, r#"
+                            <img src="{src}"{size_attrs}{style_attr} alt="{alt}" role="math">
+                        "#}?;
+                    }
+                }
+                if has_side_spans {
+                    let side_span = side_span(!numbers_left);
                     writedoc! {out, r#"
-                            <span>{number}</span>
+                            {side_span}
                         "#}?;
                 }
                 writedoc! {out, r#"
@@ -70,13 +414,12 @@ fn display_paragraph_part<'a>(
         match part {
             InlineWhitespace(ws) => {
                 let has_newlines = ws.find('\n').is_some();
-                if has_newlines {
-                    write!(out, "\n")?;
-                } else if !ws.is_empty() {
-                    write!(out, " ")?;
+                if !is_joining_whitespace(ws) {
+                    let separator = if has_newlines { "\n" } else { " " };
+                    write!(out, "{separator}")?;
                 }
             }
-            TextToken(tok) => out.write_str(tok)?,
+            TextToken(tok) => write!(out, "{}", display_escaped(tok))?,
             Math(math) => {
                 write!(out, "{}", display_math(analysis, math))?;
             }
@@ -85,26 +428,83 @@ fn display_paragraph_part<'a>(
                     None => "???",
                     Some(name) => name.as_str(),
                 };
-                let value = display_label_value(value);
-                write!(out, "<a href=\"#{value}\">{name}</a>")?;
-            }
-            Cite { ids, text } => {
-                let links = ids.iter().copied().format_with(", ", |id, f| {
-                    let display_text = match analysis.cite_display_text.get(id) {
-                        None => "???",
-                        Some(name) => name.as_str(),
-                    };
-                    let id = display_cite_value(id);
-                    f(&format_args!("<a href=\"#{id}\">{display_text}</a>"))
+                let href = display_href(analysis, display_label_value(value));
+                write!(out, "<a href=\"{href}\">{name}</a>")?;
+            }
+            Cref { ids, capitalized } => {
+                write!(out, "{}", display_cref(analysis, ids, *capitalized))?;
+            }
+            Cite {
+                command,
+                ids,
+                prenote,
+                postnote,
+            } => {
+                // The prenote is followed by a space and the postnote preceded by a comma.
+                let prenote = DisplayFn(|out: &mut Formatter| {
+                    if let Some(prenote) = prenote {
+                        for part in prenote.iter() {
+                            write!(out, "{}", display_paragraph_part(analysis, part))?;
+                        }
+                        write!(out, " ")?;
+                    }
+                    Ok(())
                 });
-                write!(out, "[{links}")?;
-                if let Some(text) = text {
-                    write!(out, ", ")?;
-                    for part in text.iter() {
-                        write!(out, "{}", display_paragraph_part(analysis, part))?;
+                let postnote = DisplayFn(|out: &mut Formatter| {
+                    if let Some(postnote) = postnote {
+                        write!(out, ", ")?;
+                        for part in postnote.iter() {
+                            write!(out, "{}", display_paragraph_part(analysis, part))?;
+                        }
+                    }
+                    Ok(())
+                });
+                let author_year = |id: &str| match analysis.cite_author_year.get(id) {
+                    None => ("???", "???"),
+                    Some((authors, year)) => (authors.as_str(), year.as_str()),
+                };
+                match (analysis.citation_style, command) {
+                    (CitationStyle::Numeric, _) => {
+                        let links = ids.iter().copied().format_with(", ", |id, f| {
+                            let display_text = match analysis.cite_display_text.get(id) {
+                                None => "???",
+                                Some(name) => name.as_str(),
+                            };
+                            let href = display_href(analysis, display_cite_value(id));
+                            f(&format_args!("<a href=\"{href}\">{display_text}</a>"))
+                        });
+                        write!(out, "[{prenote}{links}{postnote}]")?;
+                    }
+                    (CitationStyle::AuthorYear, CiteCommand::Citet) => {
+                        // The notes go into the parentheses of the first and last citation.
+                        for (i, id) in ids.iter().copied().enumerate() {
+                            let (authors, year) = author_year(id);
+                            let authors = display_escaped(authors);
+                            let year = display_escaped(year);
+                            let separator = if i == 0 { "" } else { ", " };
+                            let href = display_href(analysis, display_cite_value(id));
+                            write!(out, "{separator}{authors} (")?;
+                            if i == 0 {
+                                write!(out, "{prenote}")?;
+                            }
+                            write!(out, r#"<a href="{href}">{year}</a>"#)?;
+                            if i + 1 == ids.len() {
+                                write!(out, "{postnote}")?;
+                            }
+                            write!(out, ")")?;
+                        }
+                    }
+                    (CitationStyle::AuthorYear, CiteCommand::Citep | CiteCommand::Cite) => {
+                        let links = ids.iter().copied().format_with("; ", |id, f| {
+                            let (authors, year) = author_year(id);
+                            let authors = display_escaped(authors);
+                            let year = display_escaped(year);
+                            let href = display_href(analysis, display_cite_value(id));
+                            f(&format_args!(r#"<a href="{href}">{authors}, {year}</a>"#))
+                        });
+                        write!(out, "({prenote}{links}{postnote})")?;
                     }
                 }
-                write!(out, "]")?;
             }
             Emph(child_paragraph) => {
                 write!(out, "<em>")?;
@@ -127,6 +527,84 @@ fn display_paragraph_part<'a>(
                 }
                 write!(out, "</i>")?;
             }
+            Texttt(paragraph) => {
+                write!(out, "<code>")?;
+                for part in paragraph.iter() {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                write!(out, "</code>")?;
+            }
+            Textsc(paragraph) => {
+                write!(out, r#"<span class="small-caps">"#)?;
+                for part in paragraph.iter() {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                write!(out, "</span>")?;
+            }
+            Textsf(paragraph) => {
+                write!(out, r#"<span class="sans">"#)?;
+                for part in paragraph.iter() {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                write!(out, "</span>")?;
+            }
+            Underline(paragraph) => {
+                write!(out, "<u>")?;
+                for part in paragraph.iter() {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                write!(out, "</u>")?;
+            }
+            Textsuperscript(paragraph) => {
+                write!(out, "<sup>")?;
+                for part in paragraph.iter() {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                write!(out, "</sup>")?;
+            }
+            Textsubscript(paragraph) => {
+                write!(out, "<sub>")?;
+                for part in paragraph.iter() {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                write!(out, "</sub>")?;
+            }
+            TextColor {
+                model,
+                color,
+                content,
+            } => {
+                // Colors that can't be converted to css are ignored.
+                let css_color = css_color(*model, color);
+                if let Some(css_color) = &css_color {
+                    write!(out, r#"<span style="color: {css_color}">"#)?;
+                }
+                for part in content.iter() {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                if css_color.is_some() {
+                    write!(out, "</span>")?;
+                }
+            }
+            Code(code) => {
+                write!(out, "<code>{}</code>", display_escaped(code))?;
+            }
+            Verbatim(content) => {
+                write!(out, "<pre><code>{}</code></pre>", display_escaped(content))?;
+            }
+            Href { link, text } => {
+                // Links whose text is the url itself, e.g. of \url, may break anywhere, so that
+                // long urls don't overflow the text body.
+                let class_attr = match text.as_slice() {
+                    [TextToken(token)] if token == link => r#" class="url""#,
+                    _ => "",
+                };
+                write!(out, r#"<a href="{}"{class_attr}>"#, display_escaped(link))?;
+                for part in text.iter() {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                write!(out, "</a>")?;
+            }
             Qed => {}
             Itemize(items) => {
                 write!(out, "<ul>\n")?;
@@ -152,15 +630,89 @@ fn display_paragraph_part<'a>(
                 }
                 write!(out, "</ol>\n")?;
             }
+            Description(items) => {
+                writeln!(out, "<dl>")?;
+                for (term, body) in items {
+                    write!(out, "<dt>")?;
+                    for part in term.iter() {
+                        write!(out, "{}", display_paragraph_part(analysis, part))?;
+                    }
+                    writeln!(out, "</dt>")?;
+                    writeln!(out, "<dd>")?;
+                    for paragraph in body.iter() {
+                        display_paragraph(analysis, paragraph).fmt(out)?;
+                    }
+                    writeln!(out, "</dd>")?;
+                }
+                writeln!(out, "</dl>")?;
+            }
+            Center(paragraphs) => {
+                writeln!(out, r#"<div class="center">"#)?;
+                for paragraph in paragraphs.iter() {
+                    display_paragraph(analysis, paragraph).fmt(out)?;
+                }
+                writeln!(out, "</div>")?;
+            }
+            Tabular { columns, rows } => {
+                let full_bleed_class = if analysis.wide_tabulars.contains(&addr_of!(*part)) {
+                    " full-bleed"
+                } else {
+                    ""
+                };
+                writeln!(out, r#"<table class="tabular{full_bleed_class}">"#)?;
+                for row in rows {
+                    let class_attr = match (row.border_top, row.border_bottom) {
+                        (false, false) => "",
+                        (true, false) => r#" class="border-top""#,
+                        (false, true) => r#" class="border-bottom""#,
+                        (true, true) => r#" class="border-top border-bottom""#,
+                    };
+                    write!(out, "<tr{class_attr}>")?;
+                    for (i, cell) in row.cells.iter().enumerate() {
+                        let alignment = match columns.get(i) {
+                            None | Some(ColumnAlignment::Left) => "left",
+                            Some(ColumnAlignment::Center) => "center",
+                            Some(ColumnAlignment::Right) => "right",
+                        };
+                        write!(out, r#"<td style="text-align: {alignment}">"#)?;
+                        for part in cell {
+                            write!(out, "{}", display_paragraph_part(analysis, part))?;
+                        }
+                        write!(out, "</td>")?;
+                    }
+                    writeln!(out, "</tr>")?;
+                }
+                writeln!(out, "</table>")?;
+            }
             Todo => (),
+            // The thin space is the margin of an empty span, which doesn't allow a line break.
+            ThinSpace => write!(out, r#"<span class="thinspace"></span>"#)?,
+            Today => {
+                write!(out, "{}", today())?;
+            }
+            // Shown by display_title_block only.
+            Thanks(_) => (),
             Footnote(_) => {
-                // TODO
+                let number = analysis
+                    .footnote_numbering
+                    .get(&std::ptr::addr_of!(*part))
+                    .unwrap();
+                write!(
+                    out,
+                    r##"<sup><a id="fnref{number}" href="#fn{number}">{number}</a></sup>"##
+                )?;
             }
         }
         Ok(())
     })
 }
 
+// Whether whitespace joins the text around it without a space. This is the case if the whitespace
+// is empty or starts with a comment, e.g. "foo%\nbar" is displayed as "foobar", as in latex.
+fn is_joining_whitespace(ws: &str) -> bool {
+    ws.is_empty() || ws.starts_with('%')
+}
+
 fn display_paragraph<'a>(
     analysis: &'a Analysis<'a>,
     paragraph: &'a Paragraph,
@@ -169,7 +721,23 @@ fn display_paragraph<'a>(
         writedoc! {out, r#"
             <div class="paragraph">
         "#}?;
-        for part in paragraph.iter() {
+        // Whitespace at the start and end of the paragraph is dropped, and consecutive whitespace
+        // parts are collapsed into a single space, or a newline if any of them contains one.
+        let is_whitespace =
+            |part: &&ParagraphPart| matches!(part, ParagraphPart::InlineWhitespace(_));
+        let mut pending_whitespace: Option<&str> = None;
+        for part in paragraph.iter().skip_while(is_whitespace) {
+            if let ParagraphPart::InlineWhitespace(ws) = part {
+                if !is_joining_whitespace(ws) && (pending_whitespace.is_none() || ws.contains('\n'))
+                {
+                    pending_whitespace = Some(ws);
+                }
+                continue;
+            }
+            if let Some(ws) = pending_whitespace.take() {
+                let ws = ParagraphPart::InlineWhitespace(ws);
+                write!(out, "{}", display_paragraph_part(analysis, &ws))?;
+            }
             write!(out, "{}", display_paragraph_part(analysis, part))?;
         }
         writedoc! {out, r#"
@@ -179,28 +747,64 @@ fn display_paragraph<'a>(
     })
 }
 
-pub fn display_head(title: impl Display) -> impl Display {
+pub fn display_head<'a>(title: impl 'a + Display, options: &'a EmitOptions) -> impl 'a + Display {
     DisplayFn(move |out: &mut Formatter| {
         writedoc! {out, r#"
               <head>
               <meta charset="utf-8">
               <meta name="viewport" content="width=device-width, initial-scale=1" />
+        "#}?;
+        if options.draft {
+            writedoc! {out, r#"
+                <meta name="robots" content="noindex, nofollow">
+            "#}?;
+        }
+        writedoc! {out, r#"
               <title>{title}</title>
               <link rel="stylesheet" type="text/css" href="https://cdn.rawgit.com/dreampulse/computer-modern-web-font/master/fonts.css">
-              <link rel="stylesheet" type="text/css" href="style.css">
-              <link rel="stylesheet" type="text/css" href="{SVG_OUT_DIR}/geometry.css">
+        "#}?;
+        if options.standalone {
+            let style = display_style(options);
+            writedoc! {out, r#"
+                <style>
+                {style}
+                </style>
+            "#}?;
+        } else {
+            writedoc! {out, r#"
+                <link rel="stylesheet" type="text/css" href="style.css">
+                <link rel="stylesheet" type="text/css" href="{SVG_OUT_DIR}/geometry.css">
+            "#}?;
+        }
+        writedoc! {out, r#"
               </head>
         "#}?;
         Ok(())
     })
 }
 
+fn display_permalink(id: &str) -> impl '_ + Display {
+    DisplayFn(move |out: &mut Formatter| {
+        write!(out, r##" <a class="permalink" href="#{id}">#</a>"##)
+    })
+}
+
+// The target of a link to the element with the given html id. If the output is split into pages,
+// the element may be on another page.
+fn display_href<'a>(analysis: &'a Analysis, id: impl 'a + Display) -> impl 'a + Display {
+    DisplayFn(move |out: &mut Formatter| {
+        let id = id.to_string();
+        let page = analysis.id_pages.get(&id).map_or("", String::as_str);
+        write!(out, "{page}#{id}")
+    })
+}
+
 fn display_label_value(label_value: &str) -> impl '_ + Display {
-    label_value.replace(":", "-").to_case(Case::Kebab)
+    slug(label_value)
 }
 
 fn display_cite_value(label_value: &str) -> impl '_ + Display {
-    label_value.replace(":", "-").to_case(Case::Kebab)
+    slug(label_value)
 }
 
 fn display_theorem_header<'a>(
@@ -218,7 +822,6 @@ fn display_theorem_header<'a>(
             write!(out, " {number}")?;
         }
         if let Some(note) = note {
-            // TODO: Should add style so that this span is not bold.
             write!(out, r#" <span class="theorem-note">("#)?;
             for part in note.iter() {
                 write!(out, "{}", display_paragraph_part(analysis, part))?;
@@ -241,21 +844,40 @@ fn display_title<'a>(title: Option<&'a Paragraph<'a>>) -> impl 'a + Display {
                     use ParagraphPart::*;
                     match part {
                         TextToken(tok) => {
-                            write!(out, "{tok}")?;
+                            write!(out, "{}", display_escaped(tok))?;
                         }
                         InlineWhitespace(ws) => {
-                            if ws.len() > 0 {
+                            if !is_joining_whitespace(ws) {
                                 write!(out, " ")?;
                             }
                         }
+                        Today => {
+                            write!(out, "{}", today())?;
+                        }
+                        ThinSpace => write!(out, r#"<span class="thinspace"></span>"#)?,
+                        Thanks(_) => (),
                         Math(_)
                         | Ref(_)
+                        | Cref { .. }
                         | Emph(_)
                         | Textbf(_)
                         | Textit(_)
+                        | Texttt(_)
+                        | Textsc(_)
+                        | Textsf(_)
+                        | Underline(_)
+                        | Textsuperscript(_)
+                        | Textsubscript(_)
+                        | TextColor { .. }
+                        | Code(_)
+                        | Verbatim(_)
+                        | Href { .. }
                         | Qed
                         | Enumerate(_)
                         | Itemize(_)
+                        | Description(_)
+                        | Center(_)
+                        | Tabular { .. }
                         | Todo
                         | Cite { .. }
                         | Footnote(_) => {
@@ -269,137 +891,662 @@ fn display_title<'a>(title: Option<&'a Paragraph<'a>>) -> impl 'a + Display {
     })
 }
 
-fn display_bib_person<'a>(person: &'a BibPerson<'a>) -> impl 'a + Display {
-    DisplayFn(move |out: &mut Formatter| {
-        for first_name in person.first_names.iter() {
-            use FirstName::*;
-            match first_name {
-                Full(name) => {
-                    write!(out, "{name} ")?;
-                }
-                Abbreviation(abbr) => {
-                    write!(out, "{abbr}. ")?;
-                }
+// The colors that xcolor defines without options, as rgb values.
+const XCOLOR_NAMES: [(&str, &str); 19] = [
+    ("black", "#000000"),
+    ("blue", "#0000ff"),
+    ("brown", "#bf8040"),
+    ("cyan", "#00ffff"),
+    ("darkgray", "#404040"),
+    ("gray", "#808080"),
+    ("green", "#00ff00"),
+    ("lightgray", "#bfbfbf"),
+    ("lime", "#bfff00"),
+    ("magenta", "#ff00ff"),
+    ("olive", "#808000"),
+    ("orange", "#ff8000"),
+    ("pink", "#ffbfbf"),
+    ("purple", "#bf0040"),
+    ("red", "#ff0000"),
+    ("teal", "#008080"),
+    ("violet", "#800080"),
+    ("white", "#ffffff"),
+    ("yellow", "#ffff00"),
+];
+
+// Converts a color of \textcolor to css. Supported are the predefined color names of xcolor and
+// the RGB (0 to 255), rgb (0 to 1), gray (0 to 1) and HTML models. Returns None for other colors,
+// e.g. mixes such as "red!50".
+fn css_color(model: Option<&str>, color: &str) -> Option<String> {
+    let components = |max: f64| -> Option<Vec<u8>> {
+        let values: Option<Vec<f64>> = color.split(',').map(|c| c.trim().parse().ok()).collect();
+        values?
+            .into_iter()
+            .map(|value| {
+                (0.0..=max)
+                    .contains(&value)
+                    .then(|| (value / max * 255.0).round() as u8)
+            })
+            .collect()
+    };
+    match model {
+        None => XCOLOR_NAMES
+            .iter()
+            .find(|(name, _)| *name == color)
+            .map(|(_, rgb)| rgb.to_string()),
+        Some("RGB") | Some("rgb") => {
+            let max = if model == Some("RGB") { 255.0 } else { 1.0 };
+            match components(max)?.as_slice() {
+                [r, g, b] => Some(format!("rgb({r}, {g}, {b})")),
+                _ => None,
             }
         }
-        let last_name = person.last_name;
-        write!(out, "{last_name}")?;
-        Ok(())
-    })
+        Some("gray") => match components(1.0)?.as_slice() {
+            [gray] => Some(format!("rgb({gray}, {gray}, {gray})")),
+            _ => None,
+        },
+        Some("HTML") => {
+            let is_hex = color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit());
+            is_hex.then(|| format!("#{color}"))
+        }
+        Some(_) => None,
+    }
 }
 
-fn display_bib_entry<'a>(entry: &'a BibEntry<'a>) -> impl 'a + Display {
-    let title = entry.title;
-    let authors = &entry.authors;
+// The symbols with which \thanks notes are marked, as in latex.
+const THANKS_SYMBOLS: [&str; 9] = ["*", "†", "‡", "§", "¶", "‖", "**", "††", "‡‡"];
 
-    let id_attr_value = display_cite_value(entry.tag);
+fn display_thanks_marker(number: usize) -> impl Display {
+    DisplayFn(move |out: &mut Formatter| {
+        let symbol = THANKS_SYMBOLS[(number - 1) % THANKS_SYMBOLS.len()];
+        write!(
+            out,
+            r##"<sup><a href="#thanks-{number}">{symbol}</a></sup>"##
+        )
+    })
+}
 
+// The title block shown by \maketitle: The title, the authors and the date, followed by the notes
+// of \thanks commands in the title and the authors.
+fn display_title_block<'a>(doc: &'a Document<'a>, analysis: &'a Analysis<'a>) -> impl 'a + Display {
     DisplayFn(move |out: &mut Formatter| {
+        let title = doc.parts.iter().find_map(|part| match part {
+            DocumentPart::Title(title) => Some(title),
+            _ => None,
+        });
+        let authors = doc.parts.iter().filter_map(|part| match part {
+            DocumentPart::Author(author) => Some(author),
+            _ => None,
+        });
+        // The last \date counts. An empty \date suppresses the date.
+        let date = doc
+            .parts
+            .iter()
+            .rev()
+            .find_map(|part| match part {
+                DocumentPart::Date(date) => Some(date.as_ref()),
+                _ => None,
+            })
+            .flatten();
+        let mut thanks: Vec<&[Paragraph]> = Vec::new();
+
         writedoc! {out, r#"
-            <li id="{id_attr_value}">
+            <header class="title-block">
         "#}?;
-        match authors.as_deref() {
-            None | Some([]) => (),
-            Some([author]) => {
-                write!(out, " {}.", display_bib_person(author))?;
-            }
-            Some([init @ .., before_last, last]) => {
-                for author in init {
-                    write!(out, " {},", display_bib_person(author))?;
+        if let Some(title_parts) = title {
+            let title = display_title(title);
+            write!(out, "<h1>{title}")?;
+            for part in title_parts {
+                if let ParagraphPart::Thanks(note) = part {
+                    thanks.push(note);
+                    write!(out, "{}", display_thanks_marker(thanks.len()))?;
                 }
-                write!(out, " {}", display_bib_person(before_last))?;
-                write!(out, " and {}.", display_bib_person(last))?;
             }
-        };
-        if let Some(title) = title {
-            write!(out, " {title}.")?;
-        }
-
-        // TODO: Only on of journal, booktitle or series should be present.
-        if let Some(journal) = entry.journal {
-            write!(out, " {journal}")?;
-        }
-        if let Some(booktitle) = entry.booktitle {
-            write!(out, " {booktitle}")?;
-        }
-        if let Some(series) = entry.series {
-            write!(out, " {series}")?;
+            writeln!(out, "</h1>")?;
         }
-
-        let has_volume_or_number = match (entry.volume, entry.number) {
-            (Some(volume), Some(number)) => {
-                write!(out, ", {volume}({number})")?;
-                true
-            }
-            (Some(volume), None) => {
-                write!(out, ", {volume}")?;
-                true
-            }
-            (None, Some(number)) => {
-                write!(out, ", ({number})")?;
-                true
-            }
-            (None, None) => false,
-        };
-
-        if let Some(BibPages { first, last }) = entry.pages {
-            if has_volume_or_number {
-                write!(out, ":")?;
-            } else {
-                if last.is_some() {
-                    write!(out, ", pages ")?;
+        for author in authors {
+            writeln!(out, "<div class=\"author\">")?;
+            for part in author {
+                if let ParagraphPart::Thanks(note) = part {
+                    thanks.push(note);
+                    write!(out, "{}", display_thanks_marker(thanks.len()))?;
                 } else {
-                    write!(out, ", page ")?;
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
                 }
             }
-            write!(out, "{first}")?;
-            if let Some(last) = last {
-                write!(out, "–{last}")?;
-            }
+            writeln!(out, "</div>")?;
         }
-
-        match (has_volume_or_number || entry.pages.is_some(), entry.year) {
-            (true, Some(year)) => {
-                write!(out, ", {year}.")?;
-            }
-            (true, None) => {
-                write!(out, ".")?;
+        if let Some(date) = date {
+            writeln!(out, "<div class=\"date\">")?;
+            for part in date {
+                write!(out, "{}", display_paragraph_part(analysis, part))?;
             }
-            (false, Some(year)) => {
-                if entry.journal.is_some() || entry.booktitle.is_some() || entry.series.is_some() {
-                    write!(out, ", {year}.")?;
-                } else {
-                    write!(out, " {year}.")?;
-                }
+            writeln!(out, "</div>")?;
+        }
+        for (i, note) in thanks.into_iter().enumerate() {
+            let number = i + 1;
+            let symbol = THANKS_SYMBOLS[i % THANKS_SYMBOLS.len()];
+            write!(
+                out,
+                "<div class=\"thanks\" id=\"thanks-{number}\">\n<sup>{symbol}</sup>"
+            )?;
+            for part in note.iter().flatten() {
+                write!(out, "{}", display_paragraph_part(analysis, part))?;
             }
-            (false, None) => (),
-        };
-
-        writedoc! {out, r#"</li>"#}?;
-        Ok(())
+            writeln!(out, "</div>")?;
+        }
+        writedoc! {out, r#"
+            </header>
+        "#}
     })
 }
 
-fn write_index(out: &mut impl Write, doc: &Document, analysis: &Analysis) -> Result {
-    let title: Option<&Paragraph> = doc.parts.iter().find_map(|part| {
-        if let DocumentPart::Title(title) = part {
-            Some(title)
-        } else {
+fn display_bib_person<'a>(
+    person: &'a BibPerson<'a>,
+    name_format: BibNameFormat,
+    is_first_author: bool,
+) -> impl 'a + Display {
+    DisplayFn(move |out: &mut Formatter| {
+        use FirstName::*;
+        let first_names =
+            person
+                .first_names
+                .iter()
+                .format_with(" ", |first_name, f| match first_name {
+                    Full(name) => f(&display_escaped(&replace_accents(name))),
+                    Abbreviation(abbr) => f(&format_args!(
+                        "{}.",
+                        display_escaped(&replace_accents(abbr))
+                    )),
+                });
+        let initials = person.first_names.iter().format_with(" ", |first_name, f| {
+            let (Full(name) | Abbreviation(name)) = first_name;
+            let name = replace_accents(name);
+            let initial_len = name.chars().next().map_or(0, char::len_utf8);
+            let initial = display_escaped(&name[..initial_len]).to_string();
+            f(&format_args!("{initial}."))
+        });
+        let last_name = replace_accents(person.last_name);
+        let last_name = display_escaped(&last_name);
+
+        use BibNameFormat::*;
+        match (name_format, is_first_author) {
+            _ if person.first_names.is_empty() => write!(out, "{last_name}"),
+            (FirstLast, _) | (LastFirst, false) => write!(out, "{first_names} {last_name}"),
+            (LastFirst, true) => write!(out, "{last_name}, {first_names}"),
+            (LastInitials, _) => write!(out, "{last_name}, {initials}"),
+        }
+    })
+}
+
+// Joins items as "A", "A and B" or "A, B and C".
+fn join_with_and(items: &[String]) -> String {
+    let mut result = String::new();
+    for (i, item) in items.iter().enumerate() {
+        let separator = match i {
+            0 => "",
+            _ if i + 1 == items.len() => " and ",
+            _ => ", ",
+        };
+        result.push_str(separator);
+        result.push_str(item);
+    }
+    result
+}
+
+// The plural of an English noun, e.g. "Theorems" or "Corollaries".
+fn pluralize(noun: &str) -> String {
+    let mut chars = noun.chars().rev();
+    match (chars.next(), chars.next()) {
+        (Some('y'), Some(c)) if !"aeiou".contains(c) => format!("{}ies", &noun[..noun.len() - 1]),
+        (Some('s' | 'x'), _) | (Some('h'), Some('c' | 's')) => format!("{noun}es"),
+        _ => format!("{noun}s"),
+    }
+}
+
+// Splits a number such as "2.3" or "(4)" into the text before and after its last integer, and the
+// integer, so that consecutive numbers can be recognized.
+fn split_last_integer(number: &str) -> Option<(&str, u64, &str)> {
+    let end = number.rfind(|c: char| c.is_ascii_digit())? + 1;
+    let start = number[..end]
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |pos| pos + 1);
+    let integer = number[start..end].parse().ok()?;
+    Some((&number[..start], integer, &number[end..]))
+}
+
+fn are_consecutive(lhs: &str, rhs: &str) -> bool {
+    match (split_last_integer(lhs), split_last_integer(rhs)) {
+        (Some((lhs_prefix, lhs, lhs_suffix)), Some((rhs_prefix, rhs, rhs_suffix))) => {
+            lhs_prefix == rhs_prefix && lhs_suffix == rhs_suffix && lhs + 1 == rhs
+        }
+        _ => false,
+    }
+}
+
+// Displays \cref{...} or \Cref{...}. The ids are grouped by the type of what they refer to, in
+// the order in which the types first appear, e.g. "Theorems 1 and 2 and Lemma 3", and runs of at
+// least three consecutive numbers are collapsed into ranges, e.g. "Theorems 1–3".
+fn display_cref<'a>(
+    analysis: &'a Analysis<'a>,
+    ids: &'a [&'a str],
+    capitalized: bool,
+) -> impl 'a + Display {
+    DisplayFn(move |out: &mut Formatter| {
+        let with_case = |type_name: &str| {
+            if capitalized {
+                return type_name.to_string();
+            }
+            let mut chars = type_name.chars();
+            let first = chars.next().into_iter().flat_map(char::to_lowercase);
+            first.chain(chars).collect()
+        };
+        let link = |id: &str, text: &str| {
+            let href = display_href(analysis, display_label_value(id));
+            format!("<a href=\"{href}\">{text}</a>")
+        };
+
+        if let [id] = ids {
+            let text = match analysis.cref_targets.get(id) {
+                None => "???".to_string(),
+                Some(target) => format!("{} {}", with_case(&target.type_name), target.number),
+            };
+            return write!(out, "{}", link(id, &text));
+        }
+
+        let number = |id: &str| {
+            let target = analysis.cref_targets.get(id);
+            target.map_or("???", |target| target.number.as_str())
+        };
+
+        // Unresolved ids form groups of their own.
+        let mut groups: Vec<(Option<&str>, Vec<&str>)> = Vec::new();
+        for id in ids.iter().copied() {
+            let Some(target) = analysis.cref_targets.get(id) else {
+                groups.push((None, vec![id]));
+                continue;
+            };
+            let type_name = Some(target.type_name.as_str());
+            match groups.iter_mut().find(|(name, _)| *name == type_name) {
+                Some((_, group_ids)) => group_ids.push(id),
+                None => groups.push((type_name, vec![id])),
+            }
+        }
+
+        let groups: Vec<String> = groups
+            .into_iter()
+            .map(|(type_name, ids)| {
+                let mut runs: Vec<&[&str]> = Vec::new();
+                let mut run_start = 0;
+                for i in 1..=ids.len() {
+                    if i == ids.len() || !are_consecutive(number(ids[i - 1]), number(ids[i])) {
+                        runs.push(&ids[run_start..i]);
+                        run_start = i;
+                    }
+                }
+                let mut numbers = Vec::new();
+                for run in runs {
+                    match run {
+                        [first, .., last] if run.len() >= 3 => {
+                            let first = link(first, number(first));
+                            let last = link(last, number(last));
+                            numbers.push(format!("{first}–{last}"));
+                        }
+                        _ => numbers.extend(run.iter().map(|id| link(id, number(id)))),
+                    }
+                }
+                let numbers = join_with_and(&numbers);
+                match type_name {
+                    None => numbers,
+                    Some(type_name) if ids.len() == 1 => {
+                        format!("{} {numbers}", with_case(type_name))
+                    }
+                    Some(type_name) => format!("{} {numbers}", with_case(&pluralize(type_name))),
+                }
+            })
+            .collect();
+        write!(out, "{}", join_with_and(&groups))
+    })
+}
+
+// Displays a list of persons as "A", "A and B" or "A, B and C".
+fn display_bib_persons<'a>(
+    persons: &'a [BibPerson<'a>],
+    name_format: BibNameFormat,
+    starts_author_list: bool,
+) -> impl 'a + Display {
+    DisplayFn(move |out: &mut Formatter| {
+        for (i, person) in persons.iter().enumerate() {
+            let separator = match i {
+                0 => "",
+                _ if i + 1 == persons.len() => " and ",
+                _ => ", ",
+            };
+            let person = display_bib_person(person, name_format, starts_author_list && i == 0);
+            write!(out, "{separator}{person}")?;
+        }
+        Ok(())
+    })
+}
+
+fn display_bib_entry<'a>(
+    entry: &'a BibEntry<'a>,
+    name_format: BibNameFormat,
+    options: &EmitOptions,
+) -> impl 'a + Display {
+    let title = entry.title;
+    let authors = &entry.authors;
+    let max_authors = options.max_authors;
+    let et_al_authors = options.et_al_authors;
+
+    let id_attr_value = display_cite_value(entry.tag);
+
+    DisplayFn(move |out: &mut Formatter| {
+        let title = title.map(display_escaped);
+        let journal = entry.journal.map(display_escaped);
+        let booktitle = entry.booktitle.map(display_escaped);
+        let series = entry.series.map(display_escaped);
+        let volume = entry.volume.map(display_escaped);
+        let number = entry.number.map(display_escaped);
+        // The month is shown before the year if it is recognized, e.g. "July 2020".
+        let year = entry.year.map(|year| {
+            let year = display_escaped(year);
+            match entry.month.and_then(month_name) {
+                Some(month) => format!("{month} {year}"),
+                None => year.to_string(),
+            }
+        });
+
+        use BibEntryType::*;
+        let editors = entry
+            .editors
+            .as_deref()
+            .filter(|editors| !editors.is_empty());
+        let editors_abbreviation = match editors {
+            Some([_]) => "Ed.",
+            _ => "Eds.",
+        };
+
+        writedoc! {out, r#"
+            <li id="{id_attr_value}">
+        "#}?;
+        if let Some(authors) = authors.as_deref().filter(|authors| !authors.is_empty()) {
+            let et_al = max_authors.is_some_and(|max_authors| authors.len() > max_authors);
+            let mut author_list = String::new();
+            if et_al {
+                let shown_authors = &authors[..et_al_authors.clamp(1, authors.len())];
+                for (i, author) in shown_authors.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ", " };
+                    let author = display_bib_person(author, name_format, i == 0);
+                    write!(author_list, "{separator}{author}")?;
+                }
+                write!(author_list, " et al.")?;
+            } else {
+                write!(
+                    author_list,
+                    "{}",
+                    display_bib_persons(authors, name_format, true)
+                )?;
+            }
+            // Names in some formats end with an abbreviation already.
+            if !author_list.ends_with('.') {
+                author_list.push('.');
+            }
+            write!(out, " {author_list}")?;
+        } else if let (Book, Some(editors)) = (&entry.entry_type, editors) {
+            // Edited books without authors are listed under their editors.
+            let editors = display_bib_persons(editors, name_format, true);
+            write!(out, " {editors} ({editors_abbreviation}).")?;
+        }
+        // The title links to the doi if there is one, and otherwise to the url.
+        let doi = entry.doi.map(normalize_doi);
+        let doi_url = doi.map(|doi| format!("https://doi.org/{doi}"));
+        let link = doi_url.as_deref().or(entry.url).map(display_escaped);
+        match (title, link) {
+            (Some(title), Some(link)) => write!(out, r#" <a href="{link}">{title}</a>."#)?,
+            (Some(title), None) => write!(out, " {title}.")?,
+            (None, _) => (),
+        }
+
+        // TODO: Only on of journal, booktitle or series should be present.
+        if let Some(journal) = journal {
+            write!(out, " {journal}")?;
+        }
+        match (&entry.entry_type, editors, booktitle) {
+            (Incollection, Some(editors), Some(booktitle)) => {
+                let editors = display_bib_persons(editors, name_format, false);
+                write!(out, " In {editors} ({editors_abbreviation}), {booktitle}")?;
+            }
+            (_, _, Some(booktitle)) => write!(out, " {booktitle}")?,
+            (_, _, None) => (),
+        }
+        if let Some(series) = series {
+            write!(out, " {series}")?;
+        }
+
+        // Theses and reports are described by their kind, e.g. "PhD thesis", followed by the
+        // school or institution. The number of a report is part of its description.
+        let default_kind = match entry.entry_type {
+            Phdthesis => Some("PhD thesis"),
+            Mastersthesis => Some("Master's thesis"),
+            Techreport => Some("Technical report"),
+            Misc | Article | Book | Inproceedings | Thesis | Incollection | Online => None,
+        };
+        let (number, report_number) = match entry.entry_type {
+            Techreport => (None, number),
+            _ => (number, None),
+        };
+        let kind = entry.kind.or(default_kind).map(display_escaped);
+        let organization = entry.school.or(entry.institution).map(display_escaped);
+        if let Some(kind) = &kind {
+            write!(out, " {kind}")?;
+            if let Some(report_number) = report_number {
+                write!(out, " {report_number}")?;
+            }
+        }
+        match (&kind, organization) {
+            (Some(_), Some(organization)) => write!(out, ", {organization}")?,
+            (None, Some(organization)) => write!(out, " {organization}")?,
+            (_, None) => (),
+        }
+        if let Some(howpublished) = entry.howpublished.map(display_escaped) {
+            write!(out, " {howpublished}")?;
+        }
+        let has_venue = entry.journal.is_some()
+            || entry.booktitle.is_some()
+            || entry.series.is_some()
+            || kind.is_some()
+            || entry.school.is_some()
+            || entry.institution.is_some()
+            || entry.howpublished.is_some();
+
+        let has_volume_or_number = match (volume, number) {
+            (Some(volume), Some(number)) => {
+                write!(out, ", {volume}({number})")?;
+                true
+            }
+            (Some(volume), None) => {
+                write!(out, ", {volume}")?;
+                true
+            }
+            (None, Some(number)) => {
+                write!(out, ", ({number})")?;
+                true
+            }
+            (None, None) => false,
+        };
+
+        if let Some(BibPages { first, last }) = entry.pages {
+            if has_volume_or_number {
+                write!(out, ":")?;
+            } else {
+                if last.is_some() {
+                    write!(out, ", pages ")?;
+                } else {
+                    write!(out, ", page ")?;
+                }
+            }
+            write!(out, "{}", display_escaped(first))?;
+            if let Some(last) = last {
+                write!(out, "–{}", display_escaped(last))?;
+            }
+        }
+
+        match (has_volume_or_number || entry.pages.is_some(), year) {
+            (true, Some(year)) => {
+                write!(out, ", {year}.")?;
+            }
+            (true, None) => {
+                write!(out, ".")?;
+            }
+            (false, Some(year)) => {
+                if has_venue {
+                    write!(out, ", {year}.")?;
+                } else {
+                    write!(out, " {year}.")?;
+                }
+            }
+            (false, None) => (),
+        };
+
+        if let (Some(doi), Some(doi_url)) = (doi, &doi_url) {
+            let doi = display_escaped(doi);
+            let doi_url = display_escaped(doi_url);
+            write!(out, r#" doi:<a href="{doi_url}">{doi}</a>."#)?;
+        }
+        // The url is shown separately unless the title links to it.
+        let title_links_url = entry.title.is_some() && doi_url.is_none();
+        if let (Some(url), false) = (entry.url, title_links_url) {
+            let url = display_escaped(url);
+            write!(out, r#" URL <a href="{url}" class="url">{url}</a>."#)?;
+        }
+        if let Some(isbn) = entry.isbn.map(display_escaped) {
+            write!(out, " ISBN {isbn}.")?;
+        }
+
+        writedoc! {out, r#"</li>"#}?;
+        Ok(())
+    })
+}
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// Formats the date that is the given number of days after 1970-01-01 as \today does, e.g.
+// "July 4, 2020".
+fn format_date(days: i64) -> String {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let month = MONTHS[(month - 1) as usize];
+    format!("{month} {day}, {year}")
+}
+
+// The current date as \today displays it.
+fn today() -> String {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format_date((since_epoch.as_secs() / (24 * 60 * 60)) as i64)
+}
+
+// Returns the full name of a month given as a number, e.g. "7", or by its (abbreviated) name,
+// e.g. "jul". Month macros in bib files have already been expanded to full names.
+fn month_name(month: &str) -> Option<&'static str> {
+    let month = month.trim();
+    if let Ok(number) = month.parse::<usize>() {
+        return MONTHS.get(number.checked_sub(1)?).copied();
+    }
+    if month.len() < 3 {
+        return None;
+    }
+    let month = month.trim_end_matches('.').to_lowercase();
+    MONTHS
+        .into_iter()
+        .find(|name| name.to_lowercase().starts_with(&month))
+}
+
+// Strips the resolver or "doi:" prefix that some bib files include in doi values.
+fn normalize_doi(doi: &str) -> &str {
+    [
+        "https://doi.org/",
+        "http://doi.org/",
+        "https://dx.doi.org/",
+        "http://dx.doi.org/",
+        "doi:",
+    ]
+    .iter()
+    .find_map(|prefix| doi.strip_prefix(prefix))
+    .unwrap_or(doi)
+}
+
+fn write_index(
+    out: &mut impl Write,
+    doc: &Document,
+    analysis: &Analysis,
+    options: &EmitOptions,
+) -> Result {
+    write_page(out, doc, analysis, options, &doc.parts, "")
+}
+
+// Writes an html page containing the given parts of the document. The links are shown after the
+// parts, before the footnotes.
+fn write_page(
+    out: &mut impl Write,
+    doc: &Document,
+    analysis: &Analysis,
+    options: &EmitOptions,
+    parts: &[DocumentPart],
+    links: impl Display,
+) -> Result {
+    let title: Option<&Paragraph> = doc.parts.iter().find_map(|part| {
+        if let DocumentPart::Title(title) = part {
+            Some(title)
+        } else {
             None
         }
     });
 
-    let head = display_head(display_title(title));
+    let head = display_head(display_title(title), options);
+    let body_class_attr = if options.draft {
+        r#" class="draft""#
+    } else {
+        ""
+    };
     writedoc! {out, r#"
         <!DOCTYPE html>
         <html lang="en">
         {head}
-        <body>
+        <body{body_class_attr}>
     "#}?;
 
     let config = &doc.config;
 
-    for part in doc.parts.iter() {
+    for part in parts.iter() {
         use DocumentPart::*;
         match part {
             FreeParagraph(p) => {
@@ -407,18 +1554,28 @@ fn write_index(out: &mut impl Write, doc: &Document, analysis: &Analysis) -> Res
             }
             Title(_) => (),
             Author(_) => (),
-            Date() => (),
+            Date(_) => (),
             Maketitle() => {
-                if title.is_some() {
-                    let title = display_title(title);
-                    writedoc! {out, r#"
-                        <h1>{title}</h1>
-                    "#}?;
+                write!(out, "{}", display_title_block(doc, analysis))?;
+            }
+            Section { name, .. } => {
+                let id = &analysis.heading_ids[&std::ptr::addr_of!(*part)];
+                write!(out, "<h2 id=\"{id}\">\n")?;
+                let number = analysis
+                    .doc_part_numbering
+                    .get(&std::ptr::addr_of!(*part))
+                    .map(|s| s.as_str());
+                if let Some(number) = number {
+                    write!(out, "{number} ")?;
                 }
+                for part in name {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                write!(out, "{}</h2>\n", display_permalink(id))?;
             }
-            Section { name, label } => {
-                let label = display_label_id_attr(*label);
-                write!(out, "<h2{label}>\n")?;
+            Subsection { name, .. } => {
+                let id = &analysis.heading_ids[&std::ptr::addr_of!(*part)];
+                write!(out, "<h3 id=\"{id}\">\n")?;
                 let number = analysis
                     .doc_part_numbering
                     .get(&std::ptr::addr_of!(*part))
@@ -429,11 +1586,11 @@ fn write_index(out: &mut impl Write, doc: &Document, analysis: &Analysis) -> Res
                 for part in name {
                     write!(out, "{}", display_paragraph_part(analysis, part))?;
                 }
-                write!(out, "</h2>\n")?;
+                write!(out, "{}</h3>\n", display_permalink(id))?;
             }
-            Subsection { name, label } => {
-                let label = display_label_id_attr(*label);
-                write!(out, "<h3{label}>\n")?;
+            Subsubsection { name, .. } => {
+                let id = &analysis.heading_ids[&std::ptr::addr_of!(*part)];
+                writeln!(out, "<h4 id=\"{id}\">")?;
                 let number = analysis
                     .doc_part_numbering
                     .get(&std::ptr::addr_of!(*part))
@@ -444,7 +1601,24 @@ fn write_index(out: &mut impl Write, doc: &Document, analysis: &Analysis) -> Res
                 for part in name {
                     write!(out, "{}", display_paragraph_part(analysis, part))?;
                 }
-                write!(out, "</h3>\n")?;
+                writeln!(out, "{}</h4>", display_permalink(id))?;
+            }
+            ParagraphHeading { name, label, body } | SubparagraphHeading { name, label, body } => {
+                let label = display_label_id_attr(*label);
+                writedoc! {out, r#"
+                    <div class="paragraph">
+                    <span class="paragraph-heading"{label}>
+                "#}?;
+                for part in name {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                writeln!(out, "</span>")?;
+                for part in body {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                writedoc! {out, r#"
+                    </div>
+                "#}?;
             }
             Abstract(ps) => {
                 write!(out, "<h2>Abstract</h2>\n")?;
@@ -452,6 +1626,14 @@ fn write_index(out: &mut impl Write, doc: &Document, analysis: &Analysis) -> Res
                     write!(out, "{}", display_paragraph(analysis, p))?;
                 }
             }
+            Keywords(keywords) => {
+                writeln!(out, r#"<div class="keywords">"#)?;
+                write!(out, "Keywords: ")?;
+                for part in keywords {
+                    write!(out, "{}", display_paragraph_part(analysis, part))?;
+                }
+                writeln!(out, "</div>")?;
+            }
             TheoremLike {
                 tag,
                 note,
@@ -507,29 +1689,34 @@ fn write_index(out: &mut impl Write, doc: &Document, analysis: &Analysis) -> Res
                     <div class="paragraph">
                     <i class="proof">Proof.</i>
                 "#}?;
-                let mut ps = ps.iter();
-                if let Some(parag) = ps.next() {
+                for (i, parag) in ps.iter().enumerate() {
+                    if i != 0 {
+                        writedoc! {out, r#"
+                            </div>
+                            <div class="paragraph">
+                        "#}?;
+                    }
                     for part in parag {
                         write!(out, "{}", display_paragraph_part(analysis, part))?;
                     }
                 }
-                writedoc! {out, r#"
-                    </div>
-                "#}?;
-                for p in ps {
-                    write!(out, "{}", display_paragraph(analysis, p))?;
+                // If the proof ends with display math, then the qed symbol is part of the
+                // equation row instead.
+                if ps.last().and_then(trailing_display_math).is_none() {
+                    write!(out, r#"<span class="qed">∎</span>"#)?;
                 }
                 writedoc! {out, r#"
                     </div>
+                    </div>
                 "#}?;
             }
-            Bibliography => {
+            Bibliography(_) => {
                 writedoc! {out, r#"
                     <h2>Bibliography</h2>
                     <ol class="bibliography">
                 "#}?;
                 for entry in analysis.bib_entries.iter().copied() {
-                    let entry = display_bib_entry(entry);
+                    let entry = display_bib_entry(entry, analysis.bib_name_format, options);
                     writedoc! {out, r#"
                         {entry}
                     "#}?;
@@ -538,7 +1725,134 @@ fn write_index(out: &mut impl Write, doc: &Document, analysis: &Analysis) -> Res
                     </ol>
                 "#}?;
             }
+            BibliographyStyle(_) => (),
+            Figure {
+                label,
+                graphics,
+                caption,
+                subfigures,
+            } => {
+                let label = display_label_id_attr(*label);
+                let figure_number = analysis.doc_part_numbering.get(&addr_of!(*part));
+                writeln!(out, "<figure{label}>")?;
+                write_figure_images(out, graphics)?;
+                // Subfigures are laid out side by side, each with its own caption.
+                if !subfigures.is_empty() {
+                    writeln!(out, r#"<div class="subfigures">"#)?;
+                    for subfigure in subfigures {
+                        let label = display_label_id_attr(subfigure.label);
+                        writeln!(out, r#"<figure{label} class="subfigure">"#)?;
+                        write_figure_images(out, &subfigure.graphics)?;
+                        if let Some(caption) = &subfigure.caption {
+                            write!(out, "<figcaption>")?;
+                            let letter = analysis.subfigure_letters.get(&addr_of!(*subfigure));
+                            if let Some(letter) = letter {
+                                write!(out, "({letter}) ")?;
+                            }
+                            for part in caption {
+                                write!(out, "{}", display_paragraph_part(analysis, part))?;
+                            }
+                            writeln!(out, "</figcaption>")?;
+                        }
+                        writeln!(out, "</figure>")?;
+                    }
+                    writeln!(out, "</div>")?;
+                }
+                if let Some(caption) = caption {
+                    write!(out, "<figcaption>")?;
+                    if let Some(number) = figure_number {
+                        write!(out, "Figure {number}: ")?;
+                    }
+                    for part in caption {
+                        write!(out, "{}", display_paragraph_part(analysis, part))?;
+                    }
+                    writeln!(out, "</figcaption>")?;
+                }
+                writeln!(out, "</figure>")?;
+            }
+            Table {
+                label,
+                tabulars,
+                caption,
+            } => {
+                let label = display_label_id_attr(*label);
+                writeln!(out, r#"<figure{label} class="table">"#)?;
+                // As is usual for tables, the caption is shown above the content.
+                if let Some(caption) = caption {
+                    write!(out, "<figcaption>")?;
+                    if let Some(number) = analysis.doc_part_numbering.get(&addr_of!(*part)) {
+                        write!(out, "Table {number}: ")?;
+                    }
+                    for part in caption {
+                        write!(out, "{}", display_paragraph_part(analysis, part))?;
+                    }
+                    writeln!(out, "</figcaption>")?;
+                }
+                for tabular in tabulars {
+                    write!(out, "{}", display_paragraph_part(analysis, tabular))?;
+                }
+                writeln!(out, "</figure>")?;
+            }
+            ListOfFigures => {
+                let figures = doc.parts.iter().filter_map(|part| match part {
+                    Figure {
+                        label,
+                        caption: Some(caption),
+                        ..
+                    } => Some((part, *label, caption)),
+                    _ => None,
+                });
+                write_list_of_floats(out, analysis, "Figure", figures)?;
+            }
+            ListOfTables => {
+                let tables = doc.parts.iter().filter_map(|part| match part {
+                    Table {
+                        label,
+                        caption: Some(caption),
+                        ..
+                    } => Some((part, *label, caption)),
+                    _ => None,
+                });
+                write_list_of_floats(out, analysis, "Table", tables)?;
+            }
+            Appendix | SetCounter { .. } | Nocite(_) => (),
+        }
+    }
+
+    write!(out, "{links}")?;
+
+    let footnote_numbers: HashSet<usize> = NodeLists::for_parts(parts)
+        .footnotes
+        .iter()
+        .map(|footnote| analysis.footnote_numbering[&addr_of!(**footnote)])
+        .collect();
+    if !footnote_numbers.is_empty() {
+        // Footnotes are numbered across pages.
+        let start_attr = match footnote_numbers.iter().min() {
+            Some(1) | None => String::new(),
+            Some(start) => format!(r#" start="{start}""#),
+        };
+        writedoc! {out, r#"
+            <ol{start_attr} class="footnotes">
+        "#}?;
+        for (i, content) in analysis.footnotes.iter().enumerate() {
+            let number = i + 1;
+            if !footnote_numbers.contains(&number) {
+                continue;
+            }
+            writeln!(out, r#"<li id="fn{number}">"#)?;
+            for paragraph in content.iter() {
+                write!(out, "{}", display_paragraph(analysis, paragraph))?;
+            }
+            writeln!(
+                out,
+                r##"<a class="footnote-back" href="#fnref{number}">↩</a>"##
+            )?;
+            writeln!(out, "</li>")?;
         }
+        writedoc! {out, r#"
+            </ol>
+        "#}?;
     }
     writedoc! {out, r#"
         </body>
@@ -554,14 +1868,34 @@ const STYLE: &'static str = indoc! {r#"
     }
     body {
         font-family: "Computer Modern Serif", serif;
-        max-width: 600px;
+        max-width: var(--max-width);
         margin: auto;
     }
 
+    .full-bleed {
+        width: min(var(--full-bleed-max-width), calc(100vw - 1em));
+        position: relative;
+        left: 50%;
+        transform: translateX(-50%);
+    }
+
     h4 {
         display: inline;
     }
 
+    body.draft::before {
+        content: "DRAFT";
+        position: fixed;
+        top: 0.5em;
+        right: 0.5em;
+        padding: 0.25em 0.5em;
+        border: 2px solid #c00;
+        color: #c00;
+        font-weight: bold;
+        opacity: 0.7;
+        pointer-events: none;
+    }
+
     .theorem-like {
         margin-top: 0.5em;
         margin-bottom: 0.5em;
@@ -579,16 +1913,39 @@ const STYLE: &'static str = indoc! {r#"
         font-weight: normal;
     }
 
+    .theorem-note {
+        font-weight: normal;
+    }
+
     .proof {
         margin-top: 0.5em;
         margin-bottom: 0.5em;
     }
 
+    .qed {
+        float: right;
+    }
+
     .inline-math {
         vertical-align: baseline;
         position: relative;
     }
 
+    .inline-math-html {
+        white-space: nowrap;
+    }
+
+    .math-error {
+        color: #b00020;
+        border: 1px dashed #b00020;
+        padding: 0 0.2em;
+    }
+
+    .display-math-row > .math-error {
+        margin: auto;
+        white-space: pre-wrap;
+    }
+
     .display-math-row {
         display: flex;
         flex-direction: row;
@@ -604,7 +1961,6 @@ const STYLE: &'static str = indoc! {r#"
     .display-math-row > span {
         margin: auto 0;
         display: inline-flex;
-        flex-direction: row-reverse;
         padding-left: 1em;
     }
 
@@ -612,32 +1968,527 @@ const STYLE: &'static str = indoc! {r#"
         visibility: hidden;
     }
 
-    .bibliography {
-      counter-reset: list;
+    .display-math-row .qed {
+        padding-left: 0.5em;
     }
 
-    .bibliography > li {
-      counter-increment: list;
+    .display-math-row.numbers-left > span:first-child {
+        visibility: visible;
+        padding-left: 0;
+        padding-right: 1em;
     }
 
-    .bibliography > li::marker {
+    .display-math-row.numbers-left > span:last-child {
+        visibility: hidden;
+    }
+
+    .display-math-row.numbers-left > span:first-child .qed {
+        visibility: hidden;
+    }
+
+    .display-math-row.numbers-left > span:last-child .qed {
+        visibility: visible;
+    }
+
+    .display-math-row > span.align-side {
+        align-self: stretch;
+        align-items: flex-end;
+    }
+
+    .align-numbers {
+        align-self: stretch;
+        display: flex;
+        flex-direction: column;
+        justify-content: space-around;
+    }
+
+    .align-numbers > span:empty::before {
+        content: "\00a0";
+    }
+
+    .tabular {
+        border-collapse: collapse;
+        margin: 0.5em auto;
+    }
+
+    .tabular td {
+        padding: 0.1em 0.5em;
+    }
+
+    .tabular tr.border-top > td {
+        border-top: 1px solid black;
+    }
+
+    .tabular tr.border-bottom > td {
+        border-bottom: 1px solid black;
+    }
+
+    figure {
+        margin: 1em 0;
+        text-align: center;
+    }
+
+    figure > img {
+        max-width: 100%;
+    }
+
+    .url {
+        word-break: break-all;
+    }
+
+    .center {
+        text-align: center;
+    }
+
+    .thinspace {
+        margin-left: 0.1667em;
+    }
+
+    .keywords {
+        font-style: italic;
+        margin: 1em 0;
+    }
+
+    .subfigures {
+        display: flex;
+        flex-wrap: wrap;
+        justify-content: center;
+        align-items: flex-end;
+        gap: 1em;
+    }
+
+    .subfigures > figure {
+        margin: 0;
+    }
+
+    figcaption {
+        margin-top: 0.5em;
+    }
+
+    pre {
+        overflow-x: auto;
+    }
+
+    .small-caps {
+        font-variant: small-caps;
+    }
+
+    .sans {
+        font-family: sans-serif;
+    }
+
+    .permalink {
+        visibility: hidden;
+        text-decoration: none;
+        color: inherit;
+    }
+
+    :hover > .permalink {
+        visibility: visible;
+    }
+
+    .page-navigation {
+        display: flex;
+        justify-content: space-between;
+        margin-top: 2em;
+    }
+
+    .title-block {
+        text-align: center;
+        margin-bottom: 2em;
+    }
+
+    .thanks {
+        font-size: smaller;
+        text-align: left;
+    }
+
+    .paragraph-heading {
+        font-weight: bold;
+        margin-right: 0.5em;
+    }
+
+    ol ol > li::marker {
+        content: "(" counter(list-item, lower-alpha) ") ";
+    }
+
+    ol ol ol > li::marker {
+        content: counter(list-item, lower-roman) ". ";
+    }
+
+    ol ol ol ol > li::marker {
+        content: counter(list-item, upper-alpha) ". ";
+    }
+
+    .list-of-figures,
+    .list-of-tables {
+        list-style: none;
+        padding-left: 0;
+    }
+
+    .footnotes {
+        margin-top: 2em;
+        padding-top: 0.5em;
+        border-top: 1px solid #ccc;
+        font-size: 0.9em;
+    }
+
+    .bibliography {
+      counter-reset: list;
+    }
+
+    .bibliography > li {
+      counter-increment: list;
+    }
+
+    .bibliography > li::marker {
       content: "["counter(list)"] ";
     }"#};
 
-pub fn emit(root: &Path, doc: &Document, analysis: &Analysis) {
-    fs::create_dir_all(root).unwrap();
+fn display_style(options: &EmitOptions) -> impl '_ + Display {
+    DisplayFn(move |out: &mut Formatter| {
+        let max_width_px = options.max_width_px;
+        let full_bleed_max_width_px = options.full_bleed_max_width_px;
+        writedoc! {out, r#"
+            :root {{
+                --max-width: {max_width_px}px;
+                --full-bleed-max-width: {full_bleed_max_width_px}px;
+            }}
+            {STYLE}"#}?;
+        if let Some(extra_css) = &options.extra_css {
+            write!(out, "\n\n{extra_css}")?;
+        }
+        Ok(())
+    })
+}
 
+pub fn index_html(doc: &Document, analysis: &Analysis, options: &EmitOptions) -> String {
     let mut index_src = String::new();
-    write_index(&mut index_src, &doc, &analysis).unwrap();
+    write_index(&mut index_src, doc, analysis, options).unwrap();
+    index_src
+}
 
-    let index_path = root.join("index.html");
-    let mut index_file = std::fs::OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(index_path)
-        .unwrap();
-    write!(index_file, "{}", index_src).unwrap();
+fn display_table_of_contents<'a>(
+    analysis: &'a Analysis<'a>,
+    pages: &'a [&'a [DocumentPart<'a>]],
+) -> impl 'a + Display {
+    DisplayFn(move |out: &mut Formatter| {
+        writedoc! {out, r#"
+            <nav class="table-of-contents">
+            <h2>Contents</h2>
+            <ol>
+        "#}?;
+        // Every page but the first begins with a section.
+        for (index, page) in pages.iter().enumerate().skip(1) {
+            let Some(part @ DocumentPart::Section { name, .. }) = page.first() else {
+                continue;
+            };
+            let file_name = page_file_name(index);
+            write!(out, r#"<li><a href="{file_name}">"#)?;
+            if let Some(number) = analysis.doc_part_numbering.get(&addr_of!(*part)) {
+                write!(out, "{number} ")?;
+            }
+            for part in name {
+                write!(out, "{}", display_paragraph_part(analysis, part))?;
+            }
+            writeln!(out, "</a></li>")?;
+        }
+        writedoc! {out, r#"
+            </ol>
+            </nav>
+        "#}
+    })
+}
+
+fn display_page_navigation(index: usize, page_count: usize) -> impl Display {
+    DisplayFn(move |out: &mut Formatter| {
+        writeln!(out, r#"<nav class="page-navigation">"#)?;
+        let previous = page_file_name(index - 1);
+        writeln!(out, r#"<a href="{previous}" rel="prev">Previous</a>"#)?;
+        writeln!(out, r#"<a href="index.html">Contents</a>"#)?;
+        if index + 1 < page_count {
+            let next = page_file_name(index + 1);
+            writeln!(out, r#"<a href="{next}" rel="next">Next</a>"#)?;
+        }
+        writeln!(out, "</nav>")
+    })
+}
+
+// The pages of the output if it is split into pages, as pairs of file name and html. See
+// split_pages.
+pub fn split_pages_html(
+    doc: &Document,
+    analysis: &Analysis,
+    options: &EmitOptions,
+) -> Vec<(String, String)> {
+    let pages = split_pages(&doc.parts);
+    let mut result = Vec::new();
+    for (index, parts) in pages.iter().enumerate() {
+        let mut html = String::new();
+        if index == 0 {
+            let toc = display_table_of_contents(analysis, &pages);
+            write_page(&mut html, doc, analysis, options, parts, toc).unwrap();
+        } else {
+            let navigation = display_page_navigation(index, pages.len());
+            write_page(&mut html, doc, analysis, options, parts, navigation).unwrap();
+        }
+        result.push((page_file_name(index), html));
+    }
+    result
+}
+
+pub fn style_css(options: &EmitOptions) -> String {
+    display_style(options).to_string()
+}
+
+// The directory in the output directory into which the images of figures are copied.
+pub const FIGURE_OUT_DIR: &str = "figures";
+
+// The path of the copy of an image included with \includegraphics relative to the output
+// directory. The directories of the image relative to the tex file are kept, so that images with
+// the same file name don't overwrite each other; ".." becomes "parent" to stay inside the figure
+// directory.
+fn figure_image_path(path: &str) -> String {
+    let mut result = FIGURE_OUT_DIR.to_string();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => {
+                result.push('/');
+                result.push_str(&name.to_string_lossy());
+            }
+            Component::ParentDir => result.push_str("/parent"),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => (),
+        }
+    }
+    result
+}
+
+// Writes the list of figures or tables, given the numbered floats with their labels and captions.
+// Nothing is written if there are no such floats.
+fn write_list_of_floats<'a>(
+    out: &mut impl Write,
+    analysis: &Analysis,
+    type_name: &str,
+    floats: impl Iterator<Item = (&'a DocumentPart<'a>, Option<&'a str>, &'a Paragraph<'a>)>,
+) -> Result {
+    let mut floats = floats.peekable();
+    if floats.peek().is_none() {
+        return Ok(());
+    }
+    let class = format!("list-of-{}s", type_name.to_lowercase());
+    writedoc! {out, r#"
+        <h2>List of {type_name}s</h2>
+        <ol class="{class}">
+    "#}?;
+    for (part, label, caption) in floats {
+        let number = &analysis.doc_part_numbering[&addr_of!(*part)];
+        match label {
+            Some(label) => {
+                let href = display_href(analysis, display_label_value(label));
+                write!(out, r#"<li><a href="{href}">{type_name} {number}</a>: "#)?;
+            }
+            None => write!(out, "<li>{type_name} {number}: ")?,
+        }
+        for part in caption {
+            write!(out, "{}", display_paragraph_part(analysis, part))?;
+        }
+        writeln!(out, "</li>")?;
+    }
+    writedoc! {out, r#"
+        </ol>
+    "#}
+}
+
+fn write_figure_images(out: &mut impl Write, graphics: &[&str]) -> Result {
+    for path in graphics.iter().copied() {
+        let src = figure_image_path(path);
+        let src = display_escaped(&src);
+        writeln!(out, r#"<img src="{src}" alt="">"#)?;
+    }
+    Ok(())
+}
+
+// Copies the images of figures, which are relative to the directory of the tex file, into the
+// output directory. Missing images are reported but don't abort the conversion.
+fn copy_figure_images(root: &Path, source_dir: &Path, doc: &Document) {
+    let paths = doc.parts.iter().flat_map(|part| match part {
+        DocumentPart::Figure {
+            graphics,
+            subfigures,
+            ..
+        } => {
+            let subfigure_graphics = subfigures.iter().flat_map(|sub| sub.graphics.iter());
+            graphics.iter().chain(subfigure_graphics).collect()
+        }
+        _ => Vec::new(),
+    });
+    for path in paths {
+        let out_path = root.join(figure_image_path(path));
+        fs::create_dir_all(out_path.parent().unwrap()).unwrap();
+        if let Err(err) = fs::copy(source_dir.join(path), &out_path) {
+            eprintln!("Warning: Could not copy image {path}: {err}");
+        }
+    }
+}
+
+// The text of a paragraph without markup. Inline math is shown as its source.
+pub fn paragraph_text(paragraph: &Paragraph) -> String {
+    let mut text = String::new();
+    for part in paragraph {
+        use ParagraphPart::*;
+        match part {
+            TextToken(token) | Code(token) => text.push_str(token),
+            ThinSpace => text.push('\u{202f}'),
+            InlineWhitespace(ws) if !is_joining_whitespace(ws) => text.push(' '),
+            Math(crate::ast::Math::Inline(source)) => {
+                text.push('$');
+                text.push_str(source);
+                text.push('$');
+            }
+            Emph(par)
+            | Textbf(par)
+            | Textit(par)
+            | Texttt(par)
+            | Textsc(par)
+            | Textsf(par)
+            | Underline(par)
+            | Textsuperscript(par)
+            | Textsubscript(par)
+            | Href { text: par, .. }
+            | TextColor { content: par, .. } => text.push_str(&paragraph_text(par)),
+            _ => (),
+        }
+    }
+    text
+}
+
+// The list of children with the given name of the last heading in a list of headings of
+// structure.json.
+fn last_heading_children<'a>(headings: &'a mut [Json], name: &str) -> Option<&'a mut Vec<Json>> {
+    let Json::Object(fields) = headings.last_mut()? else {
+        return None;
+    };
+    fields.iter_mut().find_map(|(field, value)| match value {
+        Json::Array(children) if *field == name => Some(children),
+        _ => None,
+    })
+}
+
+// Describes the structure of the document for other tools, e.g. static site generators:
+// The title, the tree of numbered and unnumbered sections with their anchors, the theorem-like
+// environments and the keys of the bibliography entries.
+fn structure_json(doc: &Document, analysis: &Analysis) -> Json {
+    let title = doc.parts.iter().find_map(|part| match part {
+        DocumentPart::Title(title) => Some(paragraph_text(title)),
+        _ => None,
+    });
+
+    let heading = |part: &DocumentPart, name: &Paragraph, children: Option<&'static str>| {
+        let id = &analysis.heading_ids[&addr_of!(*part)];
+        let number = analysis.doc_part_numbering.get(&addr_of!(*part));
+        let mut fields = vec![
+            ("number", number.cloned().into()),
+            ("title", paragraph_text(name).into()),
+            ("anchor", id.as_str().into()),
+            ("href", display_href(analysis, id).to_string().into()),
+        ];
+        if let Some(children) = children {
+            fields.push((children, Json::Array(Vec::new())));
+        }
+        Json::Object(fields)
+    };
+    let mut sections: Vec<Json> = Vec::new();
+    let mut theorems: Vec<Json> = Vec::new();
+    for part in doc.parts.iter() {
+        use DocumentPart::*;
+        match part {
+            Section { name, .. } => sections.push(heading(part, name, Some("subsections"))),
+            // Subsections and subsubsections without a parent are listed on the level above.
+            Subsection { name, .. } => {
+                let entry = heading(part, name, Some("subsubsections"));
+                match last_heading_children(&mut sections, "subsections") {
+                    Some(subsections) => subsections.push(entry),
+                    None => sections.push(entry),
+                }
+            }
+            Subsubsection { name, .. } => {
+                let entry = heading(part, name, None);
+                if let Some(subsections) = last_heading_children(&mut sections, "subsections") {
+                    match last_heading_children(subsections, "subsubsections") {
+                        Some(subsubsections) => subsubsections.push(entry),
+                        None => subsections.push(entry),
+                    }
+                } else {
+                    match last_heading_children(&mut sections, "subsubsections") {
+                        Some(subsubsections) => subsubsections.push(entry),
+                        None => sections.push(entry),
+                    }
+                }
+            }
+            TheoremLike { tag, label, .. } => {
+                let config = doc
+                    .config
+                    .theorem_like_configs
+                    .iter()
+                    .find(|config| config.tag == *tag);
+                let number = analysis.doc_part_numbering.get(&addr_of!(*part));
+                let anchor = label.map(|label| display_label_value(label).to_string());
+                theorems.push(Json::Object(vec![
+                    ("type", (*tag).into()),
+                    (
+                        "name",
+                        config.map(|config| paragraph_text(&config.name)).into(),
+                    ),
+                    ("number", number.cloned().into()),
+                    ("anchor", anchor.into()),
+                ]));
+            }
+            _ => (),
+        }
+    }
+    let bibliography = analysis
+        .bib_entries
+        .iter()
+        .map(|entry| entry.tag.into())
+        .collect();
+
+    Json::Object(vec![
+        ("title", title.into()),
+        ("sections", Json::Array(sections)),
+        ("theorems", Json::Array(theorems)),
+        ("bibliography", Json::Array(bibliography)),
+    ])
+}
+
+pub fn emit(
+    root: &Path,
+    source_dir: &Path,
+    doc: &Document,
+    analysis: &Analysis,
+    options: &EmitOptions,
+) {
+    fs::create_dir_all(root).unwrap();
+
+    let pages = if options.split_sections && !options.standalone {
+        split_pages_html(doc, analysis, options)
+    } else {
+        vec![("index.html".to_string(), index_html(doc, analysis, options))]
+    };
+
+    for (file_name, html) in pages {
+        let page_path = root.join(file_name);
+        let mut page_file = std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(page_path)
+            .unwrap();
+        write!(page_file, "{}", html).unwrap();
+    }
+
+    if options.standalone {
+        return;
+    }
 
     let style_path = root.join("style.css");
     let mut style_path = std::fs::OpenOptions::new()
@@ -646,5 +2497,1511 @@ pub fn emit(root: &Path, doc: &Document, analysis: &Analysis) {
         .create(true)
         .open(style_path)
         .unwrap();
-    write!(style_path, "{STYLE}").unwrap();
+    write!(style_path, "{}", style_css(options)).unwrap();
+
+    if options.structure_json {
+        let json = structure_json(doc, analysis);
+        fs::write(root.join("structure.json"), format!("{json}\n")).unwrap();
+    }
+
+    copy_figure_images(root, source_dir, doc);
+}
+
+#[cfg(test)]
+fn test_index_html(tex: &str, options: &EmitOptions) -> String {
+    let (_, doc) = crate::parse::document(tex).unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        options,
+        &std::collections::HashMap::new(),
+        &std::collections::HashMap::new(),
+    );
+    let mut html = String::new();
+    write_index(&mut html, &doc, &analysis, options).unwrap();
+    html
+}
+
+#[test]
+fn proof_qed_placement() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{proof}
+          Obvious.
+        \end{proof}
+        \begin{proof}
+          We compute
+          \begin{equation}
+            x = y
+          \end{equation}
+        \end{proof}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+
+    let (text_proof, math_proof) = html.split_once("We compute").unwrap();
+    assert!(text_proof.contains(r#"Obvious.<span class="qed">∎</span>"#));
+
+    let row_begin = math_proof.find(r#"class="display-math-row""#).unwrap();
+    let row_end = row_begin + math_proof[row_begin..].find("</div>").unwrap();
+    let row = &math_proof[row_begin..row_end];
+    assert_eq!(row.matches(r#"<span class="qed">∎</span>"#).count(), 2);
+    assert_eq!(math_proof.matches("∎").count(), 2);
+}
+
+#[test]
+fn wide_display_math_is_full_bleed() {
+    let (_, doc) = crate::parse::document(indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{equation}
+          x = y
+        \end{equation}
+        \begin{equation}
+          x_1 + x_2 + x_3 + x_4 + x_5 + x_6 + x_7 + x_8 + x_9 + x_{10} + x_{11} + x_{12}
+        \end{equation}
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let svg_info = |width_em| SvgInfo {
+        width_em,
+        height_em: 1.0,
+        baseline_em: None,
+    };
+    let math_svg_info = std::collections::HashMap::from([
+        (
+            hash_math(&doc.preamble, node_lists.math[0], &EmitOptions::default()),
+            svg_info(3.0),
+        ),
+        (
+            hash_math(&doc.preamble, node_lists.math[1], &EmitOptions::default()),
+            svg_info(45.0),
+        ),
+    ]);
+    let options = EmitOptions {
+        max_width_px: 500,
+        ..EmitOptions::default()
+    };
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &options,
+        &math_svg_info,
+        &std::collections::HashMap::new(),
+    );
+    let mut html = String::new();
+    write_index(&mut html, &doc, &analysis, &options).unwrap();
+
+    let rows: Vec<&str> = html
+        .lines()
+        .filter(|line| line.contains("display-math-row"))
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            r#"<div class="display-math-row">"#,
+            r#"<div class="display-math-row full-bleed">"#
+        ]
+    );
+
+    let style = display_style(&options).to_string();
+    assert!(style.contains("--max-width: 500px;"));
+}
+
+#[test]
+fn extra_css_is_appended() {
+    let extra_css = ".theorem-like { font-style: normal; }";
+    let options = EmitOptions {
+        extra_css: Some(extra_css.to_string()),
+        ..EmitOptions::default()
+    };
+    let style = style_css(&options);
+    assert!(style.starts_with(&style_css(&EmitOptions::default())));
+    assert!(style.ends_with(extra_css));
+}
+
+#[test]
+fn label_ids_match_ref_links() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section{Main}
+        \label{sec:2ndMain}
+        See section \ref{sec:2ndMain}.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(r#"<h2 id="sec-2ndmain">"#));
+    assert!(html.contains(r##"<a href="#sec-2ndmain">1</a>"##));
+}
+
+#[test]
+fn draft_output_is_not_indexed() {
+    let tex = indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        Hello.
+        \end{document}
+    "#};
+    let robots_meta = r#"<meta name="robots" content="noindex, nofollow">"#;
+
+    let html = test_index_html(tex, &EmitOptions::default());
+    assert!(!html.contains(robots_meta));
+    assert!(html.contains("<body>"));
+
+    let options = EmitOptions {
+        draft: true,
+        ..EmitOptions::default()
+    };
+    let html = test_index_html(tex, &options);
+    assert!(html.contains(robots_meta));
+    assert!(html.contains(r#"<body class="draft">"#));
+}
+
+#[test]
+fn bib_name_formats() {
+    let (_, entries) = crate::parse::bib(indoc! {r#"
+        @article{coalgebraic-models,
+          title={Coalgebraic models for combinatorial model categories},
+          author={Ching, Michael and Riehl, E.},
+          year={2014}
+        }
+    "#})
+    .unwrap();
+    let entry = |name_format| {
+        display_bib_entry(&entries[0], name_format, &EmitOptions::default()).to_string()
+    };
+
+    use BibNameFormat::*;
+    assert!(entry(FirstLast).contains(" Michael Ching and E. Riehl. Coalgebraic"));
+    assert!(entry(LastFirst).contains(" Ching, Michael and E. Riehl. Coalgebraic"));
+    assert!(entry(LastInitials).contains(" Ching, M. and Riehl, E. Coalgebraic"));
+
+    assert_eq!(
+        BibNameFormat::from_bibliography_style("apalike"),
+        Some(LastInitials)
+    );
+    assert_eq!(BibNameFormat::from_bibliography_style("unknown"), None);
+}
+
+#[test]
+fn thesis_and_report_entries() {
+    let (_, entries) = crate::parse::bib(indoc! {r#"
+        @phdthesis{phd,
+          title={A thesis},
+          author={Doe, Jane},
+          school={University X},
+          year={2020}
+        }
+        @techreport{report,
+          title={A report},
+          institution={Institute Z},
+          number={42},
+          year={2021}
+        }
+    "#})
+    .unwrap();
+    let entry = |i: usize| {
+        display_bib_entry(
+            &entries[i],
+            BibNameFormat::FirstLast,
+            &EmitOptions::default(),
+        )
+        .to_string()
+    };
+    assert!(entry(0).contains(" Jane Doe. A thesis. PhD thesis, University X, 2020."));
+    assert!(entry(1).contains(" A report. Technical report 42, Institute Z, 2021."));
+}
+
+#[test]
+fn doi_links() {
+    let (_, entries) = crate::parse::bib(indoc! {r#"
+        @article{with-doi,
+          title={A paper},
+          url={https://example.com/paper},
+          doi={https://doi.org/10.1000/182}
+        }
+        @article{with-url,
+          title={Another paper},
+          url={https://example.com/other}
+        }
+    "#})
+    .unwrap();
+    let entry = |i: usize| {
+        display_bib_entry(
+            &entries[i],
+            BibNameFormat::FirstLast,
+            &EmitOptions::default(),
+        )
+        .to_string()
+    };
+    assert!(entry(0).contains(r#"<a href="https://doi.org/10.1000/182">A paper</a>."#));
+    assert!(entry(0).contains(r#"doi:<a href="https://doi.org/10.1000/182">10.1000/182</a>."#));
+    assert!(entry(0).contains(
+        r#" URL <a href="https://example.com/paper" class="url">https://example.com/paper</a>."#
+    ));
+    assert!(entry(1).contains(r#"<a href="https://example.com/other">Another paper</a>."#));
+    assert!(!entry(1).contains("URL"));
+}
+
+#[test]
+fn url_without_title() {
+    let (_, entries) = crate::parse::bib(indoc! {r#"
+        @misc{website,
+          author={Doe, Jane},
+          url={https://example.com/?a=1&b=2},
+          year={2020}
+        }
+    "#})
+    .unwrap();
+    let entry = display_bib_entry(
+        &entries[0],
+        BibNameFormat::FirstLast,
+        &EmitOptions::default(),
+    )
+    .to_string();
+    let url = "https://example.com/?a=1&amp;b=2";
+    assert!(entry.contains(&format!(r#" URL <a href="{url}" class="url">{url}</a>."#)));
+}
+
+#[test]
+fn author_year_citations() {
+    let (_, doc) = crate::parse::document(indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \citet{ching}, \citep[page 3]{ching} and \cite{ching, missing}.
+        \citep[see][p.~5]{ching} and \citet[see][]{ching}.
+        \end{document}
+    "#})
+    .unwrap();
+    let (_, entries) = crate::parse::bib(indoc! {r#"
+        @article{ching,
+          title={Coalgebraic models for combinatorial model categories},
+          author={Ching, Michael and Riehl, E.},
+          year={2014}
+        }
+    "#})
+    .unwrap();
+    let options = EmitOptions {
+        citation_style: CitationStyle::AuthorYear,
+        ..EmitOptions::default()
+    };
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &entries,
+        &node_lists,
+        &options,
+        &std::collections::HashMap::new(),
+        &std::collections::HashMap::new(),
+    );
+    let mut html = String::new();
+    write_index(&mut html, &doc, &analysis, &options).unwrap();
+    assert!(html.contains(r##"Ching and Riehl (<a href="#ching">2014</a>)"##));
+    assert!(html.contains(r##"(<a href="#ching">Ching and Riehl, 2014</a>, page 3)"##));
+    assert!(html.contains(
+        r##"(<a href="#ching">Ching and Riehl, 2014</a>; <a href="#missing">???, ???</a>)"##
+    ));
+    assert!(html.contains("(see <a href=\"#ching\">Ching and Riehl, 2014</a>, p.\u{a0}5)"));
+    assert!(html.contains(r##"Ching and Riehl (see <a href="#ching">2014</a>)"##));
+}
+
+#[test]
+fn paragraph_whitespace_is_collapsed() {
+    let (_, doc) =
+        crate::parse::document("\\documentclass{article}\n\\begin{document}\n\\end{document}")
+            .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &EmitOptions::default(),
+        &std::collections::HashMap::new(),
+        &std::collections::HashMap::new(),
+    );
+    use ParagraphPart::*;
+    let paragraph = vec![
+        InlineWhitespace("  "),
+        TextToken("a"),
+        InlineWhitespace(" "),
+        InlineWhitespace(""),
+        InlineWhitespace(" "),
+        TextToken("b"),
+        InlineWhitespace(" "),
+        InlineWhitespace("\n"),
+        TextToken("c"),
+        InlineWhitespace("\n"),
+    ];
+    let html = display_paragraph(&analysis, &paragraph).to_string();
+    assert_eq!(html, "<div class=\"paragraph\">\na b\nc</div>\n");
+}
+
+#[test]
+fn blank_lines_separate_paragraphs() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{proof}
+          First sentence,
+          % A comment line doesn't end the paragraph.
+          continued.
+
+          Second sentence.
+        \end{proof}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    let paragraphs: Vec<&str> = html
+        .split(r#"<div class="paragraph">"#)
+        .skip(1)
+        .map(|paragraph| paragraph.split("</div>").next().unwrap())
+        .collect();
+    assert_eq!(paragraphs.len(), 2);
+    assert!(paragraphs[0].contains("First sentence,\ncontinued."));
+    assert!(paragraphs[1].contains("Second sentence."));
+}
+
+#[test]
+fn comments_join_lines() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        foo%
+        bar and baz %
+        qux
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains("foobar and baz\nqux"));
+}
+
+#[test]
+fn numeric_citation_notes() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \cite[p.~42]{a} and \cite[see][p.~5]{a}.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains("[<a href=\"#a\">???</a>, p.\u{a0}42]"));
+    assert!(html.contains("[see <a href=\"#a\">???</a>, p.\u{a0}5]"));
+}
+
+#[test]
+fn et_al() {
+    let (_, entries) = crate::parse::bib(indoc! {r#"
+        @article{many-authors,
+          title={A paper},
+          author={One, A. and Two, B. and Three, C. and Four, D. and Five, E.},
+          year={2020}
+        }
+    "#})
+    .unwrap();
+    let entry = |max_authors, et_al_authors| {
+        let options = EmitOptions {
+            max_authors,
+            et_al_authors,
+            ..EmitOptions::default()
+        };
+        display_bib_entry(&entries[0], BibNameFormat::FirstLast, &options).to_string()
+    };
+    assert!(entry(None, 1).contains(" A. One, B. Two, C. Three, D. Four and E. Five. A paper."));
+    assert!(entry(Some(5), 1).contains(" A. One, B. Two, C. Three, D. Four and E. Five. A paper."));
+    assert!(entry(Some(3), 1).contains(" A. One et al. A paper."));
+    assert!(entry(Some(3), 2).contains(" A. One, B. Two et al. A paper."));
+}
+
+#[test]
+fn title_block_shows_authors_and_date() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \title{On things}
+        \author{Alice Smith}
+        \date{1 April 2024}
+        \maketitle
+        Text.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(indoc! {r#"
+        <header class="title-block">
+        <h1>On things</h1>
+        <div class="author">
+        Alice Smith</div>
+        <div class="date">
+        1 April 2024</div>
+        </header>
+    "#}));
+}
+
+#[test]
+fn title_block_shows_thanks() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \title{On things\thanks{Draft.}}
+        \author{A. Smith\thanks{MIT}}
+        \maketitle
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains("<title>On things</title>"));
+    assert!(html.contains(r##"<h1>On things<sup><a href="#thanks-1">*</a></sup></h1>"##));
+    assert!(html.contains(r##"A. Smith<sup><a href="#thanks-2">†</a></sup>"##));
+    assert!(html.contains(indoc! {r#"
+        <div class="thanks" id="thanks-1">
+        <sup>*</sup>Draft.</div>
+        <div class="thanks" id="thanks-2">
+        <sup>†</sup>MIT</div>
+    "#}));
+}
+
+#[test]
+fn empty_date_is_not_shown() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \title{On things}
+        \date{}
+        \maketitle
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(!html.contains(r#"class="date""#));
+}
+
+#[test]
+fn dates_are_formatted_like_today() {
+    assert_eq!(format_date(0), "January 1, 1970");
+    assert_eq!(format_date(19723), "January 1, 2024");
+    assert_eq!(format_date(19782), "February 29, 2024");
+    assert_eq!(format_date(-1), "December 31, 1969");
+}
+
+#[test]
+fn text_is_escaped() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \title{Why a < b}
+        \maketitle
+        If a < b then "c".
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains("<title>Why a &lt; b</title>"));
+    assert!(html.contains("<h1>Why a &lt; b</h1>"));
+    assert!(html.contains("If a &lt; b then &quot;c&quot;."));
+}
+
+#[test]
+fn hyperlinks_are_emitted() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        See \href{https://example.com/?a=1&b="2"}{here} and \url{https://example.com/x_y}.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(r#"<a href="https://example.com/?a=1&amp;b=&quot;2&quot;">here</a>"#));
+    assert!(html
+        .contains(r#"<a href="https://example.com/x_y" class="url">https://example.com/x_y</a>"#));
+}
+
+#[test]
+fn inline_code_is_emitted() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        Run \texttt{make} or \verb!<a href="x">!.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains("<code>make</code>"));
+    assert!(html.contains("<code>&lt;a href=&quot;x&quot;&gt;</code>"));
+}
+
+#[test]
+fn footnotes_are_numbered() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        First\footnote{One.} and second\footnote{Two.}.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    let marker = |n| format!(r##"<sup><a id="fnref{n}" href="#fn{n}">{n}</a></sup>"##);
+    assert!(html.contains(&format!("First{}", marker(1))));
+    assert!(html.contains(&format!("second{}", marker(2))));
+
+    let one = html.find(r#"<li id="fn1">"#).unwrap();
+    let two = html.find(r#"<li id="fn2">"#).unwrap();
+    assert!(one < two);
+    assert!(html[one..two].contains("One."));
+    assert!(html[one..two].contains(r##"href="#fnref1""##));
+    assert!(html[two..].contains("Two."));
+}
+
+#[test]
+fn align_lines_are_numbered() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{align}
+          a &= b \nonumber \label{eq:a} \\
+          c &= d \label{eq:c}
+        \end{align}
+        See \eqref{eq:a} and \eqref{eq:c}.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(r#"<span class="align-numbers"><span></span><span>(1)</span></span>"#));
+    assert!(html
+        .contains(r#"<span class="align-numbers"><span></span><span id="eq-c">(1)</span></span>"#));
+    assert!(html.contains(r##"<a href="#eq-c">(1)</a>"##));
+    assert!(html.contains(r##"<a href="#eq-a">???</a>"##));
+}
+
+#[test]
+fn standalone_output_is_self_contained() {
+    let (_, doc) = crate::parse::document(indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        Let $x$ be given.
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let digest = hash_math(&doc.preamble, node_lists.math[0], &EmitOptions::default());
+    let math_svg_info = std::collections::HashMap::from([(
+        digest,
+        SvgInfo {
+            width_em: 0.5,
+            height_em: 1.0,
+            baseline_em: Some(0.25),
+        },
+    )]);
+    let math_svg_data = std::collections::HashMap::from([(digest, b"<svg/>".to_vec())]);
+    let options = EmitOptions {
+        standalone: true,
+        ..EmitOptions::default()
+    };
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &options,
+        &math_svg_info,
+        &math_svg_data,
+    );
+    let mut html = String::new();
+    write_index(&mut html, &doc, &analysis, &options).unwrap();
+
+    assert!(!html.contains(r#"href="style.css""#));
+    assert!(!html.contains("geometry.css"));
+    assert!(html.contains("<style>"));
+    assert!(html.contains(concat!(
+        r#"<img src="data:image/svg+xml;base64,PHN2Zy8+" width="8" height="16" "#,
+        r#"style="width: 0.5em; height: 1em; top: 0.75em;" alt="x" role="math" "#,
+        r#"class="inline-math">"#
+    )));
+}
+
+#[test]
+fn mathml_backend() {
+    let options = EmitOptions {
+        math_backend: MathBackend::MathMl,
+        ..EmitOptions::default()
+    };
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        Let $x$ be such that
+        \begin{equation}
+          \label{eq:x}
+          x^2 = 2.
+        \end{equation}
+        By \eqref{eq:x}, we have
+        \end{document}
+    "#},
+        &options,
+    );
+    assert!(!html.contains("<img"));
+    assert!(html.contains(r#"Let <math display="inline"><mi>x</mi></math> be"#));
+    assert!(html.contains(r#"<div id="eq-x" class="display-math-row">"#));
+    assert!(html.contains(concat!(
+        r#"<math display="block"><msup><mi>x</mi><mrow><mn>2</mn></mrow></msup>"#,
+        "<mo>=</mo><mn>2</mn><mo>.</mo></math>"
+    )));
+}
+
+#[test]
+fn failed_math_placeholder() {
+    // No svg info is passed, as if all formulas failed to compile.
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        Let $x < y$ be given.
+        \end{document}
+    "#},
+        &EmitOptions {
+            best_effort_math: true,
+            ..EmitOptions::default()
+        },
+    );
+    assert!(html.contains(r#"<code class="math-error" title="Failed to compile">x &lt; y</code>"#));
+    assert!(!html.contains("<img"));
+}
+
+#[test]
+fn nested_lists_are_emitted() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{enumerate}
+          \item
+            \begin{itemize}
+              \item Inner.
+            \end{itemize}
+        \end{enumerate}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    let li = html.find("<li>").unwrap();
+    let ul = html.find("<ul>").unwrap();
+    assert!(html.find("<ol>").unwrap() < li);
+    assert!(li < ul);
+    assert!(ul < html.find("</li>").unwrap());
+}
+
+#[test]
+fn editors() {
+    let (_, entries) = crate::parse::bib(indoc! {r#"
+        @incollection{chapter,
+          title={A chapter},
+          author={Roe, Richard},
+          editor={Smith, John and Doe, Jane},
+          booktitle={A collection},
+          year={2020}
+        }
+        @book{book,
+          title={A book},
+          editor={Smith, John},
+          year={2021}
+        }
+    "#})
+    .unwrap();
+    let entry = |i: usize| {
+        display_bib_entry(
+            &entries[i],
+            BibNameFormat::LastFirst,
+            &EmitOptions::default(),
+        )
+        .to_string()
+    };
+    assert!(entry(0).contains(
+        " Roe, Richard. A chapter. In John Smith and Jane Doe (Eds.), A collection, 2020."
+    ));
+    assert!(entry(1).contains(" Smith, John (Ed.). A book."));
+}
+
+#[test]
+fn bib_months() {
+    let (_, entries) = crate::parse::bib(indoc! {r#"
+        @article{numeric,
+          title={A paper},
+          year={2020},
+          month={7}
+        }
+        @article{abbreviated,
+          title={A paper},
+          year={2020},
+          month={Jul}
+        }
+        @article{unrecognized,
+          title={A paper},
+          year={2020},
+          month={13}
+        }
+    "#})
+    .unwrap();
+    let entry = |i: usize| {
+        display_bib_entry(
+            &entries[i],
+            BibNameFormat::FirstLast,
+            &EmitOptions::default(),
+        )
+        .to_string()
+    };
+    assert!(entry(0).contains(" A paper. July 2020."));
+    assert!(entry(1).contains(" A paper. July 2020."));
+    assert!(entry(2).contains(" A paper. 2020."));
+    assert_eq!(month_name("sept."), Some("September"));
+    assert_eq!(month_name("ju"), None);
+}
+
+#[test]
+fn math_images_have_alt_text() {
+    let (_, doc) = crate::parse::document(indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        Let $x < y$ be given.
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let digest = hash_math(&doc.preamble, node_lists.math[0], &EmitOptions::default());
+    let math_svg_info = std::collections::HashMap::from([(
+        digest,
+        SvgInfo {
+            width_em: 0.5,
+            height_em: 1.0,
+            baseline_em: None,
+        },
+    )]);
+    let options = EmitOptions::default();
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &options,
+        &math_svg_info,
+        &std::collections::HashMap::new(),
+    );
+    let mut html = String::new();
+    write_index(&mut html, &doc, &analysis, &options).unwrap();
+
+    assert!(html.contains(r#" alt="x &lt; y" role="math" class="inline-math">"#));
+}
+
+#[test]
+fn math_images_have_intrinsic_size() {
+    let (_, doc) = crate::parse::document(indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \[ x^2 \]
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let digest = hash_math(&doc.preamble, node_lists.math[0], &EmitOptions::default());
+    let math_svg_info = std::collections::HashMap::from([(
+        digest,
+        SvgInfo {
+            width_em: 2.0,
+            height_em: 1.2,
+            baseline_em: None,
+        },
+    )]);
+    let options = EmitOptions::default();
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &options,
+        &math_svg_info,
+        &std::collections::HashMap::new(),
+    );
+    let mut html = String::new();
+    write_index(&mut html, &doc, &analysis, &options).unwrap();
+
+    assert!(html.contains(&format!(
+        r#"<img src="img-math/{digest}.svg" width="32" height="19" alt="\[ x^2 \]" role="math">"#
+    )));
+}
+
+#[test]
+fn equation_number_side() {
+    let tex = indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{equation}
+          \label{eq:x}
+          x = 1
+        \end{equation}
+        By \eqref{eq:x}.
+        \end{document}
+    "#};
+    let html = |equation_numbers| {
+        let options = EmitOptions {
+            equation_numbers,
+            ..EmitOptions::default()
+        };
+        test_index_html(tex, &options)
+    };
+
+    let right = html(EquationNumberSide::Right);
+    assert!(right.contains(r#"<div id="eq-x" class="display-math-row">"#));
+    assert_eq!(right.matches("<span>(1)</span>").count(), 2);
+
+    let left = html(EquationNumberSide::Left);
+    assert!(left.contains(r#"<div id="eq-x" class="display-math-row numbers-left">"#));
+    assert_eq!(left.matches("<span>(1)</span>").count(), 2);
+
+    let none = html(EquationNumberSide::None);
+    let row_begin = none.find(r#"class="display-math-row""#).unwrap();
+    let row_end = row_begin + none[row_begin..].find("</div>").unwrap();
+    assert!(!none[row_begin..row_end].contains("<span"));
+    assert!(!none.contains("(1)"));
+}
+
+#[test]
+fn figures_are_emitted() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \listoffigures
+        \begin{figure}
+          \includegraphics{img/plot.png}
+          \caption{A plot.}
+          \label{fig:plot}
+        \end{figure}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(indoc! {r#"
+        <figure id="fig-plot">
+        <img src="figures/img/plot.png" alt="">
+        <figcaption>Figure 1: A plot.</figcaption>
+        </figure>
+    "#}));
+    assert!(html.contains(r##"<li><a href="#fig-plot">Figure 1</a>: A plot.</li>"##));
+}
+
+#[test]
+fn figure_images_keep_their_directories() {
+    let source_dir = tempdir::TempDir::new("latex-to-html").unwrap();
+    for dir in ["before", "after"] {
+        fs::create_dir_all(source_dir.path().join(dir)).unwrap();
+        fs::write(source_dir.path().join(dir).join("plot.png"), dir).unwrap();
+    }
+    let (_, doc) = crate::parse::document(indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{figure}
+          \includegraphics{before/plot.png}
+          \includegraphics{./after/plot.png}
+        \end{figure}
+        \end{document}
+    "#})
+    .unwrap();
+    let root = tempdir::TempDir::new("latex-to-html").unwrap();
+    copy_figure_images(root.path(), source_dir.path(), &doc);
+    let figures = root.path().join(FIGURE_OUT_DIR);
+    assert_eq!(
+        fs::read_to_string(figures.join("before/plot.png")).unwrap(),
+        "before"
+    );
+    assert_eq!(
+        fs::read_to_string(figures.join("after/plot.png")).unwrap(),
+        "after"
+    );
+
+    assert_eq!(
+        figure_image_path("../shared/plot.png"),
+        "figures/parent/shared/plot.png"
+    );
+}
+
+#[test]
+fn tables_are_emitted() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \listoftables
+        \begin{table}[h]
+          \centering
+          \begin{tabular}{l}
+            a
+          \end{tabular}
+          \caption{A table.}
+          \label{tab:a}
+        \end{table}
+        \begin{table}
+          \caption{Another table.}
+        \end{table}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(indoc! {r#"
+        <figure id="tab-a" class="table">
+        <figcaption>Table 1: A table.</figcaption>
+        <table class="tabular">
+    "#}));
+    assert!(html.contains("<h2>List of Tables</h2>"));
+    assert!(html.contains(r##"<li><a href="#tab-a">Table 1</a>: A table.</li>"##));
+    assert!(html.contains("<li>Table 2: Another table.</li>"));
+}
+
+#[test]
+fn subfigures_are_emitted() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{figure}
+          \begin{subfigure}{0.45\textwidth}
+            \includegraphics{img/a.png}
+            \caption{Before.}
+            \label{fig:a}
+          \end{subfigure}
+          \begin{subfigure}{0.45\textwidth}
+            \includegraphics{img/b.png}
+            \caption{After.}
+          \end{subfigure}
+          \caption{A comparison.}
+        \end{figure}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(indoc! {r#"
+        <figure>
+        <div class="subfigures">
+        <figure id="fig-a" class="subfigure">
+        <img src="figures/img/a.png" alt="">
+        <figcaption>(a) Before.</figcaption>
+        </figure>
+        <figure class="subfigure">
+        <img src="figures/img/b.png" alt="">
+        <figcaption>(b) After.</figcaption>
+        </figure>
+        </div>
+        <figcaption>Figure 1: A comparison.</figcaption>
+        </figure>
+    "#}));
+}
+
+#[test]
+fn keywords_follow_abstract() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{abstract}
+          We show things.
+        \end{abstract}
+        \keywords{graphs, trees}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(indoc! {r#"
+        We show things.</div>
+        <div class="keywords">
+        Keywords: graphs, trees</div>
+    "#}));
+}
+
+#[test]
+fn center_is_emitted() {
+    let options = EmitOptions {
+        trivial_math_html: true,
+        ..EmitOptions::default()
+    };
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{center}
+          Centered \emph{text} with $x$.
+        \end{center}
+        \centerline{One line.}
+        \end{document}
+    "#},
+        &options,
+    );
+    assert!(html.contains(indoc! {r#"
+        <div class="center">
+        <div class="paragraph">
+        Centered <em>text</em> with <span class="inline-math-html"><i>x</i></span>.</div>
+        </div>
+    "#}));
+    assert!(html.contains(indoc! {r#"
+        <div class="center">
+        <div class="paragraph">
+        One line.</div>
+        </div>
+    "#}));
+}
+
+#[test]
+fn text_colors() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \textcolor{red}{Stop} and \textcolor[RGB]{0, 128,255}{\emph{go}}.
+        \textcolor{red!50}{Mixed}, \textcolor[cmyk]{0,1,1,0}{cmyk} and \textcolor[RGB]{0,0,256}{bad}.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(r#"<span style="color: #ff0000">Stop</span>"#));
+    assert!(html.contains(r#"<span style="color: rgb(0, 128, 255)"><em>go</em></span>"#));
+    assert!(html.contains("Mixed, cmyk and bad."));
+
+    assert_eq!(
+        css_color(Some("rgb"), "1,0.5,0").unwrap(),
+        "rgb(255, 128, 0)"
+    );
+    assert_eq!(
+        css_color(Some("gray"), "0.5").unwrap(),
+        "rgb(128, 128, 128)"
+    );
+    assert_eq!(css_color(Some("HTML"), "1A2B3C").unwrap(), "#1A2B3C");
+    assert_eq!(css_color(Some("HTML"), "1A2B3"), None);
+}
+
+#[test]
+fn structure_json_lists_sections() {
+    let (_, doc) = crate::parse::document(indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \title{On \emph{things}}
+        \section{Introduction}
+        \label{sec:intro}
+        \begin{theorem}
+          \label{thm:main}
+          Things exist.
+        \end{theorem}
+        \subsection{Background}
+        \subsubsection{History}
+        \section*{Acknowledgements}
+        \end{document}
+    "#})
+    .unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let options = EmitOptions::default();
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &options,
+        &std::collections::HashMap::new(),
+        &std::collections::HashMap::new(),
+    );
+    assert_eq!(
+        structure_json(&doc, &analysis).to_string(),
+        indoc! {r##"
+            {
+              "title": "On things",
+              "sections": [
+                {
+                  "number": "1",
+                  "title": "Introduction",
+                  "anchor": "sec-intro",
+                  "href": "#sec-intro",
+                  "subsections": [
+                    {
+                      "number": "1.1",
+                      "title": "Background",
+                      "anchor": "background",
+                      "href": "#background",
+                      "subsubsections": [
+                        {
+                          "number": "1.1.1",
+                          "title": "History",
+                          "anchor": "history",
+                          "href": "#history"
+                        }
+                      ]
+                    }
+                  ]
+                },
+                {
+                  "number": null,
+                  "title": "Acknowledgements",
+                  "anchor": "acknowledgements",
+                  "href": "#acknowledgements",
+                  "subsections": []
+                }
+              ],
+              "theorems": [
+                {
+                  "type": "theorem",
+                  "name": "Theorem",
+                  "number": "1",
+                  "anchor": "thm-main"
+                }
+              ],
+              "bibliography": []
+            }"##}
+    );
+}
+
+#[test]
+fn tabular_is_emitted() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{tabular}{lr}
+          a & b \\
+          \hline
+          c & d
+        \end{tabular}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(indoc! {r#"
+        <table class="tabular">
+        <tr><td style="text-align: left">a</td><td style="text-align: right">b</td></tr>
+        <tr class="border-top"><td style="text-align: left">c</td><td style="text-align: right">d</td></tr>
+        </table>
+    "#}));
+}
+
+#[test]
+fn wide_tabulars_are_full_bleed() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{tabular}{ll}
+          short & table
+        \end{tabular}
+        \begin{tabular}{lll}
+          a column with some text & another column with more text & and a third one \\
+          x & y & z
+        \end{tabular}
+        \end{document}
+    "#},
+        &EmitOptions {
+            max_width_px: 400,
+            ..EmitOptions::default()
+        },
+    );
+    let tables: Vec<&str> = html
+        .lines()
+        .filter(|line| line.starts_with("<table"))
+        .collect();
+    assert_eq!(
+        tables,
+        [
+            r#"<table class="tabular">"#,
+            r#"<table class="tabular full-bleed">"#
+        ]
+    );
+}
+
+#[test]
+fn starred_sections_are_unnumbered() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section*{Intro}
+        \section{Results}
+        \subsection*{Details}
+        \subsection{Proofs}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains("<h2 id=\"intro\">\nIntro <a"));
+    assert!(html.contains("<h2 id=\"results\">\n1 Results <a"));
+    assert!(html.contains("<h3 id=\"details\">\nDetails <a"));
+    assert!(html.contains("<h3 id=\"proofs\">\n1.1 Proofs <a"));
+}
+
+#[test]
+fn paragraph_headings_are_run_in() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \paragraph{Setup}
+        We fix notation.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(indoc! {r#"
+        <div class="paragraph">
+        <span class="paragraph-heading">
+        Setup</span>
+        We fix notation.</div>
+    "#}));
+}
+
+#[test]
+fn verbatim_is_escaped() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \newcommand{\x}{y}
+        \begin{document}
+        \begin{verbatim}
+        if a < b && c {
+          \x
+        }
+        \end{verbatim}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains("<pre><code>if a &lt; b &amp;&amp; c {\n  \\x\n}</code></pre>"));
+}
+
+#[test]
+fn font_styles() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \textsc{Small} \textsf{sans} \underline{under}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(r#"<span class="small-caps">Small</span>"#));
+    assert!(html.contains(r#"<span class="sans">sans</span>"#));
+    assert!(html.contains("<u>under</u>"));
+}
+
+#[test]
+fn spaces_are_emitted() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        By Theorem~1, 10\,km is e.g.\ far.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains("By Theorem\u{a0}1, 10"));
+    assert!(html.contains(r#"10<span class="thinspace"></span>km is e.g. far."#));
+}
+
+#[test]
+fn text_super_and_subscripts_are_emitted() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        H\textsubscript{2}O on the 4\textsuperscript{\emph{th}} day.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains("H<sub>2</sub>O on the 4<sup><em>th</em></sup> day."));
+}
+
+#[test]
+fn cref_is_lowercase() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{lemma}
+        \label{lem:a}
+        A.
+        \end{lemma}
+        By \cref{lem:a} and \Cref{lem:a}.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(r##"By <a href="#lem-a">lemma 1</a> and <a href="#lem-a">Lemma 1</a>."##));
+}
+
+#[test]
+fn cref_lists_and_ranges() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{theorem}\label{t1}A.\end{theorem}
+        \begin{theorem}\label{t2}B.\end{theorem}
+        \begin{theorem}\label{t3}C.\end{theorem}
+        \begin{corollary}\label{c1}D.\end{corollary}
+        \begin{corollary}\label{c2}E.\end{corollary}
+
+        \Cref{t1,t2,t3}; \cref{t1, t3}; \Cref{t1,c1}; \cref{t2,c1,c2}.
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    let link = |id: &str, text: &str| format!(r##"<a href="#{id}">{text}</a>"##);
+    assert!(html.contains(&format!(
+        "Theorems {}–{};",
+        link("t1", "1"),
+        link("t3", "3")
+    )));
+    assert!(html.contains(&format!(
+        "theorems {} and {};",
+        link("t1", "1"),
+        link("t3", "3")
+    )));
+    assert!(html.contains(&format!(
+        "Theorem {} and Corollary {};",
+        link("t1", "1"),
+        link("c1", "4")
+    )));
+    assert!(html.contains(&format!(
+        "theorem {} and corollaries {} and {}.",
+        link("t2", "2"),
+        link("c1", "4"),
+        link("c2", "5")
+    )));
+}
+
+#[test]
+fn heading_ids_are_unique() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section{Examples}
+        \subsection{Examples}
+        \section{Proofs}
+        \label{examples-2}
+        \section{$x$}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    assert!(html.contains(indoc! {r##"
+        <h2 id="examples">
+        1 Examples <a class="permalink" href="#examples">#</a></h2>
+    "##}));
+    // The label of the third section takes precedence over the second id derived from
+    // "Examples".
+    assert!(html.contains(r#"<h3 id="examples-3">"#));
+    assert!(html.contains(r#"<h2 id="examples-2">"#));
+    assert!(html.contains(r#"<h2 id="section">"#));
+}
+
+#[test]
+fn split_sections() {
+    let tex = indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \title{Doc}
+        \maketitle
+        \section{One}
+        \label{sec:one}
+        First\footnote{A note.}.
+        \section{Two}
+        \label{sec:two}
+        See \ref{sec:one} and \ref{sec:two}\footnote{Another note.}.
+        \end{document}
+    "#};
+    let (_, doc) = crate::parse::document(tex).unwrap();
+    let node_lists = NodeLists::new(&doc);
+    let options = EmitOptions {
+        split_sections: true,
+        ..EmitOptions::default()
+    };
+    let analysis = Analysis::new(
+        &doc,
+        &[],
+        &node_lists,
+        &options,
+        &std::collections::HashMap::new(),
+        &std::collections::HashMap::new(),
+    );
+    let pages = split_pages_html(&doc, &analysis, &options);
+    let file_names: Vec<&str> = pages.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        file_names,
+        ["index.html", "section-1.html", "section-2.html"]
+    );
+
+    let [(_, index), (_, one), (_, two)] = &pages[..] else {
+        unreachable!()
+    };
+    assert!(index.contains("<h1>Doc</h1>"));
+    assert!(index.contains(r#"<li><a href="section-1.html">1 One</a></li>"#));
+    assert!(index.contains(r#"<li><a href="section-2.html">2 Two</a></li>"#));
+
+    assert!(one.contains(r#"<h2 id="sec-one">"#));
+    assert!(!one.contains("Two"));
+    assert!(one.contains(r#"<a href="section-2.html" rel="next">Next</a>"#));
+    assert!(one.contains(r#"<ol class="footnotes">"#));
+
+    assert!(two.contains(r#"<a href="section-1.html#sec-one">1</a>"#));
+    assert!(two.contains(r#"<a href="section-2.html#sec-two">"#));
+    assert!(!two.contains("rel=\"next\""));
+    assert!(two.contains(r#"<ol start="2" class="footnotes">"#));
+    assert!(!two.contains("A note."));
+}
+
+#[test]
+fn theorem_styles_have_classes() {
+    let html = test_index_html(
+        indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{theorem}
+          Something.
+        \end{theorem}
+        \begin{definition}[Thing]
+          Something else.
+        \end{definition}
+        \begin{remark}
+          A remark.
+        \end{remark}
+        \end{document}
+    "#},
+        &EmitOptions::default(),
+    );
+    let classes: Vec<&str> = html
+        .lines()
+        .filter_map(|line| line.strip_prefix(r#"<div class="theorem-like "#))
+        .collect();
+    assert_eq!(
+        classes,
+        [
+            r#"theorem-style-theorem">"#,
+            r#"theorem-style-definition">"#,
+            r#"theorem-style-remark">"#
+        ]
+    );
+    assert!(html.contains(r#"<span class="theorem-note">(Thing)</span>"#));
 }
@@ -0,0 +1,209 @@
+use crate::macros::literal_len;
+use crate::parse::{any_ws, Result};
+use crate::source_map::SourceMap;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_while1};
+use nom::character::complete::char;
+use nom::sequence::delimited;
+use nom::Offset;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Included files may include further files up to this depth.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub enum IncludeError {
+    // The included file could not be read.
+    Io { path: PathBuf, error: io::Error },
+    // A file includes itself, directly or via other files.
+    Cycle(PathBuf),
+    // Includes are nested deeper than MAX_INCLUDE_DEPTH.
+    TooDeep(PathBuf),
+}
+
+impl Display for IncludeError {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        use IncludeError::*;
+        match self {
+            Io { path, error } => {
+                let path = path.display();
+                write!(out, "Could not read included file \"{path}\": {error}")
+            }
+            Cycle(path) => write!(out, "File \"{}\" includes itself", path.display()),
+            TooDeep(path) => write!(
+                out,
+                "Includes are nested too deeply at \"{}\"",
+                path.display()
+            ),
+        }
+    }
+}
+
+// Parses \input{path} or \include{path} and returns the path.
+fn include_directive<'a>(i: &'a str) -> Result<'a, &'a str> {
+    let (i, _) = alt((tag("\\input"), tag("\\include")))(i)?;
+    let (i, _) = any_ws(i)?;
+    delimited(
+        char('{'),
+        take_while1(|c: char| c != '}' && c != '\n'),
+        char('}'),
+    )(i)
+}
+
+// As in latex, included paths are relative to the directory of the main file, and .tex is
+// appended if the path has no extension.
+fn resolve_include_path(base_dir: &Path, path: &str) -> PathBuf {
+    let mut path = base_dir.join(path.trim());
+    if path.extension().is_none() {
+        path.set_extension("tex");
+    }
+    path
+}
+
+fn expand_into(
+    out: &mut String,
+    source_map: &mut SourceMap,
+    // The index of the file in source_map, and its source.
+    file: usize,
+    src: &str,
+    base_dir: &Path,
+    // The canonical paths of the files that are currently being included.
+    include_stack: &mut Vec<PathBuf>,
+) -> std::result::Result<(), IncludeError> {
+    // Copies the first len bytes of i, which is a suffix of src, to the output.
+    let copy = |out: &mut String, source_map: &mut SourceMap, i: &str, len: usize| {
+        source_map.push_copy(file, out.len(), src.offset(i), len);
+        out.push_str(&i[..len]);
+    };
+
+    let mut i = src;
+    while let Some(pos) = i.find(['\\', '%']) {
+        copy(out, source_map, i, pos);
+        i = &i[pos..];
+
+        // Directives in comments, verbatim environments and \verb are not expanded.
+        if let Some(end) = literal_len(i) {
+            copy(out, source_map, i, end);
+            i = &i[end..];
+            continue;
+        }
+
+        let Ok((rest, path)) = include_directive(i) else {
+            // Skip the backslash and the character after it, which might be an escaped %.
+            let len = i[1..].chars().next().map_or(1, |c| 1 + c.len_utf8());
+            copy(out, source_map, i, len);
+            i = &i[len..];
+            continue;
+        };
+        let path = resolve_include_path(base_dir, path);
+        let io_error = |error| IncludeError::Io {
+            path: path.clone(),
+            error,
+        };
+        let canonical_path = fs::canonicalize(&path).map_err(io_error)?;
+        if include_stack.contains(&canonical_path) {
+            return Err(IncludeError::Cycle(path.clone()));
+        }
+        if include_stack.len() > MAX_INCLUDE_DEPTH {
+            return Err(IncludeError::TooDeep(path.clone()));
+        }
+        let included = fs::read_to_string(&path).map_err(io_error)?;
+        include_stack.push(canonical_path);
+        let included_file = source_map.add_file(path, included.clone());
+        expand_into(
+            out,
+            source_map,
+            included_file,
+            &included,
+            base_dir,
+            include_stack,
+        )?;
+        include_stack.pop();
+        i = rest;
+    }
+    copy(out, source_map, i, i.len());
+    Ok(())
+}
+
+// Replaces \input and \include directives in the source of the tex file at the given path by the
+// contents of the files they refer to.
+pub fn expand_includes(tex: &str, tex_path: &Path) -> std::result::Result<String, IncludeError> {
+    let (expanded, _) = expand_includes_with_source_map(tex, tex_path)?;
+    Ok(expanded)
+}
+
+// Like expand_includes, but also returns the map from the expanded source to the files its text
+// comes from.
+pub fn expand_includes_with_source_map(
+    tex: &str,
+    tex_path: &Path,
+) -> std::result::Result<(String, SourceMap), IncludeError> {
+    let base_dir = tex_path.parent().unwrap_or(Path::new("."));
+    let mut include_stack: Vec<PathBuf> = fs::canonicalize(tex_path).into_iter().collect();
+    let mut source_map = SourceMap::default();
+    let file = source_map.add_file(tex_path.to_path_buf(), tex.to_string());
+    let mut result = String::new();
+    expand_into(
+        &mut result,
+        &mut source_map,
+        file,
+        tex,
+        base_dir,
+        &mut include_stack,
+    )?;
+    Ok((result, source_map))
+}
+
+#[test]
+fn included_files_are_spliced() {
+    let dir = tempdir::TempDir::new("latex-to-html").unwrap();
+    fs::create_dir(dir.path().join("sections")).unwrap();
+    fs::write(
+        dir.path().join("sections/intro.tex"),
+        "\\section{Intro}\n\\input{sections/details}\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("sections/details.tex"), "Details.").unwrap();
+    let tex_path = dir.path().join("main.tex");
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \include{sections/intro}
+        % \input{missing}
+        \includegraphics{x} 100\%\input {sections/details.tex}
+        \end{document}
+    "#};
+    let expanded = expand_includes(tex, &tex_path).unwrap();
+    assert_eq!(
+        expanded,
+        indoc::indoc! {r#"
+            \documentclass{article}
+            \begin{document}
+            \section{Intro}
+            Details.
+
+            % \input{missing}
+            \includegraphics{x} 100\%Details.
+            \end{document}
+        "#}
+    );
+}
+
+#[test]
+fn include_errors() {
+    let dir = tempdir::TempDir::new("latex-to-html").unwrap();
+    let tex_path = dir.path().join("main.tex");
+    fs::write(dir.path().join("a.tex"), "\\input{b}").unwrap();
+    fs::write(dir.path().join("b.tex"), "\\input{a}").unwrap();
+    match expand_includes("\\input{a}", &tex_path) {
+        Err(IncludeError::Cycle(path)) => assert_eq!(path, dir.path().join("a.tex")),
+        result => panic!("Expected cycle, got {result:?}"),
+    }
+    match expand_includes("\\input{missing}", &tex_path) {
+        Err(IncludeError::Io { path, .. }) => assert_eq!(path, dir.path().join("missing.tex")),
+        result => panic!("Expected io error, got {result:?}"),
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt::{self, Display, Formatter, Write};
+
+// A json value that can be displayed as indented json.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    String(String),
+    Array(Vec<Json>),
+    // The fields of an object in the order in which they are displayed.
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl From<&str> for Json {
+    fn from(value: &str) -> Self {
+        Json::String(value.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(value: String) -> Self {
+        Json::String(value)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+fn write_json_string(out: &mut Formatter, value: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\t' => out.write_str("\\t")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+fn write_indent(out: &mut Formatter, level: usize) -> fmt::Result {
+    for _ in 0..level {
+        out.write_str("  ")?;
+    }
+    Ok(())
+}
+
+impl Json {
+    fn write(&self, out: &mut Formatter, level: usize) -> fmt::Result {
+        match self {
+            Json::Null => out.write_str("null"),
+            Json::String(value) => write_json_string(out, value),
+            Json::Array(items) if items.is_empty() => out.write_str("[]"),
+            Json::Array(items) => {
+                out.write_str("[\n")?;
+                for (i, item) in items.iter().enumerate() {
+                    write_indent(out, level + 1)?;
+                    item.write(out, level + 1)?;
+                    if i + 1 < items.len() {
+                        out.write_char(',')?;
+                    }
+                    out.write_char('\n')?;
+                }
+                write_indent(out, level)?;
+                out.write_char(']')
+            }
+            Json::Object(fields) if fields.is_empty() => out.write_str("{}"),
+            Json::Object(fields) => {
+                out.write_str("{\n")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    write_indent(out, level + 1)?;
+                    write_json_string(out, name)?;
+                    out.write_str(": ")?;
+                    value.write(out, level + 1)?;
+                    if i + 1 < fields.len() {
+                        out.write_char(',')?;
+                    }
+                    out.write_char('\n')?;
+                }
+                write_indent(out, level)?;
+                out.write_char('}')
+            }
+        }
+    }
+}
+
+impl Display for Json {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        self.write(out, 0)
+    }
+}
+
+#[test]
+fn json_is_indented_and_escaped() {
+    let json = Json::Object(vec![
+        ("title", "A \"quoted\"\ntitle".into()),
+        ("date", Json::Null),
+        ("keys", Json::Array(vec!["a".into(), "b\\c".into()])),
+        ("empty", Json::Array(Vec::new())),
+    ]);
+    assert_eq!(
+        json.to_string(),
+        indoc::indoc! {r#"
+            {
+              "title": "A \"quoted\"\ntitle",
+              "date": null,
+              "keys": [
+                "a",
+                "b\\c"
+              ],
+              "empty": []
+            }"#}
+    );
+}
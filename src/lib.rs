@@ -1,24 +1,49 @@
 mod analysis;
-mod ast;
+pub mod ast;
+mod bib_strings;
 mod display_source;
 mod emit;
+mod includes;
+mod json;
+mod macros;
+mod math_html;
+mod math_mathml;
 mod math_svg;
 mod parse;
+mod source_map;
 mod util;
 
-use crate::analysis::Analysis;
+pub use crate::analysis::DocumentSummary;
+use crate::analysis::{svg_math, Analysis};
 use crate::ast::*;
+pub use crate::bib_strings::expand_bib_strings;
+pub use crate::bib_strings::UndefinedStringError;
 use crate::display_source::*;
-use crate::emit::emit;
+use crate::emit::{emit, index_html, style_css};
+pub use crate::emit::{BibNameFormat, EmitOptions, MathBackend};
+use crate::includes::expand_includes_with_source_map;
+pub use crate::includes::{expand_includes, IncludeError};
+pub use crate::macros::expand_macros;
+use crate::macros::expand_macros_with_spans;
+pub use crate::macros::MacroRecursionError;
+pub use crate::math_mathml::MathMlError;
 use crate::math_svg::*;
+pub use crate::math_svg::{parse_tex_log, LatexEngine, LatexToSvgError, TexLogError};
 use crate::parse::{bib, document};
+use crate::source_map::SourceMap;
 use indoc::eprintdoc;
+use itertools::Itertools;
 use nom::combinator::complete;
 use nom::Offset;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::{self, Display, Formatter};
+use std::io;
 use std::iter::repeat;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::str::from_utf8;
+use tempdir::TempDir;
 
 fn read_file(file_path: &Path) -> String {
     match std::fs::read_to_string(file_path) {
@@ -33,25 +58,93 @@ fn read_file(file_path: &Path) -> String {
     }
 }
 
-fn parse_source<'a, O>(
+// Finds the bib file. An explicitly given path takes precedence; otherwise, the name in the
+// \bibliography command of the document is used, relative to the directory of the tex file. Bib
+// files are conventionally referred to without the .bib extension, e.g. in \bibliography{refs}, so
+// we try the path as given first and then with .bib appended. On failure, returns the paths that
+// were tried, which is empty if there is neither an explicit path nor a \bibliography command.
+fn resolve_bib_path(
+    bib_path: Option<&Path>,
+    tex_path: &Path,
+    bibliography_name: Option<&str>,
+) -> Result<PathBuf, Vec<PathBuf>> {
+    let tex_dir = tex_path.parent().unwrap_or(Path::new("."));
+    let bib_path = match (bib_path, bibliography_name) {
+        (Some(bib_path), _) => bib_path.to_path_buf(),
+        (None, Some(name)) => tex_dir.join(name),
+        (None, None) => return Err(Vec::new()),
+    };
+    let mut candidates = vec![bib_path.clone()];
+    if bib_path.extension() != Some(OsStr::new("bib")) {
+        let mut with_extension = bib_path.into_os_string();
+        with_extension.push(".bib");
+        candidates.push(PathBuf::from(with_extension));
+    }
+
+    match candidates.iter().find(|path| path.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => Err(candidates),
+    }
+}
+
+fn bibliography_name<'a>(doc: &Document<'a>) -> Option<&'a str> {
+    doc.parts.iter().find_map(|part| match part {
+        DocumentPart::Bibliography(name) => Some(*name),
+        _ => None,
+    })
+}
+
+// The position at which a source could not be parsed, and what was expected there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub offset: usize,
+    pub expected: Vec<String>,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        write!(out, "Unexpected token")?;
+        if !self.expected.is_empty() {
+            let expected = self.expected.iter().format(" or ");
+            write!(out, ", expected {expected}")?;
+        }
+        Ok(())
+    }
+}
+
+// Parses the complete source. On failure, returns the offset at which parsing failed.
+fn parse_str<'a, O>(
     parser: impl FnMut(&'a str) -> parse::Result<'a, O>,
     source: &'a str,
-    source_path: &'a Path,
-) -> O {
+) -> Result<O, SyntaxError> {
     match complete(parser)(source) {
-        Ok((_, o)) => o,
+        Ok((_, o)) => Ok(o),
         Err(nom::Err::Incomplete(_)) => panic!(),
         Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
-            //let location = remaining_begin_location(e.input, source);
-            let location = Location(source.offset(e.input), source.offset(e.input) + 1);
-            let location_display = SourceDisplay {
-                source,
-                location,
-                source_path: Some(source_path),
-                underlined: true,
-            };
+            let mut expected = e.expected;
+            expected.dedup();
+            Err(SyntaxError {
+                offset: source.offset(e.input),
+                expected,
+            })
+        }
+    }
+}
+
+// Parses the complete source or exits with an error at the location in the file that source_map
+// maps the source to.
+fn parse_source<'a, O>(
+    parser: impl FnMut(&'a str) -> parse::Result<'a, O>,
+    source: &'a str,
+    source_map: &SourceMap,
+) -> O {
+    match parse_str(parser, source) {
+        Ok(o) => o,
+        Err(err) => {
+            let location = Location(err.offset, err.offset + 1);
+            let location_display = source_map.display(location, true);
             eprintdoc! {"
-                Error: Unexpected token
+                Error: {err}
                 {location_display}
             "};
             process::exit(1);
@@ -59,19 +152,132 @@ fn parse_source<'a, O>(
     }
 }
 
-pub fn print_latex_to_svg_error(
-    tex_path: &Path,
+fn math_source<'a>(math: &Math<'a>) -> &'a str {
+    use Math::*;
+    match math {
+        Inline(src) => src,
+        Display { source, .. } | Mathpar { source, .. } | Align { source, .. } => source,
+    }
+}
+
+// Returns the first math node that can't be converted if math is rendered as MathML.
+fn unsupported_mathml<'a>(
+    node_lists: &NodeLists<'a>,
+    options: &EmitOptions,
+) -> Option<(&'a Math<'a>, MathMlError)> {
+    if options.math_backend != MathBackend::MathMl {
+        return None;
+    }
+    node_lists
+        .math
+        .iter()
+        .copied()
+        .find_map(|math| Some((math, math_mathml::math_to_mathml(math).err()?)))
+}
+
+fn print_mathml_error(tex_src: &str, source_map: &SourceMap, math: &Math, error: &MathMlError) {
+    let math_source = math_source(math);
+    let location_begin = tex_src.offset(math_source);
+    let location = Location(location_begin, location_begin + math_source.len());
+    let location_display = source_map.display(location, matches!(math, Math::Inline(_)));
+    eprintdoc! {r#"
+        Error: Math formula is not supported by the MathML backend: {error}
+        {location_display}
+    "#};
+}
+
+// Prints warnings for \ref and \cite ids that are displayed as "???" because there is no matching
+// label or bibliography entry.
+fn print_unresolved_warnings(tex_src: &str, source_map: &SourceMap, analysis: &Analysis) {
+    let unresolved_refs = analysis.unresolved_refs.iter().map(|id| ("reference", id));
+    let unresolved_cites = analysis.unresolved_cites.iter().map(|id| ("citation", id));
+    for (kind, id) in unresolved_refs.chain(unresolved_cites) {
+        let location_begin = tex_src.offset(id);
+        let location = Location(location_begin, location_begin + id.len());
+        let location_display = source_map.display(location, true);
+        eprintdoc! {r#"
+            Warning: Undefined {kind} "{id}"
+            {location_display}
+        "#};
+    }
+}
+
+// Prints an error, or a warning if is_error is false, for each label that is defined more than
+// once.
+fn print_duplicate_labels(
+    tex_src: &str,
+    source_map: &SourceMap,
+    analysis: &Analysis,
+    is_error: bool,
+) {
+    let severity = if is_error { "Error" } else { "Warning" };
+    let label_display = |label: &str| {
+        let location_begin = tex_src.offset(label);
+        source_map.display(Location(location_begin, location_begin + label.len()), true)
+    };
+    for (first, duplicate) in analysis.duplicate_labels.iter().copied() {
+        let duplicate_display = label_display(duplicate);
+        let first_display = label_display(first);
+        eprintdoc! {r#"
+            {severity}: Duplicate label "{duplicate}"
+            {duplicate_display}
+            Note: The label was first defined here:
+            {first_display}
+        "#};
+    }
+}
+
+fn print_latex_to_svg_error(
     tex_src: &str,
+    source_map: &SourceMap,
     preamble: &[&str],
     math: &Math,
     error: &LatexToSvgError,
+    options: &EmitOptions,
 ) {
+    use Math::*;
+    let math_source = math_source(math);
+    let location_begin = tex_src.offset(math_source);
+    let location = Location(location_begin, location_begin + math_source.len());
+    debug_assert_eq!(&tex_src[location.0..location.1], math_source);
+
+    let underlined = match math {
+        Inline(_) => true,
+        Display { .. } | Mathpar { .. } | Align { .. } => false,
+    };
+    let location_display = source_map.display(location, underlined);
+
+    // A timeout is usually caused by a macro that loops, either in the formula itself or in the
+    // preamble, so diagnosing the preamble would likely run into the timeout again.
+    if let LatexToSvgError::Timeout = error {
+        let timeout_secs = options.latex_timeout.as_secs_f64();
+        eprintdoc! {r#"
+            Error: Compiling math formula timed out after {timeout_secs}s
+            {location_display}
+
+            Note: The formula or the preamble might contain a macro that never terminates.
+        "#};
+        return;
+    }
+
+    if let LatexToSvgError::UnbalancedBraces(offset) = error {
+        let offset = location_begin + offset;
+        let location_display = source_map.display(Location(offset, offset + 1), true);
+        eprintdoc! {r#"
+            Error: Unbalanced braces in math formula
+            {location_display}
+
+            Note: Every "{{" and "\begin{{...}}" needs a matching "}}" and "\end{{...}}".
+        "#};
+        return;
+    }
+
     // First obtain the output from just compiling a dummy formuala with the preamble. This way,
     // we can either diagnose problems with the preamble (if there are some) or remove irrelevant
     // parts from the output for compiling the formula at hand.
-    let default_output = match diagnose_preamble(preamble).unwrap() {
-        PreambleDiagnosis::Ok(output) => output,
-        PreambleDiagnosis::OffendingLines(output, lines) => {
+    let default_output = match diagnose_preamble(preamble, options) {
+        Ok(PreambleDiagnosis::Ok(output)) => Some(output),
+        Ok(PreambleDiagnosis::OffendingLines(output, lines)) => {
             let location = match lines {
                 [] => Location(0, 1),
                 [line] => {
@@ -86,12 +292,7 @@ pub fn print_latex_to_svg_error(
                 }
             };
 
-            let location_display = SourceDisplay {
-                source: tex_src,
-                location,
-                source_path: Some(tex_path),
-                underlined: false,
-            };
+            let location_display = source_map.display(location, false);
 
             let stdout = from_utf8(&output.stdout).unwrap();
 
@@ -104,13 +305,30 @@ pub fn print_latex_to_svg_error(
 
                         % LATEX_TO_HTML_IGNORE
                          
-                      to make latex-to-html ignore the next line.
+                      to make latex-to-html ignore the next line, or the line
+
+                        % LATEX_TO_HTML_NO_MATH
+
+                      to use the next line for the document but not for compiling math.
 
                 ================================================================================
                 {stdout}
             "#};
             return;
         }
+        // E.g. the tex engine is missing or times out. The error in the formula is still shown,
+        // only without removing the lines that compiling any formula produces.
+        Err(err) => {
+            let reason = match err {
+                LatexToSvgError::Io(err) => err.to_string(),
+                LatexToSvgError::Timeout => "The tex engine timed out".to_string(),
+                err => format!("{err:?}"),
+            };
+            eprintdoc! {"
+                Error: Could not check the preamble: {reason}
+            "};
+            None
+        }
     };
 
     use LatexToSvgError::*;
@@ -126,34 +344,23 @@ pub fn print_latex_to_svg_error(
     };
 
     let stdout = from_utf8(&pdf_latex_output.stdout).unwrap();
-
-    use Math::*;
-    let math_source = match math {
-        Inline(src) => src,
-        Display { source, .. } | Mathpar { source, .. } => source,
-    };
-    let location_begin = tex_src.offset(math_source);
-    let location = Location(location_begin, location_begin + math_source.len());
-    debug_assert_eq!(&&tex_src[location.0..location.1], math_source);
-
-    let location_display = SourceDisplay {
-        source: tex_src,
-        location,
-        source_path: Some(tex_path),
-        underlined: match math {
-            Inline(_) => true,
-            Display { .. } | Mathpar { .. } => false,
-        },
-    };
+    let log_errors = parse_tex_log(stdout)
+        .into_iter()
+        .format_with("\n", |error, f| f(&format_args!("! {}", error.message)));
 
     eprintdoc! {r#"
         Error: Math formula is invalid
         {location_display}
 
+        {log_errors}
+
         ================================================================================
     "#};
 
-    let default_stdout = from_utf8(&default_output.stdout).unwrap();
+    let default_stdout = match &default_output {
+        Some(output) => from_utf8(&output.stdout).unwrap(),
+        None => "",
+    };
 
     // Only display those lines that do not appear in the default output.
     let relevant_lines = stdout
@@ -170,35 +377,640 @@ pub fn print_latex_to_svg_error(
     }
 }
 
-pub fn latex_to_html(tex_path: &Path, bib_path: &Path, out_path: &Path) {
-    let tex_src = read_file(tex_path);
-    let doc = parse_source(document, tex_src.as_str(), tex_path);
+// Converts the tex file at tex_path. If bib_path is None, the bib file named by the \bibliography
+// command of the document is used.
+pub fn latex_to_html(
+    tex_path: &Path,
+    bib_path: Option<&Path>,
+    out_path: &Path,
+    options: &EmitOptions,
+) {
+    let (tex_src, source_map) =
+        match expand_includes_with_source_map(&read_file(tex_path), tex_path) {
+            Ok(expanded) => expanded,
+            Err(err) => {
+                eprintln!("Error: {err}");
+                process::exit(1);
+            }
+        };
+    let (expanded_src, spans) = match expand_macros_with_spans(&tex_src) {
+        Ok(expanded) => expanded,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            process::exit(1);
+        }
+    };
+    // Diagnostics refer to the expanded source, which the source map maps back to the files.
+    let source_map = source_map.then(&spans);
+    let tex_src = expanded_src;
+    let doc = parse_source(document, tex_src.as_str(), &source_map);
 
-    let bib_src = read_file(bib_path);
-    let bib_entries = parse_source(bib, bib_src.as_str(), bib_path);
+    let bib_path = match resolve_bib_path(bib_path, tex_path, bibliography_name(&doc)) {
+        Ok(bib_path) => bib_path,
+        Err(tried) if tried.is_empty() => {
+            eprintdoc! {r#"
+                Error: No bibliography file given, and the document has no \bibliography command
+            "#};
+            process::exit(1);
+        }
+        Err(tried) => {
+            let tried = tried
+                .iter()
+                .format_with("\n", |path, f| f(&format_args!("  {}", path.display())));
+            eprintdoc! {r#"
+                Error: Could not find bibliography file. Tried the following paths:
+                {tried}
+            "#};
+            process::exit(1);
+        }
+    };
+    let bib_src = read_file(&bib_path);
+    let bib_src = match expand_bib_strings(&bib_src) {
+        Ok(expanded) => expanded,
+        Err(err) => {
+            let location = Location(err.offset, err.offset + err.name.len());
+            let location_display = SourceDisplay {
+                source: &bib_src,
+                location,
+                source_path: Some(&bib_path),
+                underlined: true,
+            };
+            eprintdoc! {"
+                Error: {err}
+                {location_display}
+            "};
+            process::exit(1);
+        }
+    };
+    let bib_source_map = SourceMap::new(bib_path.clone(), bib_src.clone());
+    let bib_entries = parse_source(bib, bib_src.as_str(), &bib_source_map);
 
-    // Generate lists of nodes and analyze the bib/latex asts.
+    // Generate lists of nodes and compile math to svg files. Math that is rendered as html
+    // doesn't need to be compiled.
     let node_lists = NodeLists::new(&doc);
-    let analysis = Analysis::new(&doc, &bib_entries, &node_lists);
-
-    emit(&out_path, &doc, &analysis);
-    if let Err((math, err)) = emit_math_svg_files(&out_path, &doc.preamble, &node_lists.math) {
+    if let Some((math, err)) = unsupported_mathml(&node_lists, options) {
+        print_mathml_error(tex_src.as_str(), &source_map, math, &err);
+        process::exit(1);
+    }
+    let svg_math = svg_math(&node_lists, options);
+    // Standalone output consists of index.html only, so we compile math in a temporary directory
+    // and embed the svgs into the html.
+    let svg_tmp_dir;
+    let svg_root = if options.standalone {
+        svg_tmp_dir = TempDir::new("latex-to-html").unwrap();
+        svg_tmp_dir.path()
+    } else {
+        out_path
+    };
+    let (math_svg_info, math_svg_errors) =
+        emit_math_svg_files(svg_root, &doc.preamble, &svg_math, options);
+    for (math, err) in math_svg_errors.iter() {
         print_latex_to_svg_error(
-            tex_path,
             tex_src.as_str(),
+            &source_map,
             doc.preamble.as_slice(),
             math,
-            &err,
+            err,
+            options,
         );
+    }
+    if !options.best_effort_math && !math_svg_errors.is_empty() {
         process::exit(1);
     }
+    if options.prune_math_svgs && !options.standalone {
+        let digests = math_svg_info.keys().copied().collect();
+        prune_math_svg_files(svg_root, &digests).unwrap();
+    }
+
+    let math_svg_data = if options.standalone {
+        read_math_svg_files(svg_root, &math_svg_info).unwrap()
+    } else {
+        HashMap::new()
+    };
+
+    // Analyze the bib/latex asts.
+    let analysis = Analysis::new(
+        &doc,
+        &bib_entries,
+        &node_lists,
+        options,
+        &math_svg_info,
+        &math_svg_data,
+    );
+    print_unresolved_warnings(tex_src.as_str(), &source_map, &analysis);
+    // Duplicate labels break links, but it's better to see the rest of the document in best
+    // effort mode.
+    let duplicate_labels_are_errors = !options.best_effort_math;
+    print_duplicate_labels(
+        tex_src.as_str(),
+        &source_map,
+        &analysis,
+        duplicate_labels_are_errors,
+    );
+    if duplicate_labels_are_errors && !analysis.duplicate_labels.is_empty() {
+        process::exit(1);
+    }
+
+    let source_dir = tex_path.parent().unwrap_or(Path::new("."));
+    emit(out_path, source_dir, &doc, &analysis, options);
+}
+
+// The files of a document converted by convert_str. Paths are relative to the output directory.
+#[derive(Debug)]
+pub struct ConvertOutput {
+    // index.html
+    pub index_html: String,
+    // style.css
+    pub style_css: String,
+    // img-math/geometry.css, which sizes the math images. Empty for standalone output.
+    pub geometry_css: String,
+    // The math images as (path, contents) pairs, e.g. ("img-math/<digest>.svg", ...), sorted by
+    // path. Empty for standalone output, where the images are embedded into index_html.
+    pub svgs: Vec<(String, Vec<u8>)>,
+    // The sources of the formulas that failed to compile, together with the errors. Only
+    // non-empty with EmitOptions::best_effort_math; the formulas are shown as placeholders.
+    pub math_errors: Vec<(String, LatexToSvgError)>,
+}
+
+#[derive(Debug)]
+pub enum ConversionError {
+    // The tex source could not be parsed.
+    TexSyntax(SyntaxError),
+    // The bib source could not be parsed.
+    BibSyntax(SyntaxError),
+    // A label is defined more than once. Not an error with EmitOptions::best_effort_math.
+    DuplicateLabel(String),
+    // A field in the bib source refers to a string that was not defined with @string.
+    BibString(UndefinedStringError),
+    // A macro defined with \newcommand expands infinitely.
+    Macro(MacroRecursionError),
+    // A file included with \input or \include could not be read, or includes itself.
+    Include(IncludeError),
+    // A math formula is not supported by the MathML backend.
+    MathMl {
+        source: String,
+        error: MathMlError,
+    },
+    // A math formula could not be compiled to svg.
+    Math {
+        source: String,
+        error: LatexToSvgError,
+    },
+    Io(io::Error),
+}
+
+impl From<io::Error> for ConversionError {
+    fn from(err: io::Error) -> ConversionError {
+        ConversionError::Io(err)
+    }
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        use ConversionError::*;
+        match self {
+            TexSyntax(err) => write!(out, "{err} in tex source at byte {}", err.offset),
+            BibSyntax(err) => write!(out, "{err} in bib source at byte {}", err.offset),
+            Math { source, error } => write!(out, "Math formula is invalid: {source}: {error}"),
+            DuplicateLabel(label) => write!(out, "Duplicate label \"{label}\""),
+            BibString(err) => write!(out, "{err}"),
+            Macro(err) => write!(out, "{err}"),
+            Include(err) => write!(out, "{err}"),
+            MathMl { source, error } => write!(out, "Unsupported math formula: {source}: {error}"),
+            Io(err) => write!(out, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+// Reads a tex file and replaces \input and \include directives by the contents of the files they
+// refer to.
+pub fn read_tex_file(tex_path: &Path) -> Result<String, ConversionError> {
+    let tex = std::fs::read_to_string(tex_path)?;
+    expand_includes(&tex, tex_path).map_err(ConversionError::Include)
+}
+
+/// Parses a tex document without converting it. Macros defined with `\newcommand` are not
+/// expanded; pass the source through `expand_macros` first if the document uses them.
+///
+/// ```
+/// use latex_to_html::ast::DocumentPart;
+/// use latex_to_html::{expand_macros, parse_document};
+///
+/// let tex = expand_macros(
+///     r"\documentclass{article}
+/// \newcommand{\name}{world}
+/// \begin{document}
+/// \section{Hello}
+/// Hello \name.
+/// \end{document}",
+/// )
+/// .unwrap();
+/// let doc = parse_document(&tex).unwrap();
+/// assert!(matches!(doc.parts[0], DocumentPart::Section { .. }));
+/// assert!(matches!(doc.parts[1], DocumentPart::FreeParagraph(_)));
+/// ```
+pub fn parse_document<'a>(tex: &'a str) -> Result<Document<'a>, ConversionError> {
+    parse_str(document, tex).map_err(ConversionError::TexSyntax)
+}
+
+// Parses the entries of a bib file. Strings defined with @string are not expanded; pass the source
+// through expand_bib_strings first if the file uses them.
+pub fn parse_bibliography<'a>(bib_src: &'a str) -> Result<Vec<BibEntry<'a>>, ConversionError> {
+    parse_str(bib, bib_src).map_err(ConversionError::BibSyntax)
+}
+
+// Computes the numbers, labels and citations of a parsed document as they would be displayed after
+// conversion with the given options.
+pub fn summarize_document<'a>(
+    doc: &'a Document<'a>,
+    bib_entries: &'a [BibEntry<'a>],
+    options: &EmitOptions,
+) -> DocumentSummary<'a> {
+    DocumentSummary::new(doc, bib_entries, options)
+}
+
+// Converts a document without touching the file system, except for a temporary directory in which
+// math is compiled.
+pub fn convert_str(
+    tex: &str,
+    bib_src: &str,
+    options: &EmitOptions,
+) -> Result<ConvertOutput, ConversionError> {
+    let tex = expand_macros(tex).map_err(ConversionError::Macro)?;
+    let doc = parse_str(document, &tex).map_err(ConversionError::TexSyntax)?;
+    let bib_src = expand_bib_strings(bib_src).map_err(ConversionError::BibString)?;
+    let bib_entries = parse_str(bib, &bib_src).map_err(ConversionError::BibSyntax)?;
+
+    let node_lists = NodeLists::new(&doc);
+    if let Some((math, error)) = unsupported_mathml(&node_lists, options) {
+        return Err(ConversionError::MathMl {
+            source: math_source(math).to_string(),
+            error,
+        });
+    }
+    let svg_math = svg_math(&node_lists, options);
+    // The svgs are written to a temporary directory, which is only needed if there is math to
+    // compile.
+    let svg_tmp_dir = if svg_math.is_empty() {
+        None
+    } else {
+        Some(TempDir::new("latex-to-html")?)
+    };
+    let (math_svg_info, math_svg_errors) = match &svg_tmp_dir {
+        None => (HashMap::new(), Vec::new()),
+        Some(dir) => emit_math_svg_files(dir.path(), &doc.preamble, &svg_math, options),
+    };
+    let mut math_errors = math_svg_errors
+        .into_iter()
+        .map(|(math, error)| (math_source(math).to_string(), error));
+    if !options.best_effort_math {
+        if let Some((source, error)) = math_errors.next() {
+            return Err(ConversionError::Math { source, error });
+        }
+    }
+    let math_errors = math_errors.collect();
+    let math_svg_data = match &svg_tmp_dir {
+        None => HashMap::new(),
+        Some(dir) => read_math_svg_files(dir.path(), &math_svg_info)?,
+    };
+
+    let analysis = Analysis::new(
+        &doc,
+        &bib_entries,
+        &node_lists,
+        options,
+        &math_svg_info,
+        &math_svg_data,
+    );
+    if !options.best_effort_math {
+        if let Some((_, label)) = analysis.duplicate_labels.first() {
+            return Err(ConversionError::DuplicateLabel(label.to_string()));
+        }
+    }
+
+    let mut geometry_css = Vec::new();
+    let mut svgs = Vec::new();
+    if !options.standalone {
+        let mut digests: Vec<MathDigest> = math_svg_data.keys().copied().collect();
+        digests.sort_by_key(|digest| digest.0);
+        for digest in digests {
+            write_svg_geometry(&mut geometry_css, digest, &math_svg_info[&digest])?;
+            let path = format!("{SVG_OUT_DIR}/{digest}.svg");
+            svgs.push((path, math_svg_data[&digest].clone()));
+        }
+    }
+
+    Ok(ConvertOutput {
+        index_html: index_html(&doc, &analysis, options),
+        style_css: style_css(options),
+        geometry_css: String::from_utf8(geometry_css).unwrap(),
+        svgs,
+        math_errors,
+    })
+}
+
+#[test]
+fn convert_str_in_memory() {
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        Let $x_1$ be given \cite{doe}.
+        \bibliography{refs}
+        \end{document}
+    "#};
+    let bib = indoc::indoc! {r#"
+        @article{doe,
+          author = {Doe, Jane},
+          title = {On things},
+          year = {2020},
+        }
+    "#};
+    let options = EmitOptions {
+        trivial_math_html: true,
+        ..EmitOptions::default()
+    };
+    let output = convert_str(tex, bib, &options).unwrap();
+    assert!(output.index_html.contains("<i>x</i><sub>1</sub>"));
+    assert!(output.index_html.contains("On things"));
+    assert!(output.style_css.contains("--max-width: 600px;"));
+    assert!(output.svgs.is_empty());
+
+    match convert_str(
+        "\\documentclass{article}\n\\begin{document}\n}",
+        "",
+        &options,
+    ) {
+        Err(ConversionError::TexSyntax(_)) => (),
+        result => panic!("Expected syntax error, got {result:?}"),
+    }
+
+    let error = convert_str(
+        "\\documentclass{article}\n\\begin{document}\n$\\frac{a$\n\\end{document}",
+        "",
+        &EmitOptions::default(),
+    )
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Math formula is invalid: \\frac{a: Unbalanced braces at byte 5"
+    );
+}
+
+#[test]
+fn parse_only() {
+    let bib_src = expand_bib_strings(indoc::indoc! {r#"
+        @string{pub = "Publisher"}
+        @book{doe, author = {Doe, Jane}, title = {A book}, publisher = pub, year = {2020}}
+    "#})
+    .unwrap();
+    let entries = parse_bibliography(&bib_src).unwrap();
+    assert_eq!(entries[0].tag, "doe");
+    assert_eq!(entries[0].publisher, Some("Publisher"));
+
+    match parse_document("\\documentclass{article}\n\\begin{document}\n}") {
+        Err(ConversionError::TexSyntax(err)) => assert_eq!(err.offset, 41),
+        result => panic!("Expected syntax error, got {result:?}"),
+    }
+}
+
+#[test]
+fn document_summary() {
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section{Intro}
+        \begin{theorem}
+          \label{thm:main}
+          See \cite{doe} and \eqref{eq:a}.
+          \begin{equation}
+            \label{eq:a}
+            a
+          \end{equation}
+        \end{theorem}
+        \begin{align}
+          b \label{eq:b} \\
+          c \nonumber
+        \end{align}
+        \end{document}
+    "#};
+    let bib_src = "@misc{doe, title = {Things}}\n@misc{unused, title = {Other}}";
+    let doc = parse_document(tex).unwrap();
+    let bib_entries = parse_bibliography(bib_src).unwrap();
+    let options = EmitOptions {
+        number_all_display_math: true,
+        ..EmitOptions::default()
+    };
+    let summary = summarize_document(&doc, &bib_entries, &options);
+
+    let theorem_numbers: Vec<(&str, &str)> = summary
+        .numbered_parts
+        .iter()
+        .filter_map(|(part, number)| match part {
+            DocumentPart::TheoremLike { label, .. } => Some(((*label)?, number.as_str())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(theorem_numbers, [("thm:main", "1")]);
+    assert_eq!(summary.numbered_parts.len(), 2);
+    assert_eq!(summary.numbered_math.len(), 1);
+    assert_eq!(summary.numbered_math[0].0.label(), Some("eq:a"));
+    assert_eq!(summary.numbered_align_lines.len(), 1);
+    assert_eq!(summary.ref_display_text["eq:b"], "(2)");
+    assert_eq!(summary.cite_order, ["doe"]);
+    assert_eq!(summary.bib_entries.len(), 1);
+    assert_eq!(summary.cite_display_text["doe"], "1");
+}
+
+#[cfg(unix)]
+#[test]
+fn math_error_with_missing_latex_program() {
+    use std::os::unix::process::ExitStatusExt;
+    let tex_src = r"$\foo$";
+    let math = Math::Inline(&tex_src[1..5]);
+    let error = LatexToSvgError::PdfLatex(process::Output {
+        status: process::ExitStatus::from_raw(1 << 8),
+        stdout: b"! Undefined control sequence.\n".to_vec(),
+        stderr: Vec::new(),
+    });
+    let options = EmitOptions {
+        latex_program: Some(PathBuf::from("/nonexistent/latex")),
+        ..EmitOptions::default()
+    };
+    // Diagnosing the preamble fails, which must not abort reporting the error in the formula.
+    let source_map = SourceMap::new(PathBuf::from("doc.tex"), tex_src.to_string());
+    print_latex_to_svg_error(tex_src, &source_map, &[], &math, &error, &options);
+}
+
+#[test]
+fn explicit_bib_path() {
+    let dir = TempDir::new("latex-to-html").unwrap();
+    let tex_path = dir.path().join("doc.tex");
+    std::fs::write(dir.path().join("refs.bib"), "").unwrap();
+    std::fs::write(dir.path().join("other.bib"), "").unwrap();
+    let resolve = |bib_path: &str| {
+        let bib_path = dir.path().join(bib_path);
+        resolve_bib_path(Some(&bib_path), &tex_path, Some("other")).unwrap()
+    };
+    assert_eq!(resolve("refs"), dir.path().join("refs.bib"));
+    assert_eq!(resolve("refs.bib"), dir.path().join("refs.bib"));
+}
+
+#[test]
+fn bib_path_from_bibliography_command() {
+    let dir = TempDir::new("latex-to-html").unwrap();
+    std::fs::create_dir(dir.path().join("paper")).unwrap();
+    std::fs::write(dir.path().join("paper/refs.bib"), "").unwrap();
+    let tex_path = dir.path().join("paper/doc.tex");
+    let resolve = |name| resolve_bib_path(None, &tex_path, Some(name)).unwrap();
+    assert_eq!(resolve("refs"), dir.path().join("paper/refs.bib"));
+    assert_eq!(resolve("refs.bib"), dir.path().join("paper/refs.bib"));
+
+    let (_, doc) = document(
+        "\\documentclass{article}\n\\begin{document}\n\\bibliography{refs}\n\\end{document}\n",
+    )
+    .unwrap();
+    assert_eq!(bibliography_name(&doc), Some("refs"));
+}
+
+#[test]
+fn missing_bib_file() {
+    let dir = TempDir::new("latex-to-html").unwrap();
+    let tex_path = dir.path().join("doc.tex");
+    let missing = dir.path().join("missing");
+    assert_eq!(
+        resolve_bib_path(Some(&missing), &tex_path, None),
+        Err(vec![missing.clone(), dir.path().join("missing.bib")])
+    );
+    assert_eq!(
+        resolve_bib_path(None, &tex_path, Some("missing")),
+        Err(vec![missing, dir.path().join("missing.bib")])
+    );
+    assert_eq!(resolve_bib_path(None, &tex_path, None), Err(Vec::new()));
+}
+
+#[test]
+fn syntax_error_line_number() {
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        Some text.
+
+        More } text.
+        \end{document}
+    "#};
+    let offset = parse_str(document, tex).unwrap_err().offset;
+    assert_eq!(line_column(tex, offset), (5, 6));
+    let location_display = SourceDisplay {
+        source: tex,
+        location: Location(offset, offset + 1),
+        source_path: Some(Path::new("doc.tex")),
+        underlined: true,
+    };
+    let location_display = location_display.to_string();
+    assert!(location_display.contains("--> doc.tex:5:6\n"));
+    assert!(location_display.contains("5 | More } text.\n"));
+}
+
+#[test]
+fn syntax_error_after_macro_use() {
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \newcommand{\R}{\mathbb{R}}
+        \begin{document}
+        Let $x \in \R$ and $y \in \R$.
+
+        More } text.
+        \end{document}
+    "#};
+    let (expanded, spans) = expand_macros_with_spans(tex).unwrap();
+    let source_map = SourceMap::new(PathBuf::from("doc.tex"), tex.to_string()).then(&spans);
+    let offset = parse_str(document, &expanded).unwrap_err().offset;
+    let location_display = source_map.display(Location(offset, offset + 1), true);
+    let location_display = location_display.to_string();
+    assert!(location_display.contains("--> doc.tex:6:6\n"));
+    assert!(location_display.contains("6 | More } text.\n"));
+
+    // Text produced by a macro maps to the macro.
+    let math = expanded.find(r"\mathbb{R}$").unwrap();
+    let location = Location(math, math + r"\mathbb{R}".len());
+    let (_, Location(begin, end)) = source_map.locate(location);
+    assert_eq!(&tex[begin..end], r"\R");
+}
+
+#[test]
+fn syntax_error_in_included_file() {
+    let dir = TempDir::new("latex-to-html").unwrap();
+    std::fs::create_dir(dir.path().join("sections")).unwrap();
+    let intro = "\\section{Intro}\nSome text.\nMore } text.\n";
+    std::fs::write(dir.path().join("sections/intro.tex"), intro).unwrap();
+    let tex_path = dir.path().join("main.tex");
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \newcommand{\R}{\mathbb{R}}
+        \begin{document}
+        \input{sections/intro}
+        Let $x \in \R$.
+        \end{document}
+    "#};
+    let (spliced, source_map) = expand_includes_with_source_map(tex, &tex_path).unwrap();
+    let (expanded, spans) = expand_macros_with_spans(&spliced).unwrap();
+    let source_map = source_map.then(&spans);
+
+    let offset = parse_str(document, &expanded).unwrap_err().offset;
+    let location_display = source_map.display(Location(offset, offset + 1), true);
+    let location_display = location_display.to_string();
+    let intro_path = dir.path().join("sections/intro.tex");
+    let pointer = format!("--> {}:3:6\n", intro_path.display());
+    assert!(location_display.contains(&pointer));
+    assert!(location_display.contains("3 | More } text.\n"));
+
+    // Text after the included file maps back to the main file.
+    let math = expanded.find(r"\mathbb{R}$").unwrap();
+    let (file, Location(begin, _)) = source_map.locate(Location(math, math + 1));
+    assert_eq!(file.path, tex_path);
+    assert_eq!(line_column(tex, begin), (5, 12));
+}
+
+#[test]
+fn syntax_error_expected_end() {
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \begin{proof}
+        Trivial.
+    "#};
+    let err = parse_str(document, tex).unwrap_err();
+    assert_eq!(err.offset, tex.len());
+    assert!(err.expected.contains(&"\\end{proof}".to_string()));
+    assert!(err.to_string().contains("expected \\end{proof}"));
+}
+
+#[test]
+fn example_parses() {
+    let tex = expand_macros(include_str!("../example.tex")).unwrap();
+    let doc = parse_str(document, &tex).unwrap();
+    let bib_src = expand_bib_strings(include_str!("../example.bib")).unwrap();
+    let bib_entries = parse_str(bib, &bib_src).unwrap();
+    let node_lists = NodeLists::new(&doc);
+    assert!(!node_lists.math.is_empty());
+    assert!(node_lists
+        .cite_ids
+        .iter()
+        .all(|id| bib_entries.iter().any(|entry| entry.tag == *id)));
 }
 
+// Requires pdflatex, pdfcrop and pdf2svg; run with `cargo test -- --ignored`.
 #[test]
+#[ignore]
 fn example() {
+    let out_dir = TempDir::new("latex-to-html").unwrap();
     latex_to_html(
         Path::new("example.tex"),
-        Path::new("example.bib"),
-        Path::new("out/example"),
+        Some(Path::new("example.bib")),
+        out_dir.path(),
+        &EmitOptions::default(),
     );
+    assert!(out_dir.path().join("index.html").exists());
 }
@@ -0,0 +1,328 @@
+use crate::parse::{any_ws, raw_command_arg, Result};
+use crate::source_map::{push_copy, Span};
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_while1};
+use nom::character::complete::{anychar, char, digit1};
+use nom::combinator::{opt, recognize};
+use nom::sequence::{delimited, pair};
+use nom::Offset;
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+// Macros are expanded textually. Expanding a macro may produce further macro invocations, which
+// are expanded up to this depth.
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+// The expanded source may be at most this long, so that macros that don't recurse but double their
+// output at every level, e.g. \newcommand{\a}{\b\b}, can't exhaust memory.
+const MAX_EXPANDED_LEN: usize = 16 << 20;
+
+pub const VERBATIM_BEGIN: &str = "\\begin{verbatim}";
+pub const VERBATIM_END: &str = "\\end{verbatim}";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Macro<'a> {
+    pub arity: usize,
+    // The replacement text, with #1..#9 referring to arguments.
+    pub body: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroRecursionError {
+    // The name of the macro whose expansion exceeded the depth or the length limit, without
+    // backslash.
+    pub name: String,
+}
+
+impl Display for MacroRecursionError {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        let name = &self.name;
+        write!(
+            out,
+            "Expansion of macro \\{name} does not terminate or is too long"
+        )
+    }
+}
+
+// Parses the name of a control sequence, i.e. either a sequence of letters or a single other
+// character, following a backslash.
+fn control_sequence_name<'a>(i: &'a str) -> Result<'a, &'a str> {
+    let (i, _) = char('\\')(i)?;
+    alt((
+        take_while1(|c: char| c.is_ascii_alphabetic()),
+        recognize(anychar),
+    ))(i)
+}
+
+// Parses \newcommand{\name}[arity]{body} and the \renewcommand and starred variants.
+pub fn macro_definition<'a>(i: &'a str) -> Result<'a, (&'a str, Macro<'a>)> {
+    let (i, _) = alt((tag("\\newcommand"), tag("\\renewcommand")))(i)?;
+    let (i, _) = opt(char('*'))(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, name) = alt((
+        delimited(
+            pair(char('{'), any_ws),
+            control_sequence_name,
+            pair(any_ws, char('}')),
+        ),
+        control_sequence_name,
+    ))(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, arity) = opt(delimited(char('['), digit1, char(']')))(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, body) = delimited(char('{'), raw_command_arg, char('}'))(i)?;
+
+    let arity = arity.map_or(0, |arity| arity.parse().unwrap());
+    Ok((i, (name, Macro { arity, body })))
+}
+
+// Collects the macros defined in the given source. Later definitions override earlier ones.
+pub fn macro_definitions<'a>(src: &'a str) -> HashMap<&'a str, Macro<'a>> {
+    let mut macros = HashMap::new();
+    for (pos, _) in src.match_indices('\\') {
+        if let Ok((_, (name, definition))) = macro_definition(&src[pos..]) {
+            macros.insert(name, definition);
+        }
+    }
+    macros
+}
+
+// The length of the text at the beginning of i that latex takes literally, i.e. of a comment, a
+// verbatim environment or \verb|...|, if i begins with one.
+pub fn literal_len(i: &str) -> Option<usize> {
+    if i.starts_with('%') {
+        return Some(i.find('\n').unwrap_or(i.len()));
+    }
+    if i.starts_with(VERBATIM_BEGIN) {
+        let end = i.find(VERBATIM_END);
+        return Some(end.map_or(i.len(), |pos| pos + VERBATIM_END.len()));
+    }
+    let after_verb = i.strip_prefix("\\verb")?;
+    let delim = after_verb
+        .chars()
+        .next()
+        .filter(|c| !c.is_alphabetic() && !c.is_whitespace())?;
+    let content = &after_verb[delim.len_utf8()..];
+    let end = content
+        .find([delim, '\n'])
+        .map_or(content.len(), |pos| pos + delim.len_utf8());
+    Some(i.len() - content.len() + end)
+}
+
+// Parses a macro argument: Either a group in braces, whose content is returned, or a single
+// token.
+fn macro_argument<'a>(i: &'a str) -> Result<'a, &'a str> {
+    let (i, _) = any_ws(i)?;
+    alt((
+        delimited(char('{'), raw_command_arg, char('}')),
+        recognize(control_sequence_name),
+        recognize(anychar),
+    ))(i)
+}
+
+fn expand_into(
+    out: &mut String,
+    // The spans of the output, which are only recorded for the top-level source. The expansion of
+    // a macro maps to its invocation in the top-level source as a whole.
+    spans: &mut Vec<Span>,
+    src: &str,
+    // The offset of src in the input, used for spans.
+    src_offset: usize,
+    macros: &HashMap<&str, Macro>,
+    depth: usize,
+) -> std::result::Result<(), MacroRecursionError> {
+    // Copies the first len bytes of i, which is a suffix of src, to the output.
+    let copy = |out: &mut String, spans: &mut Vec<Span>, i: &str, len: usize| {
+        if depth == 0 {
+            push_copy(spans, out.len(), src_offset + src.offset(i), len);
+        }
+        out.push_str(&i[..len]);
+    };
+
+    let mut i = src;
+    while let Some(pos) = i.find(['\\', '%']) {
+        copy(out, spans, i, pos);
+        i = &i[pos..];
+
+        // Comments, verbatim environments and \verb are taken literally.
+        if let Some(len) = literal_len(i) {
+            copy(out, spans, i, len);
+            i = &i[len..];
+            continue;
+        }
+
+        let (after_name, name) = control_sequence_name(i).unwrap_or((&i[1..], ""));
+        let Some(definition) = macros.get(name) else {
+            copy(out, spans, i, i.len() - after_name.len());
+            i = after_name;
+            continue;
+        };
+        if depth == MAX_EXPANSION_DEPTH || out.len() > MAX_EXPANDED_LEN {
+            return Err(MacroRecursionError {
+                name: name.to_string(),
+            });
+        }
+
+        let mut rest = after_name;
+        let mut args = Vec::new();
+        for _ in 0..definition.arity {
+            match macro_argument(rest) {
+                Ok((after_arg, arg)) => {
+                    args.push(arg);
+                    rest = after_arg;
+                }
+                Err(_) => break,
+            }
+        }
+        // Like tex, we skip spaces after control words without arguments.
+        if definition.arity == 0 && name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            rest = rest.trim_start_matches([' ', '\t']);
+        }
+
+        let mut replacement = String::new();
+        let mut body_chars = definition.body.chars().peekable();
+        while let Some(c) = body_chars.next() {
+            let arg_index = match (c, body_chars.peek()) {
+                ('#', Some(digit)) => digit.to_digit(10),
+                _ => None,
+            };
+            match arg_index.and_then(|index| args.get((index as usize).checked_sub(1)?)) {
+                Some(arg) => {
+                    replacement.push_str(arg);
+                    body_chars.next();
+                }
+                None => replacement.push(c),
+            }
+        }
+        let output_begin = out.len();
+        expand_into(out, spans, &replacement, 0, macros, depth + 1)?;
+        if depth == 0 {
+            let input_begin = src_offset + src.offset(i);
+            spans.push(Span {
+                output: output_begin..out.len(),
+                input: input_begin..input_begin + (i.len() - rest.len()),
+            });
+        }
+        i = rest;
+    }
+    copy(out, spans, i, i.len());
+    Ok(())
+}
+
+// Expands the macros defined in the preamble of a tex document in the document body. The preamble
+// is left as is, so that the definitions are still available when compiling math.
+pub fn expand_macros(tex: &str) -> std::result::Result<String, MacroRecursionError> {
+    let (expanded, _) = expand_macros_with_spans(tex)?;
+    Ok(expanded)
+}
+
+// Like expand_macros, but also returns the spans that map the expanded source back to tex.
+pub fn expand_macros_with_spans(
+    tex: &str,
+) -> std::result::Result<(String, Vec<Span>), MacroRecursionError> {
+    let body_begin = tex.find("\\begin{document}").unwrap_or(tex.len());
+    let (preamble, body) = tex.split_at(body_begin);
+    let macros = macro_definitions(preamble);
+
+    let mut result = preamble.to_string();
+    let mut spans = Vec::new();
+    push_copy(&mut spans, 0, 0, preamble.len());
+    if macros.is_empty() {
+        result.push_str(body);
+        push_copy(&mut spans, body_begin, body_begin, body.len());
+    } else {
+        expand_into(&mut result, &mut spans, body, body_begin, &macros, 0)?;
+    }
+    Ok((result, spans))
+}
+
+#[test]
+fn zero_argument_macros() {
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \newcommand{\R}{\mathbb{R}}
+        \newcommand\Rn{\R^n}
+        \begin{document}
+        Let $x \in \Rn$ and $y \in \R$, \R .
+        \end{document}
+    "#};
+    let expanded = expand_macros(tex).unwrap();
+    assert!(expanded.contains(r"\newcommand{\R}{\mathbb{R}}"));
+    assert!(expanded.contains(r"Let $x \in \mathbb{R}^n$ and $y \in \mathbb{R}$, \mathbb{R}."));
+}
+
+#[test]
+fn one_argument_macros() {
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \newcommand{\norm}[1]{\lVert #1 \rVert}
+        \renewcommand*{\emphasize}[1]{\textbf{#1}}
+        \begin{document}
+        $\norm{x + \norm y}$ is \emphasize{large}.
+        \end{document}
+    "#};
+    let expanded = expand_macros(tex).unwrap();
+    assert!(expanded.contains(r"$\lVert x + \lVert y \rVert \rVert$ is \textbf{large}."));
+}
+
+#[test]
+fn recursive_macros() {
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \newcommand{\loop}{a\loop}
+        \begin{document}
+        \loop
+        \end{document}
+    "#};
+    assert_eq!(
+        expand_macros(tex),
+        Err(MacroRecursionError {
+            name: "loop".to_string()
+        })
+    );
+}
+
+#[test]
+fn exponential_macros() {
+    use std::fmt::Write;
+    // \ma expands to two copies of \mb and so on, i.e. to 2^23 copies of the body of \mx.
+    let mut tex = String::from("\\documentclass{article}\n");
+    for (name, next) in ('a'..'x').zip('b'..='x') {
+        writeln!(tex, "\\newcommand{{\\m{name}}}{{\\m{next}\\m{next}}}").unwrap();
+    }
+    writeln!(tex, "\\newcommand{{\\mx}}{{{}}}", "x".repeat(1024)).unwrap();
+    tex.push_str("\\begin{document}\n\\ma\n\\end{document}\n");
+    assert!(expand_macros(&tex).is_err());
+}
+
+#[test]
+fn comments_and_verb_are_not_expanded() {
+    let tex = indoc::indoc! {r#"
+        \documentclass{article}
+        \newcommand{\loop}{a\loop}
+        \newcommand{\R}{\mathbb{R}}
+        \begin{document}
+        % \loop
+        \verb|\loop| and \verb+\R+ but \R, 100\% \R.
+        \end{document}
+    "#};
+    let expanded = expand_macros(tex).unwrap();
+    assert!(expanded.contains("% \\loop\n"));
+    assert!(expanded.contains(r"\verb|\loop| and \verb+\R+ but \mathbb{R}, 100\% \mathbb{R}."));
+}
+
+#[test]
+fn expansion_spans() {
+    let tex = "\\newcommand{\\R}{\\mathbb{R}}\\begin{document}$\\R$ \\R x";
+    let (expanded, spans) = expand_macros_with_spans(tex).unwrap();
+    let invocation = tex.find("$\\R$").unwrap() + 1;
+    let replacement = expanded.find("\\mathbb{R}$").unwrap();
+    assert!(spans.contains(&Span {
+        output: replacement..replacement + r"\mathbb{R}".len(),
+        input: invocation..invocation + 2,
+    }));
+    let end = spans.last().unwrap();
+    assert_eq!(end.output.end, expanded.len());
+    assert_eq!(end.input.end, tex.len());
+    assert_eq!(&tex[end.input.clone()], "x");
+}
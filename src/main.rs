@@ -1,17 +1,193 @@
-use latex_to_html::latex_to_html;
+use latex_to_html::{latex_to_html, EmitOptions};
 use std::env::args;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+fn print_usage_and_exit() -> ! {
+    eprintln!("Usage: latex-to-html [OPTIONS] <SOURCE.tex> [BIBLIOGRAPHY.bib] <OUT_DIR>");
+    eprintln!();
+    eprintln!("Options:");
+    eprintln!("  --trivial-math-html          Render trivial inline math as html");
+    eprintln!("  --draft                      Ask search engines not to index the output");
+    eprintln!("  --standalone                 Write a single self-contained index.html");
+    eprintln!("  --split-sections             Write every section to a page of its own");
+    eprintln!("  --structure-json             Also write structure.json describing the document");
+    eprintln!("  --math-backend=<svg|mathml>  How to render math [default: svg]");
+    eprintln!("  --best-effort-math           Show formulas that fail to compile as placeholders");
+    eprintln!("  --equation-numbers=<SIDE>    left, right or none [default: right]");
+    eprintln!("  --number-all-equations       Number equations even if they are not referenced");
+    eprintln!("  --citation-style=<STYLE>     numeric or author-year [default: numeric]");
+    eprintln!(
+        "  --bib-sort=<ORDER>           alphabetical or citation-order [default: alphabetical]"
+    );
+    eprintln!("  --max-authors=<N>            Abbreviate longer author lists with \"et al.\"");
+    eprintln!("  --et-al-authors=<N>          Authors listed before \"et al.\" [default: 1]");
+    eprintln!("  --max-width=<PX>             Maximal width of the text body [default: 600]");
+    eprintln!("  --full-bleed-max-width=<PX>  Maximal width of wide display math [default: 960]");
+    eprintln!("  --latex-engine=<ENGINE>      pdflatex, xelatex or lualatex [default: pdflatex]");
+    eprintln!("  --latex-program=<PATH>       Path of the tex engine program");
+    eprintln!("  --pdfcrop-program=<PATH>     Path of pdfcrop [default: pdfcrop]");
+    eprintln!("  --pdf2svg-program=<PATH>     Path of pdf2svg [default: pdf2svg]");
+    eprintln!(
+        "  --math-packages=<NAMES>      Packages loaded for math [default: amsmath,mathtools]"
+    );
+    eprintln!("  --math-showonlyrefs=<BOOL>   Compile math with showonlyrefs [default: true]");
+    eprintln!("  --math-preamble=<FILE>       Lines added to the preamble when compiling math");
+    eprintln!("  --latex-timeout=<SECS>       Time limit for compiling a formula [default: 30]");
+    eprintln!("  --base-font-size=<PT>        Font size with which math is typeset [default: 10]");
+    eprintln!("  --math-scale=<FACTOR>        Size of math relative to the text [default: 1]");
+    eprintln!("  --math-current-color         Draw math in white for dark color schemes");
+    eprintln!("  --math-jobs=<N>              Number of formulas compiled in parallel");
+    eprintln!("  --prune-math-svgs            Delete svgs of formulas no longer in the document");
+    eprintln!("  --extra-css=<FILE>           Css appended to the built-in stylesheet");
+    process::exit(1);
+}
+
+fn parse_option_value<T: FromStr>(option: &str, value: &str) -> T {
+    match value.parse() {
+        Ok(value) => value,
+        Err(_) => {
+            eprintln!("Error: Invalid value for option {option}: {value}");
+            print_usage_and_exit();
+        }
+    }
+}
 
 fn main() {
-    if args().len() != 4 {
-        eprintln!("Usage: latex-to-html <SOURCE.tex> <BIBLIOGRAPHY.bib> <OUT_DIR>");
-        process::exit(1);
+    let mut options = EmitOptions::default();
+    let mut positional_args: Vec<String> = Vec::new();
+    for arg in args().skip(1) {
+        let (option, value) = match arg.split_once('=') {
+            Some((option, value)) => (option, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        match (option, value) {
+            ("--trivial-math-html", None) => {
+                options.trivial_math_html = true;
+            }
+            ("--draft", None) => {
+                options.draft = true;
+            }
+            ("--standalone", None) => {
+                options.standalone = true;
+            }
+            ("--split-sections", None) => {
+                options.split_sections = true;
+            }
+            ("--structure-json", None) => {
+                options.structure_json = true;
+            }
+            ("--best-effort-math", None) => {
+                options.best_effort_math = true;
+            }
+            ("--math-current-color", None) => {
+                options.math_current_color = true;
+            }
+            ("--prune-math-svgs", None) => {
+                options.prune_math_svgs = true;
+            }
+            ("--number-all-equations", None) => {
+                options.number_all_display_math = true;
+            }
+            ("--math-backend", Some(value)) => {
+                options.math_backend = parse_option_value(option, value);
+            }
+            ("--equation-numbers", Some(value)) => {
+                options.equation_numbers = parse_option_value(option, value);
+            }
+            ("--citation-style", Some(value)) => {
+                options.citation_style = parse_option_value(option, value);
+            }
+            ("--bib-sort", Some(value)) => {
+                options.bib_sort = parse_option_value(option, value);
+            }
+            ("--max-authors", Some(value)) => {
+                options.max_authors = Some(parse_option_value(option, value));
+            }
+            ("--et-al-authors", Some(value)) => {
+                options.et_al_authors = parse_option_value(option, value);
+            }
+            ("--max-width", Some(value)) => {
+                options.max_width_px = parse_option_value(option, value);
+            }
+            ("--full-bleed-max-width", Some(value)) => {
+                options.full_bleed_max_width_px = parse_option_value(option, value);
+            }
+            ("--latex-engine", Some(value)) => {
+                options.latex_engine = parse_option_value(option, value);
+            }
+            ("--latex-program", Some(path)) => {
+                options.latex_program = Some(PathBuf::from(path));
+            }
+            ("--pdfcrop-program", Some(path)) => {
+                options.pdfcrop_program = PathBuf::from(path);
+            }
+            ("--pdf2svg-program", Some(path)) => {
+                options.pdf2svg_program = PathBuf::from(path);
+            }
+            ("--math-packages", Some(value)) => {
+                options.math_packages = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|package| !package.is_empty())
+                    .map(str::to_string)
+                    .collect();
+            }
+            ("--math-showonlyrefs", Some(value)) => {
+                options.math_showonlyrefs = parse_option_value(option, value);
+            }
+            ("--math-preamble", Some(path)) => match fs::read_to_string(path) {
+                Ok(preamble) => {
+                    options.math_preamble_extra = preamble.lines().map(str::to_string).collect()
+                }
+                Err(err) => {
+                    eprintln!("Error: Could not read {path}: {err}");
+                    process::exit(1);
+                }
+            },
+            ("--latex-timeout", Some(value)) => {
+                options.latex_timeout = Duration::from_secs(parse_option_value(option, value));
+            }
+            ("--base-font-size", Some(value)) => {
+                options.base_font_pt = parse_option_value(option, value);
+            }
+            ("--math-scale", Some(value)) => {
+                options.math_scale = parse_option_value(option, value);
+            }
+            ("--math-jobs", Some(value)) => {
+                options.math_jobs = Some(parse_option_value(option, value));
+            }
+            ("--extra-css", Some(path)) => match fs::read_to_string(path) {
+                Ok(css) => options.extra_css = Some(css),
+                Err(err) => {
+                    eprintln!("Error: Could not read {path}: {err}");
+                    process::exit(1);
+                }
+            },
+            _ if arg.starts_with("--") => {
+                eprintln!("Error: Unknown option {arg}");
+                print_usage_and_exit();
+            }
+            _ => {
+                positional_args.push(arg);
+            }
+        }
     }
 
-    let tex_path = PathBuf::from(args().nth(1).unwrap());
-    let bib_path = PathBuf::from(args().nth(2).unwrap());
-    let out_path = PathBuf::from(args().nth(3).unwrap());
+    // Without a bib file, the one named by the \bibliography command of the document is used.
+    let (tex_path, bib_path, out_path) = match positional_args.as_slice() {
+        [tex_path, out_path] => (tex_path, None, out_path),
+        [tex_path, bib_path, out_path] => (tex_path, Some(bib_path), out_path),
+        _ => print_usage_and_exit(),
+    };
 
-    latex_to_html(tex_path.as_path(), bib_path.as_path(), out_path.as_path());
+    latex_to_html(
+        Path::new(tex_path),
+        bib_path.map(Path::new),
+        Path::new(out_path),
+        &options,
+    );
 }
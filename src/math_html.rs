@@ -0,0 +1,130 @@
+use std::fmt::Write;
+
+// Renders trivial inline math formulas as html so that we don't have to spawn pdflatex for them.
+// Only a small whitelist is recognized:
+// - a single latin letter, e.g. "x", rendered in italics,
+// - a sequence of digits, e.g. "42",
+// - either of these followed by a subscript and/or a superscript, e.g. "x_i^2" or "a_{ij}", where
+//   scripts consist of letters and digits only.
+// Returns None for all other formulas, which then need to be rendered via pdflatex.
+pub fn trivial_math_to_html(source: &str) -> Option<String> {
+    let source = source.trim();
+    let (base, mut rest) = trivial_math_base(source)?;
+
+    let mut html = String::new();
+    html.push_str(r#"<span class="inline-math-html">"#);
+    write_atoms(&mut html, base);
+
+    let mut has_sub = false;
+    let mut has_sup = false;
+    while !rest.is_empty() {
+        let (tag, has_tag) = match rest.chars().next() {
+            Some('_') => ("sub", &mut has_sub),
+            Some('^') => ("sup", &mut has_sup),
+            _ => return None,
+        };
+        if *has_tag {
+            return None;
+        }
+        *has_tag = true;
+
+        let (script, after) = trivial_math_script(rest[1..].trim_start())?;
+        write!(html, "<{tag}>").unwrap();
+        write_atoms(&mut html, script);
+        write!(html, "</{tag}>").unwrap();
+        rest = after.trim_start();
+    }
+
+    html.push_str("</span>");
+    Some(html)
+}
+
+// Splits off the base of a trivial formula, i.e. a single letter or a sequence of digits.
+fn trivial_math_base(source: &str) -> Option<(&str, &str)> {
+    let first = source.chars().next()?;
+    let len = if first.is_ascii_alphabetic() {
+        1
+    } else if first.is_ascii_digit() {
+        source
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(source.len())
+    } else {
+        return None;
+    };
+    Some((&source[..len], source[len..].trim_start()))
+}
+
+// Splits off the argument of a _ or ^, i.e. either a single letter or digit or a non-empty group
+// of letters and digits enclosed in braces.
+fn trivial_math_script(source: &str) -> Option<(&str, &str)> {
+    let first = source.chars().next()?;
+    if first.is_ascii_alphanumeric() {
+        return Some((&source[..1], &source[1..]));
+    }
+    if first != '{' {
+        return None;
+    }
+
+    let end = source.find('}')?;
+    let content = source[1..end].trim();
+    if content.is_empty() || !content.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some((content, &source[end + 1..]))
+}
+
+fn write_atoms(html: &mut String, atoms: &str) {
+    for c in atoms.chars() {
+        if c.is_ascii_alphabetic() {
+            write!(html, "<i>{c}</i>").unwrap();
+        } else {
+            html.push(c);
+        }
+    }
+}
+
+#[test]
+fn trivial_math_whitelist() {
+    let html = |source| trivial_math_to_html(source);
+    assert_eq!(
+        html("x").as_deref(),
+        Some(r#"<span class="inline-math-html"><i>x</i></span>"#)
+    );
+    assert_eq!(
+        html(" 42 ").as_deref(),
+        Some(r#"<span class="inline-math-html">42</span>"#)
+    );
+    assert_eq!(
+        html("x_1").as_deref(),
+        Some(r#"<span class="inline-math-html"><i>x</i><sub>1</sub></span>"#)
+    );
+    assert_eq!(
+        html("a_{ij}^2").as_deref(),
+        Some(
+            r#"<span class="inline-math-html"><i>a</i><sub><i>i</i><i>j</i></sub><sup>2</sup></span>"#
+        )
+    );
+    assert_eq!(
+        html("2^n").as_deref(),
+        Some(r#"<span class="inline-math-html">2<sup><i>n</i></sup></span>"#)
+    );
+}
+
+#[test]
+fn trivial_math_fallback() {
+    for source in [
+        "",
+        "xy",
+        "x+y",
+        "\\alpha",
+        "x'",
+        "x_{i+1}",
+        "x_{}",
+        "x_1_2",
+        "x^2^3",
+        "x_",
+        "\\mathbb{R}",
+    ] {
+        assert_eq!(trivial_math_to_html(source), None, "{source}");
+    }
+}
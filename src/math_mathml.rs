@@ -0,0 +1,632 @@
+use crate::ast::*;
+use crate::parse::align_lines;
+use crate::util::display_escaped;
+use std::fmt::{self, Display, Formatter, Write};
+
+// Converts math to MathML without external tools. Only a subset of latex math is supported:
+// - letters, digits and the usual operator characters,
+// - sub- and superscripts,
+// - greek letters and common symbols such as \cdot, \leq, \in, \to, \infty, \sum, \int,
+// - function names such as \sin, \log, \lim,
+// - \frac, \sqrt (also with an index), \left ... \right,
+// - \mathbb, \mathcal, \mathbf, \mathrm, \text,
+// - spacing commands \, \; \quad \qquad,
+// - the equation, align and align* environments, \[ ... \] and $$ ... $$.
+// Other commands, e.g. user-defined macros that are not expanded, and mathpar result in an error.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MathMlError {
+    UnsupportedCommand(String),
+    UnsupportedEnvironment,
+    UnbalancedBraces,
+    MissingArgument(String),
+}
+
+impl Display for MathMlError {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        use MathMlError::*;
+        match self {
+            UnsupportedCommand(name) => write!(out, "Unsupported command \\{name}"),
+            UnsupportedEnvironment => write!(out, "Unsupported environment"),
+            UnbalancedBraces => write!(out, "Unbalanced braces"),
+            MissingArgument(name) => write!(out, "Missing argument of \\{name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    // A control sequence without the backslash, e.g. "frac" or ",".
+    Command(&'a str),
+    Char(char),
+    Number(&'a str),
+}
+
+fn tokenize<'a>(latex: &'a str) -> Vec<Token<'a>> {
+    let mut tokens = Vec::new();
+    let mut i = latex;
+    while let Some(c) = i.chars().next() {
+        if c.is_whitespace() {
+            i = &i[c.len_utf8()..];
+        } else if c == '%' {
+            i = i.find('\n').map_or("", |end| &i[end..]);
+        } else if c == '\\' {
+            let rest = &i[1..];
+            let len = match rest.find(|c: char| !c.is_ascii_alphabetic()) {
+                Some(0) => rest.chars().next().map_or(0, char::len_utf8),
+                Some(len) => len,
+                None => rest.len(),
+            };
+            tokens.push(Token::Command(&rest[..len]));
+            i = &rest[len..];
+        } else if c.is_ascii_digit() {
+            // Digits, optionally followed by a decimal point and more digits.
+            let digits_len = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+            let mut len = digits_len(i);
+            if i[len..].starts_with('.') && digits_len(&i[len + 1..]) > 0 {
+                len += 1 + digits_len(&i[len + 1..]);
+            }
+            tokens.push(Token::Number(&i[..len]));
+            i = &i[len..];
+        } else {
+            tokens.push(Token::Char(c));
+            i = &i[c.len_utf8()..];
+        }
+    }
+    tokens
+}
+
+fn greek_letter(name: &str) -> Option<char> {
+    let c = match name {
+        "alpha" => 'α',
+        "beta" => 'β',
+        "gamma" => 'γ',
+        "delta" => 'δ',
+        "epsilon" => 'ϵ',
+        "varepsilon" => 'ε',
+        "zeta" => 'ζ',
+        "eta" => 'η',
+        "theta" => 'θ',
+        "vartheta" => 'ϑ',
+        "iota" => 'ι',
+        "kappa" => 'κ',
+        "lambda" => 'λ',
+        "mu" => 'μ',
+        "nu" => 'ν',
+        "xi" => 'ξ',
+        "pi" => 'π',
+        "rho" => 'ρ',
+        "sigma" => 'σ',
+        "tau" => 'τ',
+        "upsilon" => 'υ',
+        "phi" => 'ϕ',
+        "varphi" => 'φ',
+        "chi" => 'χ',
+        "psi" => 'ψ',
+        "omega" => 'ω',
+        "Gamma" => 'Γ',
+        "Delta" => 'Δ',
+        "Theta" => 'Θ',
+        "Lambda" => 'Λ',
+        "Xi" => 'Ξ',
+        "Pi" => 'Π',
+        "Sigma" => 'Σ',
+        "Phi" => 'Φ',
+        "Psi" => 'Ψ',
+        "Omega" => 'Ω',
+        _ => return None,
+    };
+    Some(c)
+}
+
+// Symbols that are rendered as identifiers rather than operators.
+fn identifier_symbol(name: &str) -> Option<char> {
+    let c = match name {
+        "infty" => '∞',
+        "emptyset" | "varnothing" => '∅',
+        "partial" => '∂',
+        "nabla" => '∇',
+        "ell" => 'ℓ',
+        _ => return None,
+    };
+    Some(c)
+}
+
+fn operator_symbol(name: &str) -> Option<char> {
+    let c = match name {
+        "cdot" => '⋅',
+        "times" => '×',
+        "pm" => '±',
+        "mp" => '∓',
+        "circ" => '∘',
+        "leq" | "le" => '≤',
+        "geq" | "ge" => '≥',
+        "neq" | "ne" => '≠',
+        "approx" => '≈',
+        "equiv" => '≡',
+        "sim" => '∼',
+        "cong" => '≅',
+        "in" => '∈',
+        "notin" => '∉',
+        "subset" => '⊂',
+        "subseteq" => '⊆',
+        "supset" => '⊃',
+        "supseteq" => '⊇',
+        "cup" => '∪',
+        "cap" => '∩',
+        "setminus" => '∖',
+        "to" | "rightarrow" => '→',
+        "leftarrow" => '←',
+        "mapsto" => '↦',
+        "Rightarrow" | "implies" => '⇒',
+        "Leftarrow" => '⇐',
+        "Leftrightarrow" | "iff" => '⇔',
+        "vdash" => '⊢',
+        "models" => '⊨',
+        "perp" | "bot" => '⊥',
+        "top" => '⊤',
+        "ast" => '∗',
+        "star" => '⋆',
+        "ll" => '≪',
+        "gg" => '≫',
+        "prec" => '≺',
+        "preceq" => '⪯',
+        "leftrightarrow" => '↔',
+        "hookrightarrow" => '↪',
+        "longrightarrow" => '⟶',
+        "longmapsto" => '⟼',
+        "forall" => '∀',
+        "exists" => '∃',
+        "neg" => '¬',
+        "land" | "wedge" => '∧',
+        "lor" | "vee" => '∨',
+        "oplus" => '⊕',
+        "otimes" => '⊗',
+        "sum" => '∑',
+        "prod" => '∏',
+        "coprod" => '∐',
+        "int" => '∫',
+        "oint" => '∮',
+        "bigcup" => '⋃',
+        "bigcap" => '⋂',
+        "ldots" | "dots" => '…',
+        "cdots" => '⋯',
+        "mid" => '∣',
+        "colon" => ':',
+        "vert" | "lvert" | "rvert" | "|" => '|',
+        "Vert" | "lVert" | "rVert" => '‖',
+        "langle" => '⟨',
+        "rangle" => '⟩',
+        "{" => '{',
+        "}" => '}',
+        _ => return None,
+    };
+    Some(c)
+}
+
+fn is_function_name(name: &str) -> bool {
+    matches!(
+        name,
+        "sin"
+            | "cos"
+            | "tan"
+            | "cot"
+            | "sinh"
+            | "cosh"
+            | "tanh"
+            | "log"
+            | "ln"
+            | "exp"
+            | "lim"
+            | "limsup"
+            | "liminf"
+            | "sup"
+            | "inf"
+            | "max"
+            | "min"
+            | "det"
+            | "dim"
+            | "ker"
+            | "deg"
+            | "gcd"
+            | "arg"
+            | "Hom"
+    )
+}
+
+fn space_width(name: &str) -> Option<&'static str> {
+    let width = match name {
+        "," => "0.1667em",
+        ":" | ">" => "0.2222em",
+        ";" => "0.2778em",
+        " " => "0.25em",
+        "quad" => "1em",
+        "qquad" => "2em",
+        _ => return None,
+    };
+    Some(width)
+}
+
+fn write_mo(out: &mut String, c: char) {
+    write!(
+        out,
+        "<mo>{}</mo>",
+        display_escaped(c.encode_utf8(&mut [0; 4]))
+    )
+    .unwrap();
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+}
+
+// Where a row of math ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RowEnd {
+    Eof,
+    Brace,
+    Right,
+    Ampersand,
+    LineBreak,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.peek()?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    // Parses a row of atoms up to one of the given ends and returns the end that was found.
+    fn row(&mut self, out: &mut String, ends: &[RowEnd]) -> Result<RowEnd, MathMlError> {
+        loop {
+            let end = match self.peek() {
+                None => RowEnd::Eof,
+                Some(Token::Char('}')) => RowEnd::Brace,
+                Some(Token::Command("right")) => RowEnd::Right,
+                Some(Token::Char('&')) => RowEnd::Ampersand,
+                Some(Token::Command("\\")) => RowEnd::LineBreak,
+                Some(_) => {
+                    self.scripted_atom(out)?;
+                    continue;
+                }
+            };
+            if !ends.contains(&end) {
+                return Err(match end {
+                    RowEnd::Eof | RowEnd::Brace => MathMlError::UnbalancedBraces,
+                    RowEnd::Right => MathMlError::UnsupportedCommand("right".to_string()),
+                    RowEnd::Ampersand | RowEnd::LineBreak => MathMlError::UnsupportedEnvironment,
+                });
+            }
+            if end != RowEnd::Eof {
+                self.next();
+            }
+            return Ok(end);
+        }
+    }
+
+    // Parses an atom together with its sub- and superscripts.
+    fn scripted_atom(&mut self, out: &mut String) -> Result<(), MathMlError> {
+        let mut base = String::new();
+        self.atom(&mut base)?;
+
+        let mut sub = None;
+        let mut sup = None;
+        loop {
+            let script = match self.peek() {
+                Some(Token::Char('_')) => &mut sub,
+                Some(Token::Char('^')) => &mut sup,
+                Some(Token::Char('\'')) => {
+                    self.next();
+                    let primes = sup.get_or_insert_with(String::new);
+                    primes.push_str("<mo>′</mo>");
+                    continue;
+                }
+                _ => break,
+            };
+            self.next();
+            let mut arg = String::new();
+            self.argument(&mut arg, "_")?;
+            script.get_or_insert_with(String::new).push_str(&arg);
+        }
+
+        match (sub, sup) {
+            (None, None) => out.push_str(&base),
+            (Some(sub), None) => write!(out, "<msub>{base}<mrow>{sub}</mrow></msub>").unwrap(),
+            (None, Some(sup)) => write!(out, "<msup>{base}<mrow>{sup}</mrow></msup>").unwrap(),
+            (Some(sub), Some(sup)) => write!(
+                out,
+                "<msubsup>{base}<mrow>{sub}</mrow><mrow>{sup}</mrow></msubsup>"
+            )
+            .unwrap(),
+        }
+        Ok(())
+    }
+
+    // Parses the argument of a command or script: A group in braces or a single atom.
+    fn argument(&mut self, out: &mut String, command: &str) -> Result<(), MathMlError> {
+        match self.peek() {
+            None => Err(MathMlError::MissingArgument(command.to_string())),
+            Some(Token::Char('{')) => {
+                self.next();
+                out.push_str("<mrow>");
+                self.row(out, &[RowEnd::Brace])?;
+                out.push_str("</mrow>");
+                Ok(())
+            }
+            Some(_) => self.atom(out),
+        }
+    }
+
+    // Parses the argument of a command as plain text, e.g. for \text or \mathbb.
+    fn text_argument(&mut self, command: &str) -> Result<String, MathMlError> {
+        let mut text = String::new();
+        let mut depth = 0;
+        match self.next() {
+            Some(Token::Char('{')) => (),
+            Some(Token::Char(c)) => return Ok(c.to_string()),
+            Some(Token::Number(n)) => return Ok(n.to_string()),
+            _ => return Err(MathMlError::MissingArgument(command.to_string())),
+        }
+        loop {
+            match self.next() {
+                None => return Err(MathMlError::UnbalancedBraces),
+                Some(Token::Char('}')) if depth == 0 => return Ok(text),
+                Some(Token::Char(c)) => {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => (),
+                    }
+                    text.push(c);
+                }
+                Some(Token::Number(n)) => text.push_str(n),
+                Some(Token::Command(name)) => match space_width(name) {
+                    Some(_) => text.push(' '),
+                    None => return Err(MathMlError::UnsupportedCommand(name.to_string())),
+                },
+            }
+        }
+    }
+
+    fn fence(&mut self, out: &mut String, command: &str) -> Result<(), MathMlError> {
+        match self.next() {
+            Some(Token::Char('.')) => (),
+            Some(Token::Char(c)) => write_mo(out, c),
+            Some(Token::Command(name)) => match operator_symbol(name) {
+                Some(c) => write_mo(out, c),
+                None => return Err(MathMlError::UnsupportedCommand(name.to_string())),
+            },
+            _ => return Err(MathMlError::MissingArgument(command.to_string())),
+        }
+        Ok(())
+    }
+
+    fn atom(&mut self, out: &mut String) -> Result<(), MathMlError> {
+        let token = match self.next() {
+            None => return Ok(()),
+            Some(token) => token,
+        };
+        match token {
+            Token::Number(n) => write!(out, "<mn>{n}</mn>").unwrap(),
+            Token::Char('{') => {
+                out.push_str("<mrow>");
+                self.row(out, &[RowEnd::Brace])?;
+                out.push_str("</mrow>");
+            }
+            Token::Char(c) if c.is_alphabetic() => write!(out, "<mi>{c}</mi>").unwrap(),
+            Token::Char(c) => write_mo(out, c),
+            Token::Command(name) => self.command(out, name)?,
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, out: &mut String, name: &str) -> Result<(), MathMlError> {
+        if let Some(c) = greek_letter(name).or_else(|| identifier_symbol(name)) {
+            write!(out, "<mi>{c}</mi>").unwrap();
+            return Ok(());
+        }
+        if let Some(c) = operator_symbol(name) {
+            write_mo(out, c);
+            return Ok(());
+        }
+        if is_function_name(name) {
+            write!(out, "<mi>{name}</mi>").unwrap();
+            return Ok(());
+        }
+        if let Some(width) = space_width(name) {
+            write!(out, r#"<mspace width="{width}"/>"#).unwrap();
+            return Ok(());
+        }
+
+        match name {
+            "frac" => {
+                out.push_str("<mfrac>");
+                self.argument(out, name)?;
+                self.argument(out, name)?;
+                out.push_str("</mfrac>");
+            }
+            "sqrt" => {
+                if self.peek() == Some(Token::Char('[')) {
+                    self.next();
+                    let mut index = String::new();
+                    while self.peek() != Some(Token::Char(']')) {
+                        if self.peek().is_none() {
+                            return Err(MathMlError::MissingArgument(name.to_string()));
+                        }
+                        self.scripted_atom(&mut index)?;
+                    }
+                    self.next();
+                    let mut radicand = String::new();
+                    self.argument(&mut radicand, name)?;
+                    write!(out, "<mroot>{radicand}<mrow>{index}</mrow></mroot>").unwrap();
+                } else {
+                    out.push_str("<msqrt>");
+                    self.argument(out, name)?;
+                    out.push_str("</msqrt>");
+                }
+            }
+            "left" => {
+                out.push_str("<mrow>");
+                self.fence(out, name)?;
+                self.row(out, &[RowEnd::Right])?;
+                self.fence(out, "right")?;
+                out.push_str("</mrow>");
+            }
+            "mathbb" | "mathcal" | "mathbf" | "mathrm" | "mathit" | "operatorname" => {
+                let variant = match name {
+                    "mathbb" => "double-struck",
+                    "mathcal" => "script",
+                    "mathbf" => "bold",
+                    "mathit" => "italic",
+                    _ => "normal",
+                };
+                let text = self.text_argument(name)?;
+                let text = display_escaped(&text);
+                write!(out, r#"<mi mathvariant="{variant}">{text}</mi>"#).unwrap();
+            }
+            "text" | "textrm" | "mbox" => {
+                let text = self.text_argument(name)?;
+                write!(out, "<mtext>{}</mtext>", display_escaped(&text)).unwrap();
+            }
+            "label" | "tag" => {
+                self.text_argument(name)?;
+            }
+            "nonumber" | "notag" => (),
+            _ => return Err(MathMlError::UnsupportedCommand(name.to_string())),
+        }
+        Ok(())
+    }
+}
+
+// Converts the content of a formula, e.g. what's between $ and $, to MathML elements.
+pub fn latex_to_mathml(latex: &str) -> Result<String, MathMlError> {
+    let mut parser = Parser {
+        tokens: tokenize(latex),
+        pos: 0,
+    };
+    let mut out = String::new();
+    parser.row(&mut out, &[RowEnd::Eof])?;
+    Ok(out)
+}
+
+// Converts the lines of an align environment to a MathML table. Cells are separated by &.
+fn align_to_mathml(content: &str) -> Result<String, MathMlError> {
+    let mut out = String::new();
+    out.push_str(r#"<mtable columnalign="right left" displaystyle="true">"#);
+    for line in align_lines(content) {
+        let mut parser = Parser {
+            tokens: tokenize(line),
+            pos: 0,
+        };
+        out.push_str("<mtr><mtd>");
+        while parser.row(&mut out, &[RowEnd::Eof, RowEnd::Ampersand])? == RowEnd::Ampersand {
+            out.push_str("</mtd><mtd>");
+        }
+        out.push_str("</mtd></mtr>");
+    }
+    out.push_str("</mtable>");
+    Ok(out)
+}
+
+fn strip_delimiters<'a>(source: &'a str, begin: &str, end: &str) -> Option<&'a str> {
+    source.strip_prefix(begin)?.strip_suffix(end)
+}
+
+pub fn math_to_mathml(math: &Math) -> Result<String, MathMlError> {
+    use Math::*;
+    let (display, content) = match math {
+        Inline(source) => ("inline", latex_to_mathml(source)?),
+        Display { source, .. } => {
+            let content = strip_delimiters(source, "\\begin{equation}", "\\end{equation}")
+                .or_else(|| strip_delimiters(source, "\\[", "\\]"))
+                .or_else(|| strip_delimiters(source, "$$", "$$"))
+                .ok_or(MathMlError::UnsupportedEnvironment)?;
+            ("block", latex_to_mathml(content)?)
+        }
+        Align { source, .. } => {
+            let content = strip_delimiters(source, "\\begin{align}", "\\end{align}")
+                .or_else(|| strip_delimiters(source, "\\begin{align*}", "\\end{align*}"))
+                .ok_or(MathMlError::UnsupportedEnvironment)?;
+            ("block", align_to_mathml(content)?)
+        }
+        Mathpar { .. } => return Err(MathMlError::UnsupportedEnvironment),
+    };
+    Ok(format!(r#"<math display="{display}">{content}</math>"#))
+}
+
+#[test]
+fn mathml_inline() {
+    assert_eq!(
+        latex_to_mathml("x_i^2 + 1.5").unwrap(),
+        "<msubsup><mi>x</mi><mrow><mi>i</mi></mrow><mrow><mn>2</mn></mrow></msubsup>\
+         <mo>+</mo><mn>1.5</mn>"
+    );
+    assert_eq!(
+        latex_to_mathml(r"\frac{\alpha}{2} \leq \sqrt{x}").unwrap(),
+        "<mfrac><mrow><mi>α</mi></mrow><mrow><mn>2</mn></mrow></mfrac>\
+         <mo>≤</mo><msqrt><mrow><mi>x</mi></mrow></msqrt>"
+    );
+    assert_eq!(
+        latex_to_mathml(r"f \colon \mathbb{R} \to \mathbb{R}").unwrap(),
+        r#"<mi>f</mi><mo>:</mo><mi mathvariant="double-struck">R</mi><mo>→</mo><mi mathvariant="double-struck">R</mi>"#
+    );
+    assert_eq!(
+        latex_to_mathml(r"\left( a < b \right)").unwrap(),
+        "<mrow><mo>(</mo><mi>a</mi><mo>&lt;</mo><mi>b</mi><mo>)</mo></mrow>"
+    );
+}
+
+#[test]
+fn mathml_display() {
+    let math = Math::Display {
+        source: "\\begin{equation}\n  \\label{eq:x}\n  x = 1\n\\end{equation}",
+        label: Some("eq:x"),
+        tag: None,
+        nonumber: false,
+    };
+    assert_eq!(
+        math_to_mathml(&math).unwrap(),
+        r#"<math display="block"><mi>x</mi><mo>=</mo><mn>1</mn></math>"#
+    );
+
+    let math = Math::Align {
+        source: "\\begin{align*}\n  a &= b \\\\\n  &= c\n\\end{align*}",
+        lines: Vec::new(),
+    };
+    assert_eq!(
+        math_to_mathml(&math).unwrap(),
+        concat!(
+            r#"<math display="block"><mtable columnalign="right left" displaystyle="true">"#,
+            "<mtr><mtd><mi>a</mi></mtd><mtd><mo>=</mo><mi>b</mi></mtd></mtr>",
+            "<mtr><mtd></mtd><mtd><mo>=</mo><mi>c</mi></mtd></mtr>",
+            "</mtable></math>"
+        )
+    );
+}
+
+#[test]
+fn mathml_unsupported() {
+    assert_eq!(
+        latex_to_mathml(r"\R^n"),
+        Err(MathMlError::UnsupportedCommand("R".to_string()))
+    );
+    assert_eq!(latex_to_mathml("{x"), Err(MathMlError::UnbalancedBraces));
+    assert_eq!(
+        latex_to_mathml("a & b"),
+        Err(MathMlError::UnsupportedEnvironment)
+    );
+    assert_eq!(
+        math_to_mathml(&Math::Mathpar {
+            source: "\\begin{mathpar}a \\and b\\end{mathpar}",
+            label: None,
+            tag: None,
+        }),
+        Err(MathMlError::UnsupportedEnvironment)
+    );
+}
@@ -1,30 +1,47 @@
 use crate::ast::*;
+use crate::emit::EmitOptions;
+use crate::parse::{find_math_tag, math_preamble_lines, raw_env, strip_math_labels};
 use indoc::{formatdoc, writedoc};
 use itertools::Itertools;
 use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use sha2::{Digest, Sha256};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display, Formatter};
-use std::fs::{self, File, OpenOptions};
-use std::io;
-use std::io::Write as IoWrite;
+use std::fs::{self, File};
+use std::io::{self, Read};
 use std::path::Path;
-use std::process::{self, Command};
-use std::sync::mpsc::channel;
+use std::process::{self, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
 use tempdir::TempDir;
 
-fn write_latex(out: &mut impl io::Write, preamble: &[&str], latex: &str) -> Result<(), io::Error> {
-    let preamble = preamble
-        .iter()
-        .copied()
+fn write_latex(
+    out: &mut impl io::Write,
+    preamble: &[&str],
+    options: &EmitOptions,
+    latex: &str,
+) -> Result<(), io::Error> {
+    let preamble = math_preamble_lines(preamble)
+        .into_iter()
         .format_with("\n", |line, f| f(&format_args!("{}", line)));
-    // TODO: Get rid of mathtools here?
+    let packages = options.math_packages.iter().format_with("", |package, f| {
+        f(&format_args!("\\usepackage{{{package}}}\n"))?;
+        if package == "mathtools" && options.math_showonlyrefs {
+            f(&format_args!("\\mathtoolsset{{showonlyrefs}}\n"))?;
+        }
+        Ok(())
+    });
+    let extra = options
+        .math_preamble_extra
+        .iter()
+        .format_with("", |line, f| f(&format_args!("{line}\n")));
     writedoc! {out, r#"
         \documentclass{{minimal}}
         {preamble}
-        \usepackage{{mathtools}}
-        \mathtoolsset{{showonlyrefs}}
-        \begin{{document}}
+        {packages}{extra}\begin{{document}}
         {latex}
         \end{{document}}
     "#}
@@ -37,6 +54,13 @@ pub enum LatexToSvgError {
     PdfCrop(process::Output),
     Pdf2Svg(process::Output),
     BadSvg,
+    // The point marking the baseline of inline math was not found in the svg.
+    BaselineNotFound,
+    // One of the external programs didn't finish in time and was killed.
+    Timeout,
+    // The formula contains an unmatched brace, \begin or \end, so it's not passed to pdflatex.
+    // Holds the offset of the unmatched brace or command in the formula.
+    UnbalancedBraces(usize),
 }
 
 impl From<io::Error> for LatexToSvgError {
@@ -45,15 +69,164 @@ impl From<io::Error> for LatexToSvgError {
     }
 }
 
-pub fn pdf_latex(tex_file_path: &Path) -> Result<process::Output, io::Error> {
-    let mut cmd = Command::new("pdflatex");
+impl Display for LatexToSvgError {
+    fn fmt(&self, out: &mut Formatter) -> fmt::Result {
+        use LatexToSvgError::*;
+        match self {
+            Io(err) => write!(out, "{err}"),
+            // The first error in the log of the tex engine is usually the cause of the others.
+            PdfLatex(output) => {
+                let log = String::from_utf8_lossy(&output.stdout);
+                match parse_tex_log(&log).first() {
+                    Some(error) => write!(out, "The tex engine failed: {}", error.message),
+                    None => write!(out, "The tex engine failed"),
+                }
+            }
+            PdfCrop(_) => write!(out, "pdfcrop failed"),
+            Pdf2Svg(_) => write!(out, "pdf2svg failed"),
+            BadSvg => write!(out, "The generated svg is invalid"),
+            BaselineNotFound => write!(out, "The baseline was not found in the generated svg"),
+            Timeout => write!(out, "Compiling timed out"),
+            UnbalancedBraces(offset) => write!(out, "Unbalanced braces at byte {offset}"),
+        }
+    }
+}
+
+// An error reported in the log of a tex engine, i.e. the output of pdflatex.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TexLogError {
+    // The line of the tex file at which the error occurred, if the log names it.
+    pub line: Option<u32>,
+    // The message of the error, e.g. "Undefined control sequence.".
+    pub message: String,
+}
+
+// Extracts the errors from the log of a tex engine. Errors are reported on lines starting with
+// "! ", followed by the context of the error, which starts with "l.<line>" and the source line.
+pub fn parse_tex_log(log: &str) -> Vec<TexLogError> {
+    let mut errors: Vec<TexLogError> = Vec::new();
+    for line in log.lines() {
+        if let Some(message) = line.strip_prefix("! ") {
+            errors.push(TexLogError {
+                line: None,
+                message: message.trim_end().to_string(),
+            });
+        } else if let Some(rest) = line.strip_prefix("l.") {
+            let Some(error) = errors.last_mut().filter(|error| error.line.is_none()) else {
+                continue;
+            };
+            let digits_len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            error.line = rest[..digits_len].parse().ok();
+        }
+    }
+    errors
+}
+
+// The default time after which pdflatex, pdfcrop and pdf2svg are killed.
+pub const DEFAULT_LATEX_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Like Command::output, but kills the child process if it doesn't exit within the timeout.
+pub fn output_with_timeout(
+    cmd: &mut Command,
+    timeout: Duration,
+) -> Result<process::Output, LatexToSvgError> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Drain stdout and stderr on separate threads, since the child blocks once a pipe is full.
+    fn drain(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = pipe.read_to_end(&mut buf);
+            buf
+        })
+    }
+    let stdout = drain(child.stdout.take().unwrap());
+    let stderr = drain(child.stderr.take().unwrap());
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Err(LatexToSvgError::Timeout);
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    Ok(process::Output {
+        status,
+        stdout: stdout.join().unwrap(),
+        stderr: stderr.join().unwrap(),
+    })
+}
+
+// The tex engine with which math is compiled. All engines produce a pdf file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LatexEngine {
+    PdfLatex,
+    XeLatex,
+    LuaLatex,
+}
+
+impl LatexEngine {
+    pub fn program(self) -> &'static str {
+        use LatexEngine::*;
+        match self {
+            PdfLatex => "pdflatex",
+            XeLatex => "xelatex",
+            LuaLatex => "lualatex",
+        }
+    }
+}
+
+impl FromStr for LatexEngine {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use LatexEngine::*;
+        match s {
+            "pdflatex" => Ok(PdfLatex),
+            "xelatex" => Ok(XeLatex),
+            "lualatex" => Ok(LuaLatex),
+            _ => Err(()),
+        }
+    }
+}
+
+fn latex_command(tex_file_path: &Path, options: &EmitOptions) -> Command {
+    let mut cmd = match &options.latex_program {
+        Some(program) => Command::new(program),
+        None => Command::new(options.latex_engine.program()),
+    };
     if let Some(parent) = tex_file_path.parent() {
         cmd.current_dir(parent);
     }
     cmd.arg("-interaction=nonstopmode");
-    cmd.arg(&tex_file_path);
-    let output = cmd.output()?;
-    Ok(output)
+    cmd.arg(tex_file_path);
+    // Fix the dates that the tex engine writes into the pdf, so that compiling the same formula
+    // twice produces the same output.
+    cmd.env("SOURCE_DATE_EPOCH", "0");
+    cmd.env("FORCE_SOURCE_DATE", "1");
+    cmd
+}
+
+pub fn pdf_latex(
+    tex_file_path: &Path,
+    options: &EmitOptions,
+) -> Result<process::Output, LatexToSvgError> {
+    output_with_timeout(
+        &mut latex_command(tex_file_path, options),
+        options.latex_timeout,
+    )
 }
 
 pub enum PreambleDiagnosis<'a> {
@@ -61,14 +234,17 @@ pub enum PreambleDiagnosis<'a> {
     OffendingLines(process::Output, &'a [&'a str]),
 }
 
-pub fn dummy_pdf_latex(preamble: &[&str]) -> Result<process::Output, io::Error> {
+pub fn dummy_pdf_latex(
+    preamble: &[&str],
+    options: &EmitOptions,
+) -> Result<process::Output, LatexToSvgError> {
     let dummy_content = "$123$";
 
     let tmp_dir = TempDir::new("latex-to-html")?;
     let tex_file_path = tmp_dir.path().join("doc.tex");
     let mut tex_file = File::create(&tex_file_path)?;
-    write_latex(&mut tex_file, preamble, dummy_content)?;
-    pdf_latex(&tex_file_path)
+    write_latex(&mut tex_file, preamble, options, dummy_content)?;
+    pdf_latex(&tex_file_path, options)
 }
 
 pub fn has_even_curly_braces(preamble_part: &[&str]) -> bool {
@@ -91,6 +267,62 @@ pub fn has_even_curly_braces(preamble_part: &[&str]) -> bool {
     open == close
 }
 
+// Checks that the braces and the \begin and \end commands in the latex source are balanced.
+// Otherwise returns the offset of an unmatched brace or command. Escaped braces and comments are
+// skipped.
+pub fn check_balanced(latex: &str) -> Result<(), usize> {
+    // The offsets of the unclosed braces and \begin commands, together with the environment names
+    // of the latter.
+    let mut open: Vec<(usize, Option<&str>)> = Vec::new();
+    let mut pos = 0;
+    while let Some(c) = latex[pos..].chars().next() {
+        let rest = &latex[pos + c.len_utf8()..];
+        // The source of an environment name argument if rest starts with the given command.
+        let env_name = |command: &str| -> Option<(&str, usize)> {
+            let arg = rest.strip_prefix(command)?.trim_start().strip_prefix('{')?;
+            let end = arg.find('}')?;
+            Some((&arg[..end], latex.len() - arg.len() + end + 1))
+        };
+        let next_pos = match c {
+            '{' => {
+                open.push((pos, None));
+                pos + 1
+            }
+            '}' => match open.pop() {
+                Some((_, None)) => pos + 1,
+                _ => return Err(pos),
+            },
+            '%' => match rest.find('\n') {
+                Some(end) => latex.len() - rest.len() + end,
+                None => latex.len(),
+            },
+            '\\' => {
+                if let Some((name, end)) = env_name("begin") {
+                    open.push((pos, Some(name)));
+                    end
+                } else if let Some((name, end)) = env_name("end") {
+                    match open.pop() {
+                        Some((_, Some(open_name))) if open_name == name => end,
+                        _ => return Err(pos),
+                    }
+                } else {
+                    // Skip the escaped character, so that e.g. \{ is not counted as a brace.
+                    match rest.chars().next() {
+                        Some(escaped) => pos + 1 + escaped.len_utf8(),
+                        None => latex.len(),
+                    }
+                }
+            }
+            _ => pos + c.len_utf8(),
+        };
+        pos = next_pos;
+    }
+    match open.last() {
+        Some((pos, _)) => Err(*pos),
+        None => Ok(()),
+    }
+}
+
 pub fn split_preamble(preamble_part: &[&str]) -> Option<usize> {
     if preamble_part.len() < 2 {
         return None;
@@ -121,8 +353,11 @@ pub fn split_preamble(preamble_part: &[&str]) -> Option<usize> {
     Some(split_index)
 }
 
-pub fn diagnose_preamble<'a>(preamble: &'a [&'a str]) -> Result<PreambleDiagnosis<'a>, io::Error> {
-    let output = dummy_pdf_latex(preamble)?;
+pub fn diagnose_preamble<'a>(
+    preamble: &'a [&'a str],
+    options: &EmitOptions,
+) -> Result<PreambleDiagnosis<'a>, LatexToSvgError> {
+    let output = dummy_pdf_latex(preamble, options)?;
     if output.status.success() {
         return Ok(PreambleDiagnosis::Ok(output));
     }
@@ -133,7 +368,7 @@ pub fn diagnose_preamble<'a>(preamble: &'a [&'a str]) -> Result<PreambleDiagnosi
 
     while let Some(split_index) = split_preamble(&preamble[known_good..known_bad]) {
         let split_index = split_index + known_good;
-        let output = dummy_pdf_latex(&preamble[0..split_index])?;
+        let output = dummy_pdf_latex(&preamble[0..split_index], options)?;
         if output.status.success() {
             known_good = split_index;
         } else {
@@ -148,7 +383,24 @@ pub fn diagnose_preamble<'a>(preamble: &'a [&'a str]) -> Result<PreambleDiagnosi
     ))
 }
 
-pub fn latex_to_svg(preamble: &[&str], latex: &str) -> Result<String, LatexToSvgError> {
+// A command that runs a program such as pdfcrop with an input and an output file, in the directory
+// of the input file.
+fn file_conversion_command(program: &Path, input_path: &Path, output_path: &Path) -> Command {
+    let mut cmd = Command::new(program);
+    if let Some(parent) = input_path.parent() {
+        cmd.current_dir(parent);
+    }
+    cmd.arg(input_path);
+    cmd.arg(output_path);
+    cmd
+}
+
+pub fn latex_to_svg(
+    preamble: &[&str],
+    latex: &str,
+    options: &EmitOptions,
+) -> Result<String, LatexToSvgError> {
+    let timeout = options.latex_timeout;
     let tmp_dir = TempDir::new("latex-to-html")?;
 
     let tex_file_path = tmp_dir.path().join("doc.tex");
@@ -157,27 +409,29 @@ pub fn latex_to_svg(preamble: &[&str], latex: &str) -> Result<String, LatexToSvg
     let svg_file_path = tmp_dir.path().join("doc.svg");
 
     let mut tex_file = File::create(&tex_file_path).map_err(LatexToSvgError::Io)?;
-    write_latex(&mut tex_file, preamble, latex)?;
+    write_latex(&mut tex_file, preamble, options, latex)?;
 
-    let pdf_latex_output = pdf_latex(&tex_file_path)?;
+    let pdf_latex_output = pdf_latex(&tex_file_path, options)?;
     if !pdf_latex_output.status.success() {
         return Err(LatexToSvgError::PdfLatex(pdf_latex_output));
     }
 
-    let mut pdf_crop_cmd = Command::new("pdfcrop");
-    pdf_crop_cmd.current_dir(tmp_dir.path());
-    pdf_crop_cmd.arg(&pdf_file_path);
-    pdf_crop_cmd.arg(&pdf_crop_file_path);
-    let pdf_crop_output = pdf_crop_cmd.output()?;
+    let mut pdf_crop_cmd = file_conversion_command(
+        &options.pdfcrop_program,
+        &pdf_file_path,
+        &pdf_crop_file_path,
+    );
+    let pdf_crop_output = output_with_timeout(&mut pdf_crop_cmd, timeout)?;
     if !pdf_crop_output.status.success() {
         return Err(LatexToSvgError::PdfCrop(pdf_crop_output));
     }
 
-    let mut pdf2svg_cmd = Command::new("pdf2svg");
-    pdf2svg_cmd.current_dir(tmp_dir.path());
-    pdf2svg_cmd.arg(&pdf_crop_file_path);
-    pdf2svg_cmd.arg(&svg_file_path);
-    let pdf2svg_output = pdf2svg_cmd.output()?;
+    let mut pdf2svg_cmd = file_conversion_command(
+        &options.pdf2svg_program,
+        &pdf_crop_file_path,
+        &svg_file_path,
+    );
+    let pdf2svg_output = output_with_timeout(&mut pdf2svg_cmd, timeout)?;
     if !pdf2svg_output.status.success() {
         return Err(LatexToSvgError::Pdf2Svg(pdf2svg_output));
     }
@@ -186,14 +440,30 @@ pub fn latex_to_svg(preamble: &[&str], latex: &str) -> Result<String, LatexToSvg
     Ok(svg)
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct SvgInfo {
     pub width_em: f64,
     pub height_em: f64,
     pub baseline_em: Option<f64>,
 }
 
-// Converts the dimensions of the svg from pt to em. Returns (width, height) in em.
-pub fn svg_dimensions_to_em(svg: &mut minidom::Element) -> Result<(f64, f64), LatexToSvgError> {
+impl SvgInfo {
+    // The offset of the image from its default position, which is such that the bottom of the
+    // image is on the baseline of the surrounding text.
+    pub fn top_em(&self) -> f64 {
+        match self.baseline_em {
+            None => 0.0,
+            Some(baseline_em) => self.height_em - baseline_em,
+        }
+    }
+}
+
+// Converts the dimensions of the svg from pt to em, where an em is em_pt pt. Returns (width, height)
+// in em.
+pub fn svg_dimensions_to_em(
+    svg: &mut minidom::Element,
+    em_pt: f64,
+) -> Result<(f64, f64), LatexToSvgError> {
     let bad_svg = || LatexToSvgError::BadSvg;
 
     let width_attr = svg.attr("width").ok_or(bad_svg())?;
@@ -202,7 +472,7 @@ pub fn svg_dimensions_to_em(svg: &mut minidom::Element) -> Result<(f64, f64), La
         .ok_or(bad_svg())?
         .parse()
         .map_err(|_| bad_svg())?;
-    let width_em = width_pt / 10.0;
+    let width_em = width_pt / em_pt;
 
     let height_attr = svg.attr("height").ok_or(bad_svg())?;
     let height_pt: f64 = height_attr
@@ -210,7 +480,7 @@ pub fn svg_dimensions_to_em(svg: &mut minidom::Element) -> Result<(f64, f64), La
         .ok_or(bad_svg())?
         .parse()
         .map_err(|_| bad_svg())?;
-    let height_em = height_pt / 10.0;
+    let height_em = height_pt / em_pt;
 
     svg.set_attr("width", format!("{width_em}em"));
     svg.set_attr("height", format!("{height_em}em"));
@@ -218,56 +488,200 @@ pub fn svg_dimensions_to_em(svg: &mut minidom::Element) -> Result<(f64, f64), La
     Ok((width_em, height_em))
 }
 
-// Removes the baseline point from the svg. Returns the y coordinate of the center of the point,
-// i.e. the y-coordinate that corresponds to the baseline.
-pub fn remove_baseline_point(svg_el: &mut minidom::Element) -> Result<f64, LatexToSvgError> {
-    let bad_svg = || LatexToSvgError::BadSvg;
+// Returns whether the element is or contains a path that is drawn, i.e. not in the defs of the svg.
+fn contains_drawn_path(el: &minidom::Element) -> bool {
+    match el.name() {
+        "path" => true,
+        "defs" => false,
+        _ => el.children().any(contains_drawn_path),
+    }
+}
 
-    let g_el: &mut minidom::element::Element = svg_el
-        .get_child_mut("g", minidom::NSChoice::Any)
-        .ok_or(bad_svg())?;
-    if g_el.attr("id") != Some("surface1") {
-        return Err(LatexToSvgError::BadSvg);
+// Removes the first path in document order that is drawn, regardless of the groups it is nested in.
+fn remove_first_drawn_path(el: &mut minidom::Element) -> Option<minidom::Element> {
+    let child = el.children_mut().find(|child| contains_drawn_path(child))?;
+    if child.name() == "path" {
+        // The path is the first path child, since no earlier child contains a path.
+        el.remove_child("path", minidom::NSChoice::Any)
+    } else {
+        remove_first_drawn_path(child)
     }
+}
+
+// Removes the baseline point from the svg. Returns the y coordinate in em of the center of the
+// point, i.e. the y-coordinate that corresponds to the baseline. The point is drawn by the \rule
+// that math_to_svg puts in front of inline math, so it's the first path of the svg.
+pub fn remove_baseline_point(
+    svg_el: &mut minidom::Element,
+    em_pt: f64,
+) -> Result<f64, LatexToSvgError> {
+    let not_found = || LatexToSvgError::BaselineNotFound;
 
-    let path_el = g_el
-        .remove_child("path", minidom::NSChoice::Any)
-        .ok_or(bad_svg())?;
-    let transform_attr = path_el.attr("transform").ok_or(bad_svg())?;
+    let path_el = remove_first_drawn_path(svg_el).ok_or(not_found())?;
+    let transform_attr = path_el.attr("transform").ok_or(not_found())?;
 
-    let y_substr_begin = 1 + transform_attr.rfind(",").ok_or(bad_svg())?;
-    let y_substr_end = transform_attr.rfind(")").ok_or(bad_svg())?;
+    let y_substr_begin = 1 + transform_attr.rfind(",").ok_or(not_found())?;
+    let y_substr_end = transform_attr.rfind(")").ok_or(not_found())?;
     let y_str = &transform_attr[y_substr_begin..y_substr_end];
 
-    let y: f64 = y_str.parse().map_err(|_| bad_svg())?;
+    let y: f64 = y_str.trim().parse().map_err(|_| not_found())?;
 
-    let baseline_em = (y + 0.5) / 10.0;
+    let baseline_em = (y + 0.5) / em_pt;
     Ok(baseline_em)
 }
 
-pub fn math_to_svg(
-    preamble: &[&str],
-    math: &Math,
-) -> Result<(minidom::Element, SvgInfo), LatexToSvgError> {
+// The color with which pdf2svg draws black.
+const SVG_BLACK: &str = "rgb(0%,0%,0%)";
+
+// The style sheet embedded into svgs drawn in currentColor. Svgs that are embedded as images don't
+// inherit the color of the page, so they follow the color scheme preferred by the reader instead.
+const CURRENT_COLOR_STYLE: &str =
+    "svg { color: black; } @media (prefers-color-scheme: dark) { svg { color: white; } }";
+
+// Replaces black in the fill and stroke attributes and styles of the svg elements by currentColor,
+// and adds a style element that sets currentColor according to the preferred color scheme.
+pub fn use_current_color(svg_el: &mut minidom::Element) {
+    replace_black(svg_el);
+    let style = minidom::Element::builder("style")
+        .append(CURRENT_COLOR_STYLE)
+        .build();
+    svg_el.append_child(style);
+}
+
+fn replace_black(svg_el: &mut minidom::Element) {
+    for (name, value) in svg_el.attrs_mut() {
+        if matches!(name, "fill" | "stroke" | "style") {
+            *value = value.replace(SVG_BLACK, "currentColor");
+        }
+    }
+    for child in svg_el.children_mut() {
+        replace_black(child);
+    }
+}
+
+// Removes the parts of the svg that don't affect how it's displayed: comments, metadata elements and
+// the whitespace between elements. The xml declaration is dropped when the svg is parsed already.
+pub fn minify_svg(svg_el: &mut minidom::Element) {
+    for node in svg_el.nodes_mut() {
+        let remove = match node {
+            minidom::Node::Element(el) => el.name() == "metadata",
+            minidom::Node::Text(text) => text.trim().is_empty(),
+            minidom::Node::Comment(_) => true,
+        };
+        if remove {
+            *node = minidom::Node::Text(String::new());
+        } else if let minidom::Node::Element(el) = node {
+            minify_svg(el);
+        }
+    }
+}
+
+// Returns the latex with which math is compiled to svg.
+fn math_latex(math: &Math) -> String {
     use Math::*;
-    let latex = match math {
+    match math {
         Inline(content) => {
             formatdoc! {r#"
                     $\makebox[0pt][l]{{\rule{{1pt}}{{1pt}}}}{content}$
                 "#}
         }
-        Display { source, .. } | Mathpar { source, .. } => source.to_string(),
+        // A \tag is displayed next to the image like an equation number, so it is removed here.
+        Display { source, .. } => match find_math_tag(source) {
+            Some((tag_command, _)) => unnumbered_math(&source.replacen(tag_command, "", 1)),
+            None => unnumbered_math(source),
+        },
+        Mathpar { source, .. } => match find_math_tag(source) {
+            Some((tag_command, _)) => source.replacen(tag_command, "", 1),
+            None => source.to_string(),
+        },
+        Align { source, .. } => unnumbered_math(source),
+    }
+}
+
+// Equation numbers are displayed next to the image of display math, so equation and align
+// environments are compiled as their unnumbered variants, and without labels.
+fn unnumbered_math(source: &str) -> String {
+    let unnumbered = match (raw_env("equation")(source), raw_env("align")(source)) {
+        (Ok(("", content)), _) => format!("\\[{content}\\]"),
+        (_, Ok(("", content))) => format!("\\begin{{align*}}{content}\\end{{align*}}"),
+        _ => source.to_string(),
     };
+    strip_math_labels(&unnumbered)
+}
+
+// Renames the ids of the svg to id1, id2 and so on in document order and updates the references
+// to them, so that the svg doesn't depend on the ids that pdf2svg happens to choose.
+pub fn normalize_svg_ids(svg_el: &mut minidom::Element) {
+    let mut ids = HashMap::new();
+    collect_svg_ids(svg_el, &mut ids);
+    rename_svg_ids(svg_el, &ids);
+}
+
+fn collect_svg_ids(el: &minidom::Element, ids: &mut HashMap<String, String>) {
+    if let Some(id) = el.attr("id") {
+        let new_id = format!("id{}", ids.len() + 1);
+        ids.entry(id.to_string()).or_insert(new_id);
+    }
+    for child in el.children() {
+        collect_svg_ids(child, ids);
+    }
+}
+
+fn rename_svg_ids(el: &mut minidom::Element, ids: &HashMap<String, String>) {
+    for (name, value) in el.attrs_mut() {
+        let renamed = if name == "id" {
+            ids.get(value.as_str()).cloned()
+        } else if let Some(id) = value.strip_prefix('#') {
+            ids.get(id).map(|id| format!("#{id}"))
+        } else if let Some(id) = value
+            .strip_prefix("url(#")
+            .and_then(|v| v.strip_suffix(')'))
+        {
+            ids.get(id).map(|id| format!("url(#{id})"))
+        } else {
+            None
+        };
+        if let Some(renamed) = renamed {
+            *value = renamed;
+        }
+    }
+    for child in el.children_mut() {
+        rename_svg_ids(child, ids);
+    }
+}
 
-    let svg = latex_to_svg(preamble, &latex)?;
+// Compiles math to an svg whose dimensions are given in em, see EmitOptions::math_em_pt.
+pub fn math_to_svg(
+    preamble: &[&str],
+    math: &Math,
+    options: &EmitOptions,
+) -> Result<(minidom::Element, SvgInfo), LatexToSvgError> {
+    let em_pt = options.math_em_pt();
+    use Math::*;
+    let latex = math_latex(math);
+
+    let (Inline(source) | Display { source, .. } | Mathpar { source, .. } | Align { source, .. }) =
+        math;
+    check_balanced(source).map_err(LatexToSvgError::UnbalancedBraces)?;
+
+    let svg = latex_to_svg(preamble, &latex, options)?;
     let bad_svg = || LatexToSvgError::BadSvg;
     let mut svg_el: minidom::Element = svg.parse().map_err(|_| bad_svg())?;
-    let (width_em, height_em) = svg_dimensions_to_em(&mut svg_el)?;
+    let (width_em, height_em) = svg_dimensions_to_em(&mut svg_el, em_pt)?;
 
     let baseline_em = match math {
-        Inline(_) => Some(remove_baseline_point(&mut svg_el)?),
-        Display { .. } | Mathpar { .. } => None,
+        Inline(_) => Some(remove_baseline_point(&mut svg_el, em_pt)?),
+        Display { .. } | Mathpar { .. } | Align { .. } => None,
     };
+    // Persist the baseline so that it can be recovered by read_svg_info when the svg is reused.
+    if let Some(baseline_em) = baseline_em {
+        svg_el.set_attr("data-baseline", format!("{baseline_em}em"));
+    }
+    if options.math_current_color {
+        use_current_color(&mut svg_el);
+    }
+    minify_svg(&mut svg_el);
+    normalize_svg_ids(&mut svg_el);
 
     Ok((
         svg_el,
@@ -279,6 +693,25 @@ pub fn math_to_svg(
     ))
 }
 
+// Reads the geometry of an svg file that was previously generated by math_to_svg.
+pub fn read_svg_info(svg_path: &Path) -> Result<SvgInfo, LatexToSvgError> {
+    let bad_svg = || LatexToSvgError::BadSvg;
+    let svg = fs::read_to_string(svg_path)?;
+    let svg_el: minidom::Element = svg.parse().map_err(|_| bad_svg())?;
+
+    let em_attr = |name: &str| -> Option<Result<f64, LatexToSvgError>> {
+        let value = svg_el.attr(name)?;
+        let em = value.strip_suffix("em").ok_or(bad_svg());
+        Some(em.and_then(|em| em.parse().map_err(|_| bad_svg())))
+    };
+
+    Ok(SvgInfo {
+        width_em: em_attr("width").ok_or(bad_svg())??,
+        height_em: em_attr("height").ok_or(bad_svg())??,
+        baseline_em: em_attr("data-baseline").transpose()?,
+    })
+}
+
 #[derive(Copy, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MathDigest(pub [u8; 32]);
 
@@ -289,10 +722,44 @@ impl Display for MathDigest {
     }
 }
 
-pub fn hash_math(preamble: &[&str], math: &Math) -> MathDigest {
+// The version of the pipeline that compiles math to svgs. Must be increased whenever the svg
+// generated for a given formula changes, e.g. because the latex wrapping the formula or the
+// post-processing of the svg changes, so that svgs from previous versions are not reused.
+pub const MATH_PIPELINE_VERSION: u32 = 6;
+
+pub fn hash_math(preamble: &[&str], math: &Math, options: &EmitOptions) -> MathDigest {
+    hash_math_with_version(MATH_PIPELINE_VERSION, preamble, math, options)
+}
+
+fn hash_math_with_version(
+    version: u32,
+    preamble: &[&str],
+    math: &Math,
+    options: &EmitOptions,
+) -> MathDigest {
     let mut hasher = Sha256::new();
 
-    for line in preamble {
+    hasher.update(version.to_le_bytes());
+
+    // Different engines produce different svgs for the same formula.
+    hasher.update(options.latex_engine.program());
+
+    // The size of an em is stored in the svgs.
+    hasher.update(options.math_em_pt().to_le_bytes());
+    hasher.update([u8::from(options.math_current_color)]);
+    hasher.update([u8::from(options.math_showonlyrefs)]);
+
+    for line in math_preamble_lines(preamble) {
+        hasher.update(line.as_bytes());
+    }
+
+    // The packages and extra lines are added after the preamble.
+    for package in &options.math_packages {
+        hasher.update([0]);
+        hasher.update(package.as_bytes());
+    }
+    for line in &options.math_preamble_extra {
+        hasher.update([1]);
         hasher.update(line.as_bytes());
     }
 
@@ -302,106 +769,765 @@ pub fn hash_math(preamble: &[&str], math: &Math) -> MathDigest {
             hasher.update(&[0]);
             hasher.update(source);
         }
-        Display { source, label: _ } => {
+        Display { source, .. } => {
             hasher.update(&[1]);
             hasher.update(source);
         }
-        Mathpar { source, label: _ } => {
+        Mathpar { source, .. } => {
             hasher.update(&[2]);
             hasher.update(source);
         }
+        Align { source, lines: _ } => {
+            hasher.update([3]);
+            hasher.update(source);
+        }
     }
 
     MathDigest(hasher.finalize().as_slice().try_into().unwrap())
 }
 
+// Writes the css rule that sizes and positions the image of the svg with the given digest.
+pub fn write_svg_geometry(
+    out: &mut impl io::Write,
+    digest: MathDigest,
+    svg_info: &SvgInfo,
+) -> Result<(), io::Error> {
+    let SvgInfo {
+        width_em,
+        height_em,
+        ..
+    } = svg_info;
+    let top_em = svg_info.top_em();
+    writedoc! {out, r#"
+        img[src$="{digest}.svg"] {{
+            width: {width_em}em;
+            height: {height_em}em;
+            top: {top_em}em;
+        }}
+    "#}
+}
+
+// Reads the svg files that emit_math_svg_files has written for the given digests.
+pub fn read_math_svg_files(
+    out_dir: &Path,
+    svg_infos: &HashMap<MathDigest, SvgInfo>,
+) -> Result<HashMap<MathDigest, Vec<u8>>, io::Error> {
+    let out_dir = out_dir.join(SVG_OUT_DIR);
+    svg_infos
+        .keys()
+        .map(|digest| Ok((*digest, fs::read(out_dir.join(format!("{digest}.svg")))?)))
+        .collect()
+}
+
 pub const SVG_OUT_DIR: &'static str = "img-math";
 
+// Removes the svgs and geometry of previous runs unless they were generated by the current
+// version of the pipeline, as recorded in the "version" file of the svg directory. Svgs of other
+// versions would never be reused because their digests differ, so we might as well delete them.
+fn purge_stale_svg_cache(svg_dir: &Path) -> Result<(), io::Error> {
+    let version_path = svg_dir.join("version");
+    let current_version = MATH_PIPELINE_VERSION.to_string();
+    match fs::read_to_string(&version_path) {
+        Ok(version) if version.trim() == current_version => return Ok(()),
+        Ok(_) => fs::remove_dir_all(svg_dir)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            // Caches from before the version file was introduced are stale, too.
+            if svg_dir.exists() {
+                fs::remove_dir_all(svg_dir)?;
+            }
+        }
+        Err(err) => return Err(err),
+    }
+    fs::create_dir_all(svg_dir)?;
+    fs::write(version_path, current_version)
+}
+
+// Removes the svg files in the output directory whose digests are not in the given set. Svgs of
+// formulas that are no longer in the document would otherwise accumulate over time, since
+// emit_math_svg_files never deletes svgs.
+pub fn prune_math_svg_files(
+    out_dir: &Path,
+    digests: &HashSet<MathDigest>,
+) -> Result<(), io::Error> {
+    let out_dir = out_dir.join(SVG_OUT_DIR);
+    let file_names: HashSet<String> = digests.iter().map(|d| format!("{d}.svg")).collect();
+
+    for entry in fs::read_dir(&out_dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().and_then(|name| name.to_str());
+        if let Some(file_name) = file_name {
+            if file_name.ends_with(".svg") && !file_names.contains(file_name) {
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Compiles math nodes to svg files in the output directory, reusing svg files that exist from
+// previous runs. Returns the geometry of the svgs of all math nodes that were compiled
+// successfully, and the math nodes that failed to compile. Unless EmitOptions::best_effort_math is
+// set, compilation stops at the first failure. Formulas are compiled on a pool of
+// EmitOptions::math_jobs threads, or on rayon's global pool if that is not set.
 pub fn emit_math_svg_files<'a, 'b>(
     out_dir: &'a Path,
     preamble: &'b [&'b str],
     math: &[&'b Math<'b>],
-) -> Result<(), (&'b Math<'b>, LatexToSvgError)> {
+    options: &EmitOptions,
+) -> (
+    HashMap<MathDigest, SvgInfo>,
+    Vec<(&'b Math<'b>, LatexToSvgError)>,
+) {
+    let best_effort = options.best_effort_math;
     let out_dir = out_dir.join(SVG_OUT_DIR);
-    fs::create_dir_all(&out_dir).unwrap();
+    purge_stale_svg_cache(&out_dir).unwrap();
 
     // Collect all math nodes that need to be compiled. There may be duplicate new math nodes;
     // these need to be compiled only once. We detect duplicates by saving digests in a hash set.
+    let mut svg_infos: HashMap<MathDigest, SvgInfo> = HashMap::new();
+    let mut errors: Vec<(&'b Math<'b>, LatexToSvgError)> = Vec::new();
     let mut old_math_digests: HashSet<MathDigest> = HashSet::new();
-    let new_math: Vec<&'b Math<'b>> = math
-        .iter()
-        .copied()
-        .filter(|math| {
-            let digest = hash_math(preamble, &math);
-            let svg_path = out_dir.join(&format!("{digest}.svg"));
-            let is_new = !old_math_digests.contains(&digest) && !svg_path.exists();
-            old_math_digests.insert(digest);
-            is_new
-        })
-        .collect();
+    let mut new_math: Vec<&'b Math<'b>> = Vec::new();
+    for math in math.iter().copied() {
+        let digest = hash_math(preamble, math, options);
+        if !old_math_digests.insert(digest) {
+            continue;
+        }
+        let svg_path = out_dir.join(format!("{digest}.svg"));
+        if !svg_path.exists() {
+            new_math.push(math);
+            continue;
+        }
+        match read_svg_info(&svg_path) {
+            Ok(svg_info) => {
+                svg_infos.insert(digest, svg_info);
+            }
+            Err(err) => {
+                errors.push((math, err));
+                if !best_effort {
+                    return (svg_infos, errors);
+                }
+            }
+        }
+    }
 
     // Compile math nodes to svgs in parallel. We write to temporary files first and rename later
     // to ensure consistency: We don't want files containing only partial contents, and we want to
     // have the geometry information in the css file if the svg file exists.
     let (compiled_math_sender, compiled_math_receiver) = channel::<(&'b Math<'b>, SvgInfo)>();
-    let compile_math_result: Result<(), (&'b Math<'b>, LatexToSvgError)> =
-        new_math.par_iter().copied().try_for_each_with(
-            compiled_math_sender,
-            |compiled_math_sender, math: &'b Math<'b>| {
-                let digest = hash_math(preamble, &math);
-                let svg_path_tmp = out_dir.join(&format!("{digest}.svg.tmp"));
-
-                let (svg, svg_info) = math_to_svg(preamble, math).map_err(|err| (math, err))?;
-                fs::write(&svg_path_tmp, &String::from(&svg)).unwrap();
-                compiled_math_sender.send((math, svg_info)).unwrap();
-                Ok(())
-            },
-        );
+    let compile_math = |compiled_math_sender: &mut Sender<(&'b Math<'b>, SvgInfo)>,
+                        math: &'b Math<'b>|
+     -> Result<(), (&'b Math<'b>, LatexToSvgError)> {
+        let digest = hash_math(preamble, math, options);
+        let svg_path_tmp = out_dir.join(format!("{digest}.svg.tmp"));
+
+        let (svg, svg_info) = math_to_svg(preamble, math, options).map_err(|err| (math, err))?;
+        fs::write(&svg_path_tmp, String::from(&svg)).unwrap();
+        compiled_math_sender.send((math, svg_info)).unwrap();
+        Ok(())
+    };
+    let compile_all_math = || {
+        if best_effort {
+            // Collecting preserves the order of the math nodes, so errors are reported in
+            // document order.
+            let results: Vec<Result<(), (&'b Math<'b>, LatexToSvgError)>> = new_math
+                .par_iter()
+                .copied()
+                .map_with(compiled_math_sender, compile_math)
+                .collect();
+            results.into_iter().filter_map(Result::err).collect()
+        } else {
+            new_math
+                .par_iter()
+                .copied()
+                .try_for_each_with(compiled_math_sender, compile_math)
+                .err()
+                .into_iter()
+                .collect()
+        }
+    };
+    let compile_errors: Vec<(&'b Math<'b>, LatexToSvgError)> = match options.math_jobs {
+        Some(jobs) => ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()
+            .unwrap()
+            .install(compile_all_math),
+        None => compile_all_math(),
+    };
+    errors.extend(compile_errors);
     let compiled_math: Vec<(&'b Math<'b>, SvgInfo)> = compiled_math_receiver.iter().collect();
 
-    // Open the css file containing geometry information about the svgs. We append if it already
-    // exists and create otherwise.
-    let geometry_path = out_dir.join("geometry.css");
-    let mut geometry_file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .create(true)
-        .open(geometry_path)
-        .unwrap();
-
-    // Write geometry info for new math svgs to the css file.
     for (math, svg_info) in compiled_math.iter() {
-        let SvgInfo {
-            width_em,
-            height_em,
-            baseline_em,
-        } = svg_info;
-
-        let top_em = match baseline_em {
-            None => 0.0,
-            Some(baseline_em) => height_em - baseline_em,
-        };
+        svg_infos.insert(hash_math(preamble, math, options), *svg_info);
+    }
 
-        let digest = hash_math(preamble, &math);
-        writedoc! {geometry_file, r#"
-            img[src$="{digest}.svg"] {{
-                width: {width_em}em;
-                height: {height_em}em;
-                top: {top_em}em;
-            }}
-        "#}
-        .unwrap();
+    // Regenerate the css file containing geometry information about the svgs from the svgs of all
+    // math nodes, so that it doesn't accumulate rules of previous runs. Math that failed to compile
+    // has no svg and is skipped here and below. The file is written before the svgs are renamed, so
+    // that every svg has its geometry in the css file.
+    let geometry_path = out_dir.join("geometry.css");
+    let geometry_path_tmp = out_dir.join("geometry.css.tmp");
+    let mut geometry_file = File::create(&geometry_path_tmp).unwrap();
+    for (digest, svg_info) in svg_infos.iter().sorted_by_key(|(digest, _)| digest.0) {
+        write_svg_geometry(&mut geometry_file, *digest, svg_info).unwrap();
     }
     geometry_file.sync_data().unwrap();
+    fs::rename(geometry_path_tmp, geometry_path).unwrap();
 
     // Rename temporary svg files.
     for (math, _) in compiled_math.iter() {
-        let digest = hash_math(preamble, &math);
+        let digest = hash_math(preamble, math, options);
         let svg_path = out_dir.join(&format!("{digest}.svg"));
         let svg_path_tmp = out_dir.join(&format!("{digest}.svg.tmp"));
 
         fs::rename(svg_path_tmp, svg_path).unwrap();
     }
 
-    compile_math_result
+    (svg_infos, errors)
+}
+
+// Requires pdflatex; run with `cargo test -- --ignored`.
+#[test]
+#[ignore]
+fn looping_preamble_times_out() {
+    let preamble = [r"\def\loop{\loop}", r"\loop"];
+    let start = Instant::now();
+    let options = EmitOptions {
+        latex_timeout: Duration::from_secs(1),
+        ..EmitOptions::default()
+    };
+    let result = latex_to_svg(&preamble, "$x$", &options);
+    assert!(matches!(result, Err(LatexToSvgError::Timeout)));
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn latex_engine_program() {
+    let tex_file_path = Path::new("/tmp/doc.tex");
+    for (engine, program) in [
+        (LatexEngine::PdfLatex, "pdflatex"),
+        (LatexEngine::XeLatex, "xelatex"),
+        (LatexEngine::LuaLatex, "lualatex"),
+    ] {
+        assert_eq!(program.parse(), Ok(engine));
+        let options = EmitOptions {
+            latex_engine: engine,
+            ..EmitOptions::default()
+        };
+        let cmd = latex_command(tex_file_path, &options);
+        assert_eq!(cmd.get_program(), program);
+        assert_eq!(cmd.get_args().last(), Some(tex_file_path.as_os_str()));
+    }
+}
+
+// Writes a shell script into dir that stands in for one of the programs that compile math.
+#[cfg(all(test, unix))]
+fn stand_in_program(dir: &Path, name: &str, script: &str) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+// Runs the pipeline with stand-ins for the tex engine, pdfcrop and pdf2svg.
+#[cfg(unix)]
+#[test]
+fn custom_programs() {
+    let bin_dir = TempDir::new("latex-to-html").unwrap();
+    let program = |name: &str, script: &str| stand_in_program(bin_dir.path(), name, script);
+    let options = EmitOptions {
+        latex_program: Some(program("my-latex", "touch doc.pdf")),
+        pdfcrop_program: program("my-pdfcrop", r#"cp "$1" "$2""#),
+        pdf2svg_program: program("my-pdf2svg", r#"echo '<svg id="custom"/>' > "$2""#),
+        ..EmitOptions::default()
+    };
+    let cmd = latex_command(Path::new("/tmp/doc.tex"), &options);
+    assert_eq!(cmd.get_program(), bin_dir.path().join("my-latex"));
+    let svg = latex_to_svg(&[], "$x$", &options).unwrap();
+    assert_eq!(svg.trim(), r#"<svg id="custom"/>"#);
+}
+
+#[test]
+fn tex_log_errors() {
+    let log = indoc::indoc! {r#"
+        This is pdfTeX, Version 3.141592653-2.6-1.40.24 (TeX Live 2022/Debian) (preloaded format=pdflatex)
+         restricted \write18 enabled.
+        entering extended mode
+        (./doc.tex
+        LaTeX2e <2021-11-15> patch level 1
+        (/usr/share/texlive/texmf-dist/tex/latex/base/minimal.cls
+        Document Class: minimal 2001/05/25 Standard LaTeX minimal class
+        ) (/usr/share/texlive/texmf-dist/tex/latex/l3backend/l3backend-pdftex.def)
+        No file doc.aux.
+        ! Undefined control sequence.
+        l.6 $\frac{a}{b} \foo
+                            $
+        The control sequence at the end of the top line
+        of your error message was never \def'ed. If you have
+
+        ! Missing $ inserted.
+        <inserted text>
+                        $
+        l.7 
+            
+        ! Emergency stop.
+        <*> doc.tex
+                   
+        [1{/var/lib/texmf/fonts/map/pdftex/updmap/pdftex.map}] (./doc.aux) )
+        Output written on doc.pdf (1 page, 12345 bytes).
+        Transcript written on doc.log.
+    "#};
+    assert_eq!(
+        parse_tex_log(log),
+        [
+            TexLogError {
+                line: Some(6),
+                message: "Undefined control sequence.".to_string(),
+            },
+            TexLogError {
+                line: Some(7),
+                message: "Missing $ inserted.".to_string(),
+            },
+            TexLogError {
+                line: None,
+                message: "Emergency stop.".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn math_digest_depends_on_pipeline_version() {
+    let math = Math::Inline("x^2");
+    let options = EmitOptions::default();
+    assert_eq!(
+        hash_math(&[], &math, &options),
+        hash_math_with_version(MATH_PIPELINE_VERSION, &[], &math, &options)
+    );
+    assert_ne!(
+        hash_math_with_version(1, &[], &math, &options),
+        hash_math_with_version(2, &[], &math, &options)
+    );
+}
+
+#[test]
+fn stale_svg_cache_is_purged() {
+    let tmp_dir = TempDir::new("latex-to-html").unwrap();
+    let svg_dir = tmp_dir.path().join(SVG_OUT_DIR);
+    fs::create_dir_all(&svg_dir).unwrap();
+    fs::write(svg_dir.join("stale.svg"), "<svg/>").unwrap();
+
+    purge_stale_svg_cache(&svg_dir).unwrap();
+    assert!(!svg_dir.join("stale.svg").exists());
+    let version = fs::read_to_string(svg_dir.join("version")).unwrap();
+    assert_eq!(version, MATH_PIPELINE_VERSION.to_string());
+
+    // A cache of the current version is kept.
+    fs::write(svg_dir.join("fresh.svg"), "<svg/>").unwrap();
+    purge_stale_svg_cache(&svg_dir).unwrap();
+    assert!(svg_dir.join("fresh.svg").exists());
+}
+
+#[test]
+fn unbalanced_braces_are_detected() {
+    assert_eq!(check_balanced(r"\frac{a}{b} + \{x\} % }"), Ok(()));
+    assert_eq!(check_balanced(r"\begin{pmatrix} a \end{pmatrix}"), Ok(()));
+    assert_eq!(check_balanced(r"x}"), Err(1));
+    assert_eq!(check_balanced(r"\begin{cases} x \end{pmatrix}"), Err(16));
+    assert_eq!(check_balanced(r"\begin{cases} x"), Err(0));
+
+    let result = math_to_svg(&[], &Math::Inline(r"\frac{a}{b"), &EmitOptions::default());
+    assert!(matches!(result, Err(LatexToSvgError::UnbalancedBraces(8))));
+}
+
+#[test]
+fn math_jobs_preserve_error_order() {
+    // Formulas with unbalanced braces fail before pdflatex is run.
+    let tmp_dir = TempDir::new("latex-to-html").unwrap();
+    let math = [Math::Inline("{a"), Math::Inline("b}"), Math::Inline("{c")];
+    let math: Vec<&Math> = math.iter().collect();
+    for math_jobs in [Some(1), Some(2), None] {
+        let options = EmitOptions {
+            best_effort_math: true,
+            math_jobs,
+            ..EmitOptions::default()
+        };
+        let (svg_infos, errors) = emit_math_svg_files(tmp_dir.path(), &[], &math, &options);
+        assert!(svg_infos.is_empty());
+        let failed: Vec<&Math> = errors.iter().map(|(math, _)| *math).collect();
+        assert_eq!(failed, math);
+    }
+}
+
+#[test]
+fn orphaned_svgs_are_pruned() {
+    let tmp_dir = TempDir::new("latex-to-html").unwrap();
+    let svg_dir = tmp_dir.path().join(SVG_OUT_DIR);
+    purge_stale_svg_cache(&svg_dir).unwrap();
+
+    // An svg generated before the formula was edited, and one for the current formula.
+    let options = EmitOptions::default();
+    let old_digest = hash_math(&[], &Math::Inline("x^2"), &options);
+    let new_math = Math::Inline("x^3");
+    let new_digest = hash_math(&[], &new_math, &options);
+    let svg_info = SvgInfo {
+        width_em: 1.0,
+        height_em: 1.0,
+        baseline_em: None,
+    };
+    let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="1em" height="1em"/>"#;
+    let mut geometry = Vec::new();
+    for digest in [old_digest, new_digest] {
+        fs::write(svg_dir.join(format!("{digest}.svg")), svg).unwrap();
+        write_svg_geometry(&mut geometry, digest, &svg_info).unwrap();
+    }
+    fs::write(svg_dir.join("geometry.css"), geometry).unwrap();
+
+    let (svg_infos, errors) = emit_math_svg_files(tmp_dir.path(), &[], &[&new_math], &options);
+    assert!(errors.is_empty());
+    let digests: HashSet<MathDigest> = svg_infos.keys().copied().collect();
+    prune_math_svg_files(tmp_dir.path(), &digests).unwrap();
+
+    assert!(!svg_dir.join(format!("{old_digest}.svg")).exists());
+    assert!(svg_dir.join(format!("{new_digest}.svg")).exists());
+    let geometry = fs::read_to_string(svg_dir.join("geometry.css")).unwrap();
+    assert!(!geometry.contains(&old_digest.to_string()));
+    assert!(geometry.contains(&new_digest.to_string()));
+    assert!(geometry.trim_end().ends_with('}'));
+    assert!(svg_dir.join("version").exists());
+}
+
+#[test]
+fn geometry_css_is_regenerated() {
+    let tmp_dir = TempDir::new("latex-to-html").unwrap();
+    let svg_dir = tmp_dir.path().join(SVG_OUT_DIR);
+    purge_stale_svg_cache(&svg_dir).unwrap();
+
+    // Svgs that were compiled previously, and a rule for one of them that is out of date.
+    let options = EmitOptions::default();
+    let math = [Math::Inline("x"), Math::Inline("y")];
+    let math: Vec<&Math> = math.iter().collect();
+    let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="1em" height="2em"/>"#;
+    for math in math.iter() {
+        let digest = hash_math(&[], math, &options);
+        fs::write(svg_dir.join(format!("{digest}.svg")), svg).unwrap();
+    }
+    let stale_info = SvgInfo {
+        width_em: 3.0,
+        height_em: 3.0,
+        baseline_em: None,
+    };
+    let mut stale_geometry = Vec::new();
+    write_svg_geometry(
+        &mut stale_geometry,
+        hash_math(&[], math[0], &options),
+        &stale_info,
+    )
+    .unwrap();
+    fs::write(svg_dir.join("geometry.css"), stale_geometry).unwrap();
+
+    for _ in 0..2 {
+        let (_, errors) = emit_math_svg_files(tmp_dir.path(), &[], &math, &options);
+        assert!(errors.is_empty());
+    }
+
+    let geometry = fs::read_to_string(svg_dir.join("geometry.css")).unwrap();
+    let selectors: Vec<&str> = geometry
+        .lines()
+        .filter(|line| line.starts_with("img["))
+        .collect();
+    assert_eq!(selectors.len(), 2);
+    assert!(selectors.iter().all_unique());
+    assert!(!geometry.contains("3em"));
+    assert!(geometry.contains("height: 2em;"));
+}
+
+#[test]
+fn math_scale_changes_svg_size() {
+    let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="20pt" height="5pt"/>"#;
+    let options = EmitOptions::default();
+    let scaled_options = EmitOptions {
+        math_scale: 2.0,
+        ..EmitOptions::default()
+    };
+
+    let mut svg_el: minidom::Element = svg.parse().unwrap();
+    let (width_em, _) = svg_dimensions_to_em(&mut svg_el, options.math_em_pt()).unwrap();
+    let mut svg_el: minidom::Element = svg.parse().unwrap();
+    let (scaled_width_em, _) =
+        svg_dimensions_to_em(&mut svg_el, scaled_options.math_em_pt()).unwrap();
+    assert_eq!(width_em, 2.0);
+    assert_eq!(scaled_width_em, 4.0);
+
+    // Svgs of different sizes must not be reused for each other.
+    let math = Math::Inline("x");
+    assert_ne!(
+        hash_math(&[], &math, &options),
+        hash_math(&[], &math, &scaled_options)
+    );
+}
+
+#[test]
+fn math_uses_current_color() {
+    let svg = indoc::indoc! {r#"
+        <svg xmlns="http://www.w3.org/2000/svg" width="10pt" height="10pt">
+        <g id="surface1">
+        <path style="stroke:none;fill-rule:nonzero;fill:rgb(0%,0%,0%);fill-opacity:1;" d="M 0 0 L 1 1"/>
+        <path fill="none" stroke="rgb(0%,0%,0%)" d="M 0 0 L 1 1"/>
+        </g>
+        </svg>
+    "#};
+    let mut svg_el: minidom::Element = svg.parse().unwrap();
+    use_current_color(&mut svg_el);
+    let svg = String::from(&svg_el);
+    assert!(svg.contains("fill:currentColor;"));
+    assert!(svg.contains(r#"stroke="currentColor""#));
+    assert!(!svg.contains(SVG_BLACK));
+    assert!(svg.contains(&format!("<style>{CURRENT_COLOR_STYLE}</style></svg>")));
+}
+
+#[test]
+fn svgs_are_minified() {
+    let svg = indoc::indoc! {r##"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="10pt" height="10pt" viewBox="0 0 10 10" version="1.1">
+        <!-- Generated by pdf2svg -->
+        <metadata>
+          <rdf>Some metadata</rdf>
+        </metadata>
+        <defs>
+        <g>
+        <symbol overflow="visible" id="glyph0-0">
+        <path style="stroke:none;" d="M 1 1 L 2 2"/>
+        </symbol>
+        </g>
+        </defs>
+        <g id="surface1">
+        <g style="fill:rgb(0%,0%,0%);fill-opacity:1;">
+          <use xlink:href="#glyph0-0" x="1" y="2"/>
+        </g>
+        </g>
+        </svg>
+    "##};
+    let mut svg_el: minidom::Element = svg.parse().unwrap();
+    minify_svg(&mut svg_el);
+    let minified = String::from(&svg_el);
+    assert!(minified.len() < svg.len() * 3 / 4);
+    assert!(!minified.starts_with("<?xml"));
+    assert!(!minified.contains("metadata"));
+    assert!(!minified.contains("pdf2svg"));
+    assert!(!minified.contains('\n'));
+    assert!(minified.contains(r##"xlink:href="#glyph0-0""##));
+    assert!(minified.parse::<minidom::Element>().is_ok());
+}
+
+#[test]
+fn baseline_point_is_found_in_any_group() {
+    let svg = indoc::indoc! {r##"
+        <svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="10pt" height="10pt">
+        <defs>
+        <g><symbol id="glyph0-0"><path d="M 1 1 L 2 2"/></symbol></g>
+        </defs>
+        <g id="page1">
+        <g clip-rule="nonzero">
+        <path style="stroke:none;" d="M 0 0 L 1 0" transform="matrix(1,0,0,1,2,7)"/>
+        </g>
+        <use xlink:href="#glyph0-0" x="1" y="2"/>
+        <path d="M 3 3 L 4 3" transform="matrix(1,0,0,1,3,3)"/>
+        </g>
+        </svg>
+    "##};
+    let mut svg_el: minidom::Element = svg.parse().unwrap();
+    let baseline_em = remove_baseline_point(&mut svg_el, 10.0).unwrap();
+    assert_eq!(baseline_em, 0.75);
+    let svg = String::from(&svg_el);
+    assert!(!svg.contains("matrix(1,0,0,1,2,7)"));
+    assert!(svg.contains("matrix(1,0,0,1,3,3)"));
+    assert!(svg.contains("M 1 1 L 2 2"));
+
+    let mut svg_el: minidom::Element =
+        r#"<svg xmlns="http://www.w3.org/2000/svg"><g id="page1"/></svg>"#
+            .parse()
+            .unwrap();
+    assert!(matches!(
+        remove_baseline_point(&mut svg_el, 10.0),
+        Err(LatexToSvgError::BaselineNotFound)
+    ));
+}
+
+#[test]
+fn math_packages_are_loaded() {
+    let mut tex = Vec::new();
+    write_latex(&mut tex, &["% preamble"], &EmitOptions::default(), "$x$").unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("% preamble\n\\usepackage{amsmath}\n\\usepackage{mathtools}\n"));
+    assert!(tex.contains("\\mathtoolsset{showonlyrefs}\n\\begin{document}"));
+
+    let options = EmitOptions {
+        math_packages: Vec::new(),
+        ..EmitOptions::default()
+    };
+    let mut tex = Vec::new();
+    write_latex(&mut tex, &[], &options, "$x$").unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(!tex.contains("\\usepackage"));
+    assert!(!tex.contains("\\mathtoolsset"));
+
+    let math = Math::Inline("x");
+    let options = EmitOptions::default();
+    let amssymb_options = EmitOptions {
+        math_packages: vec!["amsmath".to_string(), "amssymb".to_string()],
+        ..EmitOptions::default()
+    };
+    assert_ne!(
+        hash_math(&[], &math, &options),
+        hash_math(&[], &math, &amssymb_options)
+    );
+}
+
+// Compiles an inline cases environment with a stand-in for the tex engine that fails unless
+// amsmath is loaded.
+#[cfg(unix)]
+#[test]
+fn inline_cases() {
+    let bin_dir = TempDir::new("latex-to-html").unwrap();
+    let script = "grep -q 'usepackage{amsmath}' doc.tex && touch doc.pdf";
+    let latex = stand_in_program(bin_dir.path(), "latex", script);
+    let options = EmitOptions {
+        latex_program: Some(latex),
+        pdfcrop_program: std::path::PathBuf::from("cp"),
+        pdf2svg_program: std::path::PathBuf::from("cp"),
+        ..EmitOptions::default()
+    };
+    let cases = r"$|x| = \begin{cases} x & x \geq 0 \\ -x & x < 0 \end{cases}$";
+    assert!(latex_to_svg(&[], cases, &options).is_ok());
+
+    let options = EmitOptions {
+        math_packages: Vec::new(),
+        ..options
+    };
+    assert!(latex_to_svg(&[], cases, &options).is_err());
+}
+
+#[test]
+fn math_preamble() {
+    let preamble = [
+        r"\usepackage{amssymb}",
+        "% LATEX_TO_HTML_NO_MATH",
+        r"\usepackage{hyperref}",
+    ];
+    let options = EmitOptions {
+        math_preamble_extra: vec![r"\newcommand{\R}{\mathbb{R}}".to_string()],
+        ..EmitOptions::default()
+    };
+    let mut tex = Vec::new();
+    write_latex(&mut tex, &preamble, &options, r"$\R$").unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("\\usepackage{amssymb}\n"));
+    assert!(!tex.contains("hyperref"));
+    assert!(tex.contains("\\newcommand{\\R}{\\mathbb{R}}\n\\begin{document}"));
+
+    let math = Math::Inline(r"\R");
+    assert_ne!(
+        hash_math(&preamble, &math, &options),
+        hash_math(&preamble, &math, &EmitOptions::default())
+    );
+    // Lines that are not used for math don't change the digest.
+    assert_eq!(
+        hash_math(&preamble, &math, &options),
+        hash_math(&preamble[..2], &math, &options)
+    );
+}
+
+#[test]
+fn display_math_is_compiled_without_numbers() {
+    use crate::parse::{align, display_math};
+    let (_, equation) =
+        display_math("\\begin{equation}\n  \\label{eq:a}\n  a = b\n\\end{equation}").unwrap();
+    assert_eq!(equation.label(), Some("eq:a"));
+    assert_eq!(math_latex(&equation), "\\[\n  a = b\\]");
+
+    let (_, equation) = display_math("\\begin{equation}a \\tag{$*$}\\end{equation}").unwrap();
+    assert_eq!(math_latex(&equation), "\\[a\\]");
+
+    let (_, align) =
+        align("\\begin{align}\n  x &= y \\label{eq:x} \\\\\n  y &= z\n\\end{align}").unwrap();
+    assert_eq!(
+        math_latex(&align),
+        "\\begin{align*}x &= y  \\\\\n  y &= z\\end{align*}"
+    );
+
+    let options = EmitOptions {
+        math_showonlyrefs: false,
+        ..EmitOptions::default()
+    };
+    let mut tex = Vec::new();
+    write_latex(&mut tex, &[], &options, "$x$").unwrap();
+    let tex = String::from_utf8(tex).unwrap();
+    assert!(tex.contains("\\usepackage{mathtools}\n\\begin{document}"));
+}
+
+// Compiles a formula twice with a stand-in for pdf2svg that chooses different ids on every run: It
+// counts its runs in a file and puts the count into the ids of the svg.
+#[cfg(unix)]
+#[test]
+fn math_svgs_are_reproducible() {
+    let bin_dir = TempDir::new("latex-to-html").unwrap();
+    let program = |name: &str, script: &str| stand_in_program(bin_dir.path(), name, script);
+    let svg_template = concat!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" "#,
+        r#"width="10pt" height="5pt"><defs><clipPath id="clipRUN"><rect/></clipPath>"#,
+        r#"<g id="glyphRUN-0"><path d="M 0 0"/></g></defs>"#,
+        r##"<g clip-path="url(#clipRUN)"><use xlink:href="#glyphRUN-0"/></g></svg>"##,
+    );
+    let template_path = bin_dir.path().join("template.svg");
+    fs::write(&template_path, svg_template).unwrap();
+    let runs_path = bin_dir.path().join("runs");
+    let pdf2svg = formatdoc! {r#"
+        runs=$(($(cat "{runs}" 2>/dev/null || echo 0) + 1))
+        echo $runs > "{runs}"
+        sed "s/RUN/$runs/g" "{template}" > "$2""#,
+        runs = runs_path.display(),
+        template = template_path.display(),
+    };
+    let options = EmitOptions {
+        latex_program: Some(program("latex", "touch doc.pdf")),
+        pdfcrop_program: program("pdfcrop", r#"cp "$1" "$2""#),
+        pdf2svg_program: program("pdf2svg", &pdf2svg),
+        ..EmitOptions::default()
+    };
+    let cmd = latex_command(Path::new("/tmp/doc.tex"), &options);
+    assert!(cmd
+        .get_envs()
+        .any(|(name, value)| name == "SOURCE_DATE_EPOCH" && value.is_some()));
+
+    let math = Math::Display {
+        source: r"\[ x \]",
+        label: None,
+        tag: None,
+        nonumber: false,
+    };
+    let digest = hash_math(&[], &math, &options);
+    let compile = || {
+        let tmp_dir = TempDir::new("latex-to-html").unwrap();
+        let (_, errors) = emit_math_svg_files(tmp_dir.path(), &[], &[&math], &options);
+        assert!(errors.is_empty());
+        fs::read_to_string(
+            tmp_dir
+                .path()
+                .join(SVG_OUT_DIR)
+                .join(format!("{digest}.svg")),
+        )
+        .unwrap()
+    };
+    let svg = compile();
+    assert_eq!(svg, compile());
+    // Both runs compiled the formula, with different ids.
+    assert_eq!(fs::read_to_string(&runs_path).unwrap().trim(), "2");
+    assert!(svg.contains(r#"<clipPath id="id1">"#));
+    assert!(svg.contains(r#"<g id="id2">"#));
+    assert!(svg.contains(r#"clip-path="url(#id1)""#));
+    assert!(svg.contains(r##"xlink:href="#id2""##));
 }
@@ -1,14 +1,42 @@
 use crate::ast::*;
 use nom::branch::alt;
 use nom::bytes::complete::{tag, take_while, take_while1};
-use nom::character::complete::{char, digit1, none_of, one_of};
-use nom::combinator::{cut, eof, opt};
-use nom::multi::{many0, many1};
-use nom::sequence::{pair, tuple};
+use nom::character::complete::{char, none_of, one_of, satisfy};
+use nom::combinator::{cut, eof, not, opt, peek, recognize};
+use nom::error::{ErrorKind, ParseError};
+use nom::multi::{many0, many1, many_m_n, separated_list1};
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
 use nom::{IResult, Parser};
-use std::str::FromStr;
 
-pub type Error<'a> = nom::error::Error<&'a str>;
+// A parse error. Failures inside constructs that can't be backtracked from, e.g. an environment
+// after its \begin, record what was expected at the position of the failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error<'a> {
+    pub input: &'a str,
+    pub kind: ErrorKind,
+    // Descriptions of the constructs that were expected at input, e.g. "\end{proof}".
+    pub expected: Vec<String>,
+}
+
+impl<'a> Error<'a> {
+    pub fn new(input: &'a str, kind: ErrorKind) -> Self {
+        Error {
+            input,
+            kind,
+            expected: Vec::new(),
+        }
+    }
+}
+
+impl<'a> ParseError<&'a str> for Error<'a> {
+    fn from_error_kind(input: &'a str, kind: ErrorKind) -> Self {
+        Error::new(input, kind)
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
 
 pub type Result<'a, O> = IResult<&'a str, O, Error<'a>>;
 
@@ -113,7 +141,7 @@ pub fn command<'a, O>(
         let (i, arg) = arg_parser(i)?;
 
         let (i, _) = any_ws(i)?;
-        let (i, _) = char('}')(i)?;
+        let (i, _) = expecting(|| "}".to_string(), char('}'))(i)?;
         Ok((i, arg))
     }
 }
@@ -186,17 +214,21 @@ pub fn dyn_env<'a, T, O>(
     mut content_parser: impl FnMut(&'a str) -> Result<'a, O>,
 ) -> impl FnMut(&'a str) -> Result<'a, O> {
     move |i: &'a str| {
-        let (i, _) = command("begin", &mut tag_parser)(i)?;
-        cut(|i: &'a str| {
+        let (i, name) = command("begin", recognize(&mut tag_parser))(i)?;
+        let mut rest = cut(|i: &'a str| {
             let (i, _) = inline_ws(i)?;
 
             let (i, content) = content_parser(i)?;
 
             let (i, _) = inline_ws(i)?;
-            let (i, _) = command("end", &mut tag_parser)(i)?;
+            let (i, _) = expecting(
+                move || format!("\\end{{{name}}}"),
+                command("end", &mut tag_parser),
+            )(i)?;
 
             Ok((i, content))
-        })(i)
+        });
+        rest(i)
     }
 }
 
@@ -212,13 +244,31 @@ pub fn env<'a, O>(
             let (i, content) = content_parser(i)?;
 
             let (i, _) = inline_ws(i)?;
-            let (i, _) = command("end", tag(name))(i)?;
+            let (i, _) = expecting(
+                move || format!("\\end{{{name}}}"),
+                command("end", tag(name)),
+            )(i)?;
 
             Ok((i, content))
         })(i)
     }
 }
 
+// Records that the construct described by description was expected if parser fails.
+pub fn expecting<'a, O>(
+    description: impl Fn() -> String,
+    mut parser: impl FnMut(&'a str) -> Result<'a, O>,
+) -> impl FnMut(&'a str) -> Result<'a, O> {
+    move |i: &'a str| {
+        parser(i).map_err(|err| {
+            err.map(|mut err| {
+                err.expected.push(description());
+                err
+            })
+        })
+    }
+}
+
 pub fn take_until<'a, O>(
     mut until_parser: impl FnMut(&'a str) -> Result<'a, O>,
 ) -> impl FnMut(&'a str) -> Result<'a, (&'a str, O)> {
@@ -232,7 +282,7 @@ pub fn take_until<'a, O>(
                 Err(_) => {
                     let mut chars = i.chars();
                     if let None = chars.next() {
-                        return Err(nom::Err::Error(Error::new(i, nom::error::ErrorKind::IsNot)));
+                        return Err(nom::Err::Error(Error::new(i, ErrorKind::IsNot)));
                     }
                     i = chars.as_str();
                 }
@@ -256,30 +306,181 @@ pub struct TextToken<'a>(&'a str);
 // TODO: [] is special only in certain contexts, e.g. when parsing the options of a command.
 const SPECIAL_CHARS: &'static str = " \n\t#$%&{}[]_~^\\";
 
+// The ligatures that latex replaces in text, in the order in which they must be tried.
+const LIGATURES: [(&str, &str); 4] = [("---", "—"), ("--", "–"), ("``", "“"), ("''", "”")];
+
 pub fn text_token(i: &str) -> Result<TextToken> {
     let before = i;
     let (i, _) = none_of(SPECIAL_CHARS)(i)?;
     let (i, _) = take_while(|c| !SPECIAL_CHARS.contains(c))(i)?;
-    Ok((i, TextToken(consumed_slice(before, i))))
+    // Ligatures end the token, so that text_symbol can replace them.
+    let token = consumed_slice(before, i);
+    let end = token
+        .char_indices()
+        .map(|(pos, _)| pos)
+        .find(|pos| {
+            LIGATURES
+                .iter()
+                .any(|(lig, _)| token[*pos..].starts_with(lig))
+        })
+        .unwrap_or(token.len());
+    if end == 0 {
+        return Err(nom::Err::Error(Error::new(before, ErrorKind::IsNot)));
+    }
+    Ok((&before[end..], TextToken(&token[..end])))
+}
+
+// Parses escaped special characters, ligatures, accents, ~, the control space and \ldots, and
+// returns the unicode text they stand for.
+pub fn text_symbol<'a>(i: &'a str) -> Result<'a, &'static str> {
+    let escaped = |c: char| preceded(char('\\'), char(c));
+    let ligature = |(lig, symbol): (&'static str, &'static str)| tag(lig).map(move |_| symbol);
+    alt((
+        escaped('&').map(|_| "&"),
+        escaped('%').map(|_| "%"),
+        escaped('_').map(|_| "_"),
+        escaped('$').map(|_| "$"),
+        escaped('#').map(|_| "#"),
+        char('~').map(|_| "\u{a0}"),
+        control_space,
+        terminated(command_no_args("ldots"), not(satisfy(char::is_alphabetic))).map(|_| "…"),
+        ligature(LIGATURES[0]),
+        ligature(LIGATURES[1]),
+        ligature(LIGATURES[2]),
+        ligature(LIGATURES[3]),
+        accent,
+    ))(i)
+}
+
+// A backslash followed by whitespace, which stands for a space. A line break after the backslash is
+// left to the whitespace parsers, so that a following blank line still ends the paragraph.
+fn control_space<'a>(i: &'a str) -> Result<'a, &'static str> {
+    let (i, _) = char('\\')(i)?;
+    let (i, _) = alt((recognize(one_of(" \t")), peek(tag("\n"))))(i)?;
+    Ok((i, " "))
+}
+
+// Accents that apply to the next letter or group, e.g. \'e or \'{e}, with the letters they can be
+// applied to and the corresponding composed characters.
+const SYMBOL_ACCENTS: [(char, &str, &str); 7] = [
+    ('\'', "aeiouyAEIOUYcnszCNSZlrLR", "áéíóúýÁÉÍÓÚÝćńśźĆŃŚŹĺŕĹŔ"),
+    ('`', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    ('^', "aeiouAEIOUcgshjwyCGSHJWY", "âêîôûÂÊÎÔÛĉĝŝĥĵŵŷĈĜŜĤĴŴŶ"),
+    ('"', "aeiouyAEIOUY", "äëïöüÿÄËÏÖÜŸ"),
+    ('~', "anoiuANOIU", "ãñõĩũÃÑÕĨŨ"),
+    ('=', "aeiouAEIOU", "āēīōūĀĒĪŌŪ"),
+    ('.', "zecgZECGI", "żėċġŻĖĊĠİ"),
+];
+
+// Accents named by a letter, e.g. \c{c}. The letter must be followed by a group or whitespace, so
+// that commands such as \cite are not mistaken for accents.
+const LETTER_ACCENTS: [(char, &str, &str); 6] = [
+    ('c', "cstCST", "çşţÇŞŢ"),
+    ('v', "csznrdteCSZNRDTE", "čšžňřďťěČŠŽŇŘĎŤĚ"),
+    ('u', "agouAGOU", "ăğŏŭĂĞŎŬ"),
+    ('H', "ouOU", "őűŐŰ"),
+    ('r', "auAU", "åůÅŮ"),
+    ('k', "aeAE", "ąęĄĘ"),
+];
+
+fn compose_accent(
+    accents: &[(char, &str, &'static str)],
+    accent: char,
+    letter: char,
+) -> Option<&'static str> {
+    let (_, letters, composed) = accents.iter().find(|(a, _, _)| *a == accent)?;
+    let index = letters.chars().position(|l| l == letter)?;
+    let (start, c) = composed.char_indices().nth(index)?;
+    Some(&composed[start..start + c.len_utf8()])
+}
+
+fn accent_letter<'a>(i: &'a str) -> Result<'a, char> {
+    let dotless_i = terminated(tag("\\i"), not(satisfy(char::is_alphabetic))).map(|_| 'i');
+    alt((satisfy(|c| c.is_ascii_alphabetic()), dotless_i))(i)
+}
+
+fn accent_group<'a>(i: &'a str) -> Result<'a, char> {
+    let space = |i| take_while(|c| c == ' ')(i);
+    delimited(
+        pair(char('{'), space),
+        accent_letter,
+        pair(space, char('}')),
+    )(i)
+}
+
+// Parses an accented letter, e.g. \'e, \'{e}, \"\i or \v{s}, and returns the composed character.
+pub fn accent<'a>(i: &'a str) -> Result<'a, &'static str> {
+    let (i, _) = char('\\')(i)?;
+    let (i, (accents, accent, base)): (_, (&[_], _, _)) = alt((
+        |i| {
+            let (i, accent) = one_of("'`^\"~=.")(i)?;
+            let (i, base) = alt((accent_group, accent_letter))(i)?;
+            Ok((i, (&SYMBOL_ACCENTS[..], accent, base)))
+        },
+        |i| {
+            let (i, accent) = one_of("cvuHrk")(i)?;
+            let (i, base) = alt((
+                accent_group,
+                preceded(take_while1(|c| c == ' ' || c == '\t'), accent_letter),
+            ))(i)?;
+            Ok((i, (&LETTER_ACCENTS[..], accent, base)))
+        },
+    ))(i)?;
+    match compose_accent(accents, accent, base) {
+        Some(composed) => Ok((i, composed)),
+        None => Err(nom::Err::Error(Error::new(i, ErrorKind::Verify))),
+    }
 }
 
 pub fn inline_math(i: &str) -> Result<Math> {
     let (i, _) = char('$')(i)?;
     let (i, content) = take_while(|c| c != '$')(i)?;
-    let (i, _) = char('$')(i)?;
+    let (i, _) = cut(expecting(|| "$".to_string(), char('$')))(i)?;
     Ok((i, Math::Inline(content)))
 }
 
+// Finds a \tag{...} command in math. Returns the whole command and its argument.
+pub fn find_math_tag(content: &str) -> Option<(&str, &str)> {
+    content.match_indices("\\tag").find_map(|(pos, _)| {
+        let i = &content[pos..];
+        let (rest, tag) = command("tag", raw_command_arg)(i).ok()?;
+        Some((consumed_slice(i, rest), tag.trim_end()))
+    })
+}
+
+// Removes the \label{...} commands from math.
+pub fn strip_math_labels(content: &str) -> String {
+    let mut result = String::new();
+    let mut rest = content;
+    while let Some(pos) = rest.find("\\label") {
+        let (before, label) = rest.split_at(pos);
+        result.push_str(before);
+        rest = match command("label", label_value)(label) {
+            Ok((after, _)) => after,
+            Err(_) => {
+                result.push_str("\\label");
+                &label["\\label".len()..]
+            }
+        };
+    }
+    result.push_str(rest);
+    result
+}
+
 pub fn display_math(i: &str) -> Result<Math> {
     let before = i;
     let (i, content) = raw_env("equation")(i)?;
     let (_, label) = opt(command("label", label_value))(content)?;
+    let math_tag = find_math_tag(content).map(|(_, tag)| tag);
+    let nonumber = content.contains("\\nonumber") || content.contains("\\notag");
 
     Ok((
         i,
         Math::Display {
             source: consumed_slice(before, i),
             label,
+            tag: math_tag,
+            nonumber,
         },
     ))
 }
@@ -289,6 +490,7 @@ pub fn display_math_double_dollar(i: &str) -> Result<Math> {
     let (i, _) = inline_ws(i)?;
     let (i, content) = take_while(|c| c != '$')(i)?;
     let (_, label) = opt(command("label", label_value))(content)?;
+    let math_tag = find_math_tag(content).map(|(_, tag)| tag);
     let (i, _) = tag("$$")(i)?;
 
     Ok((
@@ -296,6 +498,56 @@ pub fn display_math_double_dollar(i: &str) -> Result<Math> {
         Math::Display {
             source: consumed_slice(before, i),
             label,
+            tag: math_tag,
+            nonumber: false,
+        },
+    ))
+}
+
+// Consumes math up to the given closing delimiter, which is only recognized outside of braces.
+// Returns the math without the closing delimiter.
+fn math_until<'a>(close: &'static str) -> impl Fn(&'a str) -> Result<'a, &'a str> {
+    move |i: &'a str| {
+        let mut depth = 0;
+        let mut chars = i.char_indices();
+        while let Some((pos, c)) = chars.next() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                '\\' if depth == 0 && i[pos..].starts_with(close) => {
+                    return Ok((&i[pos + close.len()..], &i[..pos]));
+                }
+                '\\' => {
+                    // Skip the escaped character, so that e.g. \{ is not counted as a brace.
+                    chars.next();
+                }
+                _ => (),
+            }
+        }
+        Err(nom::Err::Error(Error::new(i, ErrorKind::TakeUntil)))
+    }
+}
+
+pub fn inline_math_parens<'a>(i: &'a str) -> Result<'a, Math<'a>> {
+    let (i, _) = tag("\\(")(i)?;
+    let (i, content) = math_until("\\)")(i)?;
+    Ok((i, Math::Inline(content)))
+}
+
+pub fn display_math_brackets<'a>(i: &'a str) -> Result<'a, Math<'a>> {
+    let before = i;
+    let (i, _) = tag("\\[")(i)?;
+    let (i, content) = math_until("\\]")(i)?;
+    let (_, label) = opt(preceded(inline_ws, command("label", label_value)))(content)?;
+    let math_tag = find_math_tag(content).map(|(_, tag)| tag);
+
+    Ok((
+        i,
+        Math::Display {
+            source: consumed_slice(before, i),
+            label,
+            tag: math_tag,
+            nonumber: false,
         },
     ))
 }
@@ -304,16 +556,80 @@ pub fn mathpar(i: &str) -> Result<Math> {
     let before = i;
     let (i, content) = raw_env("mathpar")(i)?;
     let (_, label) = opt(command("label", label_value))(content)?;
+    let math_tag = find_math_tag(content).map(|(_, tag)| tag);
 
     Ok((
         i,
         Math::Mathpar {
             source: consumed_slice(before, i),
             label,
+            tag: math_tag,
         },
     ))
 }
 
+pub fn align<'a>(i: &'a str) -> Result<'a, Math<'a>> {
+    let before = i;
+    let (i, (content, numbered)) = alt((
+        raw_env("align").map(|content| (content, true)),
+        raw_env("align*").map(|content| (content, false)),
+    ))(i)?;
+
+    let lines = align_lines(content)
+        .into_iter()
+        .map(|line| {
+            let label = line
+                .find("\\label")
+                .and_then(|pos| command("label", label_value)(&line[pos..]).ok())
+                .map(|(_, label)| label);
+            let nonumber = !numbered || line.contains("\\nonumber") || line.contains("\\notag");
+            AlignLine { label, nonumber }
+        })
+        .collect();
+
+    Ok((
+        i,
+        Math::Align {
+            source: consumed_slice(before, i),
+            lines,
+        },
+    ))
+}
+
+// Splits the content of an align environment at the \\ line breaks that are not nested in braces
+// or inner environments. A trailing empty line is dropped.
+pub fn align_lines(content: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut depth = 0;
+    let mut line_begin = 0;
+    let mut chars = content.char_indices();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '\\' => {
+                let rest = &content[pos..];
+                if rest.starts_with("\\begin") {
+                    depth += 1;
+                } else if rest.starts_with("\\end") {
+                    depth -= 1;
+                } else if rest.starts_with("\\\\") && depth == 0 {
+                    lines.push(&content[line_begin..pos]);
+                    line_begin = pos + 2;
+                }
+                // Skip the escaped character, so that e.g. \{ is not counted as a brace.
+                chars.next();
+            }
+            _ => (),
+        }
+    }
+    let last_line = &content[line_begin..];
+    if !last_line.trim().is_empty() {
+        lines.push(last_line);
+    }
+    lines
+}
+
 pub fn label_value(i: &str) -> Result<&str> {
     take_while1(|c: char| "-_:".find(c).is_some() || c.is_ascii_alphanumeric())(i)
 }
@@ -348,23 +664,153 @@ pub fn textit(i: &str) -> Result<ParagraphPart> {
     Ok((i, ParagraphPart::Textit(par)))
 }
 
+pub fn texttt<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, par) = command("texttt", paragraph)(i)?;
+    Ok((i, ParagraphPart::Texttt(par)))
+}
+
+pub fn textsc<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, par) = command("textsc", paragraph)(i)?;
+    Ok((i, ParagraphPart::Textsc(par)))
+}
+
+pub fn textsf<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, par) = command("textsf", paragraph)(i)?;
+    Ok((i, ParagraphPart::Textsf(par)))
+}
+
+pub fn underline<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, par) = command("underline", paragraph)(i)?;
+    Ok((i, ParagraphPart::Underline(par)))
+}
+
+pub fn textsuperscript<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, par) = command("textsuperscript", paragraph)(i)?;
+    Ok((i, ParagraphPart::Textsuperscript(par)))
+}
+
+pub fn textsubscript<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, par) = command("textsubscript", paragraph)(i)?;
+    Ok((i, ParagraphPart::Textsubscript(par)))
+}
+
+// \verb|...| with an arbitrary non-letter delimiter instead of |. The content is taken literally.
+pub fn verb<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, _) = command_no_args("verb")(i)?;
+    let (i, delim) = satisfy(|c| !c.is_alphabetic() && !c.is_whitespace())(i)?;
+    let (i, content) = take_while(|c| c != delim && c != '\n')(i)?;
+    let (i, _) = char(delim)(i)?;
+    Ok((i, ParagraphPart::Code(content)))
+}
+
+// \begin{verbatim}...\end{verbatim}. The content is taken literally, except that a line break
+// directly after \begin{verbatim} and the indentation of \end{verbatim} are dropped.
+pub fn verbatim<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, _) = command("begin", tag("verbatim"))(i)?;
+    let (i, _) = opt(pair(take_while(|c| c == ' ' || c == '\t'), char('\n')))(i)?;
+    let (i, (content, _)) = take_until(tag("\\end{verbatim}"))(i)?;
+    let content = match content.rfind('\n') {
+        Some(pos) if content[pos + 1..].chars().all(|c| c == ' ' || c == '\t') => &content[..pos],
+        _ => content,
+    };
+    Ok((i, ParagraphPart::Verbatim(content)))
+}
+
+pub fn href<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, link) = command("href", raw_command_arg)(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, _) = char('{')(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, text) = paragraph(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, _) = char('}')(i)?;
+    let link = link.trim();
+    Ok((i, ParagraphPart::Href { link, text }))
+}
+
+pub fn textcolor<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, (model, color)) =
+        command_with_opts(tag("textcolor"), take_while(|c| c != ']'), raw_command_arg)(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, _) = char('{')(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, content) = opt(paragraph)(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, _) = char('}')(i)?;
+    Ok((
+        i,
+        ParagraphPart::TextColor {
+            model: model.map(str::trim),
+            color: color.trim(),
+            content: content.unwrap_or_default(),
+        },
+    ))
+}
+
+// \url{...} is a link whose text is the url itself.
+pub fn url<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, link) = raw_command("url")(i)?;
+    let link = link.trim();
+    let text = vec![ParagraphPart::TextToken(link)];
+    Ok((i, ParagraphPart::Href { link, text }))
+}
+
 pub fn paragraph_qed(i: &str) -> Result<ParagraphPart> {
     let (i, _) = command_no_args("qed")(i)?;
     Ok((i, ParagraphPart::Qed))
 }
 
+pub fn today<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, _) = command_no_args("today")(i)?;
+    Ok((i, ParagraphPart::Today))
+}
+
+pub fn cref<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let ids = || intersperse0(label_value, tuple((any_ws, tag(","), any_ws)));
+    let (i, (capitalized, ids)) = alt((
+        command("Cref", ids()).map(|ids| (true, ids)),
+        command("cref", ids()).map(|ids| (false, ids)),
+    ))(i)?;
+    Ok((i, ParagraphPart::Cref { ids, capitalized }))
+}
+
 pub fn eqref(i: &str) -> Result<ParagraphPart> {
     let (i, val) = command("eqref", label_value)(i)?;
     Ok((i, ParagraphPart::Ref(val)))
 }
 
-pub fn cite(i: &str) -> Result<ParagraphPart> {
+pub fn cite<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
     let arg_sep = tuple((any_ws, tag(","), any_ws));
-    let arg_parser = intersperse0(cite_value, arg_sep);
-    let opt_parser = paragraph;
-    let command_name_parser = alt((tag("citep"), tag("citet"), tag("cite")));
-    let (i, (text, ids)) = command_with_opts(command_name_parser, opt_parser, arg_parser)(i)?;
-    Ok((i, ParagraphPart::Cite { text, ids }))
+    let (i, _) = char('\\')(i)?;
+    let (i, command) = alt((
+        tag("citep").map(|_| CiteCommand::Citep),
+        tag("citet").map(|_| CiteCommand::Citet),
+        tag("cite").map(|_| CiteCommand::Cite),
+    ))(i)?;
+    let (i, _) = any_ws(i)?;
+    // Notes may be empty, e.g. in \citep[see][]{key}.
+    let note = delimited(
+        pair(char('['), any_ws),
+        opt(paragraph).map(Option::unwrap_or_default),
+        tuple((any_ws, char(']'), any_ws)),
+    );
+    let (i, mut notes) = many_m_n(0, 2, note)(i)?;
+    let postnote = notes.pop().filter(|note| !note.is_empty());
+    let prenote = notes.pop().filter(|note| !note.is_empty());
+    let (i, ids) = delimited(
+        pair(char('{'), any_ws),
+        intersperse0(cite_value, arg_sep),
+        pair(any_ws, char('}')),
+    )(i)?;
+    Ok((
+        i,
+        ParagraphPart::Cite {
+            command,
+            ids,
+            prenote,
+            postnote,
+        },
+    ))
 }
 
 pub fn item(i: &str) -> Result<Item> {
@@ -374,8 +820,10 @@ pub fn item(i: &str) -> Result<Item> {
         let (i, val) = command("label", label_value)(i)?;
         Ok((i, val))
     })(i)?;
-    let (i, _) = inline_ws(i)?;
-    let (i, content) = many1(paragraph)(i)?;
+    // The content may start on a later line, e.g. with a nested list, and may consist of several
+    // paragraphs.
+    let (i, _) = any_ws(i)?;
+    let (i, content) = paragraphs0(i)?;
     let item = Item { content, label };
     Ok((i, item))
 }
@@ -396,6 +844,104 @@ pub fn enumerate(i: &str) -> Result<ParagraphPart> {
     Ok((i, ParagraphPart::Enumerate(items)))
 }
 
+// An item of a description environment, i.e. "\item[term] body". The term is empty if the optional
+// argument is missing.
+pub fn description_item<'a>(i: &'a str) -> Result<'a, (Paragraph<'a>, Vec<Paragraph<'a>>)> {
+    let (i, _) = command_no_args("item")(i)?;
+    let (i, term) = opt(|i| {
+        let (i, _) = any_ws(i)?;
+        let (i, _) = char('[')(i)?;
+        let (i, _) = any_ws(i)?;
+        let (i, term) = opt(paragraph)(i)?;
+        let (i, _) = any_ws(i)?;
+        let (i, _) = char(']')(i)?;
+        Ok((i, term.unwrap_or_default()))
+    })(i)?;
+    // Unlike in enumerate environments, a \label in a description item refers to the enclosing
+    // section in latex. We don't support this and ignore the label.
+    let (i, _) = opt(|i| {
+        let (i, _) = any_ws(i)?;
+        command("label", label_value)(i)
+    })(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, content) = paragraphs0(i)?;
+    Ok((i, (term.unwrap_or_default(), content)))
+}
+
+pub fn description<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, items) = env("description", intersperse0(description_item, any_ws))(i)?;
+    Ok((i, ParagraphPart::Description(items)))
+}
+
+pub fn center<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, content) = env("center", paragraphs0)(i)?;
+    Ok((i, ParagraphPart::Center(content)))
+}
+
+pub fn centerline<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, content) = command("centerline", opt(paragraph))(i)?;
+    Ok((i, ParagraphPart::Center(content.into_iter().collect())))
+}
+
+// Parses the column specification of a tabular environment, e.g. "|l|cr". Vertical rules are
+// ignored, and p{...} columns are left aligned.
+fn tabular_columns<'a>(i: &'a str) -> Result<'a, Vec<ColumnAlignment>> {
+    let column = alt((
+        char('l').map(|_| Some(ColumnAlignment::Left)),
+        char('c').map(|_| Some(ColumnAlignment::Center)),
+        char('r').map(|_| Some(ColumnAlignment::Right)),
+        tuple((one_of("pmb"), char('{'), raw_command_arg, char('}')))
+            .map(|_| Some(ColumnAlignment::Left)),
+        char('|').map(|_| None),
+        satisfy(|c| c.is_whitespace()).map(|_| None),
+    ));
+    let (i, columns) = many0(column)(i)?;
+    Ok((i, columns.into_iter().flatten().collect()))
+}
+
+fn hlines<'a>(i: &'a str) -> Result<'a, bool> {
+    let (i, hlines) = many0(terminated(command_no_args("hline"), any_ws))(i)?;
+    Ok((i, !hlines.is_empty()))
+}
+
+pub fn tabular<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let tabular_content = |i: &'a str| {
+        let (mut i, columns) = delimited(char('{'), tabular_columns, char('}'))(i)?;
+        (i, _) = any_ws(i)?;
+
+        let mut rows: Vec<TabularRow<'a>> = Vec::new();
+        loop {
+            let (j, border_top) = hlines(i)?;
+            if j.starts_with("\\end") {
+                // \hlines after the last row are a border below it.
+                if let Some(last) = rows.last_mut() {
+                    last.border_bottom = border_top;
+                }
+                i = j;
+                break;
+            }
+
+            let cell = opt(paragraph).map(Option::unwrap_or_default);
+            let (j, cells) = separated_list1(tuple((any_ws, char('&'), any_ws)), cell)(j)?;
+            let (j, _) = any_ws(j)?;
+            let (j, _) = opt(pair(tag("\\\\"), any_ws))(j)?;
+            if j.len() == i.len() {
+                // Nothing that we understand; the error is reported at the missing \end.
+                break;
+            }
+            rows.push(TabularRow {
+                cells,
+                border_top,
+                border_bottom: false,
+            });
+            i = j;
+        }
+        Ok((i, (columns, rows)))
+    };
+    let (i, (columns, rows)) = env("tabular", tabular_content)(i)?;
+    Ok((i, ParagraphPart::Tabular { columns, rows }))
+}
+
 pub fn todo(i: &str) -> Result<ParagraphPart> {
     let (i, _) = raw_command("todo")(i)?;
     Ok((i, ParagraphPart::Todo))
@@ -406,6 +952,11 @@ pub fn footnote(i: &str) -> Result<ParagraphPart> {
     Ok((i, ParagraphPart::Footnote(content)))
 }
 
+pub fn thanks<'a>(i: &'a str) -> Result<'a, ParagraphPart<'a>> {
+    let (i, content) = command("thanks", intersperse0(paragraph, any_ws))(i)?;
+    Ok((i, ParagraphPart::Thanks(content)))
+}
+
 pub fn paragraph<'a>(i: &'a str) -> Result<Paragraph<'a>> {
     let ws_part = |i: &'a str| {
         let (i, ws) = inline_ws(i)?;
@@ -415,6 +966,14 @@ pub fn paragraph<'a>(i: &'a str) -> Result<Paragraph<'a>> {
         let (i, tok) = text_token(i)?;
         Ok((i, ParagraphPart::TextToken(tok.0)))
     };
+    let text_symbol = |i: &'a str| {
+        let (i, symbol) = text_symbol(i)?;
+        Ok((i, ParagraphPart::TextToken(symbol)))
+    };
+    let thin_space = |i: &'a str| {
+        let (i, _) = tag("\\,")(i)?;
+        Ok((i, ParagraphPart::ThinSpace))
+    };
     let ref_command = |i: &'a str| {
         let (i, r) = ref_command(i)?;
         Ok((i, ParagraphPart::Ref(r.0)))
@@ -423,25 +982,54 @@ pub fn paragraph<'a>(i: &'a str) -> Result<Paragraph<'a>> {
         let (i, emph) = emph(i)?;
         Ok((i, ParagraphPart::Emph(emph.0)))
     };
+    let font_style = |i: &'a str| {
+        alt((
+            emph,
+            textbf,
+            textit,
+            texttt,
+            textsc,
+            textsf,
+            underline,
+            textsuperscript,
+            textsubscript,
+        ))(i)
+    };
+
+    let math = |i: &'a str| {
+        alt((
+            display_math,
+            display_math_double_dollar,
+            display_math_brackets,
+            inline_math,
+            inline_math_parens,
+            mathpar,
+            align,
+        ))(i)
+    };
 
     let non_ws_part = |i: &'a str| {
         alt((
             text,
-            display_math.map(ParagraphPart::Math),
-            display_math_double_dollar.map(ParagraphPart::Math),
-            inline_math.map(ParagraphPart::Math),
-            mathpar.map(ParagraphPart::Math),
+            alt((text_symbol, thin_space)),
+            math.map(ParagraphPart::Math),
             ref_command,
             eqref,
+            cref,
             cite,
-            emph,
-            textbf,
-            textit,
-            paragraph_qed,
+            alt((font_style, textcolor)),
+            verb,
+            href,
+            url,
+            alt((paragraph_qed, today)),
             itemize,
             enumerate,
+            description,
+            alt((center, centerline)),
+            tabular,
+            verbatim,
             todo,
-            footnote,
+            alt((footnote, thanks)),
         ))(i)
     };
 
@@ -512,8 +1100,29 @@ pub fn author<'a>(i: &'a str) -> Result<DocumentPart<'a>> {
 }
 
 pub fn date<'a>(i: &'a str) -> Result<DocumentPart<'a>> {
-    let (i, _) = command("date", many0(none_of("{}"))).parse(i)?;
-    Ok((i, DocumentPart::Date()))
+    let (i, date) = command("date", opt(paragraph)).parse(i)?;
+    Ok((i, DocumentPart::Date(date)))
+}
+
+// Parses a run-in heading such as \paragraph{...} and the paragraph following it, if any.
+fn run_in_heading<'a>(
+    name: &'static str,
+) -> impl FnMut(&'a str) -> Result<'a, (Paragraph<'a>, Option<&'a str>, Paragraph<'a>)> {
+    move |i: &'a str| {
+        let (i, (_, name, label)) = sectioning_command(name)(i)?;
+        let (i, body) = opt(preceded(any_ws, paragraph))(i)?;
+        Ok((i, (name, label, body.unwrap_or_default())))
+    }
+}
+
+pub fn paragraph_heading<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let (i, (name, label, body)) = run_in_heading("paragraph")(i)?;
+    Ok((i, DocumentPart::ParagraphHeading { label, name, body }))
+}
+
+pub fn subparagraph_heading<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let (i, (name, label, body)) = run_in_heading("subparagraph")(i)?;
+    Ok((i, DocumentPart::SubparagraphHeading { label, name, body }))
 }
 
 pub fn maketitle<'a>(i: &'a str) -> Result<DocumentPart<'a>> {
@@ -521,24 +1130,61 @@ pub fn maketitle<'a>(i: &'a str) -> Result<DocumentPart<'a>> {
     Ok((i, DocumentPart::Maketitle()))
 }
 
-pub fn section<'a>(i: &'a str) -> Result<DocumentPart<'a>> {
-    let (i, name) = command("section", paragraph)(i)?;
-    let (i, label) = opt(|i| {
+// Parses \<name>{...} or its starred variant \<name>*{...}, optionally followed by a label.
+// Returns whether the section is numbered, i.e. whether it is not starred.
+fn sectioning_command<'a>(
+    name: &'static str,
+) -> impl FnMut(&'a str) -> Result<'a, (bool, Paragraph<'a>, Option<&'a str>)> {
+    move |i: &'a str| {
+        let (i, _) = pair(char('\\'), tag(name))(i)?;
+        let (i, star) = opt(char('*'))(i)?;
         let (i, _) = any_ws(i)?;
-        let (i, val) = command("label", label_value)(i)?;
-        Ok((i, val))
-    })(i)?;
-    Ok((i, DocumentPart::Section { name, label }))
+        let (i, _) = pair(char('{'), any_ws)(i)?;
+        let (i, title) = paragraph(i)?;
+        let (i, _) = pair(any_ws, char('}'))(i)?;
+        let (i, label) = opt(|i| {
+            let (i, _) = any_ws(i)?;
+            let (i, val) = command("label", label_value)(i)?;
+            Ok((i, val))
+        })(i)?;
+        Ok((i, (star.is_none(), title, label)))
+    }
+}
+
+pub fn section<'a>(i: &'a str) -> Result<DocumentPart<'a>> {
+    let (i, (numbered, name, label)) = sectioning_command("section")(i)?;
+    Ok((
+        i,
+        DocumentPart::Section {
+            name,
+            label,
+            numbered,
+        },
+    ))
 }
 
 pub fn subsection<'a>(i: &'a str) -> Result<DocumentPart<'a>> {
-    let (i, name) = command("subsection", paragraph)(i)?;
-    let (i, label) = opt(|i| {
-        let (i, _) = any_ws(i)?;
-        let (i, val) = command("label", label_value)(i)?;
-        Ok((i, val))
-    })(i)?;
-    Ok((i, DocumentPart::Subsection { name, label }))
+    let (i, (numbered, name, label)) = sectioning_command("subsection")(i)?;
+    Ok((
+        i,
+        DocumentPart::Subsection {
+            name,
+            label,
+            numbered,
+        },
+    ))
+}
+
+pub fn subsubsection<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let (i, (numbered, name, label)) = sectioning_command("subsubsection")(i)?;
+    Ok((
+        i,
+        DocumentPart::Subsubsection {
+            name,
+            label,
+            numbered,
+        },
+    ))
 }
 
 pub fn abstract_env<'a>(i: &'a str) -> Result<DocumentPart<'a>> {
@@ -547,13 +1193,26 @@ pub fn abstract_env<'a>(i: &'a str) -> Result<DocumentPart<'a>> {
         .parse(i)
 }
 
+// The \abstract{...} command used by some document classes instead of the environment.
+pub fn abstract_command<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    command("abstract", paragraphs0)
+        .map(DocumentPart::Abstract)
+        .parse(i)
+}
+
+pub fn keywords<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    command("keywords", paragraph)
+        .map(DocumentPart::Keywords)
+        .parse(i)
+}
+
 pub fn theorem_like<'a, 'b>(
     configs: &'b [TheoremLikeConfig<'a>],
     i: &'a str,
 ) -> Result<'a, DocumentPart<'a>> {
     let (first, tail) = match configs {
         [] => {
-            return Err(nom::Err::Error(Error::new(i, nom::error::ErrorKind::IsNot)));
+            return Err(nom::Err::Error(Error::new(i, ErrorKind::IsNot)));
         }
         [first, tail @ ..] => (first, tail),
     };
@@ -594,8 +1253,225 @@ pub fn proof<'a>(i: &'a str) -> Result<DocumentPart<'a>> {
 }
 
 pub fn bibliography<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
-    let (i, _) = command("bibliography", take_while(|c| c != '{' && c != '}'))(i)?;
-    Ok((i, DocumentPart::Bibliography))
+    let (i, name) = command("bibliography", take_while(|c| c != '{' && c != '}'))(i)?;
+    Ok((i, DocumentPart::Bibliography(name.trim())))
+}
+
+pub fn bibliography_style<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let (i, style) = raw_command("bibliographystyle")(i)?;
+    Ok((i, DocumentPart::BibliographyStyle(style.trim())))
+}
+
+enum FigureItem<'a> {
+    Graphics(&'a str),
+    Caption(Paragraph<'a>),
+    Label(&'a str),
+    Subfigure(Subfigure<'a>),
+    // Commands that only affect the layout in latex, e.g. \centering.
+    Ignored,
+}
+
+fn include_graphics<'a>(i: &'a str) -> Result<'a, &'a str> {
+    let (i, (_, path)) = command_with_opts(
+        tag("includegraphics"),
+        take_while(|c| c != ']'),
+        raw_command_arg,
+    )(i)?;
+    Ok((i, path.trim_end()))
+}
+
+// The items of a subfigure, i.e. the items of a figure except for subfigures.
+fn subfigure_item<'a>(i: &'a str) -> Result<'a, FigureItem<'a>> {
+    alt((
+        include_graphics.map(FigureItem::Graphics),
+        command("caption", paragraph).map(FigureItem::Caption),
+        command("label", label_value).map(FigureItem::Label),
+        command_no_args("centering").map(|_| FigureItem::Ignored),
+        command_no_args("hfill").map(|_| FigureItem::Ignored),
+        command_no_args("qquad").map(|_| FigureItem::Ignored),
+        command_no_args("quad").map(|_| FigureItem::Ignored),
+        raw_command("hspace").map(|_| FigureItem::Ignored),
+    ))(i)
+}
+
+fn figure_item<'a>(i: &'a str) -> Result<'a, FigureItem<'a>> {
+    alt((subfigure.map(FigureItem::Subfigure), subfigure_item))(i)
+}
+
+// Parses \begin{subfigure}[alignment]{width} ... \end{subfigure}. Subfigures can't be nested.
+fn subfigure<'a>(i: &'a str) -> Result<'a, Subfigure<'a>> {
+    let subfigure_content = |i: &'a str| {
+        // The alignment and the width only matter for latex.
+        let (i, _) = opt(tuple((char('['), take_while(|c| c != ']'), char(']'))))(i)?;
+        let (i, _) = any_ws(i)?;
+        let (i, _) = delimited(char('{'), raw_command_arg, char('}'))(i)?;
+        let (i, _) = any_ws(i)?;
+        let (i, items) = intersperse0(subfigure_item, any_ws)(i)?;
+        let (i, _) = any_ws(i)?;
+        Ok((i, items))
+    };
+    let (i, items) = env("subfigure", subfigure_content)(i)?;
+
+    let mut label = None;
+    let mut graphics = Vec::new();
+    let mut caption = None;
+    for item in items {
+        match item {
+            FigureItem::Graphics(path) => graphics.push(path),
+            FigureItem::Caption(par) => caption = Some(par),
+            FigureItem::Label(value) => label = Some(value),
+            FigureItem::Subfigure(_) => unreachable!("subfigure_item doesn't parse subfigures"),
+            FigureItem::Ignored => (),
+        }
+    }
+    Ok((
+        i,
+        Subfigure {
+            label,
+            graphics,
+            caption,
+        },
+    ))
+}
+
+pub fn figure<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let figure_content = |i: &'a str| {
+        // The placement specifier, e.g. [htbp], doesn't matter for html.
+        let (i, _) = opt(tuple((char('['), take_while(|c| c != ']'), char(']'))))(i)?;
+        let (i, _) = any_ws(i)?;
+        let (i, items) = intersperse0(figure_item, any_ws)(i)?;
+        let (i, _) = any_ws(i)?;
+        Ok((i, items))
+    };
+    let (i, items) = env("figure", figure_content)(i)?;
+
+    let mut label = None;
+    let mut graphics = Vec::new();
+    let mut caption = None;
+    let mut subfigures = Vec::new();
+    for item in items {
+        match item {
+            FigureItem::Graphics(path) => graphics.push(path),
+            FigureItem::Caption(par) => caption = Some(par),
+            FigureItem::Label(value) => label = Some(value),
+            FigureItem::Subfigure(subfigure) => subfigures.push(subfigure),
+            FigureItem::Ignored => (),
+        }
+    }
+    Ok((
+        i,
+        DocumentPart::Figure {
+            label,
+            graphics,
+            caption,
+            subfigures,
+        },
+    ))
+}
+
+enum TableItem<'a> {
+    Tabular(ParagraphPart<'a>),
+    Caption(Paragraph<'a>),
+    Label(&'a str),
+    Ignored,
+}
+
+fn table_item<'a>(i: &'a str) -> Result<'a, TableItem<'a>> {
+    alt((
+        tabular.map(TableItem::Tabular),
+        command("caption", paragraph).map(TableItem::Caption),
+        command("label", label_value).map(TableItem::Label),
+        command_no_args("centering").map(|_| TableItem::Ignored),
+    ))(i)
+}
+
+// Parses a table environment. As for figures, the label may appear before or after the caption.
+pub fn table<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let table_content = |i: &'a str| {
+        let (i, _) = opt(tuple((char('['), take_while(|c| c != ']'), char(']'))))(i)?;
+        let (i, _) = any_ws(i)?;
+        let (i, items) = intersperse0(table_item, any_ws)(i)?;
+        let (i, _) = any_ws(i)?;
+        Ok((i, items))
+    };
+    let (i, items) = env("table", table_content)(i)?;
+
+    let mut label = None;
+    let mut tabulars = Vec::new();
+    let mut caption = None;
+    for item in items {
+        match item {
+            TableItem::Tabular(tabular) => tabulars.push(tabular),
+            TableItem::Caption(par) => caption = Some(par),
+            TableItem::Label(value) => label = Some(value),
+            TableItem::Ignored => (),
+        }
+    }
+    Ok((
+        i,
+        DocumentPart::Table {
+            label,
+            tabulars,
+            caption,
+        },
+    ))
+}
+
+pub fn list_of_figures<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let (i, _) = command_no_args("listoffigures")(i)?;
+    Ok((i, DocumentPart::ListOfFigures))
+}
+
+pub fn list_of_tables<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let (i, _) = command_no_args("listoftables")(i)?;
+    Ok((i, DocumentPart::ListOfTables))
+}
+
+// Parses a command with two arguments, e.g. \numberwithin{equation}{section}.
+fn command2<'a, O1, O2>(
+    name: &'static str,
+    mut first_parser: impl FnMut(&'a str) -> Result<'a, O1>,
+    second_parser: impl FnMut(&'a str) -> Result<'a, O2>,
+) -> impl FnMut(&'a str) -> Result<'a, (O1, O2)> {
+    let mut second = delimited(
+        pair(char('{'), any_ws),
+        second_parser,
+        pair(any_ws, char('}')),
+    );
+    move |i: &'a str| {
+        let (i, first) = command(name, &mut first_parser)(i)?;
+        let (i, _) = any_ws(i)?;
+        let (i, second) = second(i)?;
+        Ok((i, (first, second)))
+    }
+}
+
+pub fn numberwithin<'a>(i: &'a str) -> Result<'a, (&'a str, &'a str)> {
+    command2("numberwithin", label_value, label_value)(i)
+}
+
+pub fn setcounter<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let value = |i: &'a str| {
+        let (i, digits) = take_while1(|c: char| c.is_ascii_digit())(i)?;
+        match digits.parse() {
+            Ok(value) => Ok((i, value)),
+            Err(_) => Err(nom::Err::Error(Error::new(i, ErrorKind::Digit))),
+        }
+    };
+    let (i, (counter, value)) = command2("setcounter", label_value, value)(i)?;
+    Ok((i, DocumentPart::SetCounter { counter, value }))
+}
+
+pub fn appendix<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let (i, _) = command_no_args("appendix")(i)?;
+    Ok((i, DocumentPart::Appendix))
+}
+
+pub fn nocite<'a>(i: &'a str) -> Result<'a, DocumentPart<'a>> {
+    let arg_sep = tuple((any_ws, tag(","), any_ws));
+    let id = alt((tag("*"), cite_value));
+    let (i, ids) = command("nocite", intersperse0(id, arg_sep))(i)?;
+    Ok((i, DocumentPart::Nocite(ids)))
 }
 
 pub fn document_part<'a, 'b>(
@@ -612,10 +1488,20 @@ pub fn document_part<'a, 'b>(
         maketitle,
         section,
         subsection,
-        abstract_env,
+        subsubsection,
+        paragraph_heading,
+        subparagraph_heading,
+        alt((abstract_env, abstract_command, keywords)),
         theorem_like,
         proof,
         bibliography,
+        bibliography_style,
+        alt((figure, table)),
+        list_of_figures,
+        list_of_tables,
+        appendix,
+        setcounter,
+        nocite,
     ))(i)?;
     Ok((i, part))
 }
@@ -648,12 +1534,144 @@ pub fn preamble_lines<'a>(preamble: &'a str) -> Vec<&'a str> {
     lines
 }
 
+// Returns the lines of the preamble that are used when compiling math, i.e. all lines except
+// those directly after a line "% LATEX_TO_HTML_NO_MATH".
+pub fn math_preamble_lines<'a>(preamble: &[&'a str]) -> Vec<&'a str> {
+    let mut lines: Vec<&'a str> = Vec::new();
+    let mut prev_line_was_no_math = false;
+    for line in preamble.iter().copied() {
+        if line.trim() == "% LATEX_TO_HTML_NO_MATH" {
+            prev_line_was_no_math = true;
+            continue;
+        }
+
+        if !prev_line_was_no_math {
+            lines.push(line);
+        }
+        prev_line_was_no_math = false;
+    }
+
+    lines
+}
+
+pub fn theorem_style<'a>(i: &'a str) -> Result<'a, TheoremStyle> {
+    let (i, style) = command(
+        "theoremstyle",
+        alt((
+            tag("plain").map(|_| TheoremStyle::Theorem),
+            tag("definition").map(|_| TheoremStyle::Definition),
+            tag("remark").map(|_| TheoremStyle::Remark),
+        )),
+    )(i)?;
+    Ok((i, style))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewTheorem<'a> {
+    tag: &'a str,
+    shared_counter: Option<&'a str>,
+    name: Paragraph<'a>,
+    numbered_within: Option<&'a str>,
+}
+
+// Parses \newtheorem{tag}[shared counter]{Name}[numbered within], where at most one of the
+// optional arguments may be present.
+pub fn newtheorem<'a>(i: &'a str) -> Result<'a, NewTheorem<'a>> {
+    let opt_arg = |i: &'a str| {
+        let (i, _) = any_ws(i)?;
+        let (i, _) = char('[')(i)?;
+        let (i, _) = any_ws(i)?;
+        let (i, value) = label_value(i)?;
+        let (i, _) = any_ws(i)?;
+        let (i, _) = char(']')(i)?;
+        Ok((i, value))
+    };
+
+    let (i, tag) = command("newtheorem", label_value)(i)?;
+    let (i, shared_counter) = opt(opt_arg)(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, _) = char('{')(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, name) = paragraph(i)?;
+    let (i, _) = any_ws(i)?;
+    let (i, _) = char('}')(i)?;
+    let (i, numbered_within) = match shared_counter {
+        None => opt(opt_arg)(i)?,
+        Some(_) => (i, None),
+    };
+    Ok((
+        i,
+        NewTheorem {
+            tag,
+            shared_counter,
+            name,
+            numbered_within,
+        },
+    ))
+}
+
+// Returns the default config extended by the theorem-like environments declared in the preamble
+// with \newtheorem. A declaration for a tag that is already known replaces the known config.
+// \numberwithin applies to the equation, figure and table counters and to counters of theorem-like
+// environments.
+pub fn document_config<'a>(preamble: &[&'a str]) -> DocumentConfig<'a> {
+    let mut config = DocumentConfig::default();
+    let mut style = TheoremStyle::Theorem;
+    for line in preamble.iter() {
+        let line = line.trim_start();
+        if let Ok((_, line_style)) = theorem_style(line) {
+            style = line_style;
+            continue;
+        }
+        if let Ok((_, (counter, within))) = numberwithin(line) {
+            // The counter of theorem-like environments is that of the environment with the same tag.
+            if counter == "equation" {
+                config.equation_numbered_within = Some(within);
+            } else if counter == "figure" {
+                config.figure_numbered_within = Some(within);
+            } else if counter == "table" {
+                config.table_numbered_within = Some(within);
+            } else if let Some(counter_config) = config
+                .theorem_like_configs
+                .iter_mut()
+                .find(|config| config.tag == counter)
+            {
+                counter_config.numbered_within = Some(within);
+            }
+            continue;
+        }
+
+        let Ok((_, decl)) = newtheorem(line) else {
+            continue;
+        };
+        let counter = match decl.shared_counter {
+            None => decl.tag,
+            Some(shared_counter) => config
+                .theorem_like_configs
+                .iter()
+                .find(|config| config.tag == shared_counter)
+                .map_or(shared_counter, |config| config.counter),
+        };
+        config
+            .theorem_like_configs
+            .retain(|config| config.tag != decl.tag);
+        config.theorem_like_configs.push(TheoremLikeConfig {
+            tag: decl.tag,
+            name: decl.name,
+            style,
+            counter,
+            numbered_within: decl.numbered_within,
+        });
+    }
+    config
+}
+
 pub fn document<'a>(i: &'a str) -> Result<Document<'a>> {
     let (i, _) = any_ws(i)?;
     let (i, _) = documentclass(i)?;
     let (i, (preamble, _)) = take_until(command("begin", tag("document")))(i)?;
     let preamble = preamble_lines(preamble);
-    let config = DocumentConfig::default();
+    let config = document_config(&preamble);
     let (i, _) = any_ws(i)?;
     let document_part = |i: &'a str| document_part(&config, i);
     let (i, parts) = intersperse0(document_part, any_ws)(i)?;
@@ -683,10 +1701,15 @@ pub fn bib_entry_type<'a>(i: &'a str) -> Result<'a, BibEntryType> {
         tag("inproceedings").map(|_| Inproceedings),
         tag("thesis").map(|_| Thesis),
         tag("incollection").map(|_| Incollection),
+        tag("phdthesis").map(|_| Phdthesis),
+        tag("mastersthesis").map(|_| Mastersthesis),
+        tag("techreport").map(|_| Techreport),
+        tag("online").map(|_| Online),
+        tag("electronic").map(|_| Online),
     ))(i)
 }
 
-fn bib_entry_tag<'a>(i: &'a str) -> Result<'a, &'a str> {
+pub fn bib_entry_tag<'a>(i: &'a str) -> Result<'a, &'a str> {
     let (i, val) = take_while(|c| " \t\n,".find(c).is_none())(i)?;
     Ok((i, val))
 }
@@ -700,15 +1723,37 @@ fn bib_entry_item<'a, O, N>(
         let (i, _) = bib_ws(i)?;
         let (i, _) = char('=')(i)?;
         let (i, _) = bib_ws(i)?;
-        let (i, _) = char('{')(i)?;
-        let (i, _) = bib_ws(i)?;
-        let (i, o) = value_parser(i)?;
-        let (i, _) = bib_ws(i)?;
-        let (i, _) = char('}')(i)?;
+        let (i, value) = alt((
+            delimited(char('{'), raw_command_arg, char('}')),
+            bib_quoted_value,
+        ))(i)?;
+
+        // The value parser must consume the whole content of the delimiters.
+        let (value, _) = bib_ws(value)?;
+        let (value, o) = value_parser(value)?;
+        let (value, _) = bib_ws(value)?;
+        eof(value)?;
         Ok((i, o))
     }
 }
 
+// Parses a bib value in double quotes and returns its content. Braces within the quotes still
+// group, so the content may contain double quotes within braces.
+pub fn bib_quoted_value<'a>(i: &'a str) -> Result<'a, &'a str> {
+    let (i, _) = char('"')(i)?;
+    let mut nesting = 0;
+    for (pos, c) in i.char_indices() {
+        match c {
+            '{' => nesting += 1,
+            '}' if nesting == 0 => break,
+            '}' => nesting -= 1,
+            '"' if nesting == 0 => return Ok((&i[pos + 1..], &i[..pos])),
+            _ => (),
+        }
+    }
+    Err(nom::Err::Error(Error::new(i, ErrorKind::Char)))
+}
+
 fn bib_item_raw_value<'a>(i: &'a str) -> Result<'a, &'a str> {
     //let (i, value) = take_while(|c| c != '{' && c != '}')(i)?;
     let (i, value) = raw_command_arg(i)?;
@@ -725,20 +1770,52 @@ fn bib_year_item<'a>(i: &'a str) -> Result<'a, BibEntryItem> {
     Ok((i, BibEntryItem::Year(val)))
 }
 
+fn bib_month_item<'a>(i: &'a str) -> Result<'a, BibEntryItem<'a>> {
+    let (i, val) = bib_entry_item(tag("month"), bib_item_raw_value)(i)?;
+    Ok((i, BibEntryItem::Month(val)))
+}
+
+// Parses a (part of a) name, which may contain accents such as M\"uller, M\"{u}ller or M{\"u}ller.
+// Accents are kept in the source and replaced when the name is displayed.
+fn bib_name<'a>(i: &'a str) -> Result<'a, &'a str> {
+    recognize(many1(alt((
+        recognize(accent),
+        recognize(delimited(char('{'), raw_command_arg, char('}'))),
+        take_while1(|c| !",;={}.\\ \t\n".contains(c)),
+        tag("\\"),
+    ))))(i)
+}
+
+// Replaces the accents in a bib name by the characters they stand for and removes braces.
+pub fn replace_accents(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut i = name;
+    while let Some(c) = i.chars().next() {
+        if let Ok((rest, composed)) = accent(i) {
+            result.push_str(composed);
+            i = rest;
+            continue;
+        }
+        if c != '{' && c != '}' {
+            result.push(c);
+        }
+        i = &i[c.len_utf8()..];
+    }
+    result
+}
+
 fn bib_abbreviated_first_name<'a>(i: &'a str) -> Result<'a, FirstName<'a>> {
     let before = i;
-    let (i, _) = none_of(",;={} \t\n")(i)?;
+    let (i, _) = alt((recognize(accent), recognize(none_of(",;={} \t\n"))))(i)?;
     let first_name = FirstName::Abbreviation(consumed_slice(before, i));
     let (i, _) = char('.')(i)?;
     Ok((i, first_name))
 }
 
 fn bib_full_first_name<'a>(i: &'a str) -> Result<'a, FirstName<'a>> {
-    let before = i;
-    let (i, _) = take_while1(|c| !",;={}. \t\n".contains(c))(i)?;
-    let value = consumed_slice(before, i);
+    let (i, value) = bib_name(i)?;
     if value == "and" {
-        return Err(nom::Err::Error(Error::new(i, nom::error::ErrorKind::IsA)));
+        return Err(nom::Err::Error(Error::new(i, ErrorKind::IsA)));
     }
     let first_name = FirstName::Full(value);
     Ok((i, first_name))
@@ -749,11 +1826,9 @@ fn bib_first_name<'a>(i: &'a str) -> Result<'a, FirstName<'a>> {
 }
 
 fn bib_last_name<'a>(i: &'a str) -> Result<'a, &'a str> {
-    let before = i;
-    let (i, _) = take_while1(|c| !",;={}. \t\n".contains(c))(i)?;
-    let value = consumed_slice(before, i);
+    let (i, value) = bib_name(i)?;
     if value == "and" {
-        return Err(nom::Err::Error(Error::new(i, nom::error::ErrorKind::IsA)));
+        return Err(nom::Err::Error(Error::new(i, ErrorKind::IsA)));
     }
     Ok((i, value))
 }
@@ -782,6 +1857,12 @@ fn bib_authors_item<'a>(i: &'a str) -> Result<'a, BibEntryItem> {
     Ok((i, BibEntryItem::Authors(authors)))
 }
 
+fn bib_editors_item<'a>(i: &'a str) -> Result<'a, BibEntryItem<'a>> {
+    let sep = tuple((bib_ws, tag("and"), bib_ws));
+    let (i, editors) = bib_entry_item(tag("editor"), intersperse0(bib_person, sep))(i)?;
+    Ok((i, BibEntryItem::Editors(editors)))
+}
+
 fn bib_url_item<'a>(i: &'a str) -> Result<'a, BibEntryItem> {
     let (i, val) = bib_entry_item(tag("url"), bib_item_raw_value)(i)?;
     Ok((i, BibEntryItem::Url(val)))
@@ -817,20 +1898,52 @@ fn bib_number_item<'a>(i: &'a str) -> Result<'a, BibEntryItem> {
     Ok((i, BibEntryItem::Number(val)))
 }
 
-fn bib_pages_item<'a>(i: &'a str) -> Result<'a, BibEntryItem> {
+fn bib_page<'a>(i: &'a str) -> Result<'a, &'a str> {
+    take_while1(|c: char| !c.is_whitespace() && !"-–,;{}".contains(c))(i)
+}
+
+fn bib_pages_item<'a>(i: &'a str) -> Result<'a, BibEntryItem<'a>> {
     bib_entry_item(tag("pages"), |i| {
-        let (i, first) = digit1(i)?;
-        let first = u64::from_str(first).unwrap();
+        let (i, first) = bib_page(i)?;
         let (i, last) = opt(|i| {
             let (i, _) = alt((tag("--"), tag("–"), tag("-")))(i)?;
-            let (i, last) = digit1(i)?;
-            let last = u64::from_str(last).unwrap();
+            let (i, last) = bib_page(i)?;
             Ok((i, last))
         })(i)?;
         Ok((i, BibEntryItem::Pages(BibPages { first, last })))
     })(i)
 }
 
+fn bib_school_item<'a>(i: &'a str) -> Result<'a, BibEntryItem<'a>> {
+    let (i, val) = bib_entry_item(tag("school"), bib_item_raw_value)(i)?;
+    Ok((i, BibEntryItem::School(val)))
+}
+
+fn bib_institution_item<'a>(i: &'a str) -> Result<'a, BibEntryItem<'a>> {
+    let (i, val) = bib_entry_item(tag("institution"), bib_item_raw_value)(i)?;
+    Ok((i, BibEntryItem::Institution(val)))
+}
+
+fn bib_type_item<'a>(i: &'a str) -> Result<'a, BibEntryItem<'a>> {
+    let (i, val) = bib_entry_item(tag("type"), bib_item_raw_value)(i)?;
+    Ok((i, BibEntryItem::Type(val)))
+}
+
+fn bib_howpublished_item<'a>(i: &'a str) -> Result<'a, BibEntryItem<'a>> {
+    let (i, val) = bib_entry_item(tag("howpublished"), bib_item_raw_value)(i)?;
+    Ok((i, BibEntryItem::Howpublished(val)))
+}
+
+fn bib_doi_item<'a>(i: &'a str) -> Result<'a, BibEntryItem<'a>> {
+    let (i, val) = bib_entry_item(tag("doi"), bib_item_raw_value)(i)?;
+    Ok((i, BibEntryItem::Doi(val)))
+}
+
+fn bib_isbn_item<'a>(i: &'a str) -> Result<'a, BibEntryItem<'a>> {
+    let (i, val) = bib_entry_item(tag("isbn"), bib_item_raw_value)(i)?;
+    Ok((i, BibEntryItem::Isbn(val)))
+}
+
 fn unused_bib_item<'a>(i: &'a str) -> Result<'a, BibEntryItem> {
     let name = take_while(|c| !" ={}".contains(c));
     let (i, _) = bib_entry_item(name, bib_item_raw_value)(i)?;
@@ -841,7 +1954,9 @@ fn bib_item<'a>(i: &'a str) -> Result<'a, BibEntryItem> {
     alt((
         bib_title_item,
         bib_year_item,
+        bib_month_item,
         bib_authors_item,
+        bib_editors_item,
         bib_url_item,
         bib_journal_item,
         bib_booktitle_item,
@@ -850,6 +1965,12 @@ fn bib_item<'a>(i: &'a str) -> Result<'a, BibEntryItem> {
         bib_volume_item,
         bib_number_item,
         bib_pages_item,
+        bib_school_item,
+        bib_institution_item,
+        bib_type_item,
+        bib_howpublished_item,
+        bib_doi_item,
+        bib_isbn_item,
         unused_bib_item,
     ))(i)
 }
@@ -864,7 +1985,9 @@ fn make_bib_entry<'a, 'b>(
         entry_type,
         title: None,
         year: None,
+        month: None,
         authors: None,
+        editors: None,
         url: None,
         journal: None,
         booktitle: None,
@@ -873,6 +1996,12 @@ fn make_bib_entry<'a, 'b>(
         volume: None,
         number: None,
         pages: None,
+        school: None,
+        institution: None,
+        kind: None,
+        howpublished: None,
+        doi: None,
+        isbn: None,
     };
 
     for item in items {
@@ -886,10 +2015,18 @@ fn make_bib_entry<'a, 'b>(
                 assert!(result.year.is_none(), "Duplicate year value");
                 result.year = Some(year);
             }
+            Month(month) => {
+                assert!(result.month.is_none(), "Duplicate month value");
+                result.month = Some(month);
+            }
             Authors(authors) => {
                 assert!(result.authors.is_none(), "Duplicate authors value");
                 result.authors = Some(authors);
             }
+            Editors(editors) => {
+                assert!(result.editors.is_none(), "Duplicate editors value");
+                result.editors = Some(editors);
+            }
             Url(url) => {
                 assert!(result.url.is_none(), "Duplicate url value");
                 result.url = Some(url);
@@ -922,6 +2059,33 @@ fn make_bib_entry<'a, 'b>(
                 assert!(result.pages.is_none(), "Duplicate pages value");
                 result.pages = Some(pages);
             }
+            School(school) => {
+                assert!(result.school.is_none(), "Duplicate school value");
+                result.school = Some(school);
+            }
+            Institution(institution) => {
+                assert!(result.institution.is_none(), "Duplicate institution value");
+                result.institution = Some(institution);
+            }
+            Type(kind) => {
+                assert!(result.kind.is_none(), "Duplicate type value");
+                result.kind = Some(kind);
+            }
+            Howpublished(howpublished) => {
+                assert!(
+                    result.howpublished.is_none(),
+                    "Duplicate howpublished value"
+                );
+                result.howpublished = Some(howpublished);
+            }
+            Doi(doi) => {
+                assert!(result.doi.is_none(), "Duplicate doi value");
+                result.doi = Some(doi);
+            }
+            Isbn(isbn) => {
+                assert!(result.isbn.is_none(), "Duplicate isbn value");
+                result.isbn = Some(isbn);
+            }
             Unused => (),
         }
     }
@@ -960,3 +2124,782 @@ pub fn bib<'a>(i: &'a str) -> Result<'a, Vec<BibEntry<'a>>> {
     let (i, _) = bib_ws(i)?;
     Ok((i, entries))
 }
+
+#[test]
+fn list_of_floats() {
+    let (_, doc) = document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \listoffigures
+        \listoftables
+        \end{document}
+    "#})
+    .unwrap();
+    assert_eq!(
+        doc.parts,
+        [DocumentPart::ListOfFigures, DocumentPart::ListOfTables]
+    );
+}
+
+#[test]
+fn nested_sections() {
+    let (_, doc) = document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \section{A}
+        \subsection{B}
+        \subsubsection{C}
+        \label{sec:c}
+        \end{document}
+    "#})
+    .unwrap();
+    assert_eq!(doc.parts.len(), 3);
+    assert_eq!(
+        doc.parts[2],
+        DocumentPart::Subsubsection {
+            label: Some("sec:c"),
+            name: vec![ParagraphPart::TextToken("C")],
+            numbered: true,
+        }
+    );
+}
+
+#[test]
+fn hyperlinks() {
+    let (_, par) = paragraph(r"See \href{https://example.com/a_b#c}{the \emph{docs}}.").unwrap();
+    assert_eq!(
+        par[2],
+        ParagraphPart::Href {
+            link: "https://example.com/a_b#c",
+            text: vec![
+                ParagraphPart::TextToken("the"),
+                ParagraphPart::InlineWhitespace(" "),
+                ParagraphPart::Emph(vec![ParagraphPart::TextToken("docs")]),
+            ],
+        }
+    );
+
+    let (_, par) = paragraph(r"\url{https://example.com/~x%20y}").unwrap();
+    assert_eq!(
+        par,
+        [ParagraphPart::Href {
+            link: "https://example.com/~x%20y",
+            text: vec![ParagraphPart::TextToken("https://example.com/~x%20y")],
+        }]
+    );
+}
+
+#[test]
+fn inline_code() {
+    let (_, par) = paragraph(r"\texttt{ls \textbf{-l}}").unwrap();
+    assert_eq!(
+        par,
+        [ParagraphPart::Texttt(vec![
+            ParagraphPart::TextToken("ls"),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::Textbf(vec![ParagraphPart::TextToken("-l")]),
+        ])]
+    );
+
+    let (_, par) = paragraph(r"\verb|\emph{x} $y$| and \verb+a|b+").unwrap();
+    assert_eq!(par[0], ParagraphPart::Code(r"\emph{x} $y$"));
+    assert_eq!(par[4], ParagraphPart::Code("a|b"));
+
+    assert!(paragraph(r"\verb|unterminated").is_err());
+}
+
+#[test]
+fn align_lines_and_labels() {
+    let source = concat!(
+        "\\begin{align}\n",
+        "  f(x) &= \\begin{cases} 0 \\\\ 1 \\end{cases} \\nonumber \\\\\n",
+        "  g(x) &= \\sum_{\\substack{i \\\\ j}} \\{ i \\} \\label{eq:g} \\\\\n",
+        "\\end{align}",
+    );
+    let (_, math) = align(source).unwrap();
+    let Math::Align { lines, .. } = math else {
+        panic!("Expected align");
+    };
+    assert_eq!(
+        lines,
+        [
+            AlignLine {
+                label: None,
+                nonumber: true,
+            },
+            AlignLine {
+                label: Some("eq:g"),
+                nonumber: false,
+            },
+        ]
+    );
+
+    let (_, math) = align("\\begin{align*} a \\\\ b \\label{eq:b} \\end{align*}").unwrap();
+    assert_eq!(math.label(), None);
+    let Math::Align { lines, .. } = math else {
+        panic!("Expected align");
+    };
+    assert!(lines.iter().all(|line| line.nonumber));
+}
+
+#[test]
+fn bracket_math_delimiters() {
+    let (_, par) = paragraph(r"\[ x^{\]} \] and \(x\)").unwrap();
+    assert_eq!(
+        par,
+        [
+            ParagraphPart::Math(Math::Display {
+                source: r"\[ x^{\]} \]",
+                label: None,
+                tag: None,
+                nonumber: false,
+            }),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::TextToken("and"),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::Math(Math::Inline("x")),
+        ]
+    );
+
+    let (_, math) = display_math_brackets(r"\[ x^2 \]").unwrap();
+    assert_eq!(
+        math,
+        Math::Display {
+            source: r"\[ x^2 \]",
+            label: None,
+            tag: None,
+            nonumber: false,
+        }
+    );
+
+    assert!(inline_math_parens(r"\(x").is_err());
+}
+
+#[test]
+fn newtheorem_declarations() {
+    let (_, doc) = document(indoc::indoc! {r#"
+        \documentclass{article}
+        \newtheorem{claim}{Claim}
+        \theoremstyle{definition}
+        \newtheorem{construction}[claim]{Construction}
+        \newtheorem{theorem}{Theorem}[section]
+        \begin{document}
+        \begin{claim}
+          Something holds.
+        \end{claim}
+        \end{document}
+    "#})
+    .unwrap();
+
+    let config = |tag| {
+        doc.config
+            .theorem_like_configs
+            .iter()
+            .find(|config| config.tag == tag)
+            .unwrap()
+    };
+    assert_eq!(
+        config("claim"),
+        &TheoremLikeConfig {
+            tag: "claim",
+            name: vec![ParagraphPart::TextToken("Claim")],
+            style: TheoremStyle::Theorem,
+            counter: "claim",
+            numbered_within: None,
+        }
+    );
+    assert_eq!(config("construction").counter, "claim");
+    assert_eq!(config("construction").style, TheoremStyle::Definition);
+    assert_eq!(config("theorem").numbered_within, Some("section"));
+    assert_eq!(config("lemma").counter, "theorem");
+
+    assert!(matches!(
+        doc.parts.as_slice(),
+        [DocumentPart::TheoremLike { tag: "claim", .. }]
+    ));
+}
+
+#[test]
+fn description_items() {
+    let (rest, part) = description(indoc::indoc! {r#"
+        \begin{description}
+          \item[First term] The first definition.
+          \item [Second \emph{term}] \label{item:second}
+            The second definition.
+        \end{description}"#})
+    .unwrap();
+    assert_eq!(rest, "");
+    let items = match part {
+        ParagraphPart::Description(items) => items,
+        _ => panic!("Expected description"),
+    };
+    assert_eq!(items.len(), 2);
+    assert_eq!(
+        items[0].0,
+        vec![
+            ParagraphPart::TextToken("First"),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::TextToken("term"),
+        ]
+    );
+    assert_eq!(items[0].1.len(), 1);
+    assert_eq!(
+        items[1].0[2],
+        ParagraphPart::Emph(vec![ParagraphPart::TextToken("term")])
+    );
+    assert_eq!(items[1].1.len(), 1);
+}
+
+#[test]
+fn nested_lists() {
+    let (rest, part) = itemize(indoc::indoc! {r#"
+        \begin{itemize}
+          \item
+
+            \begin{enumerate}
+              \item Inner.
+            \end{enumerate}
+          \item First paragraph.
+
+            Second paragraph.
+            \begin{itemize}
+              \item Deep.
+            \end{itemize}
+        \end{itemize}"#})
+    .unwrap();
+    assert_eq!(rest, "");
+    let items = match part {
+        ParagraphPart::Itemize(items) => items,
+        _ => panic!("Expected itemize"),
+    };
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].content.len(), 1);
+    assert!(matches!(
+        items[0].content[0].as_slice(),
+        [ParagraphPart::Enumerate(inner)] if inner.len() == 1
+    ));
+    assert_eq!(items[1].content.len(), 2);
+    assert!(matches!(
+        items[1].content[1].last(),
+        Some(ParagraphPart::Itemize(inner)) if inner.len() == 1
+    ));
+}
+
+#[test]
+fn thesis_report_and_online_entries() {
+    let (_, entries) = bib(indoc::indoc! {r#"
+        @phdthesis{phd,
+          title={A thesis},
+          school={University X},
+          year={2020}
+        }
+        @mastersthesis{msc,
+          title={Another thesis},
+          school={University Y},
+          type={Diploma thesis}
+        }
+        @techreport{report,
+          title={A report},
+          institution={Institute Z},
+          number={42}
+        }
+        @online{website,
+          title={A website},
+          howpublished={Online},
+          url={https://example.com}
+        }
+        @electronic{electronic,
+          title={Another website}
+        }
+    "#})
+    .unwrap();
+    assert_eq!(entries.len(), 5);
+
+    assert_eq!(entries[0].entry_type, BibEntryType::Phdthesis);
+    assert_eq!(entries[0].school, Some("University X"));
+    assert_eq!(entries[0].year, Some("2020"));
+
+    assert_eq!(entries[1].entry_type, BibEntryType::Mastersthesis);
+    assert_eq!(entries[1].school, Some("University Y"));
+    assert_eq!(entries[1].kind, Some("Diploma thesis"));
+
+    assert_eq!(entries[2].entry_type, BibEntryType::Techreport);
+    assert_eq!(entries[2].institution, Some("Institute Z"));
+    assert_eq!(entries[2].number, Some("42"));
+
+    assert_eq!(entries[3].entry_type, BibEntryType::Online);
+    assert_eq!(entries[3].howpublished, Some("Online"));
+    assert_eq!(entries[3].url, Some("https://example.com"));
+
+    assert_eq!(entries[4].entry_type, BibEntryType::Online);
+}
+
+#[test]
+fn doi_and_isbn() {
+    let (_, entries) = bib(indoc::indoc! {r#"
+        @book{hott,
+          title={Homotopy Type Theory},
+          doi={10.1000/182},
+          isbn={978-3-16-148410-0}
+        }
+    "#})
+    .unwrap();
+    assert_eq!(entries[0].doi, Some("10.1000/182"));
+    assert_eq!(entries[0].isbn, Some("978-3-16-148410-0"));
+}
+
+#[test]
+fn quoted_bib_values() {
+    let (_, braced) = bib(indoc::indoc! {r#"
+        @article{paper,
+          title={A {"}Quoted{"} Title},
+          author={Smith, John and Doe, Jane},
+          pages={1--10}
+        }
+    "#})
+    .unwrap();
+    let (_, quoted) = bib(indoc::indoc! {r#"
+        @article{paper,
+          title="A {"}Quoted{"} Title",
+          author="Smith, John and Doe, Jane",
+          pages="1--10"
+        }
+    "#})
+    .unwrap();
+    assert_eq!(braced, quoted);
+    assert_eq!(quoted[0].title, Some(r#"A {"}Quoted{"} Title"#));
+}
+
+#[test]
+fn bib_editors() {
+    let (_, entries) = bib(indoc::indoc! {r#"
+        @incollection{chapter,
+          title={A chapter},
+          editor={Smith, John and Doe, Jane},
+          booktitle={A collection}
+        }
+    "#})
+    .unwrap();
+    let editors = entries[0].editors.as_ref().unwrap();
+    assert_eq!(editors.len(), 2);
+    assert_eq!(editors[0].last_name, "Smith");
+    assert_eq!(editors[1].last_name, "Doe");
+    assert_eq!(entries[0].authors, None);
+}
+
+#[test]
+fn non_numeric_pages() {
+    let (_, entries) = bib(indoc::indoc! {r#"
+        @book{roman,
+          pages={iv--xii}
+        }
+        @article{article-number,
+          pages={e1001}
+        }
+        @article{prefixed,
+          pages={A1-A9}
+        }
+    "#})
+    .unwrap();
+    let pages: Vec<_> = entries.iter().map(|entry| entry.pages).collect();
+    assert_eq!(
+        pages,
+        [
+            Some(BibPages {
+                first: "iv",
+                last: Some("xii"),
+            }),
+            Some(BibPages {
+                first: "e1001",
+                last: None,
+            }),
+            Some(BibPages {
+                first: "A1",
+                last: Some("A9"),
+            }),
+        ]
+    );
+}
+
+#[test]
+fn equation_tag() {
+    let (_, math) = display_math(indoc::indoc! {r#"
+        \begin{equation}
+          x = 1 \tag{A.1}
+        \end{equation}
+    "#})
+    .unwrap();
+    assert_eq!(math.tag(), Some("A.1"));
+
+    let (_, math) = display_math_brackets(r"\[ x \]").unwrap();
+    assert_eq!(math.tag(), None);
+    assert_eq!(find_math_tag(r"x \tag{*} y"), Some((r"\tag{*}", "*")));
+}
+
+#[test]
+fn figures() {
+    let (_, part) = figure(indoc::indoc! {r#"
+        \begin{figure}[htbp]
+          \centering
+          \includegraphics[width=0.5\textwidth]{img/plot.png}
+          \caption{A \emph{nice} plot.}
+          \label{fig:plot}
+        \end{figure}
+    "#})
+    .unwrap();
+    let DocumentPart::Figure {
+        label,
+        graphics,
+        caption,
+        ..
+    } = part
+    else {
+        panic!("Expected a figure");
+    };
+    assert_eq!(label, Some("fig:plot"));
+    assert_eq!(graphics, ["img/plot.png"]);
+    assert_eq!(caption.unwrap().len(), 5);
+}
+
+#[test]
+fn tables() {
+    let (_, part) = table(indoc::indoc! {r#"
+        \begin{table}[t]
+          \label{tab:data}
+          \caption{Some data.}
+          \centering
+          \begin{tabular}{lr}
+            a & 1
+          \end{tabular}
+        \end{table}
+    "#})
+    .unwrap();
+    let DocumentPart::Table {
+        label,
+        tabulars,
+        caption,
+    } = part
+    else {
+        panic!("Expected a table");
+    };
+    assert_eq!(label, Some("tab:data"));
+    assert!(matches!(tabulars[..], [ParagraphPart::Tabular { .. }]));
+    assert!(caption.is_some());
+}
+
+#[test]
+fn nested_subfigures_are_rejected() {
+    let result = figure(indoc::indoc! {r#"
+        \begin{figure}
+          \begin{subfigure}{0.5\textwidth}
+            \begin{subfigure}{0.5\textwidth}
+              \includegraphics{a.png}
+            \end{subfigure}
+          \end{subfigure}
+        \end{figure}
+    "#});
+    assert!(matches!(result, Err(nom::Err::Failure(_))));
+}
+
+#[test]
+fn tabular_2x2() {
+    let (_, part) = tabular(indoc::indoc! {r#"
+        \begin{tabular}{|l|c|}
+          \hline
+          $x$ & b \\
+          c & \emph{d} \\
+          \hline
+        \end{tabular}
+    "#})
+    .unwrap();
+    let ParagraphPart::Tabular { columns, rows } = part else {
+        panic!("Expected a tabular");
+    };
+    assert_eq!(columns, [ColumnAlignment::Left, ColumnAlignment::Center]);
+    assert_eq!(rows.len(), 2);
+    assert_eq!(
+        rows[0].cells,
+        [
+            vec![ParagraphPart::Math(Math::Inline("x"))],
+            vec![ParagraphPart::TextToken("b")],
+        ]
+    );
+    assert_eq!(
+        rows[1].cells[1],
+        [ParagraphPart::Emph(vec![ParagraphPart::TextToken("d")])]
+    );
+    assert!(rows[0].border_top && !rows[0].border_bottom);
+    assert!(!rows[1].border_top && rows[1].border_bottom);
+}
+
+#[test]
+fn text_escapes_and_ligatures() {
+    let (_, par) = paragraph(r"A---B--C ``q'' 5\% \& x~y\ldots").unwrap();
+    let text: String = par
+        .iter()
+        .map(|part| match part {
+            ParagraphPart::TextToken(tok) | ParagraphPart::InlineWhitespace(tok) => *tok,
+            _ => panic!("Unexpected paragraph part"),
+        })
+        .collect();
+    assert_eq!(text, "A—B–C “q” 5% & x\u{a0}y…");
+    assert!(par.contains(&ParagraphPart::TextToken("—")));
+    assert!(par.contains(&ParagraphPart::TextToken("&")));
+}
+
+#[test]
+fn spacing_commands() {
+    let text = |src| {
+        let (rest, par) = paragraph(src).unwrap();
+        assert_eq!(rest, "");
+        par.iter()
+            .map(|part| match part {
+                ParagraphPart::TextToken(tok) | ParagraphPart::InlineWhitespace(tok) => *tok,
+                _ => panic!("Unexpected paragraph part"),
+            })
+            .collect::<String>()
+    };
+    assert_eq!(text("Theorem~1"), "Theorem\u{a0}1");
+    assert_eq!(text(r"e.g.\ this"), "e.g. this");
+    assert_eq!(text("Dr.\\\tWho"), "Dr. Who");
+    assert_eq!(text("Dr.\\\nWho"), "Dr. \nWho");
+    assert!(paragraph(r"e.g.\ this")
+        .unwrap()
+        .1
+        .contains(&ParagraphPart::TextToken(" ")));
+    assert_eq!(
+        paragraph(r"10\,km").unwrap().1,
+        [
+            ParagraphPart::TextToken("10"),
+            ParagraphPart::InlineWhitespace(""),
+            ParagraphPart::ThinSpace,
+            ParagraphPart::InlineWhitespace(""),
+            ParagraphPart::TextToken("km"),
+        ]
+    );
+}
+
+#[test]
+fn control_space_at_end_of_line() {
+    let (_, paragraphs) = paragraphs0("Dr.\\\n\nWho").unwrap();
+    assert_eq!(paragraphs.len(), 2);
+}
+
+#[test]
+fn text_accents() {
+    let (_, par) = paragraph(r#"caf\'e \"{o} \c{c} \v s \~n \^{\i} \u{o} \u{u} \cite{x}"#).unwrap();
+    for composed in ["é", "ö", "ç", "š", "ñ", "î", "ŏ", "ŭ"] {
+        assert!(par.contains(&ParagraphPart::TextToken(composed)));
+    }
+    assert!(par
+        .iter()
+        .any(|part| matches!(part, ParagraphPart::Cite { .. })));
+}
+
+#[test]
+fn bib_name_accents() {
+    let (_, entries) = bib(indoc::indoc! {r#"
+        @article{accents,
+          author={M\"{u}ller, J\"urgen and Erd{\H o}s, \'E. and Sch\"onberg, Anna}
+        }
+    "#})
+    .unwrap();
+    let authors = entries[0].authors.as_ref().unwrap();
+    let last_names: Vec<_> = authors
+        .iter()
+        .map(|author| replace_accents(author.last_name))
+        .collect();
+    assert_eq!(last_names, ["Müller", "Erdős", "Schönberg"]);
+    assert_eq!(authors[0].first_names, [FirstName::Full(r#"J\"urgen"#)]);
+    assert_eq!(authors[1].first_names, [FirstName::Abbreviation(r"\'E")]);
+}
+
+#[test]
+fn paragraph_headings() {
+    let (_, doc) = document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \paragraph{Setup} We fix notation.
+
+        \subparagraph{Details}\label{par:details}
+
+        \section{Next}
+        \end{document}
+    "#})
+    .unwrap();
+    assert_eq!(doc.parts.len(), 3);
+    assert_eq!(
+        doc.parts[0],
+        DocumentPart::ParagraphHeading {
+            label: None,
+            name: vec![ParagraphPart::TextToken("Setup")],
+            body: vec![
+                ParagraphPart::TextToken("We"),
+                ParagraphPart::InlineWhitespace(" "),
+                ParagraphPart::TextToken("fix"),
+                ParagraphPart::InlineWhitespace(" "),
+                ParagraphPart::TextToken("notation."),
+            ],
+        }
+    );
+    assert_eq!(
+        doc.parts[1],
+        DocumentPart::SubparagraphHeading {
+            label: Some("par:details"),
+            name: vec![ParagraphPart::TextToken("Details")],
+            body: vec![],
+        }
+    );
+}
+
+#[test]
+fn verbatim_environment() {
+    let (_, par) = paragraph(indoc::indoc! {r#"
+        Code:
+        \begin{verbatim}
+        fn main() {
+            // 100% <safe> & $sound$ \emph{x}
+        }
+        \end{verbatim}
+    "#})
+    .unwrap();
+    assert_eq!(
+        par[2],
+        ParagraphPart::Verbatim(indoc::indoc! {r#"
+            fn main() {
+                // 100% <safe> & $sound$ \emph{x}
+            }"#})
+    );
+}
+
+#[test]
+fn date_argument() {
+    let (_, date_part) = date(r"\date{1 April \today}").unwrap();
+    assert_eq!(
+        date_part,
+        DocumentPart::Date(Some(vec![
+            ParagraphPart::TextToken("1"),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::TextToken("April"),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::Today,
+        ]))
+    );
+    assert_eq!(date(r"\date{}").unwrap().1, DocumentPart::Date(None));
+}
+
+#[test]
+fn author_with_thanks() {
+    let (_, author_part) = author(r"\author{A. Smith\thanks{MIT}}").unwrap();
+    let DocumentPart::Author(author) = author_part else {
+        panic!("Expected author");
+    };
+    assert_eq!(
+        author.last(),
+        Some(&ParagraphPart::Thanks(vec![vec![
+            ParagraphPart::TextToken("MIT")
+        ]]))
+    );
+}
+
+#[test]
+fn cite_notes() {
+    let notes = |src| match cite(src).unwrap() {
+        (
+            "",
+            ParagraphPart::Cite {
+                prenote, postnote, ..
+            },
+        ) => (prenote, postnote),
+        _ => panic!("Unexpected parse result"),
+    };
+    let text = |tok| Some(vec![ParagraphPart::TextToken(tok)]);
+    assert_eq!(notes(r"\cite{key}"), (None, None));
+    assert_eq!(notes(r"\cite[42]{key}"), (None, text("42")));
+    assert_eq!(notes(r"\citep[see][42]{key}"), (text("see"), text("42")));
+    assert_eq!(notes(r"\citep[see][]{key}"), (text("see"), None));
+}
+
+#[test]
+fn abstract_command_and_environment() {
+    let (_, doc) = document(indoc::indoc! {r#"
+        \documentclass{article}
+        \begin{document}
+        \abstract{
+          We show things.
+
+          And more.
+        }
+        \begin{abstract}
+          We show things.
+
+          And more.
+        \end{abstract}
+        \end{document}
+    "#})
+    .unwrap();
+    let content = vec![
+        vec![
+            ParagraphPart::TextToken("We"),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::TextToken("show"),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::TextToken("things."),
+        ],
+        vec![
+            ParagraphPart::TextToken("And"),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::TextToken("more."),
+        ],
+    ];
+    assert_eq!(
+        doc.parts,
+        [
+            DocumentPart::Abstract(content.clone()),
+            DocumentPart::Abstract(content)
+        ]
+    );
+}
+
+#[test]
+fn keywords_command() {
+    let (_, part) = keywords(r"\keywords{graphs, \emph{trees}}").unwrap();
+    assert_eq!(
+        part,
+        DocumentPart::Keywords(vec![
+            ParagraphPart::TextToken("graphs,"),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::Emph(vec![ParagraphPart::TextToken("trees")]),
+        ])
+    );
+}
+
+#[test]
+fn text_super_and_subscripts() {
+    let (_, par) = paragraph(r"H\textsubscript{2}O, 4\textsuperscript{\emph{th}}").unwrap();
+    assert_eq!(
+        par,
+        [
+            ParagraphPart::TextToken("H"),
+            ParagraphPart::InlineWhitespace(""),
+            ParagraphPart::Textsubscript(vec![ParagraphPart::TextToken("2")]),
+            ParagraphPart::InlineWhitespace(""),
+            ParagraphPart::TextToken("O,"),
+            ParagraphPart::InlineWhitespace(" "),
+            ParagraphPart::TextToken("4"),
+            ParagraphPart::InlineWhitespace(""),
+            ParagraphPart::Textsuperscript(vec![ParagraphPart::Emph(vec![
+                ParagraphPart::TextToken("th")
+            ])]),
+        ]
+    );
+}
+
+#[test]
+fn blank_line_ends_paragraph() {
+    let (rest, paragraphs) = paragraphs0("a\nb % comment\n\n\t \nc\n%\nd").unwrap();
+    assert_eq!(rest, "");
+    assert_eq!(paragraphs.len(), 2);
+    assert_eq!(paragraphs[0].first(), Some(&ParagraphPart::TextToken("a")));
+    assert_eq!(paragraphs[1].last(), Some(&ParagraphPart::TextToken("d")));
+}
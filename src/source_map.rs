@@ -0,0 +1,233 @@
+use crate::display_source::{Location, SourceDisplay};
+use std::cmp::{max, min};
+use std::ops::Range;
+use std::path::PathBuf;
+
+// A file as it was read, before includes and macros were expanded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub source: String,
+}
+
+// A piece of the output of a source transformation such as macro expansion, and the range of the
+// input it was produced from. Text that was copied has ranges of equal length. Generated text, e.g.
+// the replacement of a macro, maps to the text it replaces as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub output: Range<usize>,
+    pub input: Range<usize>,
+}
+
+impl Span {
+    fn is_copy(&self) -> bool {
+        self.output.len() == self.input.len()
+    }
+
+    // The input offsets of the beginning and the end of a range that begins or ends at the given
+    // output offset.
+    fn input_begin(&self, offset: usize) -> usize {
+        if self.is_copy() {
+            self.input.start + (offset - self.output.start)
+        } else {
+            self.input.start
+        }
+    }
+    fn input_end(&self, offset: usize) -> usize {
+        if self.is_copy() {
+            self.input.start + (offset - self.output.start)
+        } else {
+            self.input.end
+        }
+    }
+
+    // Extends a copied span by len bytes if they are contiguous with it in the output and the input.
+    fn extend_copy(&mut self, output_begin: usize, input_begin: usize, len: usize) -> bool {
+        let contiguous = self.output.end == output_begin && self.input.end == input_begin;
+        if self.is_copy() && contiguous {
+            self.output.end += len;
+            self.input.end += len;
+        }
+        self.is_copy() && contiguous
+    }
+}
+
+// Appends a span of len bytes copied from the input to the output, extending the last span if the
+// two are contiguous.
+pub fn push_copy(spans: &mut Vec<Span>, output_begin: usize, input_begin: usize, len: usize) {
+    if len == 0 {
+        return;
+    }
+    if let Some(last) = spans.last_mut() {
+        if last.extend_copy(output_begin, input_begin, len) {
+            return;
+        }
+    }
+    spans.push(Span {
+        output: output_begin..output_begin + len,
+        input: input_begin..input_begin + len,
+    });
+}
+
+// Maps offsets into the source that is parsed back to the files its text comes from, so that
+// diagnostics show the file, line and column that the user wrote.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    // Pairs of file index and span, whose input range refers to that file. Sorted by output range.
+    spans: Vec<(usize, Span)>,
+}
+
+impl SourceMap {
+    // The map of a file that is parsed as is.
+    pub fn new(path: PathBuf, source: String) -> Self {
+        let mut spans = Vec::new();
+        push_copy(&mut spans, 0, 0, source.len());
+        SourceMap {
+            files: vec![SourceFile { path, source }],
+            spans: spans.into_iter().map(|span| (0, span)).collect(),
+        }
+    }
+
+    // Adds a file whose text is copied into the mapped source and returns its index.
+    pub fn add_file(&mut self, path: PathBuf, source: String) -> usize {
+        self.files.push(SourceFile { path, source });
+        self.files.len() - 1
+    }
+
+    // Records that len bytes at the end of the mapped source were copied from the given file.
+    pub fn push_copy(&mut self, file: usize, output_begin: usize, input_begin: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        if let Some((last_file, last)) = self.spans.last_mut() {
+            if *last_file == file && last.extend_copy(output_begin, input_begin, len) {
+                return;
+            }
+        }
+        let span = Span {
+            output: output_begin..output_begin + len,
+            input: input_begin..input_begin + len,
+        };
+        self.spans.push((file, span));
+    }
+
+    // The map of the output of a further transformation, given the spans that the transformation
+    // recorded with the source mapped by self as input.
+    pub fn then(self, spans: &[Span]) -> Self {
+        let mut result = Vec::new();
+        for span in spans.iter().filter(|span| !span.output.is_empty()) {
+            if !span.is_copy() {
+                let (file, Location(begin, end)) =
+                    self.locate_in_file(Location(span.input.start, span.input.end));
+                let span = Span {
+                    output: span.output.clone(),
+                    input: begin..end,
+                };
+                result.push((file, span));
+                continue;
+            }
+            let first = self
+                .spans
+                .partition_point(|(_, s)| s.output.end <= span.input.start);
+            for (file, s) in self.spans[first..].iter() {
+                if s.output.start >= span.input.end {
+                    break;
+                }
+                let begin = max(span.input.start, s.output.start);
+                let end = min(span.input.end, s.output.end);
+                let output_begin = span.output.start + (begin - span.input.start);
+                let output_end = span.output.start + (end - span.input.start);
+                let input = if s.is_copy() {
+                    s.input_begin(begin)..s.input_end(end)
+                } else {
+                    s.input.clone()
+                };
+                let span = Span {
+                    output: output_begin..output_end,
+                    input,
+                };
+                result.push((*file, span));
+            }
+        }
+        SourceMap {
+            files: self.files,
+            spans: result,
+        }
+    }
+
+    fn locate_in_file(&self, location: Location) -> (usize, Location) {
+        let Location(begin, end) = location;
+        let Some(last_index) = self.spans.len().checked_sub(1) else {
+            return (0, Location(0, 0));
+        };
+        let first_index = min(
+            self.spans.partition_point(|(_, s)| s.output.end <= begin),
+            last_index,
+        );
+        let (file, first) = &self.spans[first_index];
+        let file_begin = first.input_begin(begin.clamp(first.output.start, first.output.end));
+        if end <= begin {
+            return (*file, Location(file_begin, file_begin));
+        }
+
+        let end_index = self.spans.partition_point(|(_, s)| s.output.start < end);
+        let (end_file, last) = &self.spans[max(end_index, first_index + 1) - 1];
+        // Locations that span several files are cut off at the end of the first.
+        let file_end = if end_file == file {
+            last.input_end(min(end, last.output.end))
+        } else {
+            first.input_end(first.output.end)
+        };
+        (*file, Location(file_begin, max(file_begin, file_end)))
+    }
+
+    // The file and the location in it that a location in the mapped source comes from.
+    pub fn locate(&self, location: Location) -> (&SourceFile, Location) {
+        let (file, location) = self.locate_in_file(location);
+        (&self.files[file], location)
+    }
+
+    pub fn display(&self, location: Location, underlined: bool) -> SourceDisplay<'_> {
+        let (file, location) = self.locate(location);
+        SourceDisplay {
+            source: &file.source,
+            location,
+            source_path: Some(&file.path),
+            underlined,
+        }
+    }
+}
+
+#[test]
+fn copied_and_generated_spans() {
+    // "ab\Xcd" with \X expanded to "XYZW", followed by a transformation that drops the "a".
+    let source_map = SourceMap::new(PathBuf::from("doc.tex"), r"ab\Xcd".to_string());
+    let expanded = source_map.then(&[
+        Span {
+            output: 0..2,
+            input: 0..2,
+        },
+        Span {
+            output: 2..6,
+            input: 2..4,
+        },
+        Span {
+            output: 6..8,
+            input: 4..6,
+        },
+    ]);
+    assert_eq!(expanded.locate(Location(1, 2)).1, Location(1, 2));
+    assert_eq!(expanded.locate(Location(3, 4)).1, Location(2, 4));
+    assert_eq!(expanded.locate(Location(6, 8)).1, Location(4, 6));
+    assert_eq!(expanded.locate(Location(1, 7)).1, Location(1, 5));
+    assert_eq!(expanded.locate(Location(8, 8)).1, Location(6, 6));
+
+    let dropped = expanded.then(&[Span {
+        output: 0..7,
+        input: 1..8,
+    }]);
+    assert_eq!(dropped.locate(Location(0, 1)).1, Location(1, 2));
+    assert_eq!(dropped.locate(Location(2, 3)).1, Location(2, 4));
+    assert_eq!(dropped.locate(Location(6, 7)).1, Location(5, 6));
+}
@@ -1,4 +1,4 @@
-use std::fmt::{Display, Formatter, Result};
+use std::fmt::{Display, Formatter, Result, Write};
 
 pub struct DisplayFn<F: Fn(&mut Formatter) -> Result>(pub F);
 
@@ -7,3 +7,53 @@ impl<F: Fn(&mut Formatter) -> Result> Display for DisplayFn<F> {
         self.0(f)
     }
 }
+
+// Displays a string with the html metacharacters &, <, > and " replaced by entities.
+pub fn display_escaped(value: &str) -> impl '_ + Display {
+    DisplayFn(move |out: &mut Formatter| {
+        for c in value.chars() {
+            match c {
+                '&' => out.write_str("&amp;")?,
+                '<' => out.write_str("&lt;")?,
+                '>' => out.write_str("&gt;")?,
+                '"' => out.write_str("&quot;")?,
+                c => out.write_char(c)?,
+            }
+        }
+        Ok(())
+    })
+}
+
+// Converts a label or citation key to a string that is suitable as html id: Letters are
+// lowercased, every run of other characters than ascii letters and digits is replaced by a single
+// "-", and leading or trailing "-" are removed. For example, "thm:2ndMain" becomes "thm-2ndmain".
+pub fn slug(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii_alphanumeric() {
+            result.push(c.to_ascii_lowercase());
+        } else if !result.is_empty() && !result.ends_with('-') {
+            result.push('-');
+        }
+    }
+    if result.ends_with('-') {
+        result.pop();
+    }
+    result
+}
+
+#[test]
+fn escape_html() {
+    assert_eq!(
+        display_escaped(r#"if a < b & c > d then "e""#).to_string(),
+        "if a &lt; b &amp; c &gt; d then &quot;e&quot;"
+    );
+}
+
+#[test]
+fn slug_labels() {
+    assert_eq!(slug("eq:E=mc2"), "eq-e-mc2");
+    assert_eq!(slug("thm:2ndMain"), "thm-2ndmain");
+    assert_eq!(slug("sec:intro_and--overview"), "sec-intro-and-overview");
+    assert_eq!(slug(":x:"), "x");
+}